@@ -24,6 +24,9 @@ lazy_static::lazy_static! {
              --bench-file=[FILE] 'Bench file name override'
              --reset            'Reset all states except for bench results, linux.tar and testfiles'
              --keep-reports     'Don't delete expired report files, also affects --reset'
+             --rep-seg          'Store reports in compressed hourly segment files instead of JSON'
+             --metrics-listen=[ADDR] 'Serve Prometheus metrics over HTTP on ADDR (e.g. 127.0.0.1:9470)'
+             --metrics-textfile=[FILE] 'Write Prometheus metrics to FILE for textfile collectors'
              --bypass           'Skip startup and periodic health checks'
          -v...                  'Sets the level of verbosity'
              --logfile=[FILE]   'Specify file to dump logs'",
@@ -42,6 +45,13 @@ lazy_static::lazy_static! {
          -p, --report=[PATH]          'Report file path'"
     );
 
+    static ref CONVERT_REPORTS_USAGE: String = format!(
+        "-f, --from=[DIR]       'Source report directory'
+         -t, --to=[DIR]         'Destination report directory'
+         -s, --seg              'Convert to segment files (default: convert to JSON files)'
+         -p, --period=[FROM-TO] 'Convert only reports in the unix timestamp period'"
+    );
+
     static ref HELP_BODY: Mutex<&'static str> = Mutex::new("");
 }
 
@@ -163,6 +173,14 @@ impl Default for BanditMemHogArgs {
     }
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct ConvertReportsArgs {
+    pub from: String,
+    pub to: String,
+    pub to_seg: bool,
+    pub period: Option<(u64, u64)>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Bandit {
     MemHog(BanditMemHogArgs),
@@ -178,6 +196,8 @@ pub struct Args {
     pub rep_1min_retention: u64,
     pub systemd_timeout: f64,
     pub enforce: EnforceConfig,
+    pub metrics_listen: Option<String>,
+    pub metrics_textfile: Option<String>,
 
     #[serde(skip)]
     pub no_iolat: bool,
@@ -196,11 +216,15 @@ pub struct Args {
     #[serde(skip)]
     pub keep_reports: bool,
     #[serde(skip)]
+    pub rep_seg: bool,
+    #[serde(skip)]
     pub bypass: bool,
     #[serde(skip)]
     pub verbosity: u32,
     #[serde(skip)]
     pub logfile: Option<String>,
+    #[serde(skip)]
+    pub convert_reports: Option<ConvertReportsArgs>,
 
     pub bandit: Option<Bandit>,
 }
@@ -215,6 +239,8 @@ impl Default for Args {
             rep_1min_retention: 24 * 3600,
             systemd_timeout: systemd::SYSTEMD_DFL_TIMEOUT,
             enforce: Default::default(),
+            metrics_listen: None,
+            metrics_textfile: None,
             no_iolat: false,
             force: false,
             force_running: false,
@@ -223,9 +249,11 @@ impl Default for Args {
             bench_file: None,
             reset: false,
             keep_reports: false,
+            rep_seg: false,
             bypass: false,
             verbosity: 0,
             logfile: None,
+            convert_reports: None,
             bandit: None,
         }
     }
@@ -281,6 +309,28 @@ impl Args {
         }
         updated_base
     }
+
+    fn process_convert_reports(&mut self, subm: &clap::ArgMatches) {
+        let period = subm.value_of("period").map(|v| {
+            let (from, to) = v.split_once('-').expect("failed to parse \"period\"");
+            (
+                from.parse::<u64>().expect("failed to parse \"period\""),
+                to.parse::<u64>().expect("failed to parse \"period\""),
+            )
+        });
+        self.convert_reports = Some(ConvertReportsArgs {
+            from: subm
+                .value_of("from")
+                .expect("\"from\" must be specified")
+                .to_owned(),
+            to: subm
+                .value_of("to")
+                .expect("\"to\" must be specified")
+                .to_owned(),
+            to_seg: subm.is_present("seg"),
+            period,
+        });
+    }
 }
 
 impl JsonArgs for Args {
@@ -295,6 +345,11 @@ impl JsonArgs for Args {
                     .about("Bandit mode - keep bloating up memory")
                     .args_from_usage(&BANDIT_MEM_HOG_USAGE),
             )
+            .subcommand(
+                clap::SubCommand::with_name("convert-reports")
+                    .about("Convert report directory between JSON files and segment files")
+                    .args_from_usage(&CONVERT_REPORTS_USAGE),
+            )
             .setting(clap::AppSettings::UnifiedHelpMessage)
             .setting(clap::AppSettings::DeriveDisplayOrder)
             .get_matches()
@@ -358,6 +413,23 @@ impl JsonArgs for Args {
             updated_base = true;
         }
 
+        if let Some(v) = matches.value_of("metrics-listen") {
+            self.metrics_listen = if v.len() > 0 {
+                Some(v.to_string())
+            } else {
+                None
+            };
+            updated_base = true;
+        }
+        if let Some(v) = matches.value_of("metrics-textfile") {
+            self.metrics_textfile = if v.len() > 0 {
+                Some(v.to_string())
+            } else {
+                None
+            };
+            updated_base = true;
+        }
+
         if let Some(v) = matches.value_of("systemd-timeout") {
             self.systemd_timeout = if v.len() > 0 {
                 parse_duration(v).unwrap().max(1.0)
@@ -375,6 +447,7 @@ impl JsonArgs for Args {
         self.bench_file = matches.value_of("bench-file").map(|x| x.to_string());
         self.reset = matches.is_present("reset");
         self.keep_reports = matches.is_present("keep-reports");
+        self.rep_seg = matches.is_present("rep-seg");
         self.verbosity = Self::verbosity(&matches);
         self.logfile = matches.value_of("logfile").map(|x| x.to_string());
         self.bypass = matches.is_present("bypass");
@@ -384,8 +457,10 @@ impl JsonArgs for Args {
             None => self.enforce = Default::default(),
         }
 
-        if let (bandit, Some(subm)) = matches.subcommand() {
-            updated_base |= self.process_bandit(bandit, subm);
+        match matches.subcommand() {
+            ("convert-reports", Some(subm)) => self.process_convert_reports(subm),
+            (bandit, Some(subm)) => updated_base |= self.process_bandit(bandit, subm),
+            _ => {}
         }
 
         updated_base
@@ -1,4 +1,5 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
+use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 
@@ -16,9 +17,18 @@ lazy_static::lazy_static! {
 // Setting it to a number equal to or lower than cancels if currently running.
 // While a benchmark is running, all other workloads are stopped.
 //
-// One or two rd-hashd instances are used as the latency sensitive primary
-// workloads. When both instances are active, between the two, resources are
-// distributed according to their relative weights.
+// One or more rd-hashd instances are used as the latency sensitive primary
+// workloads. hashd[] always has at least two entries - A and B - and can be
+// extended up to ${max_hashd} instances which are named C, D and so on. Each
+// instance runs in its own sub-slice of workload.slice, e.g. rd-hashd-C.service
+// in workload-hashdC.slice. When multiple instances are active, resources are
+// distributed among them according to their relative weights.
+//
+// An instance can be placed deeper in the workload.slice hierarchy by naming
+// a sub-slice configured in slices.json in hashd[].slice, e.g.
+// workload-tier1.slice puts rd-hashd-A.service in
+// workload-tier1.slice/workload-tier1-hashdA.slice. The memory protection of
+// the instances sharing a parent is split according to their weights.
 //
 // Any number of sysloads and sideloads can be used. The only difference between
 // sysloads and sideloads is that sysloads are run under system.slice without
@@ -46,7 +56,8 @@ lazy_static::lazy_static! {
 //  hashd[].file_addr_stdev: Memory access stdev in ratio of mean, null to use ${dfl_file_addr_stdev}
 //  hashd[].anon_addr_stdev: Memory access stdev in ratio of mean, null to use ${dfl_anon_addr_stdev}
 //  hashd[].log_bps: IO write bandwidth, default ${dfl_log_bps}Mbps
-//  hashd[].weight: Relative weight between the two hashd instances
+//  hashd[].weight: Relative weight among the active hashd instances
+//  hashd[].slice: Parent sub-slice of workload.slice, null for workload.slice
 //  sysloads{{}}: \"NAME\": \"DEF_ID\" pairs for active sysloads
 //  sideloads{{}}: \"NAME\": \"DEF_ID\" pairs for active sideloads
 //  swappiness: /proc/sys/vm/swappiness, null to leave as-is
@@ -54,6 +65,7 @@ lazy_static::lazy_static! {
 //  balloon_ratio: Memory balloon size given as a ratio to total memory, default 0.0
 //
 ",
+                max_hashd = super::HASHD_MAX_INSTANCES,
                 dfl_bench_balloon = Cmd::default().bench_hashd_balloon_size,
                 dfl_file_ratio = rd_hashd_intf::Params::default().file_frac,
                 dfl_file_max_ratio = rd_hashd_intf::Args::default().file_max_frac,
@@ -82,6 +94,7 @@ pub struct HashdCmd {
     pub file_max_ratio: f64,
     pub log_bps: u64,
     pub weight: f64,
+    pub slice: Option<String>,
 }
 
 impl Default for HashdCmd {
@@ -98,6 +111,7 @@ impl Default for HashdCmd {
             file_max_ratio: rd_hashd_intf::Args::default().file_max_frac,
             log_bps: rd_hashd_intf::Params::default().log_bps,
             weight: 1.0,
+            slice: None,
         }
     }
 }
@@ -111,7 +125,8 @@ pub struct Cmd {
     pub bench_hashd_args: Vec<String>,
     pub bench_iocost_seq: u64,
     pub sideloader: SideloaderCmd,
-    pub hashd: [HashdCmd; 2],
+    #[serde(deserialize_with = "super::deserialize_hashd_vec")]
+    pub hashd: Vec<HashdCmd>,
     pub sysloads: BTreeMap<String, String>,
     pub sideloads: BTreeMap<String, String>,
     pub swappiness: Option<u32>,
@@ -134,7 +149,7 @@ impl Default for Cmd {
             bench_hashd_args: vec![],
             bench_iocost_seq: 0,
             sideloader: SideloaderCmd { cpu_headroom: 0.2 },
-            hashd: Default::default(),
+            hashd: vec![Default::default(); super::HASHD_DFL_INSTANCES],
             sysloads: BTreeMap::new(),
             sideloads: BTreeMap::new(),
             swappiness: None,
@@ -144,10 +159,73 @@ impl Default for Cmd {
     }
 }
 
-impl JsonLoad for Cmd {}
+impl JsonLoad for Cmd {
+    fn loaded(&mut self, _prev: Option<&mut Self>) -> Result<()> {
+        if self.hashd.len() > super::HASHD_MAX_INSTANCES {
+            bail!(
+                "too many hashd instances, at most {} allowed",
+                super::HASHD_MAX_INSTANCES
+            );
+        }
+        for (i, hashd) in self.hashd.iter().enumerate() {
+            if let Some(slice) = hashd.slice.as_ref() {
+                if !super::is_work_sub_slice(slice) {
+                    bail!(
+                        "hashd[{}].slice {:?} isn't a workload.slice sub-slice",
+                        i,
+                        slice
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
 
 impl JsonSave for Cmd {
     fn preamble() -> Option<String> {
         Some(CMD_DOC.clone())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cmd_hashd_instances() {
+        let cmd: Cmd = serde_json::from_str(r#"{ "hashd": [] }"#).unwrap();
+        assert_eq!(cmd.hashd.len(), crate::HASHD_DFL_INSTANCES);
+
+        let mut cmd: Cmd = serde_json::from_str(
+            r#"{ "hashd": [ { "active": true, "lat_target_pct": 0.95, "lat_target": 0.1,
+                             "rps_target_ratio": 0.5, "mem_ratio": null,
+                             "file_addr_stdev": null, "anon_addr_stdev": null,
+                             "file_ratio": 0.25, "file_max_ratio": 1.0,
+                             "log_bps": 1048576, "weight": 2.0 } ] }"#,
+        )
+        .unwrap();
+        assert_eq!(cmd.hashd.len(), crate::HASHD_DFL_INSTANCES);
+        assert!(cmd.hashd[0].active && cmd.hashd[0].weight == 2.0);
+        assert_eq!(cmd.hashd[1], HashdCmd::default());
+        assert!(cmd.loaded(None).is_ok());
+
+        cmd.hashd[1].slice = Some("workload-tier1.slice".into());
+        assert!(cmd.loaded(None).is_ok());
+        cmd.hashd[1].slice = Some("batch.slice".into());
+        assert!(cmd.loaded(None).is_err());
+        cmd.hashd[1].slice = None;
+
+        cmd.hashd = vec![Default::default(); crate::HASHD_MAX_INSTANCES + 1];
+        assert!(cmd.loaded(None).is_err());
+
+        assert_eq!(crate::hashd_svc_name(2), "rd-hashd-C.service");
+        assert_eq!(crate::hashd_slice_name(0), "workload-hashdA.slice");
+        assert_eq!(
+            crate::hashd_sub_slice_name("workload-tier1.slice", 1),
+            "workload-tier1-hashdB.slice"
+        );
+        assert_eq!(crate::hashd_idx("D"), Some(3));
+        assert_eq!(crate::hashd_idx("d"), None);
+    }
+}
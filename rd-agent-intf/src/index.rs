@@ -11,13 +11,14 @@ const INDEX_DOC: &str = "\
 //  cmd_ack: Command sequence ack
 //  sysreqs: Satisfied and missed system requirements
 //  report: Summary report of the current state (per-second)
-//  report_d: Per-second report directory
+//  report_d: Per-second report directory (JSON files or .rseg segments)
 //  report_1min: Summary report of the current state (per-minute)
-//  report_1min_d: Per-minute report directory
+//  report_1min_d: Per-minute report directory (JSON files or .rseg segments)
 //  bench: Benchmark results
 //  slices: Top-level slice resource control configurations
 //  oomd: OOMD on/off and configurations
 //  sideloader_stats: Sideloader status
+//  hashd[]: rd-hashd instances A, B, C... in order, at least two
 //  hashd[].args: rd-hashd arguments
 //  hashd[].params: rd-hashd runtime adjustable parameters
 //  hashd[].report: rd-hashd summary report
@@ -45,7 +46,8 @@ pub struct Index {
     pub slices: String,
     pub oomd: String,
     pub sideloader_status: String,
-    pub hashd: [HashdIndex; 2],
+    #[serde(deserialize_with = "super::deserialize_hashd_vec")]
+    pub hashd: Vec<HashdIndex>,
     pub sideload_defs: String,
 }
 
@@ -1,6 +1,6 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
 use log::error;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::io;
 
 use rd_util::*;
@@ -13,11 +13,12 @@ pub mod cmd_ack;
 pub mod index;
 pub mod oomd;
 pub mod report;
+pub mod report_store;
 pub mod side_defs;
 pub mod slices;
 pub mod sysreqs;
 
-pub use args::{Args, Bandit, BanditMemHogArgs, EnforceConfig};
+pub use args::{Args, Bandit, BanditMemHogArgs, ConvertReportsArgs, EnforceConfig};
 pub use bandit_report::BanditMemHogReport;
 pub use bench::{BenchKnobs, HashdKnobs, IoCostKnobs, BENCH_FILENAME};
 pub use cmd::{Cmd, HashdCmd, SideloaderCmd};
@@ -30,8 +31,12 @@ pub use report::{
     SideloadReport, SideloaderReport, StatMap, SvcReport, SvcStateReport, SysloadReport,
     UsageReport,
 };
+pub use report_store::{ReportReader, ReportSegWriter, REPORT_SEG_SPAN};
 pub use side_defs::{SideloadDefs, SideloadSpec};
-pub use slices::{DisableSeqKnobs, MemoryKnob, Slice, SliceConfig, SliceKnobs, ROOT_SLICE};
+pub use slices::{
+    is_work_sub_slice, slice_cgrp, slice_parent, CpuMaxKnob, DisableSeqKnobs, IoMaxKnob,
+    MemoryKnob, Slice, SliceConfig, SliceKnobs, ROOT_SLICE,
+};
 pub use sysreqs::{MissedSysReqs, SysReq, SysReqsReport, ALL_SYSREQS_SET};
 
 lazy_static::lazy_static! {
@@ -65,6 +70,56 @@ pub fn sideload_svc_name(name: &str) -> String {
     format!("{}{}.service", SIDELOAD_SVC_PREFIX, name)
 }
 
+/// rd-hashd instances are named with uppercase letters in order - A, B, C
+/// and so on. The first two always exist in cmd, report and index files.
+pub const HASHD_DFL_INSTANCES: usize = 2;
+pub const HASHD_MAX_INSTANCES: usize = 26;
+
+pub fn hashd_name(idx: usize) -> String {
+    assert!(idx < HASHD_MAX_INSTANCES);
+    ((b'A' + idx as u8) as char).to_string()
+}
+
+pub fn hashd_idx(name: &str) -> Option<usize> {
+    match name.as_bytes() {
+        [ch] if ch.is_ascii_uppercase() => Some((ch - b'A') as usize),
+        _ => None,
+    }
+}
+
+pub fn hashd_svc_name(idx: usize) -> String {
+    format!("rd-hashd-{}.service", hashd_name(idx))
+}
+
+/// Each rd-hashd instance runs in its own sub-slice of workload.slice.
+pub fn hashd_slice_name(idx: usize) -> String {
+    hashd_sub_slice_name(Slice::Work.name(), idx)
+}
+
+/// The sub-slice of an rd-hashd instance placed under `parent`, e.g.
+/// workload-tier1-hashdA.slice under workload-tier1.slice.
+pub fn hashd_sub_slice_name(parent: &str, idx: usize) -> String {
+    format!(
+        "{}-hashd{}.slice",
+        parent.trim_end_matches(".slice"),
+        hashd_name(idx)
+    )
+}
+
+/// Deserialize a per-hashd-instance array padding it up to
+/// HASHD_DFL_INSTANCES so that the A and B entries can always be indexed.
+fn deserialize_hashd_vec<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + Default,
+{
+    let mut v = Vec::<T>::deserialize(deserializer)?;
+    if v.len() < HASHD_DFL_INSTANCES {
+        v.resize_with(HASHD_DFL_INSTANCES, Default::default);
+    }
+    Ok(v)
+}
+
 #[derive(Default)]
 pub struct AgentFiles {
     pub args_path: String,
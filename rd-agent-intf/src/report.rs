@@ -7,6 +7,7 @@ use std::collections::BTreeMap;
 use std::ops;
 use std::time::UNIX_EPOCH;
 
+use super::report_store::ReportReader;
 use super::RunnerState;
 use rd_util::*;
 
@@ -40,6 +41,7 @@ const REPORT_DOC: &str = "\
 //  bench.hashd.mem_probe_at: the timestamp this memory probing started at
 //  bench.iocost.svc.name: iocost benchmark systemd service name
 //  bench.iocost.svc.state: iocost benchmark systemd service state
+//  hashd[]: rd-hashd instances A, B, C... in order, at least two
 //  hashd[].svc.name: rd-hashd systemd service name
 //  hashd[].svc.state: rd-hashd systemd service state
 //  hashd[].load: Current rps / rps_max
@@ -467,7 +469,8 @@ pub struct Report {
     pub sideloader: SideloaderReport,
     pub bench_hashd: BenchHashdReport,
     pub bench_iocost: BenchIoCostReport,
-    pub hashd: [HashdReport; 2],
+    #[serde(deserialize_with = "super::deserialize_hashd_vec")]
+    pub hashd: Vec<HashdReport>,
     pub sysloads: BTreeMap<String, SysloadReport>,
     pub sideloads: BTreeMap<String, SideloadReport>,
     pub usages: BTreeMap<String, UsageReport>,
@@ -492,7 +495,7 @@ impl Default for Report {
             sideloader: Default::default(),
             bench_hashd: Default::default(),
             bench_iocost: Default::default(),
-            hashd: Default::default(),
+            hashd: vec![Default::default(); super::HASHD_DFL_INSTANCES],
             sysloads: Default::default(),
             sideloads: Default::default(),
             usages: Default::default(),
@@ -560,13 +563,17 @@ impl DoubleEndedIterator for ReportPathIter {
 }
 
 pub struct ReportIter {
-    piter: ReportPathIter,
+    reader: ReportReader,
+    front: u64,
+    back: u64,
 }
 
 impl ReportIter {
     pub fn new(dir: &str, period: (u64, u64)) -> Self {
         Self {
-            piter: ReportPathIter::new(dir, period),
+            reader: ReportReader::new(dir),
+            front: period.0,
+            back: period.1,
         }
     }
 }
@@ -574,16 +581,25 @@ impl ReportIter {
 impl Iterator for ReportIter {
     type Item = (Result<Report>, u64);
     fn next(&mut self) -> Option<Self::Item> {
-        self.piter
-            .next()
-            .map(|(path, at)| (Report::load(&path), at))
+        if self.front >= self.back {
+            return None;
+        }
+        let front = self.front;
+        self.front += 1;
+
+        trace!("ReportIter: {}, {}", self.reader.dir(), front);
+        Some((self.reader.load(front), front))
     }
 }
 
 impl DoubleEndedIterator for ReportIter {
     fn next_back(&mut self) -> Option<Self::Item> {
-        self.piter
-            .next_back()
-            .map(|(path, at)| (Report::load(&path), at))
+        if self.front >= self.back {
+            return None;
+        }
+        let back = self.back;
+        self.back -= 1;
+
+        Some((self.reader.load(back), back))
     }
 }
@@ -0,0 +1,424 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// Segmented report store.
+//
+// Instead of one pretty-printed JSON file per report, reports can be
+// appended to segment files each covering REPORT_SEG_SPAN seconds. A
+// segment file is named "$START.rseg" where $START is the segment span
+// aligned timestamp and has the following layout.
+//
+//  header: REPORT_SEG_MAGIC (8 bytes)
+//  record: at (u64 LE) | len (u32 LE) | deflated compact JSON (len bytes)
+//  record: ...
+//
+// Records are only ever appended. A reader builds an in-memory timestamp
+// index by walking the record headers and extends it as the segment grows.
+// A partially written trailing record is ignored by readers and truncated
+// away when a writer reopens the segment.
+use anyhow::{bail, Context, Result};
+use log::{debug, trace, warn};
+use scan_fmt::scan_fmt;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, prelude::*, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use super::Report;
+use rd_util::*;
+
+pub const REPORT_SEG_SPAN: u64 = 3600;
+pub const REPORT_SEG_MAGIC: &[u8; 8] = b"RDRSEG01";
+const REC_HDR_LEN: u64 = 12;
+
+pub fn report_seg_start(at: u64) -> u64 {
+    at / REPORT_SEG_SPAN * REPORT_SEG_SPAN
+}
+
+pub fn report_seg_path(dir: &str, at: u64) -> PathBuf {
+    format!("{}/{}.rseg", dir, report_seg_start(at)).into()
+}
+
+pub fn report_json_path(dir: &str, at: u64) -> PathBuf {
+    format!("{}/{}.json", dir, at).into()
+}
+
+fn encode_report(rep: &Report) -> Result<Vec<u8>> {
+    let mut enc = libflate::deflate::Encoder::new(Vec::new());
+    serde_json::to_writer(&mut enc, rep)?;
+    Ok(enc.finish().into_result()?)
+}
+
+fn decode_report(buf: &[u8]) -> Result<Report> {
+    let dec = libflate::deflate::Decoder::new(buf);
+    Ok(serde_json::from_reader(dec)?)
+}
+
+/// Scan the record headers of `f` starting at `from` and add them to
+/// `index`. Returns the offset right after the last complete record.
+fn scan_records(f: &mut fs::File, from: u64, index: &mut BTreeMap<u64, (u64, u32)>) -> Result<u64> {
+    let size = f.metadata()?.len();
+    let mut pos = from;
+    let mut hdr = [0u8; REC_HDR_LEN as usize];
+
+    while pos + REC_HDR_LEN <= size {
+        f.seek(SeekFrom::Start(pos))?;
+        f.read_exact(&mut hdr)?;
+        let at = u64::from_le_bytes(hdr[0..8].try_into().unwrap());
+        let len = u32::from_le_bytes(hdr[8..12].try_into().unwrap());
+        if pos + REC_HDR_LEN + len as u64 > size {
+            break;
+        }
+        index.insert(at, (pos + REC_HDR_LEN, len));
+        pos += REC_HDR_LEN + len as u64;
+    }
+    Ok(pos)
+}
+
+fn check_magic(f: &mut fs::File) -> Result<()> {
+    let mut magic = [0u8; 8];
+    f.seek(SeekFrom::Start(0))?;
+    f.read_exact(&mut magic)?;
+    if &magic != REPORT_SEG_MAGIC {
+        bail!("invalid report segment magic {:?}", &magic);
+    }
+    Ok(())
+}
+
+struct ReportSegReader {
+    start: u64,
+    file: fs::File,
+    index: BTreeMap<u64, (u64, u32)>,
+    scanned_to: u64,
+}
+
+impl ReportSegReader {
+    fn open(dir: &str, at: u64) -> Result<Self> {
+        let path = report_seg_path(dir, at);
+        let mut file = fs::OpenOptions::new().read(true).open(&path)?;
+        check_magic(&mut file).with_context(|| format!("opening {:?}", &path))?;
+
+        let mut seg = Self {
+            start: report_seg_start(at),
+            file,
+            index: BTreeMap::new(),
+            scanned_to: REPORT_SEG_MAGIC.len() as u64,
+        };
+        seg.rescan()?;
+        Ok(seg)
+    }
+
+    fn rescan(&mut self) -> Result<()> {
+        self.scanned_to = scan_records(&mut self.file, self.scanned_to, &mut self.index)?;
+        Ok(())
+    }
+
+    fn load(&mut self, at: u64) -> Result<Option<Report>> {
+        if !self.index.contains_key(&at) {
+            // The segment may have grown since the last scan.
+            self.rescan()?;
+        }
+        let (off, len) = match self.index.get(&at) {
+            Some(v) => *v,
+            None => return Ok(None),
+        };
+
+        let mut buf = vec![0u8; len as usize];
+        self.file.seek(SeekFrom::Start(off))?;
+        self.file.read_exact(&mut buf)?;
+        Ok(Some(decode_report(&buf).with_context(|| {
+            format!("decoding report {} in segment {}", at, self.start)
+        })?))
+    }
+}
+
+/// Loads reports from a report directory which may contain legacy per-report
+/// JSON files, report segments or a mix of both. Segments take precedence.
+pub struct ReportReader {
+    dir: String,
+    seg: Option<ReportSegReader>,
+}
+
+impl ReportReader {
+    pub fn new(dir: &str) -> Self {
+        Self {
+            dir: dir.into(),
+            seg: None,
+        }
+    }
+
+    pub fn dir(&self) -> &str {
+        &self.dir
+    }
+
+    fn load_from_seg(&mut self, at: u64) -> Result<Option<Report>> {
+        let start = report_seg_start(at);
+        if self.seg.as_ref().map(|seg| seg.start) != Some(start) {
+            self.seg = match ReportSegReader::open(&self.dir, at) {
+                Ok(seg) => Some(seg),
+                Err(e) => match e.downcast_ref::<io::Error>() {
+                    Some(ie) if ie.raw_os_error() == Some(libc::ENOENT) => return Ok(None),
+                    _ => return Err(e),
+                },
+            };
+        }
+        self.seg.as_mut().unwrap().load(at)
+    }
+
+    /// Load the report for `at`. If there's none, an io::Error with ENOENT
+    /// is returned just like when loading a missing JSON report file.
+    pub fn load(&mut self, at: u64) -> Result<Report> {
+        if let Some(rep) = self.load_from_seg(at)? {
+            return Ok(rep);
+        }
+        Report::load(&report_json_path(&self.dir, at))
+    }
+
+    /// List the timestamps of all reports in the directory.
+    pub fn list(&self) -> Result<Vec<u64>> {
+        let mut ats = vec![];
+        for path in fs::read_dir(&self.dir)?
+            .filter_map(|x| x.ok())
+            .map(|x| x.path())
+        {
+            let name = path
+                .file_name()
+                .unwrap_or_else(|| OsStr::new(""))
+                .to_str()
+                .unwrap_or("");
+            if let Ok(at) = scan_fmt!(name, "{d}.json", u64) {
+                ats.push(at);
+            } else if let Ok(start) = scan_fmt!(name, "{d}.rseg", u64) {
+                let seg = ReportSegReader::open(&self.dir, start)?;
+                ats.extend(seg.index.keys());
+            }
+        }
+        ats.sort();
+        ats.dedup();
+        Ok(ats)
+    }
+}
+
+/// Appends reports to segment files in a report directory.
+pub struct ReportSegWriter {
+    dir: String,
+    cur: Option<(u64, fs::File)>,
+}
+
+impl ReportSegWriter {
+    pub fn new(dir: &str) -> Self {
+        Self {
+            dir: dir.into(),
+            cur: None,
+        }
+    }
+
+    fn open_seg(&self, at: u64) -> Result<fs::File> {
+        let path = report_seg_path(&self.dir, at);
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(&path)
+            .with_context(|| format!("opening {:?}", &path))?;
+
+        if file.metadata()?.len() == 0 {
+            file.write_all(REPORT_SEG_MAGIC)?;
+            return Ok(file);
+        }
+
+        check_magic(&mut file).with_context(|| format!("opening {:?}", &path))?;
+        let end = scan_records(
+            &mut file,
+            REPORT_SEG_MAGIC.len() as u64,
+            &mut BTreeMap::new(),
+        )?;
+        if end < file.metadata()?.len() {
+            warn!(
+                "report: Truncating partial record at {} in {:?}",
+                end, &path
+            );
+            file.set_len(end)?;
+        }
+        file.seek(SeekFrom::Start(end))?;
+        Ok(file)
+    }
+
+    pub fn append(&mut self, at: u64, rep: &Report) -> Result<()> {
+        let start = report_seg_start(at);
+        if self.cur.as_ref().map(|(s, _)| *s) != Some(start) {
+            self.cur = None;
+            self.cur = Some((start, self.open_seg(at)?));
+        }
+
+        let payload = encode_report(rep)?;
+        let mut buf = Vec::with_capacity(REC_HDR_LEN as usize + payload.len());
+        buf.extend_from_slice(&at.to_le_bytes());
+        buf.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&payload);
+
+        trace!(
+            "report: Appending {} ({}b) to segment {}",
+            at,
+            buf.len(),
+            start
+        );
+        self.cur.as_mut().unwrap().1.write_all(&buf)?;
+        Ok(())
+    }
+}
+
+/// Paths of the existing segment files which overlap `period`.
+pub fn report_seg_paths(dir: &str, period: (u64, u64)) -> Vec<PathBuf> {
+    let mut paths = vec![];
+    if period.0 >= period.1 {
+        return paths;
+    }
+    let mut start = report_seg_start(period.0);
+    while start < period.1 {
+        let path = report_seg_path(dir, start);
+        if path.exists() {
+            paths.push(path);
+        }
+        start += REPORT_SEG_SPAN;
+    }
+    paths
+}
+
+/// Remove JSON report files and segments which are entirely older than
+/// `now - retention`.
+pub fn clear_old_reports(d_path: &str, retention: u64, now: u64) -> Result<()> {
+    let cutoff = now.saturating_sub(retention);
+    for path in fs::read_dir(d_path)?
+        .filter_map(|x| x.ok())
+        .map(|x| x.path())
+    {
+        let name = path
+            .file_name()
+            .unwrap_or_else(|| OsStr::new(""))
+            .to_str()
+            .unwrap_or("");
+        let last = if let Ok(at) = scan_fmt!(name, "{d}.json", u64) {
+            at
+        } else if let Ok(start) = scan_fmt!(name, "{d}.rseg", u64) {
+            start + REPORT_SEG_SPAN - 1
+        } else {
+            continue;
+        };
+        if last < cutoff {
+            if let Err(e) = fs::remove_file(&path) {
+                warn!(
+                    "report: Failed to remove stale report {:?} ({:?})",
+                    &path, &e
+                );
+            } else {
+                debug!("report: Removed stale report {:?}", &path);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Convert all reports in `src` within `period` into `dst`, either as
+/// segments if `to_seg` or as per-report JSON files. Returns the number of
+/// converted reports.
+pub fn convert_reports(src: &str, dst: &str, to_seg: bool, period: (u64, u64)) -> Result<usize> {
+    if Path::new(src) == Path::new(dst) {
+        bail!("source and destination directories are the same");
+    }
+    fs::create_dir_all(dst).with_context(|| format!("creating {:?}", dst))?;
+
+    let mut reader = ReportReader::new(src);
+    let mut writer = ReportSegWriter::new(dst);
+    let mut nr_converted = 0;
+
+    for at in reader.list()?.into_iter() {
+        if at < period.0 || at >= period.1 {
+            continue;
+        }
+        let rep = reader
+            .load(at)
+            .with_context(|| format!("loading report {} from {:?}", at, src))?;
+        if to_seg {
+            writer.append(at, &rep)?;
+        } else {
+            let path = report_json_path(dst, at);
+            rep.save(&path)
+                .with_context(|| format!("saving {:?}", &path))?;
+        }
+        nr_converted += 1;
+    }
+    Ok(nr_converted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> String {
+        let dir = format!(
+            "{}/rd-report-store-{}-{}",
+            std::env::temp_dir().to_str().unwrap(),
+            name,
+            std::process::id()
+        );
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn report(seq: u64) -> Report {
+        Report {
+            seq,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_report_store_roundtrip() {
+        let seg_dir = test_dir("seg");
+        let json_dir = test_dir("json");
+        let base = 10 * REPORT_SEG_SPAN - 2;
+
+        let mut writer = ReportSegWriter::new(&seg_dir);
+        for at in base..base + 4 {
+            writer.append(at, &report(at)).unwrap();
+        }
+        drop(writer);
+        assert_eq!(report_seg_paths(&seg_dir, (base, base + 4)).len(), 2);
+
+        // A partial trailing record must be invisible to readers and
+        // dropped when the writer reopens the segment.
+        let path = report_seg_path(&seg_dir, base + 3);
+        let mut f = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        f.write_all(&[1, 2, 3]).unwrap();
+        drop(f);
+
+        let mut reader = ReportReader::new(&seg_dir);
+        assert_eq!(reader.list().unwrap(), (base..base + 4).collect::<Vec<_>>());
+        assert_eq!(reader.load(base + 1).unwrap().seq, base + 1);
+        assert!(reader.load(base + 4).is_err());
+
+        let mut writer = ReportSegWriter::new(&seg_dir);
+        writer.append(base + 4, &report(base + 4)).unwrap();
+        assert_eq!(reader.load(base + 4).unwrap().seq, base + 4);
+
+        assert_eq!(
+            convert_reports(&seg_dir, &json_dir, false, (base + 1, base + 5)).unwrap(),
+            4
+        );
+        let mut reader = ReportReader::new(&json_dir);
+        assert!(reader.load(base).is_err());
+        assert_eq!(reader.load(base + 3).unwrap().seq, base + 3);
+
+        clear_old_reports(&seg_dir, 1, base + 3).unwrap();
+        assert_eq!(
+            ReportReader::new(&seg_dir).list().unwrap(),
+            (base + 2..base + 5).collect::<Vec<_>>()
+        );
+
+        fs::remove_dir_all(&seg_dir).unwrap();
+        fs::remove_dir_all(&json_dir).unwrap();
+    }
+}
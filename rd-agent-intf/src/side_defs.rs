@@ -154,6 +154,13 @@ impl Default for SideloadDefs {
                         frozen_exp: 30,
                     },
                 ),
+                (
+                    "write-bomb".into(),
+                    SideloadSpec {
+                        args: vec!["write-bomb.py".into(), "4096".into(), "64".into()],
+                        frozen_exp: 30,
+                    },
+                ),
                 (
                     "burn-cpus-50pct".into(),
                     SideloadSpec {
@@ -1,5 +1,5 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
-use anyhow::Result;
+use anyhow::{bail, Result};
 use enum_iterator::Sequence;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
@@ -15,6 +15,26 @@ const SLICE_DOC: &str = "\
 //
 // Memory configuration can be either None or Bytes.
 //
+// SLICE_ID is either one of the built-in slices - init.scope,
+// hostcritical.slice, user.slice, system.slice, workload.slice and
+// sideload.slice - or a user-defined top-level slice, e.g. batch.slice.
+// User-defined slices are created and configured the same way and their
+// names must end with \".slice\" and can't contain '-' or '/'. Built-in
+// slices which are missing are filled in with the default configurations.
+//
+// workload.slice can be further divided into a hierarchy of user-defined
+// sub-slices which follow the systemd naming convention, e.g.
+// workload-tier1.slice is a child of workload.slice and
+// workload-tier1-web.slice a child of workload-tier1.slice. The parent of a
+// sub-slice must also be configured. rd-hashd instances can be placed in
+// them with cmd.json hashd[].slice.
+//
+// Each level is configured with its own memory protection. With
+// memory_recursiveprot, the protection which a level doesn't explicitly
+// pass down is distributed to its children by the kernel. Without it,
+// rd-agent configures the sub-slices with None mem_min or mem_low with an
+// even share of what their parent doesn't pass down to the others.
+//
 //  disable_seqs.cpu: Disable CPU control if >= report::seq
 //  disable_seqs.mem: Disable memory control if >= report::seq
 //  disable_seqs.io: Disable IO control if >= report::seq
@@ -23,6 +43,13 @@ const SLICE_DOC: &str = "\
 //  slices.SLICE_ID.mem_min: memory.min
 //  slices.SLICE_ID.mem_low: memory.low
 //  slices.SLICE_ID.mem_high: memory.high
+//  slices.SLICE_ID.mem_max: memory.max
+//  slices.SLICE_ID.swap_max: memory.swap.max
+//  slices.SLICE_ID.zswap_max: memory.zswap.max
+//  slices.SLICE_ID.cpu_max: cpu.max, null or { quota_usec, period_usec }
+//  slices.SLICE_ID.io_max.DEVNAME.{rbps|wbps|riops|wiops}: io.max for the
+//                  device /dev/DEVNAME, null for no limit
+//  slices.SLICE_ID.pids_max: pids.max, null for no limit
 //
 ";
 
@@ -37,6 +64,10 @@ pub enum Slice {
 }
 
 impl Slice {
+    pub fn from_name(name: &str) -> Option<Self> {
+        enum_iterator::all::<Slice>().find(|slc| slc.name() == name)
+    }
+
     pub fn name(&self) -> &'static str {
         match self {
             Slice::Init => "init.scope",
@@ -60,6 +91,77 @@ impl Slice {
     }
 }
 
+/// The parent of a sub-slice following the systemd naming convention, e.g.
+/// workload-tier1.slice for workload-tier1-web.slice. None for top-level
+/// slices.
+pub fn slice_parent(name: &str) -> Option<String> {
+    let stem = name.strip_suffix(".slice")?;
+    match stem.rfind('-') {
+        Some(pos) if pos > 0 => Some(format!("{}.slice", &stem[..pos])),
+        _ => None,
+    }
+}
+
+/// Whether `name` is a valid name for a user-defined sub-slice of
+/// workload.slice. The hashd[A-Z] components are reserved for the rd-hashd
+/// instance sub-slices.
+pub fn is_work_sub_slice(name: &str) -> bool {
+    let stem = match name.strip_suffix(".slice") {
+        Some(v) => v,
+        None => return false,
+    };
+    let mut comps = stem.split('-');
+    comps.next() == Some("workload")
+        && stem.contains('-')
+        && !stem.contains('/')
+        && comps.all(|comp| {
+            comp.len() > 0
+                && comp
+                    .strip_prefix("hashd")
+                    .and_then(crate::hashd_idx)
+                    .is_none()
+        })
+}
+
+pub fn slice_cgrp(name: &str) -> String {
+    match Slice::from_name(name) {
+        Some(slc) => slc.cgrp().to_string(),
+        None => match slice_parent(name) {
+            Some(parent) => format!("{}/{}", slice_cgrp(&parent), name),
+            None => format!("/sys/fs/cgroup/{}", name),
+        },
+    }
+}
+
+// Distribute the protection of a level among its children. The children
+// can't claim more than the parent has and are scaled down proportionally
+// if they do. With `recursive`, the rest is split evenly among the children
+// without their own configuration.
+fn distribute_mem_prot(parent: u64, knobs: &[MemoryKnob], recursive: bool) -> Vec<u64> {
+    let mut prot: Vec<u64> = knobs
+        .iter()
+        .map(|knob| knob.nr_bytes(false).min(parent))
+        .collect();
+    let sum: u64 = prot.iter().sum();
+
+    if sum > parent {
+        for v in prot.iter_mut() {
+            *v = (*v as f64 * parent as f64 / sum as f64) as u64;
+        }
+    } else if recursive {
+        let nr_unset = knobs.iter().filter(|k| **k == MemoryKnob::None).count();
+        if nr_unset > 0 {
+            let share = (parent - sum) / nr_unset as u64;
+            for (v, knob) in prot.iter_mut().zip(knobs.iter()) {
+                if *knob == MemoryKnob::None {
+                    *v = share;
+                }
+            }
+        }
+    }
+    prot
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub enum MemoryKnob {
     None,
@@ -85,6 +187,25 @@ impl MemoryKnob {
     }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CpuMaxKnob {
+    pub quota_usec: u64,
+    pub period_usec: u64,
+}
+
+impl CpuMaxKnob {
+    pub const DFL_PERIOD_USEC: u64 = 100_000;
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IoMaxKnob {
+    pub rbps: Option<u64>,
+    pub wbps: Option<u64>,
+    pub riops: Option<u64>,
+    pub wiops: Option<u64>,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SliceConfig {
     pub cpu_weight: u32,
@@ -92,6 +213,18 @@ pub struct SliceConfig {
     pub mem_min: MemoryKnob,
     pub mem_low: MemoryKnob,
     pub mem_high: MemoryKnob,
+    #[serde(default)]
+    pub mem_max: MemoryKnob,
+    #[serde(default)]
+    pub swap_max: MemoryKnob,
+    #[serde(default)]
+    pub zswap_max: MemoryKnob,
+    #[serde(default)]
+    pub cpu_max: Option<CpuMaxKnob>,
+    #[serde(default)]
+    pub io_max: BTreeMap<String, IoMaxKnob>,
+    #[serde(default)]
+    pub pids_max: Option<u64>,
 }
 
 impl Default for SliceConfig {
@@ -102,6 +235,12 @@ impl Default for SliceConfig {
             mem_min: Default::default(),
             mem_low: Default::default(),
             mem_high: Default::default(),
+            mem_max: Default::default(),
+            swap_max: Default::default(),
+            zswap_max: Default::default(),
+            cpu_max: None,
+            io_max: Default::default(),
+            pids_max: None,
         }
     }
 }
@@ -194,6 +333,48 @@ impl Default for SliceKnobs {
 
 impl JsonLoad for SliceKnobs {
     fn loaded(&mut self, _prev: Option<&mut Self>) -> Result<()> {
+        for slc in enum_iterator::all::<Slice>() {
+            if !self.slices.contains_key(slc.name()) {
+                self.slices
+                    .insert(slc.name().into(), SliceConfig::default(slc));
+            }
+        }
+
+        for (name, sc) in self.slices.iter() {
+            if let Some(cm) = sc.cpu_max.as_ref() {
+                if cm.quota_usec == 0 || cm.period_usec == 0 {
+                    bail!("{:?} has invalid cpu_max {:?}", name, cm);
+                }
+            }
+            for dev in sc.io_max.keys() {
+                if dev.len() == 0 || dev.contains('/') {
+                    bail!("{:?} has invalid io_max device name {:?}", name, dev);
+                }
+            }
+        }
+
+        for name in self.custom_slices() {
+            match slice_parent(&name) {
+                None => {
+                    if !name.ends_with(".slice")
+                        || name.len() == ".slice".len()
+                        || name.contains('-')
+                        || name.contains('/')
+                    {
+                        bail!("invalid top-level slice name {:?}", &name);
+                    }
+                }
+                Some(parent) => {
+                    if !is_work_sub_slice(&name) {
+                        bail!("invalid workload.slice sub-slice name {:?}", &name);
+                    }
+                    if !self.slices.contains_key(&parent) {
+                        bail!("parent {:?} of {:?} isn't configured", &parent, &name);
+                    }
+                }
+            }
+        }
+
         let sk = self.slices.get(Slice::Work.name()).unwrap();
         self.work_mem_low_none = if let MemoryKnob::None = sk.mem_low {
             true
@@ -215,6 +396,93 @@ impl SliceKnobs {
         let dseqs = &self.disable_seqs;
         dseqs.cpu >= seq || dseqs.mem >= seq || dseqs.io >= seq
     }
+
+    /// Names of the user-defined slices, i.e. the ones which aren't built-in.
+    /// Parents always come before their children.
+    pub fn custom_slices(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .slices
+            .keys()
+            .filter(|name| Slice::from_name(name).is_none())
+            .cloned()
+            .collect();
+        names.sort_by_key(|name| name.matches('-').count());
+        names
+    }
+
+    /// Names of all configured slices, built-in ones first.
+    pub fn all_slices(&self) -> Vec<String> {
+        let mut names: Vec<String> = enum_iterator::all::<Slice>()
+            .map(|slc| slc.name().to_string())
+            .collect();
+        names.append(&mut self.custom_slices());
+        names
+    }
+
+    /// The effective (memory.min, memory.low) in bytes of each configured
+    /// slice. A level can't be protected more than its parent and, if the
+    /// children of a level claim more than it has, they're scaled down
+    /// proportionally. `recursive` selects memory_recursiveprot behavior
+    /// where the unclaimed protection is split evenly among the children
+    /// which don't have their own. The actual split depends on usage.
+    pub fn effective_mem_prot(&self, recursive: bool) -> BTreeMap<String, (u64, u64)> {
+        self.effective_mem_prot_with(recursive, &[])
+    }
+
+    /// Like effective_mem_prot() but `extra` leaf slices which aren't
+    /// configured, e.g. the rd-hashd sub-slices, take part as children
+    /// without their own protection.
+    pub fn effective_mem_prot_with(
+        &self,
+        recursive: bool,
+        extra: &[String],
+    ) -> BTreeMap<String, (u64, u64)> {
+        let mut prot = BTreeMap::new();
+        let mut names = self.all_slices();
+        for name in extra.iter() {
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+        let knob_of = |name: &str, low: bool| match self.slices.get(name) {
+            Some(sc) if low => sc.mem_low,
+            Some(sc) => sc.mem_min,
+            None => MemoryKnob::None,
+        };
+
+        for name in names.iter() {
+            if slice_parent(name).is_none() {
+                let sc = match self.slices.get(name) {
+                    Some(v) => v,
+                    None => continue,
+                };
+                prot.insert(
+                    name.clone(),
+                    (sc.mem_min.nr_bytes(false), sc.mem_low.nr_bytes(false)),
+                );
+            }
+        }
+
+        // all_slices() lists the parents before their children
+        for parent in names.iter() {
+            let children: Vec<&String> = names
+                .iter()
+                .filter(|name| slice_parent(name).as_ref() == Some(parent))
+                .collect();
+            if children.len() == 0 {
+                continue;
+            }
+            let (pmin, plow) = prot.get(parent).cloned().unwrap_or_default();
+            let mins: Vec<MemoryKnob> = children.iter().map(|c| knob_of(c, false)).collect();
+            let lows: Vec<MemoryKnob> = children.iter().map(|c| knob_of(c, true)).collect();
+            let mins = distribute_mem_prot(pmin, &mins, recursive);
+            let lows = distribute_mem_prot(plow, &lows, recursive);
+            for (i, child) in children.into_iter().enumerate() {
+                prot.insert(child.clone(), (mins[i], lows[i]));
+            }
+        }
+        prot
+    }
 }
 
 impl Index<Slice> for SliceKnobs {
@@ -230,3 +498,153 @@ impl IndexMut<Slice> for SliceKnobs {
         self.slices.get_mut(slc.name()).unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_custom_slices() {
+        let mut knobs = SliceKnobs::default();
+        knobs.slices.remove(Slice::Side.name());
+        knobs
+            .slices
+            .insert("batch.slice".into(), Default::default());
+        knobs.loaded(None).unwrap();
+
+        assert!(knobs.slices.contains_key(Slice::Side.name()));
+        assert_eq!(knobs.custom_slices(), vec!["batch.slice".to_string()]);
+        assert_eq!(knobs.all_slices().last().unwrap(), "batch.slice");
+
+        for name in &["batch", "tenant-a.slice", "a/b.slice", ".slice"] {
+            let mut knobs = SliceKnobs::default();
+            knobs.slices.insert(name.to_string(), Default::default());
+            assert!(knobs.loaded(None).is_err());
+        }
+    }
+
+    #[test]
+    fn test_work_sub_slices() {
+        assert_eq!(slice_parent("workload.slice"), None);
+        assert_eq!(slice_parent(ROOT_SLICE), None);
+        assert_eq!(
+            slice_parent("workload-tier1-web.slice").unwrap(),
+            "workload-tier1.slice"
+        );
+        assert_eq!(
+            slice_cgrp("workload-tier1-web.slice"),
+            "/sys/fs/cgroup/workload.slice/workload-tier1.slice/workload-tier1-web.slice"
+        );
+        assert_eq!(slice_cgrp("batch.slice"), "/sys/fs/cgroup/batch.slice");
+
+        let mut knobs = SliceKnobs::default();
+        for name in &["workload-tier1-web.slice", "workload-tier1.slice"] {
+            knobs.slices.insert(name.to_string(), Default::default());
+        }
+        knobs.loaded(None).unwrap();
+        assert_eq!(
+            knobs.custom_slices(),
+            vec!["workload-tier1.slice", "workload-tier1-web.slice"]
+        );
+
+        for name in &[
+            "workload-tier2-web.slice",
+            "workload--x.slice",
+            "workload-hashdA.slice",
+            "sideload-x.slice",
+        ] {
+            let mut knobs = knobs.clone();
+            knobs.slices.insert(name.to_string(), Default::default());
+            assert!(knobs.loaded(None).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_effective_mem_prot() {
+        const G: u64 = 1 << 30;
+        let mut knobs = SliceKnobs::default();
+        knobs[Slice::Work].mem_min = MemoryKnob::None;
+        knobs[Slice::Work].mem_low = MemoryKnob::Bytes(8 * G);
+        let mut add = |name: &str, low: MemoryKnob| {
+            knobs.slices.insert(
+                name.into(),
+                SliceConfig {
+                    mem_low: low,
+                    ..Default::default()
+                },
+            );
+        };
+        // workload: 8G
+        //   tier1: 6G
+        //     web: None
+        //     db: 2G
+        //   tier2: None
+        //     batch: 4G
+        //   tier3: None
+        add("workload-tier1.slice", MemoryKnob::Bytes(6 * G));
+        add("workload-tier1-web.slice", MemoryKnob::None);
+        add("workload-tier1-db.slice", MemoryKnob::Bytes(2 * G));
+        add("workload-tier2.slice", MemoryKnob::None);
+        add("workload-tier2-batch.slice", MemoryKnob::Bytes(4 * G));
+        add("workload-tier3.slice", MemoryKnob::None);
+        knobs.loaded(None).unwrap();
+
+        let low = |prot: &BTreeMap<String, (u64, u64)>, name: &str| prot[name].1;
+
+        // Without recursive protection, nothing reaches the unconfigured
+        // levels and their children.
+        let prot = knobs.effective_mem_prot(false);
+        assert_eq!(low(&prot, "workload.slice"), 8 * G);
+        assert_eq!(low(&prot, "workload-tier1.slice"), 6 * G);
+        assert_eq!(low(&prot, "workload-tier1-web.slice"), 0);
+        assert_eq!(low(&prot, "workload-tier1-db.slice"), 2 * G);
+        assert_eq!(low(&prot, "workload-tier2.slice"), 0);
+        assert_eq!(low(&prot, "workload-tier2-batch.slice"), 0);
+        assert_eq!(prot["workload-tier1.slice"].0, 0);
+
+        // With it, the unclaimed 2G of workload is split between tier2 and
+        // tier3 and tier2 passes its share down to batch.
+        let prot = knobs.effective_mem_prot(true);
+        assert_eq!(low(&prot, "workload-tier1-web.slice"), 4 * G);
+        assert_eq!(low(&prot, "workload-tier2.slice"), G);
+        assert_eq!(low(&prot, "workload-tier3.slice"), G);
+        assert_eq!(low(&prot, "workload-tier2-batch.slice"), G);
+
+        // Overcommitted children are scaled down proportionally.
+        knobs
+            .slices
+            .get_mut("workload-tier2.slice")
+            .unwrap()
+            .mem_low = MemoryKnob::Bytes(6 * G);
+        let prot = knobs.effective_mem_prot(true);
+        assert_eq!(low(&prot, "workload-tier1.slice"), 4 * G);
+        assert_eq!(low(&prot, "workload-tier2.slice"), 4 * G);
+        assert_eq!(low(&prot, "workload-tier3.slice"), 0);
+        assert_eq!(low(&prot, "workload-tier1-db.slice"), 2 * G);
+        assert_eq!(low(&prot, "workload-tier1-web.slice"), 2 * G);
+
+        // Unconfigured extra leaves share the unclaimed rest as siblings.
+        let extra = vec!["workload-tier1-hashdA.slice".to_string()];
+        let prot = knobs.effective_mem_prot_with(true, &extra);
+        assert_eq!(low(&prot, "workload-tier1-web.slice"), G);
+        assert_eq!(low(&prot, "workload-tier1-hashdA.slice"), G);
+        assert_eq!(low(&prot, "workload-tier1-db.slice"), 2 * G);
+    }
+
+    #[test]
+    fn test_slice_config_limits() {
+        let sc: SliceConfig = serde_json::from_str(
+            r#"{ "cpu_weight": 100, "io_weight": 100, "mem_min": "None",
+                 "mem_low": "None", "mem_high": "None" }"#,
+        )
+        .unwrap();
+        assert_eq!(sc, <SliceConfig as Default>::default());
+
+        let mut knobs = SliceKnobs::default();
+        knobs[Slice::Side].cpu_max = Some(CpuMaxKnob {
+            quota_usec: 0,
+            period_usec: CpuMaxKnob::DFL_PERIOD_USEC,
+        });
+        assert!(knobs.loaded(None).is_err());
+    }
+}
@@ -10,9 +10,11 @@ use rd_agent_intf::{BenchKnobs, IoCostKnobs};
 use rd_hashd_intf;
 use rd_util::*;
 
-use rd_agent_intf::{Slice, HASHD_BENCH_SVC_NAME, IOCOST_BENCH_SVC_NAME};
+use rd_agent_intf::{
+    Slice, HASHD_BENCH_SVC_NAME, HASHD_DFL_INSTANCES, HASHD_MAX_INSTANCES, IOCOST_BENCH_SVC_NAME,
+};
 
-use super::{hashd, Config, HashdSel};
+use super::{hashd, Config};
 
 pub const IOCOST_QOS_PATH: &str = "/sys/fs/cgroup/io.cost.qos";
 const IOCOST_MODEL_PATH: &str = "/sys/fs/cgroup/io.cost.model";
@@ -23,7 +25,7 @@ pub fn start_hashd_bench(
     mem_high: u64,
     mut extra_args: Vec<String>,
 ) -> Result<TransientService> {
-    let mut args = hashd::hashd_path_args(&cfg, HashdSel::A);
+    let mut args = hashd::hashd_path_args(&cfg, 0);
     args.push(format!("--bench-log-bps={}", log_bps));
     args.push("--bench".into());
     args.append(&mut extra_args);
@@ -73,8 +75,8 @@ pub fn start_iocost_bench(cfg: &Config) -> Result<TransientService> {
 }
 
 pub fn update_hashd(knobs: &mut BenchKnobs, cfg: &Config, hashd_seq: u64) -> Result<()> {
-    let args = rd_hashd_intf::Args::load(&cfg.hashd_paths(HashdSel::A).args)?;
-    let params = rd_hashd_intf::Params::load(&cfg.hashd_paths(HashdSel::A).params)?;
+    let args = rd_hashd_intf::Args::load(&cfg.hashd_paths(0).args)?;
+    let params = rd_hashd_intf::Params::load(&cfg.hashd_paths(0).params)?;
 
     knobs.hashd.hash_size = params.file_size_mean;
     knobs.hashd.rps_max = params.rps_max as u32;
@@ -90,14 +92,11 @@ pub fn update_hashd(knobs: &mut BenchKnobs, cfg: &Config, hashd_seq: u64) -> Res
     }
     knobs.timestamp = DateTime::from(SystemTime::now());
 
-    fs::copy(
-        &cfg.hashd_paths(HashdSel::A).args,
-        &cfg.hashd_paths(HashdSel::B).args,
-    )?;
-    fs::copy(
-        &cfg.hashd_paths(HashdSel::A).params,
-        &cfg.hashd_paths(HashdSel::B).params,
-    )?;
+    for idx in 1..HASHD_MAX_INSTANCES {
+        if idx < HASHD_DFL_INSTANCES || hashd::hashd_cfg_exists(cfg, idx) {
+            hashd::copy_hashd_cfg(cfg, idx)?;
+        }
+    }
     Ok(())
 }
 
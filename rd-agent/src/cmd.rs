@@ -222,11 +222,9 @@ impl RunnerData {
     fn apply_workloads(&mut self) -> Result<()> {
         let cmd = &self.sobjs.cmd_file.data;
         let bench = &self.sobjs.bench_file.data;
-        let mem_low = self.sobjs.slice_file.data[Slice::Work]
-            .mem_low
-            .nr_bytes(false);
+        let slices = &self.sobjs.slice_file.data;
 
-        self.hashd_set.apply(&cmd.hashd, &bench.hashd, mem_low)?;
+        self.hashd_set.apply(&cmd.hashd, &bench.hashd, slices)?;
         Ok(())
     }
 
@@ -1,20 +1,27 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
 use anyhow::{bail, Result};
 use log::{debug, info, warn};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
 use std::io;
 use std::path::Path;
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use rd_agent_intf::{HashdCmd, HashdKnobs, HashdReport, Slice, HASHD_A_SVC_NAME, HASHD_B_SVC_NAME};
+use rd_agent_intf::{
+    hashd_slice_name, hashd_sub_slice_name, hashd_svc_name, slice_cgrp, HashdCmd, HashdKnobs,
+    HashdReport, Slice, SliceKnobs, HASHD_DFL_INSTANCES, HASHD_MAX_INSTANCES,
+};
 use rd_hashd_intf;
 use rd_util::*;
 
-use super::Config;
-use super::HashdSel;
+use super::{Config, HashdPaths};
 
-pub fn hashd_path_args(cfg: &Config, sel: HashdSel) -> Vec<String> {
-    let paths = &cfg.hashd_paths[sel as usize];
+// The minimum share of an active hashd instance so that a tiny weight
+// doesn't starve it completely.
+const HASHD_MIN_FRAC: f64 = 0.1;
+
+pub fn hashd_path_args(cfg: &Config, idx: usize) -> Vec<String> {
+    let paths = cfg.hashd_paths(idx);
     let mut args = vec![
         paths.bin.clone(),
         "--args".into(),
@@ -36,8 +43,30 @@ pub fn hashd_path_args(cfg: &Config, sel: HashdSel) -> Vec<String> {
     args
 }
 
+fn copy_cfg(src: &HashdPaths, dst: &HashdPaths) -> Result<()> {
+    if let Some(dir) = Path::new(&dst.args).parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::copy(&src.args, &dst.args)?;
+    fs::copy(&src.params, &dst.params)?;
+    Ok(())
+}
+
+/// Copy the args and params files of hashd-A, which are updated by the
+/// benchmark, to the instance `idx`.
+pub fn copy_hashd_cfg(cfg: &Config, idx: usize) -> Result<()> {
+    copy_cfg(cfg.hashd_paths(0), cfg.hashd_paths(idx))
+}
+
+pub fn hashd_cfg_exists(cfg: &Config, idx: usize) -> bool {
+    Path::new(&cfg.hashd_paths(idx).args).exists()
+}
+
 pub struct Hashd {
     name: String,
+    slice: String,
+    paths: HashdPaths,
+    src_paths: HashdPaths,
     params_path: String,
     report_path: String,
     path_args: Vec<String>,
@@ -45,11 +74,46 @@ pub struct Hashd {
     rps_max: u32,
     file_max_ratio: f64,
     svc: Option<TransientService>,
+    slice_unit: Option<systemd::Unit>,
     started_at: Option<SystemTime>,
 }
 
 impl Hashd {
+    fn new(cfg: &Config, idx: usize) -> Self {
+        Self {
+            name: hashd_svc_name(idx),
+            slice: hashd_slice_name(idx),
+            paths: cfg.hashd_paths(idx).clone(),
+            src_paths: cfg.hashd_paths(0).clone(),
+            params_path: cfg.hashd_paths(idx).params.clone(),
+            report_path: cfg.hashd_paths(idx).report.clone(),
+            path_args: hashd_path_args(cfg, idx),
+            lat_target_pct: rd_hashd_intf::Params::default().lat_target_pct,
+            rps_max: 1,
+            file_max_ratio: rd_hashd_intf::Args::default().file_max_frac,
+            svc: None,
+            slice_unit: None,
+            started_at: None,
+        }
+    }
+
+    // The extra instances start from the configuration of hashd-A, which
+    // is what the benchmark results are applied to.
+    fn prepare(&self) -> Result<()> {
+        fs::create_dir_all(&self.paths.tf)?;
+        if self.paths.args != self.src_paths.args && !Path::new(&self.paths.args).exists() {
+            info!(
+                "hashd: Copying configuration from {:?} to {:?}",
+                &self.src_paths.args, &self.paths.args
+            );
+            copy_cfg(&self.src_paths, &self.paths)?;
+        }
+        Ok(())
+    }
+
     fn start(&mut self, mem_size: u64) -> Result<()> {
+        self.prepare()?;
+
         let mut args = self.path_args.clone();
         args.push("--size".into());
         args.push(format!("{}", mem_size));
@@ -58,12 +122,27 @@ impl Hashd {
         debug!("args: {:#?}", &args);
 
         let mut svc = TransientService::new_sys(self.name.clone(), args, Vec::new(), Some(0o002))?;
-        svc.set_slice(Slice::Work.name()).start()?;
+        svc.set_slice(&self.slice).start()?;
         self.svc = Some(svc);
+        self.slice_unit = Some(systemd::Unit::new_sys(self.slice.clone())?);
         self.started_at = Some(SystemTime::now());
         Ok(())
     }
 
+    fn stop(&mut self) {
+        self.svc = None;
+        self.started_at = None;
+        if let Some(mut unit) = self.slice_unit.take() {
+            if let Err(e) = unit.stop() {
+                warn!("hashd: Failed to stop {:?} ({:#})", &self.slice, &e);
+            }
+        }
+    }
+
+    fn cgrp(&self) -> String {
+        format!("{}/{}", slice_cgrp(&self.slice), &self.name)
+    }
+
     fn update_params(&mut self, knobs: &HashdKnobs, cmd: &HashdCmd, frac: f64) -> Result<()> {
         self.lat_target_pct = cmd.lat_target_pct;
         self.rps_max = ((knobs.rps_max as f64 * frac).round() as u32).max(1);
@@ -165,16 +244,25 @@ impl Hashd {
         Ok(())
     }
 
+    // The weights and protection are configured on both the sub-slice and
+    // the service so that the service is protected even without
+    // memory_recursiveprot. `mem_low` is the instance's share of the
+    // protection of the parent slice.
     fn update_resctl(&mut self, mem_low: u64, frac: f64) -> Result<()> {
-        let svc = self.svc.as_mut().unwrap();
-
-        svc.unit.resctl = systemd::UnitResCtl {
+        let resctl = systemd::UnitResCtl {
             cpu_weight: Some((100.0 * frac).ceil() as u64),
             io_weight: Some((100.0 * frac).ceil() as u64),
-            mem_low: Some((mem_low as f64 * frac).ceil() as u64),
+            mem_low: Some(mem_low),
             ..Default::default()
         };
 
+        if let Some(unit) = self.slice_unit.as_mut() {
+            unit.resctl = resctl.clone();
+            unit.apply()?;
+        }
+
+        let svc = self.svc.as_mut().unwrap();
+        svc.unit.resctl = resctl;
         svc.unit.apply()
     }
 
@@ -228,109 +316,192 @@ impl Hashd {
 }
 
 pub struct HashdSet {
-    hashd: [Hashd; 2],
+    hashd: Vec<Hashd>,
+    nr_hashd: usize,
 }
 
 impl HashdSet {
     pub fn new(cfg: &Config) -> Self {
         Self {
-            hashd: [
-                Hashd {
-                    name: HASHD_A_SVC_NAME.into(),
-                    params_path: cfg.hashd_paths(HashdSel::A).params.clone(),
-                    report_path: cfg.hashd_paths(HashdSel::A).report.clone(),
-                    path_args: hashd_path_args(cfg, HashdSel::A),
-                    lat_target_pct: rd_hashd_intf::Params::default().lat_target_pct,
-                    rps_max: 1,
-                    file_max_ratio: rd_hashd_intf::Args::default().file_max_frac,
-                    svc: None,
-                    started_at: None,
-                },
-                Hashd {
-                    name: HASHD_B_SVC_NAME.into(),
-                    params_path: cfg.hashd_paths(HashdSel::B).params.clone(),
-                    report_path: cfg.hashd_paths(HashdSel::B).report.clone(),
-                    path_args: hashd_path_args(cfg, HashdSel::B),
-                    lat_target_pct: rd_hashd_intf::Params::default().lat_target_pct,
-                    rps_max: 1,
-                    file_max_ratio: rd_hashd_intf::Args::default().file_max_frac,
-                    svc: None,
-                    started_at: None,
-                },
-            ],
+            hashd: (0..HASHD_MAX_INSTANCES)
+                .map(|idx| Hashd::new(cfg, idx))
+                .collect(),
+            nr_hashd: HASHD_DFL_INSTANCES,
         }
     }
 
-    fn weights_to_fracs(cmd: &[HashdCmd; 2]) -> [f64; 2] {
-        match (cmd[0].active, cmd[1].active) {
-            (false, false) => return [0.0, 0.0],
-            (true, false) => return [1.0, 0.0],
-            (false, true) => return [0.0, 1.0],
-            (true, true) => {}
+    fn weights_to_fracs(cmd: &[HashdCmd]) -> Vec<f64> {
+        let mut fracs = vec![0.0; cmd.len()];
+        let active: Vec<usize> = (0..cmd.len()).filter(|i| cmd[*i].active).collect();
+        if active.len() == 0 {
+            return fracs;
         }
 
-        let sum = cmd[0].weight + cmd[1].weight;
-        if sum <= 0.0 {
-            warn!(
-                "hashd: Invalid weights ({}, {}), using (0.5, 0.5)",
-                cmd[0].weight, cmd[1].weight
-            );
-            return [0.5, 0.5];
+        let weights: Vec<f64> = active.iter().map(|i| cmd[*i].weight).collect();
+        if weights.iter().sum::<f64>() <= 0.0 || weights.iter().any(|w| *w < 0.0) {
+            warn!("hashd: Invalid weights {:?}, distributing evenly", &weights);
+            for i in active.iter() {
+                fracs[*i] = 1.0 / active.len() as f64;
+            }
+            return fracs;
         }
 
-        let (w0, w1) = (cmd[0].weight / sum, cmd[1].weight / sum);
-        if w0 < 0.1 {
-            [0.1, 0.9]
-        } else if w1 < 0.1 {
-            [0.9, 0.1]
-        } else {
-            [w0, w1]
+        // Pin the instances which fall below the minimum share and
+        // distribute the rest among the others according to their weights
+        // until nothing else falls below. The heaviest one never gets
+        // pinned as min_frac is at most the even share.
+        let min_frac = HASHD_MIN_FRAC.min(1.0 / active.len() as f64);
+        let mut pinned = vec![false; cmd.len()];
+        loop {
+            let nr_pinned = active.iter().filter(|i| pinned[**i]).count();
+            let rest = 1.0 - min_frac * nr_pinned as f64;
+            let sum: f64 = active
+                .iter()
+                .filter(|i| !pinned[**i])
+                .map(|i| cmd[*i].weight)
+                .sum();
+
+            let mut settled = true;
+            for i in active.iter().cloned() {
+                if pinned[i] {
+                    fracs[i] = min_frac;
+                    continue;
+                }
+                fracs[i] = rest * cmd[i].weight / sum;
+                if fracs[i] < min_frac {
+                    pinned[i] = true;
+                    settled = false;
+                }
+            }
+            if settled {
+                return fracs;
+            }
         }
     }
 
-    pub fn apply(&mut self, cmd: &[HashdCmd; 2], knobs: &HashdKnobs, mem_low: u64) -> Result<()> {
+    fn parent_slice(cmd: &HashdCmd) -> &str {
+        cmd.slice.as_deref().unwrap_or(Slice::Work.name())
+    }
+
+    /// The sub-slices of the active instances.
+    fn active_slices(cmd: &[HashdCmd]) -> Vec<String> {
+        (0..cmd.len())
+            .filter(|i| cmd[*i].active)
+            .map(|i| hashd_sub_slice_name(Self::parent_slice(&cmd[i]), i))
+            .collect()
+    }
+
+    /// `mem_prot` is the effective protection with the active instances
+    /// taking part as unconfigured children of their parent slices. The
+    /// instances in a parent slice pool what they got and split it
+    /// according to `fracs`.
+    fn mem_lows(
+        cmd: &[HashdCmd],
+        fracs: &[f64],
+        mem_prot: &BTreeMap<String, (u64, u64)>,
+    ) -> Vec<u64> {
+        (0..cmd.len())
+            .map(|i| {
+                let parent = Self::parent_slice(&cmd[i]);
+                let peers: Vec<usize> = (0..cmd.len())
+                    .filter(|j| cmd[*j].active && Self::parent_slice(&cmd[*j]) == parent)
+                    .collect();
+                let sum: f64 = peers.iter().map(|j| fracs[*j]).sum();
+                if !cmd[i].active || sum <= 0.0 {
+                    return 0;
+                }
+                let pool: u64 = peers
+                    .iter()
+                    .filter_map(|j| mem_prot.get(&hashd_sub_slice_name(parent, *j)))
+                    .map(|prot| prot.1)
+                    .sum();
+                (pool as f64 * fracs[i] / sum).ceil() as u64
+            })
+            .collect()
+    }
+
+    /// The memory.low of each parent slice which isn't claimed by its
+    /// configured sub-slices is split evenly among its unconfigured
+    /// children, each active instance counting as one, and the instances'
+    /// total is then split among them according to their weights.
+    pub fn apply(
+        &mut self,
+        cmd: &[HashdCmd],
+        knobs: &HashdKnobs,
+        slice_knobs: &SliceKnobs,
+    ) -> Result<()> {
         let fracs = Self::weights_to_fracs(cmd);
         debug!("hashd: fracs={:?}", &fracs);
 
-        // handle the goners first
-        for i in 0..2 {
-            if !cmd[i].active && self.hashd[i].svc.is_some() {
-                self.hashd[i].svc = None;
-                self.hashd[i].started_at = None;
+        let inactive = HashdCmd {
+            active: false,
+            ..Default::default()
+        };
+        let cmd_of = |i: usize| cmd.get(i).unwrap_or(&inactive);
+        let frac_of = |i: usize| fracs.get(i).cloned().unwrap_or(0.0);
+        let mem_prot = slice_knobs.effective_mem_prot_with(true, &Self::active_slices(cmd));
+        let mem_lows = Self::mem_lows(cmd, &fracs, &mem_prot);
+        let mem_low_of = |i: usize| mem_lows.get(i).cloned().unwrap_or(0);
+
+        self.nr_hashd = cmd.len().max(HASHD_DFL_INSTANCES);
+
+        // handle the goners first, including the ones dropped from cmd and
+        // the ones moving to another slice which are restarted below
+        for (i, hashd) in self.hashd.iter_mut().enumerate() {
+            let slice = match cmd_of(i).slice.as_ref() {
+                Some(parent) => hashd_sub_slice_name(parent, i),
+                None => hashd_slice_name(i),
+            };
+            if hashd.svc.is_some() && (!cmd_of(i).active || slice != hashd.slice) {
+                if slice != hashd.slice {
+                    info!(
+                        "hashd: Moving {:?} from {:?} to {:?}",
+                        &hashd.name, &hashd.slice, &slice
+                    );
+                }
+                hashd.stop();
             }
+            hashd.slice = slice;
         }
 
         // adjust the args
-        for i in 0..2 {
-            if self.hashd[i].svc.is_some() && cmd[i].file_max_ratio != self.hashd[i].file_max_ratio
-            {
+        for (i, hashd) in self.hashd.iter_mut().enumerate().take(cmd.len()) {
+            if hashd.svc.is_some() && cmd[i].file_max_ratio != hashd.file_max_ratio {
                 info!(
-                    "hashd: file_max_ratio updated for active hashd {}, need a restart",
-                    i
+                    "hashd: file_max_ratio updated for active {:?}, need a restart",
+                    &hashd.name
                 );
             }
-            self.hashd[i].file_max_ratio = cmd[i].file_max_ratio;
+            hashd.file_max_ratio = cmd[i].file_max_ratio;
         }
 
         // adjust the params files
-        for i in 0..2 {
-            if fracs[i] != 0.0 {
-                self.hashd[i].update_params(knobs, &cmd[i], fracs[i])?;
+        for (i, hashd) in self.hashd.iter_mut().enumerate().take(cmd.len()) {
+            if frac_of(i) != 0.0 {
+                hashd.prepare()?;
+                hashd.update_params(knobs, &cmd[i], frac_of(i))?;
             }
         }
 
         // start missing ones
-        for i in 0..2 {
-            if cmd[i].active && self.hashd[i].svc.is_none() {
-                self.hashd[i].start(knobs.mem_size)?;
+        for (i, hashd) in self.hashd.iter_mut().enumerate().take(cmd.len()) {
+            if cmd[i].active && hashd.svc.is_none() {
+                let parent = Self::parent_slice(&cmd[i]);
+                if !slice_knobs.slices.contains_key(parent) {
+                    warn!(
+                        "hashd: {:?} for {:?} isn't configured in slices",
+                        parent, &hashd.name
+                    );
+                }
+                hashd.start(knobs.mem_size)?;
             }
         }
 
         // update resctl params
-        for i in 0..2 {
-            if self.hashd[i].svc.is_some() {
-                debug!("hashd: updating resctl on {:?}", &self.hashd[i].name);
-                self.hashd[i].update_resctl(mem_low, fracs[i])?;
+        for (i, hashd) in self.hashd.iter_mut().enumerate() {
+            if hashd.svc.is_some() {
+                debug!("hashd: updating resctl on {:?}", &hashd.name);
+                hashd.update_resctl(mem_low_of(i), frac_of(i))?;
             }
         }
 
@@ -342,35 +513,139 @@ impl HashdSet {
     }
 
     pub fn stop(&mut self) {
-        for i in 0..2 {
-            if self.hashd[i].svc.is_some() {
-                self.hashd[i].svc = None;
-                self.hashd[i].started_at = None;
+        for hashd in self.hashd.iter_mut() {
+            if hashd.svc.is_some() {
+                hashd.stop();
             }
         }
     }
 
     pub fn all_svcs(&self) -> HashSet<(String, String)> {
         let mut svcs = HashSet::<(String, String)>::new();
-        if self.hashd[0].svc.is_some() {
-            svcs.insert((
-                HASHD_A_SVC_NAME.to_owned(),
-                format!("{}/{}", Slice::Work.cgrp(), HASHD_A_SVC_NAME),
-            ));
-        }
-        if self.hashd[1].svc.is_some() {
-            svcs.insert((
-                HASHD_B_SVC_NAME.to_owned(),
-                format!("{}/{}", Slice::Work.cgrp(), HASHD_B_SVC_NAME),
-            ));
+        for hashd in self.hashd.iter() {
+            if hashd.svc.is_some() {
+                svcs.insert((hashd.name.clone(), hashd.cgrp()));
+            }
         }
         svcs
     }
 
-    pub fn report(&mut self, expiration: SystemTime) -> Result<[HashdReport; 2]> {
-        Ok([
-            self.hashd[0].report(expiration)?,
-            self.hashd[1].report(expiration)?,
-        ])
+    pub fn report(&mut self, expiration: SystemTime) -> Result<Vec<HashdReport>> {
+        let mut reps = vec![];
+        for hashd in self.hashd.iter_mut().take(self.nr_hashd) {
+            reps.push(hashd.report(expiration)?);
+        }
+        Ok(reps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rd_agent_intf::{MemoryKnob, SliceConfig};
+
+    fn cmds(weights: &[Option<f64>]) -> Vec<HashdCmd> {
+        weights
+            .iter()
+            .map(|w| HashdCmd {
+                active: w.is_some(),
+                weight: w.unwrap_or(1.0),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn assert_fracs(fracs: &[f64], expected: &[f64]) {
+        assert_eq!(fracs.len(), expected.len());
+        for (frac, exp) in fracs.iter().zip(expected.iter()) {
+            assert!((frac - exp).abs() < 1e-9, "{:?} != {:?}", fracs, expected);
+        }
+        if expected.iter().any(|x| *x > 0.0) {
+            assert!((fracs.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_weights_to_fracs() {
+        // nothing active
+        let fracs = HashdSet::weights_to_fracs(&cmds(&[None, None]));
+        assert_fracs(&fracs, &[0.0, 0.0]);
+
+        // all-zero and negative weights are split evenly
+        let fracs = HashdSet::weights_to_fracs(&cmds(&[Some(0.0), Some(0.0), None]));
+        assert_fracs(&fracs, &[0.5, 0.5, 0.0]);
+        let fracs = HashdSet::weights_to_fracs(&cmds(&[Some(3.0), Some(-1.0)]));
+        assert_fracs(&fracs, &[0.5, 0.5]);
+
+        // single active instance gets everything regardless of its weight
+        let fracs = HashdSet::weights_to_fracs(&cmds(&[None, Some(0.3), None]));
+        assert_fracs(&fracs, &[0.0, 1.0, 0.0]);
+
+        // plain proportional split
+        let fracs = HashdSet::weights_to_fracs(&cmds(&[Some(1.0), Some(3.0)]));
+        assert_fracs(&fracs, &[0.25, 0.75]);
+
+        // a tiny weight is pinned at HASHD_MIN_FRAC
+        let fracs = HashdSet::weights_to_fracs(&cmds(&[Some(1.0), Some(0.001), Some(1.0)]));
+        assert_fracs(&fracs, &[0.45, HASHD_MIN_FRAC, 0.45]);
+    }
+
+    #[test]
+    fn test_mem_lows() {
+        const G: u64 = 1 << 30;
+        let mut knobs = SliceKnobs::default();
+        knobs[Slice::Work].mem_low = MemoryKnob::Bytes(8 * G);
+        let mut add = |name: &str, low: MemoryKnob| {
+            knobs.slices.insert(
+                name.into(),
+                SliceConfig {
+                    mem_low: low,
+                    ..Default::default()
+                },
+            );
+        };
+        add("workload-tier1.slice", MemoryKnob::Bytes(5 * G));
+        add("workload-tier1-x.slice", MemoryKnob::Bytes(3 * G));
+        let mem_lows = |knobs: &SliceKnobs, cmd: &[HashdCmd]| {
+            let fracs = HashdSet::weights_to_fracs(cmd);
+            let mem_prot = knobs.effective_mem_prot_with(true, &HashdSet::active_slices(cmd));
+            HashdSet::mem_lows(cmd, &fracs, &mem_prot)
+        };
+
+        // hashd-A directly in workload.slice gets what tier1 left over
+        let cmd = cmds(&[Some(1.0), None]);
+        assert_eq!(mem_lows(&knobs, &cmd), vec![3 * G, 0]);
+
+        // hashd-B in tier1 shares what tier1-x left over only with itself
+        let mut cmd = cmds(&[Some(1.0), Some(1.0)]);
+        cmd[1].slice = Some("workload-tier1.slice".to_string());
+        assert_eq!(mem_lows(&knobs, &cmd), vec![3 * G, 2 * G]);
+
+        // an unprotected sibling takes an even share of the rest and the
+        // instances split theirs according to the weights
+        knobs
+            .slices
+            .insert("workload-batch.slice".into(), Default::default());
+        let cmd = cmds(&[Some(1.0), Some(3.0)]);
+        assert_eq!(mem_lows(&knobs, &cmd), vec![G / 2, 3 * G / 2]);
+        let prot = knobs.effective_mem_prot_with(true, &HashdSet::active_slices(&cmd));
+        assert_eq!(prot["workload-batch.slice"].1, G);
+    }
+
+    #[test]
+    fn test_weights_to_fracs_many() {
+        // with more than 10 instances, min_frac drops below HASHD_MIN_FRAC
+        let mut weights = vec![Some(0.001); 20];
+        weights[0] = Some(100.0);
+        let fracs = HashdSet::weights_to_fracs(&cmds(&weights));
+        let min_frac = 1.0 / 20.0;
+        assert!(min_frac < HASHD_MIN_FRAC);
+        let mut expected = vec![min_frac; 20];
+        expected[0] = 1.0 - min_frac * 19.0;
+        assert_fracs(&fracs, &expected);
+
+        // even weights are split evenly without any pinning
+        let fracs = HashdSet::weights_to_fracs(&cmds(&[Some(1.0); 16]));
+        assert_fracs(&fracs, &[1.0 / 16.0; 16]);
     }
 }
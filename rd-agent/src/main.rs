@@ -17,6 +17,7 @@ mod bandit;
 mod bench;
 mod cmd;
 mod hashd;
+mod metrics;
 mod misc;
 mod oomd;
 mod report;
@@ -25,8 +26,9 @@ mod sideloader;
 mod slices;
 
 use rd_agent_intf::{
-    Args, BenchKnobs, Cmd, CmdAck, EnforceConfig, MissedSysReqs, Report, SideloadDefs, SliceKnobs,
-    SvcReport, SvcStateReport, SysReq, SysReqsReport, ALL_SYSREQS_SET, OOMD_SVC_NAME,
+    hashd_name, report_store, Args, BenchKnobs, Cmd, CmdAck, EnforceConfig, MissedSysReqs, Report,
+    SideloadDefs, SliceKnobs, SvcReport, SvcStateReport, SysReq, SysReqsReport, ALL_SYSREQS_SET,
+    HASHD_DFL_INSTANCES, HASHD_MAX_INSTANCES, OOMD_SVC_NAME,
 };
 use rd_util::*;
 use report::clear_old_report_files;
@@ -113,13 +115,7 @@ fn set_iosched(dev: &str, iosched: &str) -> Result<()> {
     Ok(())
 }
 
-#[derive(Copy, Clone, Debug)]
-pub enum HashdSel {
-    A = 0,
-    B = 1,
-}
-
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct HashdPaths {
     pub bin: String,
     pub args: String,
@@ -154,7 +150,7 @@ pub struct Config {
     pub bench_path: String,
     pub slices_path: String,
     pub agent_bin: String,
-    pub hashd_paths: [HashdPaths; 2],
+    pub hashd_paths: Vec<HashdPaths>,
     pub misc_bin_path: String,
     pub biolatpcts_bin: Option<String>,
     pub iocost_paths: IoCostPaths,
@@ -175,6 +171,9 @@ pub struct Config {
 
     pub rep_retention: Option<u64>,
     pub rep_1min_retention: Option<u64>,
+    pub rep_seg: bool,
+    pub metrics_listen: Option<String>,
+    pub metrics_textfile: Option<String>,
     pub force_running: bool,
     pub bypass: bool,
     pub verbosity: u32,
@@ -386,24 +385,25 @@ impl Config {
             bench_path,
             slices_path: top_path.clone() + "/slices.json",
             agent_bin,
-            hashd_paths: [
-                HashdPaths {
-                    bin: hashd_bin.clone(),
-                    args: top_path.clone() + "/hashd-A/args.json",
-                    params: top_path.clone() + "/hashd-A/params.json",
-                    report: top_path.clone() + "/hashd-A/report.json",
-                    tf: Self::prep_dir(&(scr_path.clone() + "/hashd-A/testfiles")),
-                    log_dir: scr_path.clone() + "/hashd-A/logs",
-                },
-                HashdPaths {
-                    bin: hashd_bin.clone(),
-                    args: top_path.clone() + "/hashd-B/args.json",
-                    params: top_path.clone() + "/hashd-B/params.json",
-                    report: top_path.clone() + "/hashd-B/report.json",
-                    tf: Self::prep_dir(&(scr_path.clone() + "/hashd-B/testfiles")),
-                    log_dir: scr_path.clone() + "/hashd-B/logs",
-                },
-            ],
+            hashd_paths: (0..HASHD_MAX_INSTANCES)
+                .map(|idx| {
+                    let name = hashd_name(idx);
+                    // testfiles dirs of the extra instances are created
+                    // when the instances are first started
+                    let tf = scr_path.clone() + &format!("/hashd-{}/testfiles", &name);
+                    HashdPaths {
+                        bin: hashd_bin.clone(),
+                        args: top_path.clone() + &format!("/hashd-{}/args.json", &name),
+                        params: top_path.clone() + &format!("/hashd-{}/params.json", &name),
+                        report: top_path.clone() + &format!("/hashd-{}/report.json", &name),
+                        tf: match idx < HASHD_DFL_INSTANCES {
+                            true => Self::prep_dir(&tf),
+                            false => tf,
+                        },
+                        log_dir: scr_path.clone() + &format!("/hashd-{}/logs", &name),
+                    }
+                })
+                .collect(),
             misc_bin_path: misc_bin_path.clone(),
             biolatpcts_bin,
             iocost_paths: IoCostPaths {
@@ -438,6 +438,9 @@ impl Config {
             } else {
                 Some(args.rep_1min_retention)
             },
+            rep_seg: args.rep_seg,
+            metrics_listen: args.metrics_listen.clone(),
+            metrics_textfile: args.metrics_textfile.clone(),
             force_running: args.force_running,
             bypass: args.bypass,
             verbosity: args.verbosity,
@@ -1023,8 +1026,8 @@ impl Config {
         }
     }
 
-    pub fn hashd_paths(&self, sel: HashdSel) -> &HashdPaths {
-        &self.hashd_paths[sel as usize]
+    pub fn hashd_paths(&self, idx: usize) -> &HashdPaths {
+        &self.hashd_paths[idx]
     }
 
     pub fn memcg_recursive_prot(&self) -> bool {
@@ -1076,10 +1079,6 @@ fn reset_agent_states(cfg: &Config) {
         &cfg.sysreqs_path,
         &cfg.cmd_path,
         &cfg.slices_path,
-        &cfg.hashd_paths[0].args,
-        &cfg.hashd_paths[0].params,
-        &cfg.hashd_paths[1].args,
-        &cfg.hashd_paths[1].params,
         &cfg.misc_bin_path,
         &cfg.oomd_cfg_path,
         &cfg.oomd_daemon_cfg_path,
@@ -1092,6 +1091,10 @@ fn reset_agent_states(cfg: &Config) {
         &cfg.sys_scr_path,
     ];
 
+    for hp in cfg.hashd_paths.iter() {
+        paths.append(&mut vec![&hp.args, &hp.params]);
+    }
+
     if cfg.rep_retention.is_some() {
         paths.append(&mut vec![&cfg.report_path, &cfg.report_d_path]);
     }
@@ -1130,23 +1133,16 @@ fn reset_agent_states(cfg: &Config) {
 
     info!("cfg: Preparing hashd config files...");
 
-    let mut hashd_args = hashd::hashd_path_args(&cfg, HashdSel::A);
+    let mut hashd_args = hashd::hashd_path_args(&cfg, 0);
     hashd_args.push("--prepare-config".into());
 
     Command::new(hashd_args.remove(0))
         .args(hashd_args)
         .status()
         .expect("cfg: Failed to run rd-hashd --prepare-config");
-    fs::copy(
-        &cfg.hashd_paths(HashdSel::A).args,
-        &cfg.hashd_paths(HashdSel::B).args,
-    )
-    .unwrap();
-    fs::copy(
-        &cfg.hashd_paths(HashdSel::A).params,
-        &cfg.hashd_paths(HashdSel::B).params,
-    )
-    .unwrap();
+    for idx in 1..HASHD_DFL_INSTANCES {
+        hashd::copy_hashd_cfg(&cfg, idx).unwrap();
+    }
 }
 
 pub struct SysObjs {
@@ -1214,18 +1210,15 @@ fn update_index(cfg: &Config) -> Result<()> {
         slices: cfg.slices_path.clone(),
         oomd: cfg.oomd_cfg_path.clone(),
         sideloader_status: cfg.sideloader_daemon_status_path.clone(),
-        hashd: [
-            rd_agent_intf::index::HashdIndex {
-                args: cfg.hashd_paths[0].args.clone(),
-                params: cfg.hashd_paths[0].params.clone(),
-                report: cfg.hashd_paths[0].report.clone(),
-            },
-            rd_agent_intf::index::HashdIndex {
-                args: cfg.hashd_paths[1].args.clone(),
-                params: cfg.hashd_paths[1].params.clone(),
-                report: cfg.hashd_paths[1].report.clone(),
-            },
-        ],
+        hashd: cfg
+            .hashd_paths
+            .iter()
+            .map(|hp| rd_agent_intf::index::HashdIndex {
+                args: hp.args.clone(),
+                params: hp.params.clone(),
+                report: hp.report.clone(),
+            })
+            .collect(),
         sideload_defs: cfg.side_defs_path.clone(),
     };
 
@@ -1251,6 +1244,21 @@ fn main() {
         return;
     }
 
+    if let Some(cargs) = args_file.data.convert_reports.as_ref() {
+        let period = cargs.period.unwrap_or((0, std::u64::MAX));
+        match report_store::convert_reports(&cargs.from, &cargs.to, cargs.to_seg, period) {
+            Ok(nr) => info!(
+                "Converted {} reports from {:?} to {:?}",
+                nr, &cargs.from, &cargs.to
+            ),
+            Err(e) => {
+                error!("Failed to convert reports ({:#})", &e);
+                panic!();
+            }
+        }
+        return;
+    }
+
     systemd::set_systemd_timeout(args_file.data.systemd_timeout);
 
     let mut cfg = Config::new(&args_file);
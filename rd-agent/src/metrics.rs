@@ -0,0 +1,558 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// Prometheus text exposition of the per-second reports. The latest report
+// can be served over HTTP and/or written out to a textfile for
+// node_exporter's textfile collector.
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use std::collections::BTreeMap;
+use std::fmt::Write as FmtWrite;
+use std::fs;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread::spawn;
+use std::time::Duration;
+
+use rd_agent_intf::{HashdReport, IoLatReport, Report, SvcReport, SvcStateReport};
+
+const METRIC_PREFIX: &str = "rd";
+
+// Requests are served one at a time. Don't let a stalled client block the
+// other scrapes for long.
+const CONN_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_REQ_SIZE: u64 = 64 << 10;
+
+fn escape_label(v: &str) -> String {
+    v.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn pct_to_quantile(pct: &str) -> String {
+    match pct.parse::<f64>() {
+        Ok(v) => format!("{}", v / 100.0),
+        Err(_) => pct.to_string(),
+    }
+}
+
+struct MetricFamily {
+    name: String,
+    kind: &'static str,
+    help: String,
+    samples: Vec<String>,
+}
+
+// Samples of a metric family must be contiguous in the output. Collect them
+// per family in the order the families are first seen and render at the end.
+struct MetricsBuf {
+    families: Vec<MetricFamily>,
+    index: BTreeMap<String, usize>,
+}
+
+impl MetricsBuf {
+    fn new() -> Self {
+        Self {
+            families: vec![],
+            index: BTreeMap::new(),
+        }
+    }
+
+    fn sample(
+        &mut self,
+        name: &str,
+        kind: &'static str,
+        help: &str,
+        labels: &[(&str, &str)],
+        val: f64,
+    ) {
+        let idx = match self.index.get(name) {
+            Some(idx) => *idx,
+            None => {
+                self.families.push(MetricFamily {
+                    name: format!("{}_{}", METRIC_PREFIX, name),
+                    kind,
+                    help: help.to_string(),
+                    samples: vec![],
+                });
+                self.index.insert(name.to_string(), self.families.len() - 1);
+                self.families.len() - 1
+            }
+        };
+
+        let fam = &mut self.families[idx];
+        let mut line = fam.name.clone();
+        if labels.len() > 0 {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(k, v)| format!("{}=\"{}\"", k, escape_label(v)))
+                .collect();
+            write!(line, "{{{}}}", labels.join(",")).unwrap();
+        }
+        write!(line, " {}", val).unwrap();
+        fam.samples.push(line);
+    }
+
+    fn render(&self) -> String {
+        let mut buf = String::new();
+        for fam in self.families.iter() {
+            writeln!(buf, "# HELP {} {}", fam.name, fam.help).unwrap();
+            writeln!(buf, "# TYPE {} {}", fam.name, fam.kind).unwrap();
+            for sample in fam.samples.iter() {
+                writeln!(buf, "{}", sample).unwrap();
+            }
+        }
+        buf
+    }
+
+    fn gauge(&mut self, name: &str, help: &str, labels: &[(&str, &str)], val: f64) {
+        self.sample(name, "gauge", help, labels, val);
+    }
+
+    fn counter(&mut self, name: &str, help: &str, labels: &[(&str, &str)], val: f64) {
+        self.sample(name, "counter", help, labels, val);
+    }
+
+    fn bool_gauge(&mut self, name: &str, help: &str, labels: &[(&str, &str)], val: bool) {
+        self.gauge(name, help, labels, if val { 1.0 } else { 0.0 });
+    }
+
+    fn svc(&mut self, name: &str, help: &str, labels: &[(&str, &str)], svc: &SvcReport) {
+        let state = match svc.state {
+            SvcStateReport::Running => "Running",
+            SvcStateReport::Exited => "Exited",
+            SvcStateReport::Failed => "Failed",
+            SvcStateReport::Other => "Other",
+        };
+        let mut labels = labels.to_vec();
+        labels.push(("state", state));
+        self.bool_gauge(name, help, &labels, svc.name.len() > 0);
+    }
+}
+
+fn format_hashd(mb: &mut MetricsBuf, inst: &str, rep: &HashdReport) {
+    let lb = [("hashd", inst)];
+    mb.svc("hashd_up", "rd-hashd service exists", &lb, &rep.svc);
+    mb.gauge("hashd_load", "Current rps / rps_max", &lb, rep.load);
+    mb.gauge("hashd_rps", "Requests per second", &lb, rep.rps);
+    mb.gauge(
+        "hashd_lat_ctl_pct",
+        "Latency percentile used for rps control",
+        &lb,
+        rep.lat_pct,
+    );
+    mb.gauge(
+        "hashd_lat_ctl_seconds",
+        "Control percentile latency",
+        &lb,
+        rep.lat.ctl,
+    );
+
+    let lat = &rep.lat;
+    for (q, v) in &[
+        ("0", lat.min),
+        ("0.01", lat.p01),
+        ("0.05", lat.p05),
+        ("0.1", lat.p10),
+        ("0.16", lat.p16),
+        ("0.5", lat.p50),
+        ("0.84", lat.p84),
+        ("0.9", lat.p90),
+        ("0.95", lat.p95),
+        ("0.99", lat.p99),
+        ("0.999", lat.p99_9),
+        ("0.9999", lat.p99_99),
+        ("0.99999", lat.p99_999),
+        ("1", lat.max),
+    ] {
+        mb.gauge(
+            "hashd_lat_seconds",
+            "Request latency percentiles",
+            &[("hashd", inst), ("quantile", q)],
+            *v,
+        );
+    }
+
+    mb.gauge(
+        "hashd_in_flight",
+        "Number of requests in flight",
+        &lb,
+        rep.nr_in_flight as f64,
+    );
+    mb.gauge(
+        "hashd_done",
+        "Number of requests completed in the last period",
+        &lb,
+        rep.nr_done as f64,
+    );
+    mb.gauge(
+        "hashd_workers",
+        "Number of workers",
+        &lb,
+        rep.nr_workers as f64,
+    );
+    mb.gauge(
+        "hashd_idle_workers",
+        "Number of idle workers",
+        &lb,
+        rep.nr_idle_workers as f64,
+    );
+}
+
+fn format_iolat(mb: &mut MetricsBuf, name: &str, help: &str, iolat: &IoLatReport) {
+    for (op, pcts) in iolat.map.iter() {
+        for (pct, v) in pcts.iter() {
+            mb.gauge(
+                name,
+                help,
+                &[("op", op), ("quantile", &pct_to_quantile(pct))],
+                *v,
+            );
+        }
+    }
+}
+
+/// Format `rep` in the Prometheus text exposition format.
+pub fn format_metrics(rep: &Report) -> String {
+    let mut mb = MetricsBuf::new();
+
+    mb.gauge(
+        "report_timestamp_seconds",
+        "When the report was generated",
+        &[],
+        rep.timestamp.timestamp_millis() as f64 / 1000.0,
+    );
+    mb.gauge(
+        "state",
+        "rd-agent runner state",
+        &[("state", &format!("{:?}", rep.state))],
+        1.0,
+    );
+    for (ctrl, on) in &[
+        ("cpu", rep.resctl.cpu),
+        ("mem", rep.resctl.mem),
+        ("io", rep.resctl.io),
+    ] {
+        mb.bool_gauge(
+            "resctl_enabled",
+            "Resource control enabled",
+            &[("controller", ctrl)],
+            *on,
+        );
+    }
+
+    mb.svc("oomd_up", "OOMD service exists", &[], &rep.oomd.svc);
+    for (target, kind, on) in &[
+        ("workload", "mem_pressure", rep.oomd.work_mem_pressure),
+        ("workload", "senpai", rep.oomd.work_senpai),
+        ("system", "mem_pressure", rep.oomd.sys_mem_pressure),
+        ("system", "senpai", rep.oomd.sys_senpai),
+    ] {
+        mb.bool_gauge(
+            "oomd_enabled",
+            "OOMD action enabled",
+            &[("target", target), ("kind", kind)],
+            *on,
+        );
+    }
+
+    mb.svc(
+        "sideloader_up",
+        "Sideloader service exists",
+        &[],
+        &rep.sideloader.svc,
+    );
+    mb.bool_gauge(
+        "sideloader_overload",
+        "Sideloader is in overloaded state",
+        &[],
+        rep.sideloader.overload,
+    );
+    mb.bool_gauge(
+        "sideloader_critical",
+        "Sideloader is in critical state",
+        &[],
+        rep.sideloader.critical,
+    );
+    mb.gauge(
+        "sideloader_sysconf_warnings",
+        "Number of sideloader system configuration warnings",
+        &[],
+        rep.sideloader.sysconf_warnings.len() as f64,
+    );
+
+    for (idx, hashd) in rep.hashd.iter().enumerate() {
+        format_hashd(&mut mb, &rd_agent_intf::hashd_name(idx), hashd);
+    }
+
+    for (name, sysload) in rep.sysloads.iter() {
+        mb.svc(
+            "sysload_up",
+            "Sysload service exists",
+            &[("name", name)],
+            &sysload.svc,
+        );
+    }
+    for (name, sideload) in rep.sideloads.iter() {
+        mb.svc(
+            "sideload_up",
+            "Sideload service exists",
+            &[("name", name)],
+            &sideload.svc,
+        );
+    }
+
+    for (slice, usage) in rep.usages.iter() {
+        let lb = [("slice", slice.as_str())];
+        mb.gauge("cpu_util", "CPU utilization", &lb, usage.cpu_util);
+        mb.gauge("cpu_sys", "System CPU utilization", &lb, usage.cpu_sys);
+        mb.counter(
+            "cpu_usage_seconds_total",
+            "Cumulative CPU usage",
+            &lb,
+            usage.cpu_usage,
+        );
+        mb.counter(
+            "cpu_usage_sys_seconds_total",
+            "Cumulative system CPU usage",
+            &lb,
+            usage.cpu_usage_sys,
+        );
+        mb.gauge("mem_bytes", "Memory usage", &lb, usage.mem_bytes as f64);
+        mb.gauge("swap_bytes", "Swap usage", &lb, usage.swap_bytes as f64);
+        mb.gauge(
+            "swap_free_bytes",
+            "Available swap",
+            &lb,
+            usage.swap_free as f64,
+        );
+        mb.counter(
+            "io_read_bytes_total",
+            "Cumulative bytes read",
+            &lb,
+            usage.io_rbytes as f64,
+        );
+        mb.counter(
+            "io_written_bytes_total",
+            "Cumulative bytes written",
+            &lb,
+            usage.io_wbytes as f64,
+        );
+        mb.gauge(
+            "io_read_bps",
+            "Read bytes per second",
+            &lb,
+            usage.io_rbps as f64,
+        );
+        mb.gauge(
+            "io_write_bps",
+            "Written bytes per second",
+            &lb,
+            usage.io_wbps as f64,
+        );
+        mb.counter(
+            "io_usage_seconds_total",
+            "Cumulative iocost device time usage",
+            &lb,
+            usage.io_usage,
+        );
+        mb.gauge("io_util", "iocost device utilization", &lb, usage.io_util);
+
+        for (res, stalls, pressures) in &[
+            ("cpu", usage.cpu_stalls, usage.cpu_pressures),
+            ("memory", usage.mem_stalls, usage.mem_pressures),
+            ("io", usage.io_stalls, usage.io_pressures),
+        ] {
+            for (kind, stall, pressure) in &[
+                ("some", stalls.0, pressures.0),
+                ("full", stalls.1, pressures.1),
+            ] {
+                let lb = [("slice", slice.as_str()), ("resource", res), ("kind", kind)];
+                mb.counter(
+                    "pressure_stall_seconds_total",
+                    "Cumulative PSI stall time",
+                    &lb,
+                    *stall,
+                );
+                mb.gauge("pressure", "PSI pressure ratio", &lb, *pressure);
+            }
+        }
+    }
+
+    for (slice, stat) in rep.mem_stat.iter() {
+        for (key, v) in stat.iter() {
+            mb.gauge(
+                "memory_stat",
+                "memory.stat",
+                &[("slice", slice), ("key", key)],
+                *v,
+            );
+        }
+    }
+    for (key, v) in rep.vmstat.iter() {
+        mb.gauge("vmstat", "/proc/vmstat", &[("key", key)], *v);
+    }
+
+    format_iolat(
+        &mut mb,
+        "iolat_seconds",
+        "IO latency percentiles in the last second",
+        &rep.iolat,
+    );
+    format_iolat(
+        &mut mb,
+        "iolat_cum_seconds",
+        "Cumulative IO latency percentiles",
+        &rep.iolat_cum,
+    );
+
+    mb.gauge("iocost_vrate", "iocost vrate", &[], rep.iocost.vrate);
+    mb.gauge(
+        "iocost_qos_enabled",
+        "iocost QoS enabled",
+        &[],
+        rep.iocost.qos.enable as f64,
+    );
+    mb.gauge("swappiness", "vm.swappiness", &[], rep.swappiness as f64);
+    mb.bool_gauge("zswap_enabled", "zswap enabled", &[], rep.zswap_enabled);
+
+    mb.render()
+}
+
+fn serve_one(stream: TcpStream, latest: &Mutex<String>) -> Result<()> {
+    stream.set_read_timeout(Some(CONN_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONN_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?.take(MAX_REQ_SIZE));
+    let mut req_line = String::new();
+    reader.read_line(&mut req_line)?;
+    // drain the headers
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().len() == 0 {
+            break;
+        }
+    }
+
+    let mut stream = stream;
+    let path = req_line.split_whitespace().nth(1).unwrap_or("");
+    if !req_line.starts_with("GET ") || !(path == "/" || path == "/metrics") {
+        let body = "Not Found\n";
+        write!(
+            stream,
+            "HTTP/1.0 404 Not Found\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )?;
+        return Ok(());
+    }
+
+    let body = latest.lock().unwrap().clone();
+    write!(
+        stream,
+        "HTTP/1.0 200 OK\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\nContent-Length: {}\r\n\r\n",
+        body.len()
+    )?;
+    stream.write_all(body.as_bytes())?;
+    Ok(())
+}
+
+pub struct MetricsExporter {
+    textfile: Option<String>,
+    latest: Arc<Mutex<String>>,
+}
+
+impl MetricsExporter {
+    pub fn new(listen: Option<&str>, textfile: Option<&str>) -> Result<Self> {
+        let latest = Arc::new(Mutex::new(String::new()));
+
+        if let Some(addr) = listen {
+            let listener = TcpListener::bind(addr)
+                .with_context(|| format!("binding metrics listener to {:?}", addr))?;
+            info!("metrics: Serving Prometheus metrics on {:?}", addr);
+            let latest = latest.clone();
+            spawn(move || {
+                for stream in listener.incoming() {
+                    match stream {
+                        Ok(stream) => {
+                            if let Err(e) = serve_one(stream, &latest) {
+                                debug!("metrics: Failed to serve request ({:?})", &e);
+                            }
+                        }
+                        Err(e) => warn!("metrics: Failed to accept connection ({:?})", &e),
+                    }
+                }
+            });
+        }
+
+        Ok(Self {
+            textfile: textfile.map(|x| x.to_string()),
+            latest,
+        })
+    }
+
+    pub fn update(&mut self, rep: &Report) {
+        let metrics = format_metrics(rep);
+
+        if let Some(path) = self.textfile.as_ref() {
+            // node_exporter may read the file at any time, write atomically
+            let staging = format!("{}.staging", path);
+            if let Err(e) = fs::write(&staging, &metrics).and_then(|_| fs::rename(&staging, path)) {
+                warn!("metrics: Failed to write {:?} ({:?})", path, &e);
+            }
+        }
+
+        *self.latest.lock().unwrap() = metrics;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rd_agent_intf::UsageReport;
+
+    #[test]
+    fn test_format_metrics() {
+        let mut rep = Report::default();
+        rep.hashd[0].rps = 1234.5;
+        rep.hashd[0].lat.p99 = 0.05;
+        rep.hashd.push(Default::default());
+        rep.hashd[2].rps = 42.0;
+        rep.usages.insert(
+            "workload.slice".into(),
+            UsageReport {
+                cpu_util: 0.5,
+                mem_bytes: 1 << 30,
+                ..Default::default()
+            },
+        );
+
+        let out = format_metrics(&rep);
+        let lines: Vec<&str> = out.lines().collect();
+
+        assert!(lines.contains(&"# TYPE rd_hashd_rps gauge"));
+        assert!(lines.contains(&"rd_hashd_rps{hashd=\"A\"} 1234.5"));
+        assert!(lines.contains(&"rd_hashd_rps{hashd=\"B\"} 0"));
+        assert!(lines.contains(&"rd_hashd_rps{hashd=\"C\"} 42"));
+        assert!(lines.contains(&"rd_hashd_lat_seconds{hashd=\"A\",quantile=\"0.99\"} 0.05"));
+        assert!(lines.contains(&"rd_cpu_util{slice=\"workload.slice\"} 0.5"));
+        assert!(lines.contains(&"rd_mem_bytes{slice=\"workload.slice\"} 1073741824"));
+        assert!(lines.contains(&"# TYPE rd_cpu_usage_seconds_total counter"));
+
+        // each family is introduced once and its samples are contiguous
+        let mut seen = std::collections::HashSet::new();
+        let mut cur = "";
+        for line in lines.iter() {
+            if let Some(rest) = line.strip_prefix("# TYPE ") {
+                let name = rest.split(' ').next().unwrap();
+                assert!(seen.insert(name.to_string()), "{} repeated", name);
+                cur = name;
+            } else if !line.starts_with('#') {
+                let name = line.split(|c| c == '{' || c == ' ').next().unwrap();
+                assert_eq!(name, cur);
+            }
+        }
+
+        assert_eq!(escape_label("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
@@ -8,7 +8,6 @@ use nix::unistd::Pid;
 use procfs::prelude::*;
 use scan_fmt::scan_fmt;
 use std::collections::{BTreeMap, HashMap};
-use std::ffi::OsStr;
 use std::fs;
 use std::io::prelude::*;
 use std::io::BufReader;
@@ -19,10 +18,12 @@ use std::thread::{spawn, JoinHandle};
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use super::cmd::Runner;
+use super::metrics::MetricsExporter;
 use super::Config;
 use rd_agent_intf::{
-    report::StatMap, BenchHashdReport, BenchIoCostReport, HashdReport, IoCostReport, IoLatReport,
-    Report, ResCtlReport, Slice, UsageReport, ROOT_SLICE,
+    report::StatMap, report_store, slice_cgrp, BenchHashdReport, BenchIoCostReport, HashdReport,
+    IoCostReport, IoLatReport, Report, ReportSegWriter, ResCtlReport, Slice, UsageReport,
+    REPORT_SEG_SPAN, ROOT_SLICE,
 };
 use rd_util::*;
 
@@ -287,7 +288,19 @@ impl UsageTracker {
             );
         }
 
-        let all_svcs = self.runner.data.lock().unwrap().all_svcs();
+        let (custom_slices, all_svcs) = {
+            let runner = self.runner.data.lock().unwrap();
+            (
+                runner.sobjs.slice_file.data.custom_slices(),
+                runner.all_svcs(),
+            )
+        };
+        for slice in custom_slices.iter() {
+            usages.insert(
+                slice.to_string(),
+                read_cgroup_usage(&slice_cgrp(slice), self.devnr),
+            );
+        }
         for (svc, cgrp) in all_svcs.into_iter() {
             usages.insert(svc, read_cgroup_usage(&cgrp, self.devnr));
         }
@@ -379,9 +392,10 @@ struct ReportFile {
     retention: Option<u64>,
     path: String,
     d_path: String,
+    seg_writer: Option<ReportSegWriter>,
     next_at: u64,
     usage_tracker: UsageTracker,
-    hashd_acc: [HashdReport; 2],
+    hashd_acc: Vec<HashdReport>,
     mem_stat_acc: BTreeMap<String, StatMap>,
     io_stat_acc: BTreeMap<String, StatMap>,
     vmstat_acc: StatMap,
@@ -391,35 +405,10 @@ struct ReportFile {
 }
 
 pub fn clear_old_report_files(d_path: &str, retention: Option<u64>, now: u64) -> Result<()> {
-    for path in fs::read_dir(d_path)?
-        .filter_map(|x| x.ok())
-        .map(|x| x.path())
-    {
-        if retention.is_none() {
-            return Ok(());
-        }
-
-        let name = path
-            .file_name()
-            .unwrap_or_else(|| OsStr::new(""))
-            .to_str()
-            .unwrap_or("");
-        let stamp = match scan_fmt!(name, "{d}.json", u64) {
-            Ok(v) => v,
-            Err(_) => continue,
-        };
-        if stamp < now - retention.unwrap() {
-            if let Err(e) = fs::remove_file(&path) {
-                warn!(
-                    "report: Failed to remove stale report {:?} ({:?})",
-                    &path, &e
-                );
-            } else {
-                debug!("report: Removed stale report {:?}", &path);
-            }
-        }
+    match retention {
+        Some(retention) => report_store::clear_old_reports(d_path, retention, now),
+        None => Ok(()),
     }
-    Ok(())
 }
 
 impl ReportFile {
@@ -428,6 +417,7 @@ impl ReportFile {
         retention: Option<u64>,
         path: &str,
         d_path: &str,
+        seg: bool,
         devnr: (u32, u32),
         runner: Runner,
     ) -> ReportFile {
@@ -438,6 +428,10 @@ impl ReportFile {
             retention,
             path: path.into(),
             d_path: d_path.into(),
+            seg_writer: match seg {
+                true => Some(ReportSegWriter::new(d_path)),
+                false => None,
+            },
             next_at: ((now / intv) + 1) * intv,
             usage_tracker: UsageTracker::new(devnr, runner),
             hashd_acc: Default::default(),
@@ -489,9 +483,13 @@ impl ReportFile {
         }
     }
 
-    fn tick(&mut self, base_report: &Report, now: u64) {
-        for i in 0..2 {
-            self.hashd_acc[i] += &base_report.hashd[i];
+    fn tick(&mut self, base_report: &Report, now: u64) -> Option<Report> {
+        if self.hashd_acc.len() < base_report.hashd.len() {
+            self.hashd_acc
+                .resize_with(base_report.hashd.len(), Default::default);
+        }
+        for (acc, rep) in self.hashd_acc.iter_mut().zip(base_report.hashd.iter()) {
+            *acc += rep;
         }
         Self::acc_slice_stat_map(&mut self.mem_stat_acc, &base_report.mem_stat);
         Self::acc_slice_stat_map(&mut self.io_stat_acc, &base_report.io_stat);
@@ -501,7 +499,7 @@ impl ReportFile {
         self.nr_samples += 1;
 
         if now < self.next_at {
-            return;
+            return None;
         }
 
         trace!("report: Reporting {}s summary at {}", self.intv, now);
@@ -509,20 +507,24 @@ impl ReportFile {
         self.next_at = (now / self.intv + 1) * self.intv;
 
         // fill in report
-        let report_path = format!("{}/{}.json", &self.d_path, now / self.intv * self.intv);
-        let mut report_file = JsonReportFile::<Report>::new(Some(&report_path));
+        let report_at = now / self.intv * self.intv;
+        let report_path = format!("{}/{}.json", &self.d_path, report_at);
+        let mut report_file = match self.seg_writer {
+            Some(_) => JsonReportFile::<Report>::new(Some(&self.path)),
+            None => JsonReportFile::<Report>::new(Some(&report_path)),
+        };
         report_file.data = base_report.clone();
         let report = &mut report_file.data;
 
-        for i in 0..2 {
-            self.hashd_acc[i] /= self.nr_samples;
-            report.hashd[i] = HashdReport {
-                svc: report.hashd[i].svc.clone(),
-                phase: report.hashd[i].phase,
-                ..self.hashd_acc[i].clone()
+        for (rep, acc) in report.hashd.iter_mut().zip(self.hashd_acc.iter_mut()) {
+            *acc /= self.nr_samples;
+            *rep = HashdReport {
+                svc: rep.svc.clone(),
+                phase: rep.phase,
+                ..acc.clone()
             };
         }
-        self.hashd_acc = Default::default();
+        self.hashd_acc.clear();
 
         Self::div_slice_stat_map(&mut self.mem_stat_acc, self.nr_samples as f64);
         Self::div_slice_stat_map(&mut self.io_stat_acc, self.nr_samples as f64);
@@ -549,7 +551,7 @@ impl ReportFile {
             Ok(v) => v,
             Err(e) => {
                 warn!("report: Failed to update {}s usages ({:?})", self.intv, &e);
-                return;
+                return None;
             }
         };
 
@@ -569,25 +571,35 @@ impl ReportFile {
             Err(e) => warn!("report: Failed to read vmstat ({:?})", &e),
         }
 
-        // write out to the unix timestamped file
-        if let Err(e) = report_file.commit() {
-            warn!("report: Failed to write {}s summary ({:?})", self.intv, &e);
-        }
+        // append to the segment and write out the current report
+        if let Some(seg_writer) = self.seg_writer.as_mut() {
+            if let Err(e) = seg_writer.append(report_at, &report_file.data) {
+                warn!("report: Failed to append {}s summary ({:?})", self.intv, &e);
+            }
+            if let Err(e) = report_file.commit() {
+                warn!("report: Failed to write {}s summary ({:?})", self.intv, &e);
+            }
+        } else {
+            // write out to the unix timestamped file
+            if let Err(e) = report_file.commit() {
+                warn!("report: Failed to write {}s summary ({:?})", self.intv, &e);
+            }
 
-        // symlink the current report file
-        let staging_path = format!("{}.staging", &self.path);
-        let _ = fs::remove_file(&staging_path);
-        if let Err(e) = symlink(&report_path, &staging_path) {
-            warn!(
-                "report: Failed to symlink {:?} to {:?} ({:?})",
-                &report_path, &staging_path, &e
-            );
-        }
-        if let Err(e) = fs::rename(&staging_path, &self.path) {
-            warn!(
-                "report: Failed to move {:?} to {:?} ({:?})",
-                &staging_path, &self.path, &e
-            );
+            // symlink the current report file
+            let staging_path = format!("{}.staging", &self.path);
+            let _ = fs::remove_file(&staging_path);
+            if let Err(e) = symlink(&report_path, &staging_path) {
+                warn!(
+                    "report: Failed to symlink {:?} to {:?} ({:?})",
+                    &report_path, &staging_path, &e
+                );
+            }
+            if let Err(e) = fs::rename(&staging_path, &self.path) {
+                warn!(
+                    "report: Failed to move {:?} to {:?} ({:?})",
+                    &staging_path, &self.path, &e
+                );
+            }
         }
 
         // delete expired ones
@@ -596,8 +608,18 @@ impl ReportFile {
                 let path = format!("{}/{}.json", &self.d_path, i - retention);
                 trace!("report: Removing expired {:?}", &path);
                 let _ = fs::remove_file(&path);
+
+                // a segment expires once its last report does
+                let expired = i - retention;
+                if expired % REPORT_SEG_SPAN == 0 && expired >= REPORT_SEG_SPAN {
+                    let path = report_store::report_seg_path(&self.d_path, expired - 1);
+                    trace!("report: Removing expired {:?}", &path);
+                    let _ = fs::remove_file(&path);
+                }
             }
         }
+
+        Some(report_file.data)
     }
 }
 
@@ -711,6 +733,7 @@ struct ReportWorker {
     iolat: IoLatReport,
     iolat_cum: IoLatReport,
     iocost_devnr: (u32, u32),
+    metrics: Option<MetricsExporter>,
 }
 
 impl ReportWorker {
@@ -720,6 +743,14 @@ impl ReportWorker {
         // and unlock it.
         let cfg = &rdata.cfg;
         let scr_devnr = cfg.scr_devnr;
+        let rep_seg = cfg.rep_seg;
+        let metrics = match (
+            cfg.metrics_listen.as_deref(),
+            cfg.metrics_textfile.as_deref(),
+        ) {
+            (None, None) => None,
+            (listen, textfile) => Some(MetricsExporter::new(listen, textfile)?),
+        };
         let (rep_ret, rep_path, rep_d_path) = (
             cfg.rep_retention,
             cfg.report_path.clone(),
@@ -739,6 +770,7 @@ impl ReportWorker {
                 rep_ret,
                 &rep_path,
                 &rep_d_path,
+                rep_seg,
                 scr_devnr,
                 runner.clone(),
             ),
@@ -747,6 +779,7 @@ impl ReportWorker {
                 rep_1min_ret,
                 &rep_1min_path,
                 &rep_1min_d_path,
+                rep_seg,
                 scr_devnr,
                 runner.clone(),
             ),
@@ -754,6 +787,7 @@ impl ReportWorker {
             iolat: Default::default(),
             iolat_cum: Default::default(),
             iocost_devnr: scr_devnr,
+            metrics,
             runner,
         })
     }
@@ -937,7 +971,11 @@ impl ReportWorker {
                 }
             };
 
-            self.report_file.tick(&base_report, now);
+            if let Some(report) = self.report_file.tick(&base_report, now) {
+                if let Some(metrics) = self.metrics.as_mut() {
+                    metrics.update(&report);
+                }
+            }
             self.report_file_1min.tick(&base_report, now);
 
             // Report generation and writing could have taken a while. If we
@@ -23,7 +23,7 @@ const LINUX_TAR_XZ_URL: &str = "https://cdn.kernel.org/pub/linux/kernel/v5.x/lin
 const LINUX_TAR_PRELOAD: &str = "/usr/share/resctl-demo/linux.tar";
 const LINUX_TAR_XZ_PRELOAD: &str = "/usr/share/resctl-demo/linux.tar.xz";
 
-const SIDE_BINS: [(&str, &[u8]); 6] = [
+const SIDE_BINS: [(&str, &[u8]); 7] = [
     ("build-linux.sh", include_bytes!("side/build-linux.sh")),
     ("mem-hog.sh", include_bytes!("side/mem-hog.sh")),
     (
@@ -31,6 +31,7 @@ const SIDE_BINS: [(&str, &[u8]); 6] = [
         include_bytes!("side/memory-balloon.py"),
     ),
     ("read-bomb.py", include_bytes!("side/read-bomb.py")),
+    ("write-bomb.py", include_bytes!("side/write-bomb.py")),
     ("burn-cpus.sh", include_bytes!("side/burn-cpus.sh")),
     (
         "inodesteal-test.py",
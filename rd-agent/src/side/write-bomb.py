@@ -0,0 +1,36 @@
+#!/usr/bin/python3
+# Copyright (c) Facebook, Inc. and its affiliates
+
+import os
+import subprocess
+import sys
+
+
+JOBS = 4
+BLOCK_SIZE = 1 << 20
+
+if len(sys.argv) < 2:
+    print("Usage: write-bomb.py SIZE_MB [FSYNC_MB]", file=sys.stderr)
+    sys.exit(1)
+
+size_mb = int(sys.argv[1])
+if len(sys.argv) >= 3:
+    fsync_mb = int(sys.argv[2])
+else:
+    fsync_mb = 64
+
+size_per_job = max(size_mb // JOBS, 1)
+fsync_blocks = max(fsync_mb * (1 << 20) // BLOCK_SIZE, 1)
+
+print(f"Writing {size_per_job}M with {JOBS} jobs, fsync every {fsync_mb}M in {os.getcwd()}",
+      flush=True)
+
+cmd = (
+    f"fio --ioengine=psync --name=write-bomb --directory=. "
+    f"--readwrite=write --blocksize={BLOCK_SIZE} --size={size_per_job}M "
+    f"--fsync={fsync_blocks} --numjobs={JOBS} --time_based --runtime=365d "
+    f"--eta=always --eta-interval=1 | stdbuf -oL tr '\r' '\n'"
+)
+print(f'Running "{cmd}"', flush=True)
+
+subprocess.check_call(cmd, shell=True)
@@ -3,6 +3,7 @@ use anyhow::{Context, Result};
 use glob::glob;
 use log::{debug, error, info, trace, warn};
 use scan_fmt::scan_fmt;
+use std::collections::{BTreeMap, BTreeSet};
 use std::ffi::{OsStr, OsString};
 use std::fmt::Write;
 use std::fs;
@@ -11,8 +12,8 @@ use std::path::Path;
 
 use super::Config;
 use rd_agent_intf::{
-    DisableSeqKnobs, EnforceConfig, MemoryKnob, MissedSysReqs, Slice, SliceConfig, SliceKnobs,
-    SysReq,
+    slice_cgrp, slice_parent, DisableSeqKnobs, EnforceConfig, IoMaxKnob, MemoryKnob, MissedSysReqs,
+    Slice, SliceConfig, SliceKnobs, SysReq,
 };
 use rd_util::systemd::UnitState as US;
 use rd_util::*;
@@ -26,6 +27,7 @@ pub fn check_other_io_controllers(sr_failed: &mut MissedSysReqs) {
         .chain(glob("/sys/fs/cgroup/**/io.max").unwrap())
         .chain(glob("/sys/fs/cgroup/**/io.low").unwrap())
         .filter_map(Result::ok)
+        .filter(|path| !is_own_io_max(path))
     {
         match read_one_line(&path) {
             Ok(line) if line.trim().len() == 0 => continue,
@@ -52,6 +54,24 @@ pub fn check_other_io_controllers(sr_failed: &mut MissedSysReqs) {
     }
 }
 
+// io.max limits on the configured slices are configured by rd-agent itself.
+fn is_own_io_max(path: &Path) -> bool {
+    if path.file_name() != Some(OsStr::new("io.max")) {
+        return false;
+    }
+    let cgrp = match path.parent() {
+        Some(v) => v,
+        None => return false,
+    };
+    match cgrp.file_name().and_then(|x| x.to_str()) {
+        Some(name) => {
+            cgrp == Path::new(&slice_cgrp(name))
+                && Path::new(&crate::unit_configlet_path(name, "resctl")).exists()
+        }
+        None => false,
+    }
+}
+
 fn mknob_to_cgrp_string(knob: &MemoryKnob, is_limit: bool) -> String {
     match knob.nr_bytes(is_limit) {
         std::u64::MAX => "max".to_string(),
@@ -73,40 +93,78 @@ fn mknob_to_unit_resctl(knob: &MemoryKnob) -> Option<u64> {
     }
 }
 
-fn slice_needs_mem_prot_propagation(slice: Slice) -> bool {
-    match slice {
-        Slice::Work | Slice::Side => false,
+// Slices which aren't built-in are user-defined top-level slices or
+// sub-slices of workload.slice. They're treated like the other persistent
+// slices except that nothing else creates them, so they're started on
+// configuration but, unlike sideload.slice, left alone on exit. Like
+// workload.slice itself, the sub-slices leave the protection of what's
+// inside them to the hashd instances.
+fn slice_needs_mem_prot_propagation(slice: &str) -> bool {
+    match Slice::from_name(slice) {
+        Some(Slice::Work) | Some(Slice::Side) => false,
+        None => slice_parent(slice).is_none(),
         _ => true,
     }
 }
 
-fn slice_needs_start_stop(slice: Slice) -> bool {
-    match slice {
-        Slice::Side => true,
+fn slice_needs_start(slice: &str) -> bool {
+    match Slice::from_name(slice) {
+        Some(Slice::Side) | None => true,
+        _ => false,
+    }
+}
+
+fn slice_needs_stop(slice: &str) -> bool {
+    match Slice::from_name(slice) {
+        Some(Slice::Side) => true,
         _ => false,
     }
 }
 
-fn slice_needs_crit_mem_prot(slice: Slice) -> bool {
-    match slice {
-        Slice::Host | Slice::Init => true,
+fn slice_needs_crit_mem_prot(slice: &str) -> bool {
+    match Slice::from_name(slice) {
+        Some(Slice::Host) | Some(Slice::Init) => true,
         _ => false,
     }
 }
 
-fn slice_enforce_mem(ecfg: &EnforceConfig, slice: Slice) -> bool {
+fn slice_enforce_mem(ecfg: &EnforceConfig, slice: &str) -> bool {
     ecfg.mem || (ecfg.crit_mem_prot && slice_needs_crit_mem_prot(slice))
 }
 
+// Without memory_recursiveprot, the protection of a workload.slice level
+// doesn't reach the sub-slices which don't have their own. Fill them in
+// with what they'd get with it so that it still reaches the leaves.
+fn slice_config(
+    knobs: &SliceKnobs,
+    slice: &str,
+    recursive_prot: &BTreeMap<String, (u64, u64)>,
+    cfg: &Config,
+) -> SliceConfig {
+    let mut sk = knobs.slices.get(slice).unwrap().clone();
+    if cfg.memcg_recursive_prot() || slice_parent(slice).is_none() {
+        return sk;
+    }
+    if let Some((min, low)) = recursive_prot.get(slice) {
+        if sk.mem_min == MemoryKnob::None {
+            sk.mem_min = MemoryKnob::Bytes(*min);
+        }
+        if sk.mem_low == MemoryKnob::None {
+            sk.mem_low = MemoryKnob::Bytes(*low);
+        }
+    }
+    sk
+}
+
 fn build_configlet(
-    slice: Slice,
+    slice: &str,
     cpu_weight: Option<u32>,
     io_weight: Option<u32>,
     mem_min: Option<MemoryKnob>,
     mem_low: Option<MemoryKnob>,
     mem_high: Option<MemoryKnob>,
 ) -> String {
-    let section = if slice.name().ends_with(".slice") {
+    let section = if slice.ends_with(".slice") {
         "Slice"
     } else {
         "Scope"
@@ -137,8 +195,70 @@ fn build_configlet(
     buf
 }
 
-fn apply_configlet(slice: Slice, configlet: &str) -> Result<bool> {
-    let path = crate::unit_configlet_path(slice.name(), "resctl");
+fn append_limits_configlet(buf: &mut String, sk: &SliceConfig, cpu: bool, mem: bool, io: bool) {
+    if cpu {
+        if let Some(cm) = sk.cpu_max.as_ref() {
+            // CPUQuota is in percents of a single CPU with 0.01% granularity
+            let pct = cm.quota_usec as f64 / cm.period_usec as f64 * 100.0;
+            writeln!(buf, "CPUQuota={:.2}%", pct).unwrap();
+            writeln!(buf, "CPUQuotaPeriodSec={}us", cm.period_usec).unwrap();
+        }
+    }
+    if mem {
+        for (key, knob) in &[
+            ("MemoryMax", sk.mem_max),
+            ("MemorySwapMax", sk.swap_max),
+            ("MemoryZSwapMax", sk.zswap_max),
+        ] {
+            if let MemoryKnob::Bytes(_) = knob {
+                writeln!(buf, "{}={}", key, mknob_to_systemd_string(knob, true)).unwrap();
+            }
+        }
+    }
+    if io {
+        for (dev, im) in sk.io_max.iter() {
+            for (key, v) in &[
+                ("IOReadBandwidthMax", im.rbps),
+                ("IOWriteBandwidthMax", im.wbps),
+                ("IOReadIOPSMax", im.riops),
+                ("IOWriteIOPSMax", im.wiops),
+            ] {
+                if let Some(v) = v {
+                    writeln!(buf, "{}=/dev/{} {}", key, dev, v).unwrap();
+                }
+            }
+        }
+    }
+    if let Some(v) = sk.pids_max {
+        writeln!(buf, "TasksMax={}", v).unwrap();
+    }
+}
+
+// The cgroup interface files of the hard limits set by `configlet`.
+fn configlet_limit_files(configlet: &str) -> BTreeSet<&'static str> {
+    let mut files = BTreeSet::new();
+    for line in configlet.lines() {
+        let file = match line.split('=').next().unwrap_or("") {
+            "CPUQuota" => "cpu.max",
+            "MemorySwapMax" => "memory.swap.max",
+            "MemoryZSwapMax" => "memory.zswap.max",
+            "TasksMax" => "pids.max",
+            "IOReadBandwidthMax" | "IOWriteBandwidthMax" | "IOReadIOPSMax" | "IOWriteIOPSMax" => {
+                "io.max"
+            }
+            _ => continue,
+        };
+        files.insert(file);
+    }
+    files
+}
+
+fn read_configlet(slice: &str) -> String {
+    fs::read_to_string(crate::unit_configlet_path(slice, "resctl")).unwrap_or_default()
+}
+
+fn apply_configlet(slice: &str, configlet: &str) -> Result<bool> {
+    let path = crate::unit_configlet_path(slice, "resctl");
 
     debug!("resctl: reading {:?} to test for equality", &path);
     if let Ok(mut f) = fs::OpenOptions::new().read(true).open(&path) {
@@ -151,35 +271,36 @@ fn apply_configlet(slice: Slice, configlet: &str) -> Result<bool> {
     }
 
     debug!("resctl: writing updated {:?}", &path);
-    crate::write_unit_configlet(slice.name(), "resctl", &configlet)?;
+    crate::write_unit_configlet(slice, "resctl", &configlet)?;
 
-    if slice_needs_start_stop(slice) {
-        match systemd::Unit::new_sys(slice.name().into()) {
-            Ok(mut unit) => {
-                if let Err(e) = unit.try_start_nowait() {
-                    warn!("resctl: Failed to start {:?} ({})", slice.name(), &e);
-                }
-            }
-            Err(e) => {
-                warn!(
-                    "resctl: Failed to create unit for {:?} ({})",
-                    slice.name(),
-                    &e
-                );
-            }
-        }
+    if slice_needs_start(slice) {
+        start_slice(slice);
     }
 
     Ok(true)
 }
 
-fn propagate_one_slice(slice: Slice, resctl: &systemd::UnitResCtl) -> Result<()> {
+fn start_slice(slice: &str) {
+    match systemd::Unit::new_sys(slice.into()) {
+        Ok(mut unit) => {
+            if let Err(e) = unit.try_start_nowait() {
+                warn!("resctl: Failed to start {:?} ({})", slice, &e);
+            }
+        }
+        Err(e) => {
+            warn!("resctl: Failed to create unit for {:?} ({})", slice, &e);
+        }
+    }
+}
+
+fn propagate_one_slice(slice: &str, resctl: &systemd::UnitResCtl) -> Result<()> {
     debug!("resctl: propagating {:?} w/ {:?}", slice, &resctl);
 
-    for path in glob(&format!("{}/**/*.service", slice.cgrp()))
+    let cgrp = slice_cgrp(slice);
+    for path in glob(&format!("{}/**/*.service", &cgrp))
         .unwrap()
-        .chain(glob(&format!("{}/**/*.scope", slice.cgrp())).unwrap())
-        .chain(glob(&format!("{}/**/*.slice", slice.cgrp())).unwrap())
+        .chain(glob(&format!("{}/**/*.scope", &cgrp)).unwrap())
+        .chain(glob(&format!("{}/**/*.slice", &cgrp)).unwrap())
         .filter_map(Result::ok)
     {
         let unit_name = path.file_name().unwrap().to_str().unwrap().to_string();
@@ -244,15 +365,17 @@ pub fn apply_slices(knobs: &mut SliceKnobs, hashd_mem_size: u64, cfg: &Config) -
         sk.mem_low = MemoryKnob::Bytes((hashd_mem_size as f64 * 0.75).ceil() as u64);
     }
 
-    let mut updated = false;
-    for slice in enum_iterator::all::<Slice>() {
+    let mut updated = clear_stale_slices(knobs, &cfg.enforce);
+    let recursive_prot = knobs.effective_mem_prot(true);
+    for slice in knobs.all_slices() {
+        let slice = slice.as_str();
         let enforce_mem = slice_enforce_mem(&cfg.enforce, slice);
 
         if !cfg.enforce.cpu && !enforce_mem && !cfg.enforce.io {
             continue;
         }
 
-        let sk = knobs.slices.get(slice.name()).unwrap();
+        let sk = &slice_config(knobs, slice, &recursive_prot, cfg);
         let (cpu_weight, io_weight, mem_min, mem_low, mem_high);
 
         cpu_weight = match cfg.enforce.cpu {
@@ -267,7 +390,7 @@ pub fn apply_slices(knobs: &mut SliceKnobs, hashd_mem_size: u64, cfg: &Config) -
         if enforce_mem {
             mem_min = Some(sk.mem_min);
             mem_high = Some(sk.mem_high);
-            if slice == Slice::Work && knobs.disable_seqs.mem >= super::instance_seq() {
+            if slice == Slice::Work.name() && knobs.disable_seqs.mem >= super::instance_seq() {
                 mem_low = None;
             } else {
                 mem_low = Some(sk.mem_low);
@@ -278,13 +401,33 @@ pub fn apply_slices(knobs: &mut SliceKnobs, hashd_mem_size: u64, cfg: &Config) -
             mem_high = None;
         }
 
-        let configlet = build_configlet(slice, cpu_weight, io_weight, mem_min, mem_low, mem_high);
+        let mut configlet =
+            build_configlet(slice, cpu_weight, io_weight, mem_min, mem_low, mem_high);
+        append_limits_configlet(
+            &mut configlet,
+            sk,
+            cfg.enforce.cpu,
+            enforce_mem,
+            cfg.enforce.io,
+        );
+        let prev_limits = configlet_limit_files(&read_configlet(slice));
         if apply_configlet(slice, &configlet)? {
+            // Lift the limits which were removed from the configuration.
+            // The remaining ones are updated by the reload. The sideloader
+            // manages its own on sideload.slice.
+            if Slice::from_name(slice) != Some(Slice::Side) {
+                let cur_limits = configlet_limit_files(&configlet);
+                clear_slice_limits(slice, prev_limits.difference(&cur_limits).cloned());
+            }
             updated = true;
+        } else if Slice::from_name(slice).is_none()
+            && !AsRef::<Path>::as_ref(&slice_cgrp(slice)).exists()
+        {
+            start_slice(slice);
         }
 
         if enforce_mem && slice_needs_mem_prot_propagation(slice) {
-            let sk = knobs.slices.get(slice.name()).unwrap();
+            let sk = knobs.slices.get(slice).unwrap();
             let mut resctl = systemd::UnitResCtl::default();
 
             if !cfg.memcg_recursive_prot() {
@@ -309,8 +452,8 @@ pub fn apply_slices(knobs: &mut SliceKnobs, hashd_mem_size: u64, cfg: &Config) -
     Ok(())
 }
 
-fn clear_one_slice(slice: Slice, ecfg: &EnforceConfig) -> Result<bool> {
-    match systemd::Unit::new_sys(slice.name().into()) {
+fn clear_one_slice(slice: &str, ecfg: &EnforceConfig) -> Result<bool> {
+    match systemd::Unit::new_sys(slice.into()) {
         Ok(mut unit) => {
             if ecfg.cpu {
                 unit.resctl.cpu_weight = None;
@@ -318,29 +461,28 @@ fn clear_one_slice(slice: Slice, ecfg: &EnforceConfig) -> Result<bool> {
             if slice_enforce_mem(ecfg, slice) {
                 unit.resctl.mem_min = None;
                 unit.resctl.mem_low = None;
+                unit.resctl.mem_max = None;
             }
             if ecfg.io {
                 unit.resctl.io_weight = None;
             }
             if let Err(e) = unit.apply() {
-                error!("resctl: Failed to reset {:?} ({})", slice.name(), &e);
+                error!("resctl: Failed to reset {:?} ({})", slice, &e);
             }
-            if slice_needs_start_stop(slice) {
+            if slice_needs_stop(slice) {
                 if let Err(e) = unit.stop() {
-                    error!("resctl: Failed to stop {:?} ({})", slice.name(), &e);
+                    error!("resctl: Failed to stop {:?} ({})", slice, &e);
                 }
             }
         }
         Err(e) => {
-            error!(
-                "resctl: Failed to clear unit for {:?} ({})",
-                slice.name(),
-                &e
-            );
+            error!("resctl: Failed to clear unit for {:?} ({})", slice, &e);
         }
     }
 
-    let path = crate::unit_configlet_path(slice.name(), "resctl");
+    clear_slice_limits(slice, configlet_limit_files(&read_configlet(slice)));
+
+    let path = crate::unit_configlet_path(slice, "resctl");
     if Path::new(&path).exists() {
         debug!("resctl: Removing {:?}", &path);
         fs::remove_file(&path)?;
@@ -350,9 +492,88 @@ fn clear_one_slice(slice: Slice, ecfg: &EnforceConfig) -> Result<bool> {
     }
 }
 
+// Removing a limit from the configlet doesn't necessarily lift it on the
+// live cgroup. Reset the `files` which a previous configlet set directly.
+fn clear_slice_limits<'a>(slice: &str, files: impl IntoIterator<Item = &'a str>) {
+    let cgrp = slice_cgrp(slice);
+    let mut clear_io_max = false;
+    for file in files {
+        if file == "io.max" {
+            clear_io_max = true;
+            continue;
+        }
+        let path = format!("{}/{}", &cgrp, file);
+        if Path::new(&path).exists() {
+            if let Err(e) = write_one_line(&path, "max") {
+                debug!("resctl: Failed to reset {:?} ({:?})", &path, &e);
+            }
+        }
+    }
+    if !clear_io_max {
+        return;
+    }
+
+    let path = format!("{}/io.max", &cgrp);
+    if let Ok(io_max) = read_io_max(&path) {
+        for devnr in io_max.keys() {
+            let line = format!("{} {}", devnr, format_io_max(&Default::default()));
+            if let Err(e) = write_one_line(&path, &line) {
+                debug!("resctl: Failed to reset {:?} ({:?})", &path, &e);
+            }
+        }
+    }
+}
+
+// User-defined slices which have been configured before, built-in ones
+// aren't included.
+fn configured_custom_slices() -> Vec<String> {
+    let mut slices = vec![];
+    for path in glob(&crate::unit_configlet_path("*.slice", "resctl"))
+        .unwrap()
+        .filter_map(Result::ok)
+    {
+        if let Some(name) = path
+            .parent()
+            .and_then(|x| x.file_name())
+            .and_then(|x| x.to_str())
+            .and_then(|x| x.strip_suffix(".d"))
+        {
+            if Slice::from_name(name).is_none() {
+                slices.push(name.to_string());
+            }
+        }
+    }
+    slices
+}
+
+fn clear_stale_slices(knobs: &SliceKnobs, ecfg: &EnforceConfig) -> bool {
+    let mut updated = false;
+    for slice in configured_custom_slices() {
+        if knobs.slices.contains_key(&slice) {
+            continue;
+        }
+        info!("resctl: Clearing configurations for removed {:?}", &slice);
+        match clear_one_slice(&slice, ecfg) {
+            Ok(true) => updated = true,
+            Ok(false) => {}
+            Err(e) => warn!(
+                "resctl: Failed to clear configurations for {:?} ({:?})",
+                &slice, &e
+            ),
+        }
+    }
+    updated
+}
+
 pub fn clear_slices(ecfg: &EnforceConfig) -> Result<()> {
     let mut updated = false;
-    for slice in enum_iterator::all::<Slice>() {
+    let mut slices: Vec<String> = enum_iterator::all::<Slice>()
+        .map(|slc| slc.name().to_string())
+        .collect();
+    slices.append(&mut configured_custom_slices());
+
+    for slice in slices {
+        let slice = slice.as_str();
         let enforce_mem = slice_enforce_mem(ecfg, slice);
 
         if !ecfg.cpu && !enforce_mem && !ecfg.io {
@@ -364,8 +585,7 @@ pub fn clear_slices(ecfg: &EnforceConfig) -> Result<()> {
             Ok(false) => {}
             Err(e) => warn!(
                 "resctl: Failed to clear configurations for {:?} ({:?})",
-                slice.name(),
-                &e
+                slice, &e
             ),
         }
 
@@ -458,6 +678,39 @@ fn fix_slice_cpu(sk: &SliceConfig, path: &str, enable: bool) -> Result<()> {
     Ok(())
 }
 
+// Only the configured limits are verified. Others may be managed by
+// someone else, e.g. the sideloader sets cpu.max and memory.swap.max on
+// sideload.slice. Stale limits are lifted when the configuration changes.
+fn fix_slice_cpu_max(sk: &SliceConfig, path: &str, enable: bool) -> Result<()> {
+    let cm = match sk.cpu_max.as_ref() {
+        Some(cm) if enable => cm,
+        _ => return Ok(()),
+    };
+    let cpu_max_path = path.to_string() + "/cpu.max";
+    trace!("resctl: verify: {:?}", &cpu_max_path);
+    let line = read_one_line(&cpu_max_path)?;
+    let (quota, period) = scan_fmt!(&line, "{} {d}", String, u64)?;
+
+    // CPUQuota rounding can make the quota a bit off
+    let matches = match quota.parse::<u64>() {
+        Ok(v) => {
+            period == cm.period_usec
+                && (v as f64 - cm.quota_usec as f64).abs() <= (cm.quota_usec as f64 * 0.01).max(1.0)
+        }
+        Err(_) => false,
+    };
+    let expected = format!("{} {}", cm.quota_usec, cm.period_usec);
+
+    if !matches {
+        info!(
+            "resctl: {:?} should be {:?} but is {:?}, fixing",
+            &cpu_max_path, &expected, &line
+        );
+        write_one_line(&cpu_max_path, &expected)?;
+    }
+    Ok(())
+}
+
 fn fix_slice_io(sk: &SliceConfig, path: &str, enable: bool) -> Result<()> {
     if !enable {
         return Ok(());
@@ -478,6 +731,101 @@ fn fix_slice_io(sk: &SliceConfig, path: &str, enable: bool) -> Result<()> {
     Ok(())
 }
 
+// Returns the map of "MAJ:MIN" to "rbps=.. wbps=.. riops=.. wiops=..".
+fn read_io_max(path: &str) -> Result<BTreeMap<String, String>> {
+    let mut io_max = BTreeMap::new();
+    for line in fs::read_to_string(path)?.lines() {
+        let mut toks = line.trim().splitn(2, ' ');
+        if let (Some(devnr), Some(rest)) = (toks.next(), toks.next()) {
+            io_max.insert(devnr.to_string(), rest.trim().to_string());
+        }
+    }
+    Ok(io_max)
+}
+
+fn format_io_max(im: &IoMaxKnob) -> String {
+    let fmt = |v: Option<u64>| match v {
+        Some(v) => format!("{}", v),
+        None => "max".to_string(),
+    };
+    format!(
+        "rbps={} wbps={} riops={} wiops={}",
+        fmt(im.rbps),
+        fmt(im.wbps),
+        fmt(im.riops),
+        fmt(im.wiops)
+    )
+}
+
+fn fix_slice_io_max(sk: &SliceConfig, path: &str, enable: bool) -> Result<()> {
+    if !enable {
+        return Ok(());
+    }
+    let io_max_path = path.to_string() + "/io.max";
+    trace!("resctl: verify: {:?}", &io_max_path);
+    let cur = read_io_max(&io_max_path)?;
+    let no_limit = format_io_max(&Default::default());
+
+    let mut expected = BTreeMap::new();
+    for (dev, im) in sk.io_max.iter() {
+        match devname_to_devnr(dev) {
+            Ok((maj, min)) => {
+                expected.insert(format!("{}:{}", maj, min), format_io_max(im));
+            }
+            Err(e) => warn!(
+                "resctl: Failed to find device number for {:?} ({:?})",
+                dev, &e
+            ),
+        }
+    }
+    for devnr in cur.keys() {
+        if !expected.contains_key(devnr) {
+            expected.insert(devnr.clone(), no_limit.clone());
+        }
+    }
+
+    for (devnr, exp) in expected.iter() {
+        // Devices without any limits don't show up in io.max
+        let matches = match cur.get(devnr) {
+            Some(v) => v == exp,
+            None => exp == &no_limit,
+        };
+        if !matches {
+            info!(
+                "resctl: {:?} for {} should be {:?} but is {:?}, fixing",
+                &io_max_path,
+                devnr,
+                exp,
+                cur.get(devnr)
+            );
+            write_one_line(&io_max_path, &format!("{} {}", devnr, exp))?;
+        }
+    }
+    Ok(())
+}
+
+fn fix_slice_pids(sk: &SliceConfig, path: &str) -> Result<()> {
+    let pids_max_path = path.to_string() + "/pids.max";
+    if !Path::new(&pids_max_path).exists() {
+        return Ok(());
+    }
+    // Leave the limit alone if not configured
+    let expected = match sk.pids_max {
+        Some(v) => format!("{}", v),
+        None => return Ok(()),
+    };
+    trace!("resctl: verify: {:?}", &pids_max_path);
+    let line = read_one_line(&pids_max_path)?;
+    if line.trim() != expected {
+        info!(
+            "resctl: {:?} should be {:?} but is {:?}, fixing",
+            &pids_max_path, &expected, &line
+        );
+        write_one_line(&pids_max_path, &expected)?;
+    }
+    Ok(())
+}
+
 fn fix_cgrp_mem(path: &str, is_limit: bool, knob: MemoryKnob) -> Result<()> {
     trace!("resctl: verify: {:?}", path);
     let line = read_one_line(path)?;
@@ -555,7 +903,16 @@ fn fix_slice_mem(
     if enable {
         fix_cgrp_mem(&(path.to_string() + "/memory.min"), false, sk.mem_min)?;
         fix_cgrp_mem(&(path.to_string() + "/memory.low"), false, sk.mem_low)?;
-        fix_cgrp_mem(&(path.to_string() + "/memory.max"), true, MemoryKnob::None)?;
+        fix_cgrp_mem(&(path.to_string() + "/memory.max"), true, sk.mem_max)?;
+        for (file, knob) in &[
+            ("/memory.swap.max", sk.swap_max),
+            ("/memory.zswap.max", sk.zswap_max),
+        ] {
+            let knob_path = path.to_string() + file;
+            if *knob != MemoryKnob::None && Path::new(&knob_path).exists() {
+                fix_cgrp_mem(&knob_path, true, *knob)?;
+            }
+        }
 
         if verify_mem_high {
             fix_cgrp_mem(&(path.to_string() + "/memory.high"), true, sk.mem_high)?;
@@ -595,25 +952,35 @@ pub fn verify_and_fix_slices(
     }
 
     let recursive_mem_prot = cfg.memcg_recursive_prot();
+    let recursive_prot = knobs.effective_mem_prot(true);
 
-    for slice in enum_iterator::all::<Slice>() {
-        let sk = knobs.slices.get(slice.name()).unwrap();
+    for slice in knobs.all_slices() {
+        let slice = slice.as_str();
+        let sk = slice_config(knobs, slice, &recursive_prot, cfg);
 
-        let path = slice.cgrp();
+        let path = slice_cgrp(slice);
+        let path = path.as_str();
         if !AsRef::<Path>::as_ref(path).exists() {
             continue;
         }
 
+        let enforce_mem = slice_enforce_mem(&cfg.enforce, slice);
+
         if cfg.enforce.cpu {
             fix_slice_cpu(&sk, path, dseqs.cpu < seq)?;
+            fix_slice_cpu_max(&sk, path, dseqs.cpu < seq)?;
         }
         if cfg.enforce.io {
             fix_slice_io(&sk, path, dseqs.io < seq)?;
+            fix_slice_io_max(&sk, path, dseqs.io < seq)?;
+        }
+        if cfg.enforce.cpu || enforce_mem || cfg.enforce.io {
+            fix_slice_pids(&sk, path)?;
         }
 
-        if slice_enforce_mem(&cfg.enforce, slice) {
-            let (enable_mem, verify_mem_high) = match slice {
-                Slice::Work => (dseqs.mem < seq, !workload_senpai),
+        if enforce_mem {
+            let (enable_mem, verify_mem_high) = match Slice::from_name(slice) {
+                Some(Slice::Work) => (dseqs.mem < seq, !workload_senpai),
                 _ => (true, true),
             };
             let propagate_mem_prot = slice_needs_mem_prot_propagation(slice);
@@ -634,3 +1001,85 @@ pub fn verify_and_fix_slices(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rd_agent_intf::CpuMaxKnob;
+
+    fn limited_config() -> SliceConfig {
+        let mut io_max = BTreeMap::new();
+        io_max.insert(
+            "sda".to_string(),
+            IoMaxKnob {
+                rbps: Some(1 << 20),
+                wiops: Some(100),
+                ..Default::default()
+            },
+        );
+        SliceConfig {
+            cpu_max: Some(CpuMaxKnob {
+                quota_usec: 50_000,
+                period_usec: 100_000,
+            }),
+            mem_max: MemoryKnob::Bytes(1 << 30),
+            zswap_max: MemoryKnob::Bytes(1 << 28),
+            io_max,
+            pids_max: Some(1024),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_append_limits_configlet() {
+        let sk = limited_config();
+
+        let mut buf = String::new();
+        append_limits_configlet(&mut buf, &sk, true, true, true);
+        assert_eq!(
+            buf,
+            "CPUQuota=50.00%\n\
+             CPUQuotaPeriodSec=100000us\n\
+             MemoryMax=1073741824\n\
+             MemoryZSwapMax=268435456\n\
+             IOReadBandwidthMax=/dev/sda 1048576\n\
+             IOWriteIOPSMax=/dev/sda 100\n\
+             TasksMax=1024\n"
+        );
+        let files: Vec<&str> = configlet_limit_files(&buf).into_iter().collect();
+        assert_eq!(
+            files,
+            vec!["cpu.max", "io.max", "memory.zswap.max", "pids.max"]
+        );
+
+        // pids.max isn't tied to any of the controllers
+        let mut buf = String::new();
+        append_limits_configlet(&mut buf, &sk, false, false, false);
+        assert_eq!(buf, "TasksMax=1024\n");
+
+        let mut buf = String::new();
+        append_limits_configlet(&mut buf, &Default::default(), true, true, true);
+        assert_eq!(buf, "");
+        assert!(configlet_limit_files(&buf).is_empty());
+    }
+
+    #[test]
+    fn test_io_max_round_trip() {
+        let sk = limited_config();
+        let no_limit = format_io_max(&Default::default());
+        assert_eq!(no_limit, "rbps=max wbps=max riops=max wiops=max");
+        let limit = format_io_max(&sk.io_max["sda"]);
+        assert_eq!(limit, "rbps=1048576 wbps=max riops=max wiops=100");
+
+        let path =
+            std::env::temp_dir().join(format!("rd-agent-test-io-max-{}", std::process::id()));
+        fs::write(&path, format!("8:0 {}\n259:0 {}\n", &limit, &no_limit)).unwrap();
+        let io_max = read_io_max(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+
+        let io_max = io_max.unwrap();
+        assert_eq!(io_max.len(), 2);
+        assert_eq!(io_max["8:0"], limit);
+        assert_eq!(io_max["259:0"], no_limit);
+    }
+}
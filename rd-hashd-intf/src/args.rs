@@ -1,4 +1,5 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
+use anyhow::{bail, Result};
 use clap::{App, AppSettings, ArgMatches};
 use serde::{Deserialize, Serialize};
 use std::sync::Mutex;
@@ -14,6 +15,7 @@ lazy_static::lazy_static! {
              -s, --size=[SIZE]             'Max memory footprint, affects testfiles size (default: {dfl_size:.2}G)'
              -f, --file-max=[FRAC]         'Max fraction of page cache, affects testfiles size (default: {dfl_file_max_frac:.2})'
              -c, --compressibility=[FRAC]  'File and anon data compressibility (default: 0)
+                 --testfile-layout=[LAYOUT] 'Testfile layout - units, single, small, sparse (default: units)'
              -p, --params=[FILE]           'Runtime updatable parameters, will be created if non-existent'
              -r, --report=[FILE]           'Runtime report file, FILE.staging will be used for staging'
              -l, --log-dir=[PATH]          'Record hash results to the files in PATH'
@@ -22,6 +24,9 @@ lazy_static::lazy_static! {
              -R, --rotational=[BOOL]       'Force rotational detection to either true or false'
              -a, --args=[FILE]             'Load base command line arguments from FILE'
                  --keep-cache              'Don't drop page cache for testfiles on startup'
+                 --listen=[ADDR]           'Serve hash requests on TCP HOST:PORT or Unix socket PATH'
+                 --record-trace=[FILE]     'Record the accesses of each request to FILE'
+                 --replay-trace=[FILE]     'Replay the accesses recorded in FILE instead of sampling'
                  --clear-testfiles         'Clear testfiles before preparing them'
                  --prepare-config          'Prepare config files and exit'
                  --prepare                 'Prepare config files and testfiles and exit'
@@ -63,8 +68,52 @@ const ARGS_DOC: &str = "\
 // This file provides the base values for a subset of command line arguments.
 // They can be overridden from command line.
 //
+// `testfile_layout` determines how the testfiles are laid out under
+// `testfiles`. Changing it recreates the testfiles.
+//
+//  units: 32M files in a hex-digit directory tree
+//  single: One file covering the whole testfiles size
+//  small: 64k files, stresses inode and dentry caches
+//  sparse: 32M files with every other page left as a hole
+//
 ";
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TestFileLayout {
+    Units,
+    Single,
+    Small,
+    Sparse,
+}
+
+impl Default for TestFileLayout {
+    fn default() -> Self {
+        Self::Units
+    }
+}
+
+impl TestFileLayout {
+    pub fn parse(input: &str) -> Result<Self> {
+        Ok(match input {
+            "units" => Self::Units,
+            "single" => Self::Single,
+            "small" => Self::Small,
+            "sparse" => Self::Sparse,
+            _ => bail!("invalid testfile layout {:?}", input),
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Units => "units",
+            Self::Single => "single",
+            Self::Small => "small",
+            Self::Sparse => "sparse",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct Args {
@@ -72,6 +121,7 @@ pub struct Args {
     pub size: u64,
     pub file_max_frac: f64,
     pub compressibility: f64,
+    pub testfile_layout: TestFileLayout,
     pub params: Option<String>,
     pub report: Option<String>,
     pub log_dir: Option<String>,
@@ -82,6 +132,12 @@ pub struct Args {
     #[serde(skip)]
     pub keep_cache: bool,
     #[serde(skip)]
+    pub listen: Option<String>,
+    #[serde(skip)]
+    pub record_trace: Option<String>,
+    #[serde(skip)]
+    pub replay_trace: Option<String>,
+    #[serde(skip)]
     pub clear_testfiles: bool,
     #[serde(skip)]
     pub prepare_testfiles: bool,
@@ -132,6 +188,7 @@ impl Args {
             size: Self::DFL_SIZE_MULT * mem_size as u64,
             file_max_frac: Self::DFL_FILE_MAX_FRAC,
             compressibility: 0.0,
+            testfile_layout: Default::default(),
             params: None,
             report: None,
             log_dir: None,
@@ -140,6 +197,9 @@ impl Args {
             rotational: None,
             clear_testfiles: false,
             keep_cache: false,
+            listen: None,
+            record_trace: None,
+            replay_trace: None,
             bench_preload_cache: None,
             prepare_testfiles: true,
             prepare_and_exit: false,
@@ -267,6 +327,14 @@ impl JsonArgs for Args {
             };
             updated_base = true;
         }
+        if let Some(v) = matches.value_of("testfile-layout") {
+            self.testfile_layout = if v.len() > 0 {
+                TestFileLayout::parse(v).unwrap()
+            } else {
+                Default::default()
+            };
+            updated_base = true;
+        }
         if let Some(v) = matches.value_of("params") {
             self.params = if v.len() > 0 {
                 Some(v.to_string())
@@ -317,6 +385,18 @@ impl JsonArgs for Args {
         }
 
         self.keep_cache = matches.is_present("keep-cache");
+        self.listen = matches
+            .value_of("listen")
+            .filter(|v| v.len() > 0)
+            .map(|v| v.to_string());
+        self.record_trace = matches
+            .value_of("record-trace")
+            .filter(|v| v.len() > 0)
+            .map(|v| v.to_string());
+        self.replay_trace = matches
+            .value_of("replay-trace")
+            .filter(|v| v.len() > 0)
+            .map(|v| v.to_string());
         if let Some(v) = matches.value_of("bench-preload-cache") {
             self.bench_preload_cache = match v.parse::<usize>().unwrap() {
                 0 => None,
@@ -395,3 +475,23 @@ impl JsonArgs for Args {
         updated_base
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_testfile_layout_parse() {
+        for layout in &[
+            TestFileLayout::Units,
+            TestFileLayout::Single,
+            TestFileLayout::Small,
+            TestFileLayout::Sparse,
+        ] {
+            assert_eq!(TestFileLayout::parse(layout.name()).unwrap(), *layout);
+        }
+        assert!(TestFileLayout::parse("").is_err());
+        assert!(TestFileLayout::parse("Units").is_err());
+        assert!(TestFileLayout::parse("dense").is_err());
+    }
+}
@@ -2,10 +2,12 @@
 pub mod args;
 pub mod params;
 pub mod report;
+pub mod rpc;
 
-pub use args::Args;
-pub use params::Params;
-pub use report::{Latencies, Phase, Report, Stat};
+pub use args::{Args, TestFileLayout};
+pub use params::{ArrivalDist, FileEngine, LoadProfile, Params, ReqClass, WorkKernel};
+pub use report::{ClassStat, FileIoStat, Latencies, Phase, Report, Stat};
+pub use rpc::{RpcAddr, RPC_DROPPED, RPC_LINE_MAX};
 
 use rd_util::*;
 
@@ -1,5 +1,5 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
-use anyhow::Result;
+use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
 
 use rd_util::*;
@@ -11,6 +11,109 @@ pub struct PidParams {
     pub kd: f64,
 }
 
+/// Time-varying RPS target. The current target is `rps_target` scaled by
+/// the fraction the profile yields at the elapsed time.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LoadProfile {
+    Flat,
+    Diurnal {
+        period: f64,
+        min_frac: f64,
+        max_frac: f64,
+    },
+    Step {
+        steps: Vec<(f64, f64)>,
+    },
+    Spike {
+        period: f64,
+        duration: f64,
+        base_frac: f64,
+        spike_frac: f64,
+    },
+    Replay {
+        path: String,
+    },
+}
+
+impl Default for LoadProfile {
+    fn default() -> Self {
+        Self::Flat
+    }
+}
+
+/// A class of requests which overrides the access size, write and sleep
+/// parameters for a weighted portion of requests. Unspecified fields are
+/// inherited from `Params`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReqClass {
+    pub name: String,
+    pub weight: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_size_mean: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anon_size_ratio: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_write_frac: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anon_write_frac: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sleep_mean: Option<f64>,
+}
+
+impl Default for ReqClass {
+    fn default() -> Self {
+        Self {
+            name: "".into(),
+            weight: 1.0,
+            file_size_mean: None,
+            anon_size_ratio: None,
+            file_write_frac: None,
+            anon_write_frac: None,
+            sleep_mean: None,
+        }
+    }
+}
+
+/// Inter-arrival time distribution for open-loop dispatch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArrivalDist {
+    Poisson,
+    Uniform,
+}
+
+/// CPU work performed on the loaded bytes of each request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkKernel {
+    Sha1,
+    Deflate,
+    PointerChase,
+    MemStream,
+}
+
+impl Default for WorkKernel {
+    fn default() -> Self {
+        Self::Sha1
+    }
+}
+
+/// How hasher workers issue file reads and writes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileEngine {
+    Sync,
+    IoUring,
+}
+
+impl Default for FileEngine {
+    fn default() -> Self {
+        Self::Sync
+    }
+}
+
 const PARAMS_DOC: &str = "\
 //
 // rd-hashd runtime parameters
@@ -30,6 +133,17 @@ const PARAMS_DOC: &str = "\
 // specified latency and RPS so that neither is exceeded. The total number
 // of concurrent threads is limited by `concurrency_max`.
 //
+// If `open_loop` is set, the PID controllers are bypassed. Requests arrive
+// at the RPS target regardless of how fast they complete and wait in a queue
+// until a worker thread is available. Latencies are measured from arrival
+// and requests which wait longer than `queue_timeout` are dropped.
+//
+// `classes` splits requests into named classes picked according to their
+// `weight`. Each class can override `file_size_mean`, `anon_size_ratio`,
+// `file_write_frac`, `anon_write_frac` and `sleep_mean` and its latencies
+// are reported separately. If `lat_target_class` is set, the latency
+// controller targets the latency of the class instead of all requests.
+//
 // The total size of testfiles is set up during startup and can't be changed
 // online. However, the portion which is actively used by rd-hashd can be
 // scaled down with `file_total_frac`.
@@ -42,15 +156,40 @@ const PARAMS_DOC: &str = "\
 // `rps_max`. If `rps_max` is 0, access footprint scaling is disabled. Anon
 // footprint is scaled the same way between 'anon_addr_rps_base_frac' and 1.0.
 //
+// File and anon accesses concentrate around the start of their address
+// spaces. `hot_set_drift` moves the center of the hot set by the specified
+// fraction of the address space each second, wrapping around at the end.
+// `hot_set_jump_intv` makes the center jump to a random position at the
+// specified interval. Both can be combined and are applied every
+// `control_period`.
+//
 // Worker threads will sleep according to the sleep duration distribution and
 // their CPU consumption can be scaled up and down using `cpu_ratio`.
 //
+// `file_engine` selects how file chunks are accessed. `sync` reads and writes
+// them one by one from each worker thread. `io_uring` submits each request's
+// page cache accesses through a per-worker io_uring, keeping up to
+// `io_uring_depth` in flight. O_DIRECT accesses always use `sync`.
+//
+// `kernel` selects the work performed on the loaded bytes. Kernels differ
+// widely in per-byte cost, re-run the benchmark after changing.
+//
+//  sha1: SHA1 digest, CPU bound
+//  deflate: Deflate compression, CPU and cache heavy
+//  pointer_chase: Data-dependent random reads, cache-miss and latency bound
+//  mem_stream: Sequential read and copy, memory-bandwidth bound
+//
 //  control_period: PID control period, best left alone
 //  concurrency_max: Maximum number of worker threads
 //  lat_target_pct: Latency target percentile
 //  lat_target: Latency target
+//  lat_target_class: Request class whose latency is controlled, all if empty
+//  open_loop: Dispatch requests on arrival instead of modulating concurrency
+//  arrival_dist: Inter-arrival distribution for open_loop - poisson, uniform
+//  queue_timeout: Drop requests queued longer than this in open_loop - 0 disables
 //  rps_target: Request-per-second target
 //  rps_max: Reference maximum RPS, used to scale the amount of used memory
+//  load_profile: Time-varying scaling of rps_target, see below
 //  chunk_pages: Memory access chunk size in pages
 //  mem_frac: Memory footprint scaling factor - [0.0, 1.0]
 //  file_frac: Page cache proportion of memory footprint - [0.0, 1.0]
@@ -59,6 +198,12 @@ const PARAMS_DOC: &str = "\
 //  file_addr_stdev_ratio: Standard deviation of file access addresses
 //  file_addr_rps_base_frac: Memory scaling starting point for file accesses
 //  file_write_frac: The proportion of writes in file accesses
+//  file_direct_frac: The proportion of file accesses which bypass page cache (O_DIRECT)
+//  file_sync_frac: The proportion of file writes which are followed by fdatasync
+//  file_engine: File access engine - sync, io_uring
+//  io_uring_depth: Queue depth of each worker's io_uring, [1, 32768]
+//  hot_set_drift: Hot set center drift per second in address space fraction - 0 disables
+//  hot_set_jump_intv: Interval between random hot set center jumps - 0 disables
 //  anon_size_ratio: Anon access size average - 1.0 means equal as file accesses
 //  anon_size_stdev_ratio: Standard deviation of anon access sizes
 //  anon_addr_stdev_ratio: Standard deviation of anon access addresses
@@ -67,12 +212,31 @@ const PARAMS_DOC: &str = "\
 //  sleep_mean: Worker sleep duration average
 //  sleep_stdev_ratio: Standard deviation of sleep duration distribution
 //  cpu_ratio: CPU usage scaling - 1.0 hashes the same number of bytes as accessed
+//  kernel: Work kernel - sha1, deflate, pointer_chase, mem_stream
 //  log_bps: Log write bps at rps_max
-//  fake_cpu_load: Sleep equivalent time durations instead of calculating SHA1s
+//  fake_cpu_load: Sleep equivalent time durations instead of running the kernel
 //  acc_dist_slots: Access distribution report slots - 0 disables
+//  classes: Request classes, see above
 //  lat_pid: PID controller parameters for latency convergence
 //  rps_pid: PID controller parameters for RPS convergence
 //
+// `load_profile` makes the RPS target follow a profile which starts when the
+// profile is loaded. Each profile yields a fraction which is multiplied to
+// `rps_target`. `type` selects one of the following:
+//
+//  flat: Constant 1.0, the default
+//  diurnal: Sinusoid between `min_frac` and `max_frac` over `period`,
+//           starting at `min_frac`
+//  step: Cycles through `steps`, a list of [DURATION, FRAC] pairs
+//  spike: `base_frac` except for `duration` at the end of each `period`
+//         during which `spike_frac` is used
+//  replay: Replays the CSV trace at `path` in a loop. Each line is
+//          \"SECS,RPS\" and RPS is scaled so that the maximum matches 1.0.
+//          The trace is interpolated linearly.
+//
+// e.g. \"load_profile\": { \"type\": \"diurnal\", \"period\": 600.0,
+//                         \"min_frac\": 0.2, \"max_frac\": 1.0 }
+//
 ";
 
 /// Dispatch and hash parameters, can be adjusted dynamially.
@@ -83,8 +247,13 @@ pub struct Params {
     pub concurrency_max: u32,
     pub lat_target_pct: f64,
     pub lat_target: f64,
+    pub lat_target_class: Option<String>,
+    pub open_loop: bool,
+    pub arrival_dist: ArrivalDist,
+    pub queue_timeout: f64,
     pub rps_target: u32,
     pub rps_max: u32,
+    pub load_profile: LoadProfile,
     pub mem_frac: f64,
     pub chunk_pages: usize,
     pub file_frac: f64,
@@ -93,6 +262,12 @@ pub struct Params {
     pub file_addr_stdev_ratio: f64,
     pub file_addr_rps_base_frac: f64,
     pub file_write_frac: f64,
+    pub file_direct_frac: f64,
+    pub file_sync_frac: f64,
+    pub file_engine: FileEngine,
+    pub io_uring_depth: u32,
+    pub hot_set_drift: f64,
+    pub hot_set_jump_intv: f64,
     pub anon_size_ratio: f64,
     pub anon_size_stdev_ratio: f64,
     pub anon_addr_stdev_ratio: f64,
@@ -101,15 +276,39 @@ pub struct Params {
     pub sleep_mean: f64,
     pub sleep_stdev_ratio: f64,
     pub cpu_ratio: f64,
+    pub kernel: WorkKernel,
     pub log_bps: u64,
     pub fake_cpu_load: bool,
     pub acc_dist_slots: usize,
+    pub classes: Vec<ReqClass>,
     pub lat_pid: PidParams,
     pub rps_pid: PidParams,
 }
 
 impl Params {
     pub const FILE_FRAC_MIN: f64 = 0.001;
+    pub const IO_URING_DEPTH_MAX: u32 = 32768;
+
+    /// Params with the overrides of `class` applied.
+    pub fn class_params(&self, class: &ReqClass) -> Params {
+        let mut params = self.clone();
+        if let Some(v) = class.file_size_mean {
+            params.file_size_mean = v;
+        }
+        if let Some(v) = class.anon_size_ratio {
+            params.anon_size_ratio = v;
+        }
+        if let Some(v) = class.file_write_frac {
+            params.file_write_frac = v;
+        }
+        if let Some(v) = class.anon_write_frac {
+            params.anon_write_frac = v;
+        }
+        if let Some(v) = class.sleep_mean {
+            params.sleep_mean = v;
+        }
+        params
+    }
 
     pub fn log_padding(&self) -> u64 {
         if self.rps_max > 0 {
@@ -127,8 +326,13 @@ impl Default for Params {
             concurrency_max: 65536,
             lat_target_pct: 0.95,
             lat_target: 75.0 * MSEC,
+            lat_target_class: None,
+            open_loop: false,
+            arrival_dist: ArrivalDist::Poisson,
+            queue_timeout: 1.0,
             rps_target: 65536,
             rps_max: 0,
+            load_profile: LoadProfile::Flat,
             chunk_pages: 25,
             mem_frac: 0.80,
             file_frac: 0.25,
@@ -137,6 +341,12 @@ impl Default for Params {
             file_addr_stdev_ratio: 0.215,
             file_addr_rps_base_frac: 0.5,
             file_write_frac: 0.0,
+            file_direct_frac: 0.0,
+            file_sync_frac: 0.0,
+            file_engine: FileEngine::Sync,
+            io_uring_depth: 32,
+            hot_set_drift: 0.0,
+            hot_set_jump_intv: 0.0,
             anon_size_ratio: 2.3,
             anon_size_stdev_ratio: 0.45,
             anon_addr_stdev_ratio: 0.235,
@@ -145,9 +355,11 @@ impl Default for Params {
             sleep_mean: 20.0 * MSEC,
             sleep_stdev_ratio: 0.33,
             cpu_ratio: 0.93,
+            kernel: WorkKernel::Sha1,
             log_bps: 1100794,
             fake_cpu_load: false,
             acc_dist_slots: 0,
+            classes: vec![],
             lat_pid: PidParams {
                 kp: 0.1,
                 ki: 0.01,
@@ -165,6 +377,35 @@ impl Default for Params {
 impl JsonLoad for Params {
     fn loaded(&mut self, _prev: Option<&mut Self>) -> Result<()> {
         self.file_frac = self.file_frac.max(Self::FILE_FRAC_MIN);
+
+        if self.io_uring_depth == 0 || self.io_uring_depth > Self::IO_URING_DEPTH_MAX {
+            bail!(
+                "io_uring_depth {} out of range [1, {}]",
+                self.io_uring_depth,
+                Self::IO_URING_DEPTH_MAX
+            );
+        }
+
+        for (idx, class) in self.classes.iter().enumerate() {
+            if class.name.len() == 0 {
+                bail!("classes[{}] doesn't have a name", idx);
+            }
+            if self.classes[..idx].iter().any(|c| c.name == class.name) {
+                bail!("duplicate class name {:?}", &class.name);
+            }
+            if !class.weight.is_finite() || class.weight < 0.0 {
+                bail!(
+                    "class {:?} has invalid weight {}",
+                    &class.name,
+                    class.weight
+                );
+            }
+        }
+        if let Some(name) = self.lat_target_class.as_ref() {
+            if !self.classes.iter().any(|c| &c.name == name) {
+                bail!("unknown lat_target_class {:?}", name);
+            }
+        }
         Ok(())
     }
 }
@@ -174,3 +415,46 @@ impl JsonSave for Params {
         Some(PARAMS_DOC.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_params_io_uring_depth() {
+        for (depth, valid) in &[
+            (0, false),
+            (1, true),
+            (Params::IO_URING_DEPTH_MAX, true),
+            (Params::IO_URING_DEPTH_MAX + 1, false),
+        ] {
+            let mut params = Params {
+                io_uring_depth: *depth,
+                ..Default::default()
+            };
+            assert_eq!(params.loaded(None).is_ok(), *valid, "depth={}", depth);
+        }
+    }
+
+    #[test]
+    fn test_params_class_weights() {
+        let class = |weight: f64| ReqClass {
+            name: "a".into(),
+            weight,
+            ..Default::default()
+        };
+        for (weight, valid) in &[
+            (0.0, true),
+            (2.5, true),
+            (-1.0, false),
+            (f64::NAN, false),
+            (f64::INFINITY, false),
+        ] {
+            let mut params = Params {
+                classes: vec![class(*weight)],
+                ..Default::default()
+            };
+            assert_eq!(params.loaded(None).is_ok(), *valid, "weight={}", weight);
+        }
+    }
+}
@@ -1,6 +1,7 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
 use chrono::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::ops;
 use std::time::UNIX_EPOCH;
 
@@ -103,32 +104,106 @@ impl<T: Into<f64>> ops::DivAssign<T> for Latencies {
     }
 }
 
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ClassStat {
+    pub rps: f64,
+    pub nr_done: u64,
+    pub lat: Latencies,
+}
+
+impl ops::AddAssign<&ClassStat> for ClassStat {
+    fn add_assign(&mut self, rhs: &ClassStat) {
+        self.rps += rhs.rps;
+        self.nr_done += rhs.nr_done;
+        self.lat += &rhs.lat;
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct FileIoStat {
+    pub nr_cached_reads: u64,
+    pub nr_cached_writes: u64,
+    pub nr_uring_reads: u64,
+    pub nr_uring_writes: u64,
+    pub nr_direct_reads: u64,
+    pub nr_direct_writes: u64,
+    pub nr_syncs: u64,
+    pub cached_lat: f64,
+    pub uring_lat: f64,
+    pub direct_read_lat: f64,
+    pub direct_write_lat: f64,
+    pub sync_lat: f64,
+}
+
+impl ops::AddAssign<&FileIoStat> for FileIoStat {
+    fn add_assign(&mut self, rhs: &FileIoStat) {
+        self.nr_cached_reads += rhs.nr_cached_reads;
+        self.nr_cached_writes += rhs.nr_cached_writes;
+        self.nr_uring_reads += rhs.nr_uring_reads;
+        self.nr_uring_writes += rhs.nr_uring_writes;
+        self.nr_direct_reads += rhs.nr_direct_reads;
+        self.nr_direct_writes += rhs.nr_direct_writes;
+        self.nr_syncs += rhs.nr_syncs;
+        self.cached_lat += rhs.cached_lat;
+        self.uring_lat += rhs.uring_lat;
+        self.direct_read_lat += rhs.direct_read_lat;
+        self.direct_write_lat += rhs.direct_write_lat;
+        self.sync_lat += rhs.sync_lat;
+    }
+}
+
 const STAT_DOC: &str = "\
 //  rps: Request per second in the last control period
+//  rps_target: Current RPS target after applying params.load_profile
 //  concurrency: Current number of active worker threads
 //  concurrency_max: Current concurrency max from latency target
 //  file_addr_frac: Current file footprint fraction
 //  anon_addr_frac: Current anon footprint fraction
+//  hot_set_pos: Current hot set center position in address space fraction
+//  nr_hot_set_jumps: Total number of hot set center jumps
 //  nr_in_flight: The number of requests in flight
 //  nr_done: Total number of hashes calculated
+//  queue_depth: The number of requests waiting for dispatch in open loop mode
+//  nr_dropped: Total number of requests dropped after queue_timeout
 //  nr_workers: Number of worker threads
 //  nr_idle_workers: Number of idle workers
 //  lat.p*: Latency percentiles
-//  lat.ctl: Latency percentile used for rps control (params.lat_target_pct)
+//  lat.ctl: Latency percentile used for rps control (params.lat_target_pct of
+//           params.lat_target_class if set)
+//  classes.NAME: rps, nr_done and latencies of each request class
+//  file_io.nr_cached_reads: Total number of file chunk reads by the sync engine
+//  file_io.nr_cached_writes: Total number of file chunk writes by the sync engine
+//  file_io.nr_uring_reads: Total number of file chunk reads by the io_uring engine
+//  file_io.nr_uring_writes: Total number of file chunk writes by the io_uring engine
+//  file_io.nr_direct_reads: Total number of O_DIRECT file chunk reads
+//  file_io.nr_direct_writes: Total number of O_DIRECT file chunk writes
+//  file_io.nr_syncs: Total number of fdatasyncs after file writes
+//  file_io.cached_lat: Average sync engine chunk latency in the last control period
+//  file_io.uring_lat: Average io_uring read/write latency in the last control period
+//  file_io.direct_read_lat: Average O_DIRECT chunk read latency in the last control period
+//  file_io.direct_write_lat: Average O_DIRECT chunk write latency in the last control period
+//  file_io.sync_lat: Average fdatasync latency in the last control period
 ";
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Stat {
     pub rps: f64,
+    pub rps_target: f64,
     pub concurrency: f64,
     pub concurrency_max: f64,
     pub file_addr_frac: f64,
     pub anon_addr_frac: f64,
+    pub hot_set_pos: f64,
+    pub nr_hot_set_jumps: u64,
     pub nr_in_flight: u32,
     pub nr_done: u64,
+    pub queue_depth: u32,
+    pub nr_dropped: u64,
     pub nr_workers: usize,
     pub nr_idle_workers: usize,
     pub lat: Latencies,
+    pub classes: BTreeMap<String, ClassStat>,
+    pub file_io: FileIoStat,
 
     pub file_size: u64,
     pub file_dist: Vec<u64>,
@@ -139,15 +214,24 @@ pub struct Stat {
 impl ops::AddAssign<&Stat> for Stat {
     fn add_assign(&mut self, rhs: &Stat) {
         self.rps += rhs.rps;
+        self.rps_target += rhs.rps_target;
         self.concurrency += rhs.concurrency;
         self.concurrency_max += rhs.concurrency_max;
         self.file_addr_frac += rhs.file_addr_frac;
         self.anon_addr_frac += rhs.anon_addr_frac;
+        self.hot_set_pos += rhs.hot_set_pos;
+        self.nr_hot_set_jumps += rhs.nr_hot_set_jumps;
         self.nr_in_flight += rhs.nr_in_flight;
         self.nr_done += rhs.nr_done;
+        self.queue_depth += rhs.queue_depth;
+        self.nr_dropped += rhs.nr_dropped;
         self.nr_workers += rhs.nr_workers;
         self.nr_idle_workers += rhs.nr_idle_workers;
         self.lat += &rhs.lat;
+        for (name, cstat) in rhs.classes.iter() {
+            *self.classes.entry(name.clone()).or_default() += cstat;
+        }
+        self.file_io += &rhs.file_io;
     }
 }
 
@@ -158,15 +242,38 @@ impl Stat {
     {
         let divf64 = div.into();
         self.rps /= divf64;
+        self.rps_target /= divf64;
         self.concurrency /= divf64;
         self.concurrency_max /= divf64;
         self.file_addr_frac /= divf64;
         self.anon_addr_frac /= divf64;
+        self.hot_set_pos /= divf64;
+        self.nr_hot_set_jumps = (self.nr_hot_set_jumps as f64 / divf64).round() as u64;
         self.nr_in_flight = (self.nr_in_flight as f64 / divf64).round() as u32;
         self.nr_done = (self.nr_done as f64 / divf64).round() as u64;
+        self.queue_depth = (self.queue_depth as f64 / divf64).round() as u32;
+        self.nr_dropped = (self.nr_dropped as f64 / divf64).round() as u64;
         self.nr_workers = (self.nr_workers as f64 / divf64).round() as usize;
         self.nr_idle_workers = (self.nr_idle_workers as f64 / divf64).round() as usize;
         self.lat /= divf64;
+        for cstat in self.classes.values_mut() {
+            cstat.rps /= divf64;
+            cstat.nr_done = (cstat.nr_done as f64 / divf64).round() as u64;
+            cstat.lat /= divf64;
+        }
+        let fio = &mut self.file_io;
+        fio.nr_cached_reads = (fio.nr_cached_reads as f64 / divf64).round() as u64;
+        fio.nr_cached_writes = (fio.nr_cached_writes as f64 / divf64).round() as u64;
+        fio.nr_uring_reads = (fio.nr_uring_reads as f64 / divf64).round() as u64;
+        fio.nr_uring_writes = (fio.nr_uring_writes as f64 / divf64).round() as u64;
+        fio.nr_direct_reads = (fio.nr_direct_reads as f64 / divf64).round() as u64;
+        fio.nr_direct_writes = (fio.nr_direct_writes as f64 / divf64).round() as u64;
+        fio.nr_syncs = (fio.nr_syncs as f64 / divf64).round() as u64;
+        fio.cached_lat /= divf64;
+        fio.uring_lat /= divf64;
+        fio.direct_read_lat /= divf64;
+        fio.direct_write_lat /= divf64;
+        fio.sync_lat /= divf64;
     }
 }
 
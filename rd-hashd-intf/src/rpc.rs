@@ -0,0 +1,71 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+
+// rd-hashd RPC front-end protocol
+//
+// With `--listen`, rd-hashd accepts connections on a TCP or Unix domain
+// socket. Each line received is a request which is executed as a hash job.
+// The reply is a line consisting of the request line, a space and the
+// digest. If the request was dropped while queued, `RPC_DROPPED` is returned
+// instead of the digest. Requests on a connection are processed one by one.
+// A request line longer than `RPC_LINE_MAX` bytes closes the connection.
+
+/// Reply in place of the digest if the request was dropped.
+pub const RPC_DROPPED: &str = "!dropped";
+
+/// Maximum length of a request line including the newline.
+pub const RPC_LINE_MAX: usize = 4096;
+
+/// Addresses containing '/' are Unix domain socket paths. Everything else is
+/// a TCP "HOST:PORT".
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RpcAddr {
+    Tcp(String),
+    Unix(String),
+}
+
+impl RpcAddr {
+    pub fn parse(addr: &str) -> Self {
+        match addr.contains('/') {
+            true => Self::Unix(addr.to_string()),
+            false => Self::Tcp(addr.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for RpcAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "tcp:{}", addr),
+            Self::Unix(path) => write!(f, "unix:{}", path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rpc_addr_parse() {
+        assert_eq!(
+            RpcAddr::parse("localhost:4000"),
+            RpcAddr::Tcp("localhost:4000".into())
+        );
+        assert_eq!(
+            RpcAddr::parse("[::1]:4000"),
+            RpcAddr::Tcp("[::1]:4000".into())
+        );
+        assert_eq!(
+            RpcAddr::parse("/run/rd-hashd.sock"),
+            RpcAddr::Unix("/run/rd-hashd.sock".into())
+        );
+        assert_eq!(
+            RpcAddr::parse("./rd-hashd.sock"),
+            RpcAddr::Unix("./rd-hashd.sock".into())
+        );
+        assert_eq!(
+            format!("{}", RpcAddr::parse("/a/b")),
+            "unix:/a/b".to_string()
+        );
+    }
+}
@@ -17,7 +17,7 @@ use rd_util::*;
 
 use super::hasher;
 use super::testfiles::TestFiles;
-use super::{create_logger, report_tick, Args, TestFilesProgressBar, TESTFILE_UNIT_SIZE};
+use super::{create_logger, report_tick, Args, TestFilesProgressBar};
 
 const HIST_MAX: usize = 600;
 
@@ -277,7 +277,7 @@ impl TestHasher {
         fill_anon: bool,
         verbose: bool,
     ) -> Self {
-        let disp = hasher::Dispatch::new(max_size, tf, params, comp, logger);
+        let disp = hasher::Dispatch::new(max_size, tf, params, comp, logger, None, false);
         if fill_anon {
             disp.fill_anon();
         }
@@ -577,7 +577,7 @@ impl Bench {
 
         let mut tf = TestFiles::new(
             self.args_file.data.testfiles.as_ref().unwrap(),
-            TESTFILE_UNIT_SIZE,
+            self.args_file.data.testfile_layout,
             size,
             self.args_file.data.compressibility,
         );
@@ -647,7 +647,7 @@ impl Bench {
     }
 
     fn time_hash(size: usize, params: &Params, tf: &TestFiles) -> f64 {
-        let mut hasher = hasher::Hasher::new(1.0, 0.0);
+        let mut hasher = hasher::Hasher::new(1.0, 0.0, params.kernel);
         let chunk_size = params.chunk_pages * *PAGE_SIZE;
         let chunks_per_unit = Integer::div_ceil(&(tf.unit_size as usize), &chunk_size);
 
@@ -657,12 +657,12 @@ impl Bench {
             let path = tf.path((i / chunks_per_unit) as u64);
             let off = ((i % chunks_per_unit) * params.chunk_pages) as u64;
 
-            hasher.load(&path, off, chunk_size, false).expect(&format!(
+            hasher.load(&path, off, chunk_size, false, false, false).expect(&format!(
                 "failed to load chunk {}, chunk_size={} chunks_per_unit={} path={:?} off={}",
                 i, chunk_size, chunks_per_unit, &path, off
             ));
         }
-        hasher.sha1();
+        hasher.process();
 
         Instant::now().duration_since(started_at).as_secs_f64()
     }
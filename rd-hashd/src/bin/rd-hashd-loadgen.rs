@@ -0,0 +1,202 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// Load generator for the rd-hashd RPC front-end (rd-hashd --listen). Each
+// connection issues one request at a time. With --rps, requests are paced
+// and latencies are measured from the scheduled send time so that stalls in
+// the server aren't hidden by delayed sends.
+use anyhow::{bail, Result};
+use clap::{App, AppSettings};
+use quantiles::ckms::CKMS;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex};
+use std::thread::{sleep, spawn};
+use std::time::{Duration, Instant};
+
+use rd_hashd_intf::{RpcAddr, RPC_DROPPED};
+use rd_util::*;
+
+const ARGS_STR: &str = "\
+    -c, --connect=<ADDR>         'rd-hashd --listen address, TCP HOST:PORT or Unix socket PATH'
+    -n, --connections=[NR]       'Number of concurrent connections (default: 8)'
+    -r, --rps=[RPS]              'Total request rate, 0 for closed loop (default: 0)'
+    -d, --duration=[DUR]         'Duration to run, 0 to run until interrupted (default: 0)'
+    -i, --interval=[DUR]         'Report interval (default: 1s)'";
+
+const CKMS_ERROR: f64 = 0.001;
+
+struct LatStat {
+    ckms: CKMS<f64>,
+    max: f64,
+    nr_done: u64,
+    nr_dropped: u64,
+    nr_errors: u64,
+}
+
+impl LatStat {
+    fn new() -> Self {
+        Self {
+            ckms: CKMS::new(CKMS_ERROR),
+            max: 0.0,
+            nr_done: 0,
+            nr_dropped: 0,
+            nr_errors: 0,
+        }
+    }
+
+    fn format(&self, dur: f64) -> String {
+        let pct = |p| self.ckms.query(p).map(|x| x.1).unwrap_or(0.0) * TO_MSEC;
+        format!(
+            "rps:{:8.1} p50:{:7.2} p90:{:7.2} p99:{:7.2} max:{:7.2} dropped:{} errors:{}",
+            self.nr_done as f64 / dur,
+            pct(0.5),
+            pct(0.9),
+            pct(0.99),
+            self.max * TO_MSEC,
+            self.nr_dropped,
+            self.nr_errors
+        )
+    }
+}
+
+struct Stats {
+    intv: LatStat,
+    total: LatStat,
+}
+
+fn connect(addr: &RpcAddr) -> Result<(Box<dyn Read + Send>, Box<dyn Write + Send>)> {
+    Ok(match addr {
+        RpcAddr::Tcp(host_port) => {
+            let stream = TcpStream::connect(host_port)?;
+            stream.set_nodelay(true)?;
+            (Box::new(stream.try_clone()?), Box::new(stream))
+        }
+        RpcAddr::Unix(path) => {
+            let stream = UnixStream::connect(path)?;
+            (Box::new(stream.try_clone()?), Box::new(stream))
+        }
+    })
+}
+
+fn run_conn(conn_idx: usize, addr: RpcAddr, intv: Option<f64>, stats: Arc<Mutex<Stats>>) {
+    let (rd, mut wr) = match connect(&addr) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("[{}] Failed to connect to {} ({:#})", conn_idx, &addr, &e);
+            std::process::exit(1);
+        }
+    };
+    let mut rd = BufReader::new(rd);
+    let mut line = String::new();
+    let mut next_at = Instant::now();
+
+    for seq in 0u64.. {
+        // When pacing, measure from the scheduled time.
+        let sent_at = match intv {
+            Some(intv) => {
+                let now = Instant::now();
+                if next_at > now {
+                    sleep(next_at - now);
+                }
+                let at = next_at;
+                next_at += Duration::from_secs_f64(intv);
+                at
+            }
+            None => Instant::now(),
+        };
+
+        let req = format!("{}.{}", conn_idx, seq);
+        line.clear();
+        let res = wr
+            .write_all(format!("{}\n", &req).as_bytes())
+            .and_then(|_| rd.read_line(&mut line));
+        let lat = sent_at.elapsed().as_secs_f64();
+
+        let st = &mut *stats.lock().unwrap();
+        let reply = match res {
+            Ok(0) | Err(_) => {
+                eprintln!("[{}] Connection to {} lost", conn_idx, &addr);
+                std::process::exit(1);
+            }
+            Ok(_) => line.trim_end().strip_prefix(&req).map(|x| x.trim()),
+        };
+        for ls in [&mut st.intv, &mut st.total].iter_mut() {
+            match reply {
+                Some(RPC_DROPPED) => ls.nr_dropped += 1,
+                Some(_) => {
+                    ls.nr_done += 1;
+                    ls.ckms.insert(lat);
+                    ls.max = ls.max.max(lat);
+                }
+                None => ls.nr_errors += 1,
+            }
+        }
+    }
+}
+
+fn parse_dur_arg(val: Option<&str>, dfl: f64) -> Result<f64> {
+    match val {
+        Some(v) => parse_duration(v),
+        None => Ok(dfl),
+    }
+}
+
+fn main() -> Result<()> {
+    let matches = App::new("rd-hashd-loadgen")
+        .about("Drives rd-hashd RPC front-end and measures client-side latencies")
+        .args_from_usage(ARGS_STR)
+        .setting(AppSettings::UnifiedHelpMessage)
+        .setting(AppSettings::DeriveDisplayOrder)
+        .get_matches();
+
+    let addr = RpcAddr::parse(matches.value_of("connect").unwrap());
+    let nr_conns = match matches.value_of("connections") {
+        Some(v) => v.parse::<usize>()?,
+        None => 8,
+    };
+    let rps = match matches.value_of("rps") {
+        Some(v) => v.parse::<f64>()?,
+        None => 0.0,
+    };
+    let duration = parse_dur_arg(matches.value_of("duration"), 0.0)?;
+    let interval = parse_dur_arg(matches.value_of("interval"), 1.0)?;
+    if nr_conns == 0 || rps < 0.0 || interval <= 0.0 {
+        bail!("--connections and --interval must be positive and --rps non-negative");
+    }
+
+    // Each connection paces itself at its share of the total rate.
+    let conn_intv = match rps {
+        v if v > 0.0 => Some(nr_conns as f64 / v),
+        _ => None,
+    };
+
+    let stats = Arc::new(Mutex::new(Stats {
+        intv: LatStat::new(),
+        total: LatStat::new(),
+    }));
+    for idx in 0..nr_conns {
+        let (addr, stats) = (addr.clone(), stats.clone());
+        spawn(move || run_conn(idx, addr, conn_intv, stats));
+    }
+
+    let started_at = Instant::now();
+    let mut last_at = started_at;
+    loop {
+        sleep(Duration::from_secs_f64(interval));
+        let now = Instant::now();
+        let mut st = stats.lock().unwrap();
+        println!(
+            "{}",
+            st.intv.format(now.duration_since(last_at).as_secs_f64())
+        );
+        st.intv = LatStat::new();
+        last_at = now;
+
+        let elapsed = now.duration_since(started_at).as_secs_f64();
+        if duration > 0.0 && elapsed >= duration {
+            println!("total {}", st.total.format(elapsed));
+            return Ok(());
+        }
+    }
+}
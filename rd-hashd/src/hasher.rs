@@ -1,64 +1,231 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use crossbeam::channel::{self, select, Receiver, Sender};
-use log::{debug, error, trace, warn};
+use log::{debug, error, info, trace, warn};
 use num::Integer;
 use pid::Pid;
 use quantiles::ckms::CKMS;
+use rand::distributions::WeightedIndex;
 use rand::rngs::SmallRng;
-use rand::SeedableRng;
-use rand_distr::{Distribution, Normal, Uniform};
-use sha1_smol::{Digest, Sha1};
+use rand::{Rng, SeedableRng};
+use rand_distr::{Distribution, Exp, Normal, Uniform};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
 use std::convert::TryInto;
-use std::fs::OpenOptions;
+use std::fs::{File, OpenOptions};
 use std::io::{prelude::*, SeekFrom};
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, RwLock};
 use std::thread::{sleep, spawn, JoinHandle};
 use std::time::{Duration, Instant};
 
-use rd_hashd_intf::{Latencies, Params, Stat};
+use rd_hashd_intf::{
+    ArrivalDist, ClassStat, FileEngine, FileIoStat, Latencies, Params, Stat, WorkKernel,
+};
 use rd_util::anon_area::AnonArea;
 use rd_util::*;
 
 use super::bench::{Bench, Cfg};
+use super::kernel::{new_kernel, Kernel};
 use super::logger::Logger;
+use super::profile::LoadProfiler;
 use super::testfiles::TestFiles;
+use super::trace::{AnonAccess, FileAccess, Trace, TraceRecord};
+use super::uring::{self, IoUring, IORING_OP_READ, IORING_OP_WRITE};
 use super::workqueue::WorkQueue;
 
-/// Load files and calculate sha1.
+thread_local! {
+    // Each worker thread keeps its io_uring across requests. If setting one
+    // up fails, the thread uses sync until URING_RETRY_INTV has passed.
+    static URING: RefCell<Option<IoUring>> = const { RefCell::new(None) };
+    static URING_RETRY_AT: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+// Set if io_uring isn't supported or allowed at all.
+static URING_UNAVAILABLE: AtomicBool = AtomicBool::new(false);
+const URING_RETRY_INTV: Duration = Duration::from_secs(10);
+
+/// File access counts and durations of each engine.
+#[derive(Default)]
+pub struct FileIoCounts {
+    nr_cached_reads: u64,
+    nr_cached_writes: u64,
+    nr_uring_reads: u64,
+    nr_uring_writes: u64,
+    nr_direct_reads: u64,
+    nr_direct_writes: u64,
+    nr_syncs: u64,
+    cached_time: f64,
+    uring_time: f64,
+    direct_read_time: f64,
+    direct_write_time: f64,
+    sync_time: f64,
+}
+
+impl FileIoCounts {
+    fn add(&mut self, rhs: &FileIoCounts) {
+        self.nr_cached_reads += rhs.nr_cached_reads;
+        self.nr_cached_writes += rhs.nr_cached_writes;
+        self.nr_uring_reads += rhs.nr_uring_reads;
+        self.nr_uring_writes += rhs.nr_uring_writes;
+        self.nr_direct_reads += rhs.nr_direct_reads;
+        self.nr_direct_writes += rhs.nr_direct_writes;
+        self.nr_syncs += rhs.nr_syncs;
+        self.cached_time += rhs.cached_time;
+        self.uring_time += rhs.uring_time;
+        self.direct_read_time += rhs.direct_read_time;
+        self.direct_write_time += rhs.direct_write_time;
+        self.sync_time += rhs.sync_time;
+    }
+}
+
+/// A file chunk to be accessed through io_uring.
+pub struct UringChunk {
+    pub file: File,
+    pub off: u64,
+    pub size: usize,
+    pub is_write: bool,
+    pub sync: bool,
+}
+
+impl UringChunk {
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        off: u64,
+        size: usize,
+        is_write: bool,
+        sync: bool,
+    ) -> Result<Self> {
+        let file = OpenOptions::new().read(true).write(is_write).open(path)?;
+        let size = size.min((file.metadata()?.len() - off) as usize);
+        Ok(Self {
+            file,
+            off,
+            size,
+            is_write,
+            sync,
+        })
+    }
+}
+
+/// Take this thread's io_uring, creating a new one if there's none or the
+/// depth changed. Returns None if io_uring isn't available.
+fn take_uring(depth: u32) -> Option<IoUring> {
+    if URING_UNAVAILABLE.load(Ordering::Relaxed) {
+        return None;
+    }
+    if let Some(at) = URING_RETRY_AT.with(|retry_at| retry_at.get()) {
+        if Instant::now() < at {
+            return None;
+        }
+    }
+    match URING.with(|ring| ring.borrow_mut().take()) {
+        Some(ring) if ring.depth() == depth => Some(ring),
+        _ => match IoUring::new(depth) {
+            Ok(ring) => {
+                URING_RETRY_AT.with(|retry_at| retry_at.set(None));
+                Some(ring)
+            }
+            Err(e) if uring::is_unavailable(&e) => {
+                if !URING_UNAVAILABLE.swap(true, Ordering::Relaxed) {
+                    error!("io_uring is unavailable, falling back to sync ({:#})", &e);
+                }
+                None
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to set up io_uring, falling back to sync for {} ({:#})",
+                    format_duration(URING_RETRY_INTV.as_secs_f64()),
+                    &e
+                );
+                let at = Instant::now() + URING_RETRY_INTV;
+                URING_RETRY_AT.with(|retry_at| retry_at.set(Some(at)));
+                None
+            }
+        },
+    }
+}
+
+fn put_uring(ring: IoUring) {
+    URING.with(|slot| *slot.borrow_mut() = Some(ring));
+}
+
+/// Load files and run the work kernel on them.
 pub struct Hasher {
     buf: Vec<u8>,
     off: usize,
     cpu_ratio: f64,
     fake_cpu_load_time_per_byte: f64,
+    kernel: Box<dyn Kernel>,
+    dio_buf: Vec<u8>,
+    pub io: FileIoCounts,
 }
 
 impl Hasher {
-    pub fn new(cpu_ratio: f64, fake_cpu_load_time_per_byte: f64) -> Self {
+    pub fn new(cpu_ratio: f64, fake_cpu_load_time_per_byte: f64, kernel: WorkKernel) -> Self {
         Hasher {
             buf: vec![],
             off: 0,
             cpu_ratio,
             fake_cpu_load_time_per_byte,
+            kernel: new_kernel(kernel),
+            dio_buf: vec![],
+            io: Default::default(),
         }
     }
 
+    /// Load `input_size` bytes at `input_off`. If `direct`, the page cache
+    /// is bypassed with O_DIRECT. If `sync`, writes are followed by
+    /// fdatasync.
     pub fn load<P: AsRef<Path>>(
         &mut self,
         path: P,
         input_off: u64,
         mut input_size: usize,
         is_write: bool,
+        direct: bool,
+        sync: bool,
     ) -> Result<usize> {
         let mut opts = OpenOptions::new();
         opts.read(true);
         if is_write {
             opts.write(true);
         }
+        if direct {
+            opts.custom_flags(libc::O_DIRECT);
+        }
         let mut f = opts.open(path)?;
         input_size = input_size.min((f.metadata()?.len() - input_off) as usize);
 
+        if direct {
+            self.load_direct(&f, input_off, input_size, is_write)?;
+        } else {
+            let started_at = Instant::now();
+            self.load_cached(&mut f, input_off, input_size, is_write)?;
+            self.io.nr_cached_reads += 1;
+            self.io.nr_cached_writes += is_write as u64;
+            self.io.cached_time += started_at.elapsed().as_secs_f64();
+        }
+
+        if is_write && sync {
+            let started_at = Instant::now();
+            f.sync_data()?;
+            self.io.nr_syncs += 1;
+            self.io.sync_time += started_at.elapsed().as_secs_f64();
+        }
+        Ok(input_size)
+    }
+
+    fn load_cached(
+        &mut self,
+        f: &mut std::fs::File,
+        input_off: u64,
+        input_size: usize,
+        is_write: bool,
+    ) -> Result<()> {
         let len = self.off + input_size;
         self.buf.resize(len, 0);
 
@@ -85,7 +252,156 @@ impl Hasher {
         }
 
         self.off = len;
-        Ok(input_size)
+        Ok(())
+    }
+
+    /// Load `chunks` through `ring` keeping up to the ring depth in flight.
+    /// The write of a chunk is issued after its read completes. Returns the
+    /// loaded size of each chunk.
+    pub fn load_uring(&mut self, ring: &mut IoUring, chunks: &[UringChunk]) -> Result<Vec<usize>> {
+        let base = self.off;
+        let mut starts = vec![];
+        for chunk in chunks.iter() {
+            starts.push(self.off);
+            self.off += chunk.size;
+        }
+        self.buf.resize(self.off, 0);
+
+        // self.buf must not be reallocated until all IOs are complete.
+        let buf = &mut self.buf[base..];
+        let buf_ptr = buf.as_mut_ptr();
+        let mut sizes = vec![0; chunks.len()];
+        let mut issued_at = vec![Instant::now(); chunks.len()];
+        let mut writes = VecDeque::new();
+        let mut next_read = 0;
+        let mut nr_in_flight = 0;
+        let mut first_err = None;
+
+        loop {
+            while first_err.is_none() && nr_in_flight < ring.depth() {
+                let (idx, op, len) = match writes.pop_front() {
+                    Some(idx) => (idx, IORING_OP_WRITE, sizes[idx]),
+                    None if next_read < chunks.len() => {
+                        next_read += 1;
+                        (next_read - 1, IORING_OP_READ, chunks[next_read - 1].size)
+                    }
+                    None => break,
+                };
+                let chunk = &chunks[idx];
+                let user_data = (idx as u64) << 1 | (op == IORING_OP_WRITE) as u64;
+                // Safe as both the file and buffer outlive the IOs.
+                let pushed = unsafe {
+                    ring.push(
+                        op,
+                        chunk.file.as_raw_fd(),
+                        buf_ptr.add(starts[idx] - base),
+                        len as u32,
+                        chunk.off,
+                        user_data,
+                    )
+                };
+                if !pushed {
+                    first_err = Some(anyhow!("io_uring submission queue full"));
+                    break;
+                }
+                issued_at[idx] = Instant::now();
+                nr_in_flight += 1;
+            }
+            if nr_in_flight == 0 {
+                break;
+            }
+
+            if let Err(e) = ring.submit_and_wait(1) {
+                // The kernel may still be writing into the buffer. Leak it
+                // and let the caller discard the ring.
+                std::mem::forget(std::mem::take(&mut self.buf));
+                self.off = 0;
+                return Err(e);
+            }
+            while let Some((user_data, res)) = ring.pop() {
+                let (idx, is_write) = ((user_data >> 1) as usize, user_data & 1 == 1);
+                nr_in_flight -= 1;
+                self.io.uring_time += issued_at[idx].elapsed().as_secs_f64();
+                if res < 0 {
+                    first_err.get_or_insert(std::io::Error::from_raw_os_error(-res).into());
+                    continue;
+                }
+                if is_write {
+                    self.io.nr_uring_writes += 1;
+                    continue;
+                }
+
+                self.io.nr_uring_reads += 1;
+                sizes[idx] = res as usize;
+                if chunks[idx].is_write && res > 0 {
+                    let start = starts[idx] - base;
+                    let data = &mut buf[start..start + sizes[idx]];
+                    for off in (0..data.len().saturating_sub(7)).step_by(*PAGE_SIZE) {
+                        let val = u64::from_ne_bytes(data[off..off + 8].try_into().unwrap());
+                        data[off..off + 8].copy_from_slice(&val.wrapping_add(1).to_ne_bytes());
+                    }
+                    writes.push_back(idx);
+                }
+            }
+        }
+
+        if let Some(e) = first_err {
+            return Err(e);
+        }
+
+        for chunk in chunks.iter().filter(|c| c.is_write && c.sync) {
+            let started_at = Instant::now();
+            chunk.file.sync_data()?;
+            self.io.nr_syncs += 1;
+            self.io.sync_time += started_at.elapsed().as_secs_f64();
+        }
+        Ok(sizes)
+    }
+
+    /// O_DIRECT requires page aligned buffer, offset and size. The file
+    /// offsets are page aligned and the buffer is aligned by offsetting into
+    /// an oversized Vec. The read covers the whole padded buffer, so a write
+    /// of an unaligned size writes back the padding it read. The partial
+    /// page at the end of the file, if any, isn't written back as that would
+    /// extend the file.
+    fn load_direct(
+        &mut self,
+        f: &std::fs::File,
+        input_off: u64,
+        input_size: usize,
+        is_write: bool,
+    ) -> Result<()> {
+        let io_size = Integer::div_ceil(&input_size, &*PAGE_SIZE) * *PAGE_SIZE;
+        self.dio_buf.resize(io_size + *PAGE_SIZE, 0);
+        let pad = self.dio_buf.as_ptr().align_offset(*PAGE_SIZE);
+        let dbuf = &mut self.dio_buf[pad..pad + io_size];
+
+        let started_at = Instant::now();
+        let nr_read = f.read_at(dbuf, input_off)?;
+        if nr_read < input_size {
+            bail!("short O_DIRECT read ({}/{})", nr_read, input_size);
+        }
+        self.io.nr_direct_reads += 1;
+        self.io.direct_read_time += started_at.elapsed().as_secs_f64();
+
+        let write_size = nr_read.min(io_size) / *PAGE_SIZE * *PAGE_SIZE;
+        if is_write && write_size > 0 {
+            let started_at = Instant::now();
+            let wbuf = &mut dbuf[..write_size];
+            for off in (0..write_size).step_by(*PAGE_SIZE) {
+                let val = u64::from_ne_bytes(wbuf[off..off + 8].try_into().unwrap());
+                wbuf[off..off + 8].copy_from_slice(&val.wrapping_add(1).to_ne_bytes());
+            }
+            f.write_all_at(wbuf, input_off)?;
+            self.io.nr_direct_writes += 1;
+            self.io.direct_write_time += started_at.elapsed().as_secs_f64();
+        }
+
+        let len = self.off + input_size;
+        self.buf.resize(len, 0);
+        self.buf[self.off..len].copy_from_slice(&dbuf[..input_size]);
+        self.off = len;
+        Ok(())
     }
 
     pub fn append(&mut self, data: &[u8]) {
@@ -101,20 +417,19 @@ impl Hasher {
         }
     }
 
-    /// Calculates sha1 of self.buf * self.cpu_ratio.  Hasher exists
+    /// Runs the kernel on self.buf * self.cpu_ratio.  Hasher exists
     /// to waste cpu and io and self.cpu_ratio controls the ratio
     /// between cpu and io.
-    pub fn sha1(&mut self) -> Digest {
+    pub fn process(&mut self) -> String {
         if self.fake_cpu_load_time_per_byte > 0.0 {
-            // Sleep for the equivalent duration instead of actually calculating SHA1.
+            // Sleep for the equivalent duration instead of actually running the kernel.
             sleep(Duration::from_secs_f64(
                 self.buf.len() as f64 * self.cpu_ratio * self.fake_cpu_load_time_per_byte,
             ));
-            return Default::default();
+            return self.kernel.digest();
         }
 
         let mut repeat = self.cpu_ratio;
-        let mut hasher = Sha1::new();
         let mut nr_bytes = 0;
 
         while repeat > 0.01 {
@@ -126,13 +441,23 @@ impl Hasher {
                 repeat -= 1.0;
             }
             nr_bytes += self.buf.len();
-            hasher.update(&self.buf);
+            self.kernel.update(&self.buf);
         }
-        trace!("hashed {} bytes, cpu_ratio={}", nr_bytes, self.cpu_ratio);
-        hasher.digest()
+        trace!("processed {} bytes, cpu_ratio={}", nr_bytes, self.cpu_ratio);
+        self.kernel.digest()
     }
 }
 
+/// Rotate `page_idx` in `nr_pages` so that the page index 0 lands at
+/// `pos` fraction of the address space.
+fn shift_page_idx(page_idx: u64, nr_pages: u64, pos: f64) -> u64 {
+    if nr_pages == 0 {
+        return page_idx;
+    }
+    let shift = ((nr_pages as f64 * pos) as u64).min(nr_pages - 1);
+    (page_idx + shift) % nr_pages
+}
+
 /// Normal distribution with clamps. The portion of the distribution which is
 /// cut off by the clamps uniformly raise the distribution within the clamps
 /// such that it gradually transforms into uniform distribution as stdev
@@ -165,19 +490,69 @@ impl ClampedNormal {
     }
 }
 
+/// Reply channel of an RPC request, None if the request was dropped.
+pub type ReplyTx = Sender<Option<String>>;
+
 /// Commands from user to the dispatch thread.
 pub enum DispatchCmd {
     SetParams(Params),
     GetStat(Sender<Stat>),
     FillAnon,
+    Request(ReplyTx),
+}
+
+/// A request waiting for dispatch. `reply` is set for RPC requests.
+struct Arrival {
+    at: Instant,
+    reply: Option<ReplyTx>,
+}
+
+impl Arrival {
+    fn drop_request(self) {
+        if let Some(reply) = self.reply {
+            let _ = reply.send(None);
+        }
+    }
+}
+
+/// Interval to the next open loop arrival at `rps` which is floored at 1
+/// like in update_control().
+fn arrival_intv(dist: ArrivalDist, rps: f64, rng: &mut SmallRng) -> f64 {
+    let rps = rps.max(1.0);
+    match dist {
+        ArrivalDist::Poisson => Exp::new(rps).unwrap().sample(rng),
+        ArrivalDist::Uniform => 1.0 / rps,
+    }
+}
+
+/// Drop the arrivals which have been waiting for `timeout` or longer and
+/// return how many were dropped. 0 `timeout` disables.
+fn expire_arrivals(arrivals: &mut VecDeque<Arrival>, now: Instant, timeout: f64) -> u64 {
+    if timeout <= 0.0 {
+        return 0;
+    }
+    let timeout = Duration::from_secs_f64(timeout);
+    let mut nr_dropped = 0;
+    while let Some(arrival) = arrivals.front() {
+        if now.duration_since(arrival.at) < timeout {
+            break;
+        }
+        arrivals.pop_front().unwrap().drop_request();
+        nr_dropped += 1;
+    }
+    nr_dropped
 }
 
 /// Hasher worker thread's completion for the dispatch thread.
 struct HashCompletion {
-    digest: Digest,
+    digest: String,
     started_at: Instant,
+    class: Option<Arc<str>>,
+    reply: Option<ReplyTx>,
     file_dist: Vec<u64>,
     anon_dist: Vec<u64>,
+    file_io: FileIoCounts,
+    trace: Option<(u64, TraceRecord)>,
 }
 
 struct HasherThread {
@@ -191,6 +566,11 @@ struct HasherThread {
     file_addr_stdev_ratio: f64,
     file_addr_frac: f64,
     file_write_frac: f64,
+    file_direct_frac: f64,
+    file_sync_frac: f64,
+    file_engine: FileEngine,
+    io_uring_depth: u32,
+    hot_set_pos: f64,
 
     anon_area: Arc<RwLock<AnonArea>>,
     anon_nr_chunks: usize,
@@ -200,11 +580,17 @@ struct HasherThread {
 
     sleep_dur: f64,
     cpu_ratio: f64,
+    kernel: WorkKernel,
     fake_cpu_load_time_per_byte: f64,
 
     cmpl_tx: Sender<HashCompletion>,
 
     started_at: Instant,
+    class: Option<Arc<str>>,
+    reply: Option<ReplyTx>,
+    replay: Option<TraceRecord>,
+    // Dispatch sequence number if recording.
+    record: Option<u64>,
     file_dist_slots: usize,
     anon_dist_slots: usize,
 }
@@ -220,15 +606,7 @@ impl HasherThread {
         if rel.is_sign_negative() {
             pg_idx += 1;
         }
-        pg_idx.min(nr_pages - 1)
-    }
-
-    fn file_page_to_idx_off(&self, page: u64) -> (u64, u64) {
-        let pages_per_unit = self.tf.unit_size / *PAGE_SIZE as u64;
-        (
-            page / pages_per_unit,
-            (page % pages_per_unit) * *PAGE_SIZE as u64,
-        )
+        shift_page_idx(pg_idx.min(nr_pages - 1), nr_pages, self.hot_set_pos)
     }
 
     fn file_dist_count(file_dist: &mut [u64], page: u64, cnt: u64, tf: &TestFiles) {
@@ -253,81 +631,204 @@ impl HasherThread {
         anon_dist[slot] += cnt as u64;
     }
 
-    fn run(self) {
-        let mut rng = SmallRng::from_entropy();
+    /// Sample the file and anon accesses of this request.
+    fn sample_accesses(&self, rng: &mut SmallRng) -> TraceRecord {
+        let rw_uniform = Uniform::new_inclusive(0.0, 1.0);
+        let mut rec = TraceRecord {
+            chunk_pages: self.chunk_pages as u32,
+            sleep_dur: self.sleep_dur,
+            ..Default::default()
+        };
+
+        let file_addr_normal = ClampedNormal::new(0.0, self.file_addr_stdev_ratio, -1.0, 1.0);
+        for _ in 0..self.file_nr_chunks {
+            let rel = file_addr_normal.sample(rng) * self.file_addr_frac;
+            let is_write =
+                self.file_write_frac != 0.0 && rw_uniform.sample(rng) <= self.file_write_frac;
+            let direct =
+                self.file_direct_frac != 0.0 && rw_uniform.sample(rng) <= self.file_direct_frac;
+            let sync = is_write
+                && self.file_sync_frac != 0.0
+                && rw_uniform.sample(rng) <= self.file_sync_frac;
+            rec.file.push(FileAccess {
+                page: self.rel_to_file_page(rel),
+                is_write,
+                direct,
+                sync,
+            });
+        }
+
+        let anon_size = self.anon_area.read().unwrap().size();
+        let anon_addr_normal = ClampedNormal::new(0.0, self.anon_addr_stdev_ratio, -1.0, 1.0);
+        for _ in 0..self.anon_nr_chunks {
+            let rel = anon_addr_normal.sample(rng) * self.anon_addr_frac;
+            let base_size = anon_size - (self.chunk_pages - 1) * *PAGE_SIZE;
+            let page_base = shift_page_idx(
+                AnonArea::rel_to_page_idx(rel, base_size) as u64,
+                (base_size / *PAGE_SIZE) as u64,
+                self.hot_set_pos,
+            );
+            let is_write =
+                self.anon_write_frac != 0.0 && rw_uniform.sample(rng) <= self.anon_write_frac;
+            rec.anon.push(AnonAccess {
+                page: page_base,
+                is_write,
+            });
+        }
+        rec
+    }
+
+    fn run(mut self) {
+        let rec = match self.replay.take() {
+            Some(rec) => rec,
+            None => self.sample_accesses(&mut SmallRng::from_entropy()),
+        };
+        let chunk_pages = (rec.chunk_pages as usize).max(1);
 
         let mut file_dist = Vec::<u64>::new();
         let mut anon_dist = Vec::<u64>::new();
         file_dist.resize(self.file_dist_slots, 0);
         anon_dist.resize(self.anon_dist_slots, 0);
 
-        let rw_uniform = Uniform::new_inclusive(0.0, 1.0);
-
         // Load hash input files.
-        let file_addr_normal = ClampedNormal::new(0.0, self.file_addr_stdev_ratio, -1.0, 1.0);
-
         trace!("hasher::run(): cpu_ratio={:.2}", self.cpu_ratio);
-        let mut rdh = Hasher::new(self.cpu_ratio, self.fake_cpu_load_time_per_byte);
-        for _ in 0..self.file_nr_chunks {
-            let rel = file_addr_normal.sample(&mut rng) * self.file_addr_frac;
-            let page = self.rel_to_file_page(rel);
-            let (file_idx, file_off) = self.file_page_to_idx_off(page);
-            let path = self.tf.path(file_idx);
-            let is_write =
-                self.file_write_frac != 0.0 && rw_uniform.sample(&mut rng) <= self.file_write_frac;
-
-            match rdh.load(&path, file_off, *PAGE_SIZE * self.chunk_pages, is_write) {
-                Ok(size) => Self::file_dist_count(
-                    &mut file_dist,
-                    page,
-                    (size / *PAGE_SIZE) as u64,
-                    &self.tf,
+        let mut rdh = Hasher::new(self.cpu_ratio, self.fake_cpu_load_time_per_byte, self.kernel);
+        let mut ring = match self.file_engine {
+            FileEngine::IoUring if rec.file.len() > 0 => take_uring(self.io_uring_depth),
+            _ => None,
+        };
+        let mut uring_chunks = vec![];
+        let mut uring_pages = vec![];
+        let nr_file_pages = (self.tf.size / *PAGE_SIZE as u64).max(1);
+        for fa in rec.file.iter() {
+            // Replayed pages may be beyond the current testfiles.
+            let chunk_page = fa.page % nr_file_pages;
+
+            for (page, file_idx, file_off, size) in
+                self.tf.chunk_pieces(chunk_page, chunk_pages as u64)
+            {
+                let path = self.tf.path(file_idx);
+
+                // O_DIRECT accesses always take the sync path.
+                if ring.is_some() && !fa.direct {
+                    match UringChunk::open(&path, file_off, size, fa.is_write, fa.sync) {
+                        Ok(chunk) => {
+                            uring_chunks.push(chunk);
+                            uring_pages.push(page);
+                        }
+                        Err(e) => error!("Failed to open {:?}:{} ({:?})", &path, file_off, &e),
+                    }
+                    continue;
+                }
+
+                match rdh.load(&path, file_off, size, fa.is_write, fa.direct, fa.sync) {
+                    Ok(size) => Self::file_dist_count(
+                        &mut file_dist,
+                        page,
+                        (size / *PAGE_SIZE) as u64,
+                        &self.tf,
+                    ),
+                    Err(e) => error!("Failed to load {:?}:{} ({:?})", &path, file_off, &e),
+                }
+            }
+        }
+        if let Some(mut ring) = ring.take() {
+            match rdh.load_uring(&mut ring, &uring_chunks) {
+                Ok(sizes) => {
+                    for (page, size) in uring_pages.iter().zip(sizes.iter()) {
+                        Self::file_dist_count(
+                            &mut file_dist,
+                            *page,
+                            (size / *PAGE_SIZE) as u64,
+                            &self.tf,
+                        );
+                    }
+                    put_uring(ring);
+                }
+                // The ring is dropped and recreated on the next request.
+                Err(e) => error!(
+                    "Failed to load {} chunks with io_uring ({:?})",
+                    uring_chunks.len(),
+                    &e
                 ),
-                Err(e) => error!("Failed to load {:?}:{} ({:?})", &path, file_off, &e),
             }
         }
-        sleep(Duration::from_secs_f64(self.sleep_dur / 3.0));
+        sleep(Duration::from_secs_f64(rec.sleep_dur / 3.0));
 
         // Generate anonymous accesses.
         let aa = self.anon_area.read().unwrap();
-        let anon_addr_normal = ClampedNormal::new(0.0, self.anon_addr_stdev_ratio, -1.0, 1.0);
+        let nr_anon_pages = aa.size() / *PAGE_SIZE;
 
-        for _ in 0..self.anon_nr_chunks {
-            let rel = anon_addr_normal.sample(&mut rng) * self.anon_addr_frac;
-            let page_base =
-                AnonArea::rel_to_page_idx(rel, aa.size() - (self.chunk_pages - 1) * *PAGE_SIZE);
-            let is_write =
-                self.anon_write_frac != 0.0 && rw_uniform.sample(&mut rng) <= self.anon_write_frac;
+        for acc in rec.anon.iter().filter(|_| nr_anon_pages >= chunk_pages) {
+            // The anon area may have shrunk since the accesses were sampled.
+            let page_base = (acc.page as usize).min(nr_anon_pages - chunk_pages);
 
-            for page_idx in page_base..page_base + self.chunk_pages {
+            for page_idx in page_base..page_base + chunk_pages {
                 let page: &mut [u64] = aa.access_page(page_idx);
                 if page[0] == 0 {
                     aa.fill_page_with_random(page_idx);
                 }
-                if is_write {
+                if acc.is_write {
                     page[0] = page[0].wrapping_add(1).max(1);
                 }
                 rdh.append(aa.access_page(page_idx))
             }
-            Self::anon_dist_count(&mut anon_dist, page_base, self.chunk_pages, &aa);
+            Self::anon_dist_count(&mut anon_dist, page_base, chunk_pages, &aa);
         }
-        sleep(Duration::from_secs_f64(self.sleep_dur / 3.0));
+        sleep(Duration::from_secs_f64(rec.sleep_dur / 3.0));
 
-        // Calculate sha1 and signal completion.
-        let digest = rdh.sha1();
-        sleep(Duration::from_secs_f64(self.sleep_dur / 3.0));
+        // Run the work kernel and signal completion.
+        let digest = rdh.process();
+        let file_io = std::mem::take(&mut rdh.io);
+        sleep(Duration::from_secs_f64(rec.sleep_dur / 3.0));
 
         self.cmpl_tx
             .send(HashCompletion {
                 digest,
                 started_at: self.started_at,
+                class: self.class,
+                reply: self.reply,
                 file_dist,
                 anon_dist,
+                file_io,
+                trace: self.record.map(|seq| (seq, rec)),
             })
             .unwrap();
     }
 }
 
+/// Access patterns and latency stats of a request class.
+struct ClassState {
+    name: Arc<str>,
+    file_size_normal: ClampedNormal,
+    anon_size_normal: ClampedNormal,
+    sleep_normal: ClampedNormal,
+    file_write_frac: f64,
+    anon_write_frac: f64,
+
+    lat_min: f64,
+    lat_max: f64,
+    ckms: CKMS<f64>,
+    nr_done: u64,
+    last_nr_done: u64,
+    lat: Latencies,
+    rps: f64,
+}
+
+impl ClassState {
+    /// Take over the stats of `from` so that the cumulative counters don't
+    /// go backwards when the classes are rebuilt.
+    fn take_stats(&mut self, from: ClassState) {
+        self.lat_min = from.lat_min;
+        self.lat_max = from.lat_max;
+        self.ckms = from.ckms;
+        self.nr_done = from.nr_done;
+        self.last_nr_done = from.last_nr_done;
+        self.lat = from.lat;
+        self.rps = from.rps;
+    }
+}
+
 /// Dispatch thread which is started when Dispatch is created and
 /// keeps scheduling Hasher workers according to the params.
 struct DispatchThread {
@@ -337,6 +838,7 @@ struct DispatchThread {
     params: Params,
     params_at: Instant,
     logger: Option<Logger>,
+    trace: Option<Trace>,
     cmd_rx: Receiver<DispatchCmd>,
 
     wq: WorkQueue,
@@ -349,6 +851,10 @@ struct DispatchThread {
     anon_size_normal: ClampedNormal,
     sleep_normal: ClampedNormal,
 
+    // Request classes and the weighted picker, None if there are no classes.
+    classes: Vec<ClassState>,
+    class_pick: Option<WeightedIndex<f64>>,
+
     // Latency percentile calculation.
     lat_min: f64,
     lat_max: f64,
@@ -359,6 +865,21 @@ struct DispatchThread {
     lat_pid: Pid<f64>,
     rps_pid: Pid<f64>,
 
+    // Time-varying rps target.
+    profiler: LoadProfiler,
+    rps_target: f64,
+
+    // O_DIRECT and fdatasync stats, cumulative and in the current period.
+    file_io: FileIoStat,
+    file_io_period: FileIoCounts,
+
+    // Open loop and RPC arrivals waiting for dispatch. If serving RPC
+    // requests, no requests are generated internally.
+    serve: bool,
+    arrivals: VecDeque<Arrival>,
+    next_arrival_at: Instant,
+    nr_dropped: u64,
+
     // Runtime parameters.
     lat: Latencies,
     concurrency_max: f64,
@@ -371,6 +892,12 @@ struct DispatchThread {
     anon_addr_frac: f64,
     fake_cpu_load_time_per_byte: f64,
 
+    // Hot set center position in [0.0, 1.0) of the address spaces.
+    hot_set_pos: f64,
+    hot_set_at: Instant,
+    hot_set_jump_at: Instant,
+    nr_hot_set_jumps: u64,
+
     file_dist: Vec<u64>,
     anon_dist: Vec<u64>,
 }
@@ -378,6 +905,7 @@ struct DispatchThread {
 impl DispatchThread {
     const WQ_IDLE_TIMEOUT: f64 = 60.0;
     const CKMS_ERROR: f64 = 0.001;
+    const ARRIVALS_MAX: usize = 1 << 20;
 
     fn anon_total(max_size: u64, params: &Params) -> usize {
         (max_size as f64
@@ -434,6 +962,54 @@ impl DispatchThread {
         ClampedNormal::new(sleep_mean, sleep_stdev, 0.0, 2.0 * sleep_mean)
     }
 
+    /// Build the class states for `params`. The stats of the classes in
+    /// `prev` are carried over by name.
+    fn class_states(
+        params: &Params,
+        mut prev: Vec<ClassState>,
+    ) -> (Vec<ClassState>, Option<WeightedIndex<f64>>) {
+        if params.classes.len() == 0 {
+            return (vec![], None);
+        }
+
+        let pick = match WeightedIndex::new(params.classes.iter().map(|c| c.weight)) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Ignoring request classes ({})", &e);
+                return (vec![], None);
+            }
+        };
+
+        let classes = params
+            .classes
+            .iter()
+            .map(|class| {
+                let cp = params.class_params(class);
+                debug!("class {:?}: weight={}", &class.name, class.weight);
+                let mut cs = ClassState {
+                    name: Arc::from(class.name.as_str()),
+                    file_size_normal: Self::file_size_normal(&cp),
+                    anon_size_normal: Self::anon_size_normal(&cp),
+                    sleep_normal: Self::sleep_normal(&cp),
+                    file_write_frac: cp.file_write_frac,
+                    anon_write_frac: cp.anon_write_frac,
+                    lat_min: std::f64::MAX,
+                    lat_max: 0.0,
+                    ckms: CKMS::<f64>::new(Self::CKMS_ERROR),
+                    nr_done: 0,
+                    last_nr_done: 0,
+                    lat: Latencies::default(),
+                    rps: 0.0,
+                };
+                if let Some(pos) = prev.iter().position(|p| *p.name == *class.name) {
+                    cs.take_stats(prev.swap_remove(pos));
+                }
+                cs
+            })
+            .collect();
+        (classes, Some(pick))
+    }
+
     fn pid_controllers(params: &Params) -> (Pid<f64>, Pid<f64>) {
         let lat = &params.lat_pid;
         let rps = &params.rps_pid;
@@ -483,11 +1059,14 @@ impl DispatchThread {
         params: Params,
         anon_comp: f64,
         logger: Option<Logger>,
+        trace: Option<Trace>,
         cmd_rx: Receiver<DispatchCmd>,
+        serve: bool,
     ) -> Self {
         let (cmpl_tx, cmpl_rx) = channel::unbounded::<HashCompletion>();
         let (lat_pid, rps_pid) = Self::pid_controllers(&params);
         let anon_total = Self::anon_total(max_size, &params);
+        let (classes, class_pick) = Self::class_states(&params, vec![]);
         let now = Instant::now();
 
         let mut dt = Self {
@@ -495,6 +1074,7 @@ impl DispatchThread {
             params_at: now,
             cmd_rx,
             logger,
+            trace,
             wq: WorkQueue::new(Duration::from_secs_f64(Self::WQ_IDLE_TIMEOUT)),
 
             cmpl_tx,
@@ -503,6 +1083,8 @@ impl DispatchThread {
             anon_area: Arc::new(RwLock::new(AnonArea::new(anon_total, anon_comp))),
             anon_size_normal: Self::anon_size_normal(&params),
             sleep_normal: Self::sleep_normal(&params),
+            classes,
+            class_pick,
 
             lat_min: std::f64::MAX,
             lat_max: 0.0,
@@ -511,6 +1093,17 @@ impl DispatchThread {
             lat_pid,
             rps_pid,
 
+            profiler: LoadProfiler::new(&params.load_profile),
+            rps_target: (params.rps_target as f64).max(1.0),
+
+            file_io: Default::default(),
+            file_io_period: Default::default(),
+
+            serve,
+            arrivals: VecDeque::new(),
+            next_arrival_at: now,
+            nr_dropped: 0,
+
             lat: Latencies::default(),
             concurrency_max: params.concurrency_max as f64,
             concurrency: (nr_cpus() as f64 / 2.0).max(1.0),
@@ -522,6 +1115,11 @@ impl DispatchThread {
             anon_addr_frac: 1.0,
             fake_cpu_load_time_per_byte: 0.0,
 
+            hot_set_pos: 0.0,
+            hot_set_at: now,
+            hot_set_jump_at: now,
+            nr_hot_set_jumps: 0,
+
             file_dist: vec![],
             anon_dist: vec![],
 
@@ -557,6 +1155,10 @@ impl DispatchThread {
     fn update_params(&mut self, new_params: Params) {
         let old_anon_total = Self::anon_total(self.max_size, &self.params);
         let new_anon_total = Self::anon_total(self.max_size, &new_params);
+        if !self.serve && new_params.open_loop != self.params.open_loop {
+            self.arrivals.clear();
+            self.next_arrival_at = Instant::now();
+        }
         self.params = new_params;
         self.verify_params();
         let params = &self.params;
@@ -564,9 +1166,14 @@ impl DispatchThread {
         self.file_size_normal = Self::file_size_normal(params);
         self.anon_size_normal = Self::anon_size_normal(params);
         self.sleep_normal = Self::sleep_normal(params);
+        let (classes, class_pick) =
+            Self::class_states(params, std::mem::take(&mut self.classes));
+        self.classes = classes;
+        self.class_pick = class_pick;
         let (lp, rp) = Self::pid_controllers(params);
         self.lat_pid = lp;
         self.rps_pid = rp;
+        self.profiler.update(&params.load_profile);
 
         if new_anon_total != old_anon_total {
             let mut aa = self.anon_area.write().unwrap();
@@ -581,53 +1188,168 @@ impl DispatchThread {
         self.params_updated();
     }
 
+    fn launch_hasher(&mut self, rng: &mut SmallRng, started_at: Instant, reply: Option<ReplyTx>) {
+        let chunk_size = *PAGE_SIZE * self.params.chunk_pages;
+
+        // When replaying, the accesses come from the trace and aren't
+        // attributed to classes.
+        let replay = self.next_replay();
+
+        // Pick the request class which determines the access sizes and
+        // write ratios.
+        let class = match replay {
+            Some(_) => None,
+            None => self.class_pick.as_ref().map(|pick| pick.sample(rng)),
+        };
+        let (file_size_normal, anon_size_normal, sleep_normal, file_write_frac, anon_write_frac) =
+            match class {
+                Some(idx) => {
+                    let cs = &self.classes[idx];
+                    (
+                        &cs.file_size_normal,
+                        &cs.anon_size_normal,
+                        &cs.sleep_normal,
+                        cs.file_write_frac,
+                        cs.anon_write_frac,
+                    )
+                }
+                None => (
+                    &self.file_size_normal,
+                    &self.anon_size_normal,
+                    &self.sleep_normal,
+                    self.params.file_write_frac,
+                    self.params.anon_write_frac,
+                ),
+            };
+
+        // Determine file and anon access chunk counts. Indices are
+        // determined by each hash worker to avoid overloading the
+        // dispatch thread.
+        let file_size = file_size_normal.sample(rng).round() as usize;
+        let file_nr_chunks = Integer::div_ceil(&file_size, &chunk_size).max(1);
+        let anon_size = anon_size_normal.sample(rng).round() as usize;
+        let anon_nr_chunks = Integer::div_ceil(&anon_size, &chunk_size);
+        let sleep_dur = sleep_normal.sample(rng);
+
+        let hasher_thread = HasherThread {
+            tf: self.tf.clone(),
+            mem_frac: self.params.mem_frac,
+            chunk_pages: self.params.chunk_pages,
+
+            file_max_frac: self.tf.size as f64 / self.max_size as f64,
+            file_frac: self.params.file_frac,
+            file_nr_chunks,
+            file_addr_stdev_ratio: self.params.file_addr_stdev_ratio,
+            file_addr_frac: self.file_addr_frac,
+            file_write_frac,
+            file_direct_frac: self.params.file_direct_frac,
+            file_sync_frac: self.params.file_sync_frac,
+            file_engine: self.params.file_engine,
+            io_uring_depth: self.params.io_uring_depth,
+            hot_set_pos: self.hot_set_pos,
+
+            anon_area: self.anon_area.clone(),
+            anon_nr_chunks,
+            anon_addr_stdev_ratio: self.params.anon_addr_stdev_ratio,
+            anon_addr_frac: self.anon_addr_frac,
+            anon_write_frac,
+
+            sleep_dur,
+            cpu_ratio: self.params.cpu_ratio,
+            kernel: self.params.kernel,
+            fake_cpu_load_time_per_byte: self.fake_cpu_load_time_per_byte,
+
+            cmpl_tx: self.cmpl_tx.clone(),
+
+            started_at,
+            class: class.map(|idx| self.classes[idx].name.clone()),
+            reply,
+            replay,
+            record: match self.trace.as_mut() {
+                Some(Trace::Record(writer)) => Some(writer.next_seq()),
+                _ => None,
+            },
+            file_dist_slots: self.file_dist.len(),
+            anon_dist_slots: self.anon_dist.len(),
+        };
+
+        self.wq.queue(move || hasher_thread.run());
+
+        self.nr_in_flight += 1;
+    }
+
+    fn next_replay(&mut self) -> Option<TraceRecord> {
+        let reader = match self.trace.as_mut() {
+            Some(Trace::Replay(reader)) => reader,
+            _ => return None,
+        };
+        let nr_wraps = reader.nr_wraps;
+        match reader.next() {
+            Ok(rec) => {
+                if reader.nr_wraps != nr_wraps {
+                    info!("Trace replay wrapped around ({})", reader.nr_wraps);
+                }
+                Some(rec)
+            }
+            Err(e) => {
+                error!("Failed to read trace, stopping replay ({:#})", &e);
+                self.trace = None;
+                None
+            }
+        }
+    }
+
+    fn record_trace(&mut self, seq: u64, rec: TraceRecord) {
+        if let Some(Trace::Record(writer)) = self.trace.as_mut() {
+            if let Err(e) = writer.write(seq, rec) {
+                error!("Failed to write trace, stopping recording ({:#})", &e);
+                self.trace = None;
+            }
+        }
+    }
+
     fn launch_hashers(&mut self) {
         // Fire off hash workers to fill up the target concurrency.
         let mut rng = SmallRng::from_entropy();
 
         while self.nr_in_flight < self.concurrency as u32 {
-            let chunk_size = *PAGE_SIZE * self.params.chunk_pages;
-
-            // Determine file and anon access chunk counts. Indices are
-            // determined by each hash worker to avoid overloading the
-            // dispatch thread.
-            let file_size = self.file_size_normal.sample(&mut rng).round() as usize;
-            let file_nr_chunks = Integer::div_ceil(&file_size, &chunk_size).max(1);
-            let anon_size = self.anon_size_normal.sample(&mut rng).round() as usize;
-            let anon_nr_chunks = Integer::div_ceil(&anon_size, &chunk_size);
-
-            let hasher_thread = HasherThread {
-                tf: self.tf.clone(),
-                mem_frac: self.params.mem_frac,
-                chunk_pages: self.params.chunk_pages,
-
-                file_max_frac: self.tf.size as f64 / self.max_size as f64,
-                file_frac: self.params.file_frac,
-                file_nr_chunks,
-                file_addr_stdev_ratio: self.params.file_addr_stdev_ratio,
-                file_addr_frac: self.file_addr_frac,
-                file_write_frac: self.params.file_write_frac,
-
-                anon_area: self.anon_area.clone(),
-                anon_nr_chunks,
-                anon_addr_stdev_ratio: self.params.anon_addr_stdev_ratio,
-                anon_addr_frac: self.anon_addr_frac,
-                anon_write_frac: self.params.anon_write_frac,
-
-                sleep_dur: self.sleep_normal.sample(&mut rng),
-                cpu_ratio: self.params.cpu_ratio,
-                fake_cpu_load_time_per_byte: self.fake_cpu_load_time_per_byte,
-
-                cmpl_tx: self.cmpl_tx.clone(),
-
-                started_at: Instant::now(),
-                file_dist_slots: self.file_dist.len(),
-                anon_dist_slots: self.anon_dist.len(),
-            };
+            self.launch_hasher(&mut rng, Instant::now(), None);
+        }
+    }
+
+    fn queue_arrival(&mut self, arrival: Arrival) {
+        if self.arrivals.len() < Self::ARRIVALS_MAX {
+            self.arrivals.push_back(arrival);
+        } else {
+            arrival.drop_request();
+            self.nr_dropped += 1;
+        }
+    }
+
+    /// Queue the requests which arrived at the rps target since the last
+    /// invocation, drop the ones which timed out and dispatch as many as
+    /// concurrency_max allows. Latencies are measured from arrivals. If
+    /// serving RPC requests, only the queued ones are dispatched.
+    fn dispatch_arrivals(&mut self) {
+        let mut rng = SmallRng::from_entropy();
+        let now = Instant::now();
 
-            self.wq.queue(move || hasher_thread.run());
+        while !self.serve && self.next_arrival_at <= now {
+            self.queue_arrival(Arrival {
+                at: self.next_arrival_at,
+                reply: None,
+            });
+            let intv = arrival_intv(self.params.arrival_dist, self.rps_target, &mut rng);
+            self.next_arrival_at += Duration::from_secs_f64(intv);
+        }
+
+        self.nr_dropped += expire_arrivals(&mut self.arrivals, now, self.params.queue_timeout);
 
-            self.nr_in_flight += 1;
+        while self.nr_in_flight < self.params.concurrency_max {
+            match self.arrivals.pop_front() {
+                Some(arrival) => self.launch_hasher(&mut rng, arrival.at, arrival.reply),
+                None => break,
+            }
         }
     }
 
@@ -637,6 +1359,33 @@ impl DispatchThread {
         self.ckms_at = now;
         self.ckms = CKMS::<f64>::new(Self::CKMS_ERROR);
         self.last_nr_done = self.nr_done;
+        self.file_io_period = Default::default();
+        for cs in self.classes.iter_mut() {
+            cs.lat_min = std::f64::MAX;
+            cs.lat_max = 0.0;
+            cs.ckms = CKMS::<f64>::new(Self::CKMS_ERROR);
+            cs.last_nr_done = cs.nr_done;
+        }
+    }
+
+    fn ckms_lat(ckms: &CKMS<f64>, min: f64, max: f64, ctl_pct: f64) -> Latencies {
+        Latencies {
+            min,
+            p01: ckms.query(0.01).unwrap().1,
+            p05: ckms.query(0.05).unwrap().1,
+            p10: ckms.query(0.10).unwrap().1,
+            p16: ckms.query(0.16).unwrap().1,
+            p50: ckms.query(0.50).unwrap().1,
+            p84: ckms.query(0.84).unwrap().1,
+            p90: ckms.query(0.90).unwrap().1,
+            p95: ckms.query(0.95).unwrap().1,
+            p99: ckms.query(0.99).unwrap().1,
+            p99_9: ckms.query(0.999).unwrap().1,
+            p99_99: ckms.query(0.9999).unwrap().1,
+            p99_999: ckms.query(0.99999).unwrap().1,
+            max,
+            ctl: ckms.query(ctl_pct).unwrap().1,
+        }
     }
 
     fn refresh_lat_rps(&mut self, now: Instant) -> bool {
@@ -645,22 +1394,26 @@ impl DispatchThread {
             return false;
         }
 
+        let ctl_pct = self.params.lat_target_pct;
+        for cs in self.classes.iter_mut() {
+            cs.lat = match cs.nr_done > cs.last_nr_done {
+                true => Self::ckms_lat(&cs.ckms, cs.lat_min, cs.lat_max, ctl_pct),
+                false => Default::default(),
+            };
+            cs.rps = (cs.nr_done - cs.last_nr_done) as f64 / dur.as_secs_f64();
+        }
+
         if self.nr_done > self.last_nr_done {
-            self.lat.min = self.lat_min;
-            self.lat.p01 = self.ckms.query(0.01).unwrap().1;
-            self.lat.p05 = self.ckms.query(0.05).unwrap().1;
-            self.lat.p10 = self.ckms.query(0.10).unwrap().1;
-            self.lat.p16 = self.ckms.query(0.16).unwrap().1;
-            self.lat.p50 = self.ckms.query(0.50).unwrap().1;
-            self.lat.p84 = self.ckms.query(0.84).unwrap().1;
-            self.lat.p90 = self.ckms.query(0.90).unwrap().1;
-            self.lat.p95 = self.ckms.query(0.95).unwrap().1;
-            self.lat.p99 = self.ckms.query(0.99).unwrap().1;
-            self.lat.p99_9 = self.ckms.query(0.999).unwrap().1;
-            self.lat.p99_99 = self.ckms.query(0.9999).unwrap().1;
-            self.lat.p99_999 = self.ckms.query(0.99999).unwrap().1;
-            self.lat.max = self.lat_max;
-            self.lat.ctl = self.ckms.query(self.params.lat_target_pct).unwrap().1;
+            self.lat = Self::ckms_lat(&self.ckms, self.lat_min, self.lat_max, ctl_pct);
+
+            // Control the latency of the target class if it has completions.
+            if let Some(name) = self.params.lat_target_class.as_ref() {
+                if let Some(cs) = self.classes.iter().find(|cs| *cs.name == **name) {
+                    if cs.nr_done > cs.last_nr_done {
+                        self.lat.ctl = cs.lat.ctl;
+                    }
+                }
+            }
         } else {
             self.lat = Default::default();
             if self.nr_in_flight > 0 {
@@ -677,6 +1430,29 @@ impl DispatchThread {
         }
         self.rps = (self.nr_done - self.last_nr_done) as f64 / dur.as_secs_f64();
 
+        let fiop = &self.file_io_period;
+        self.file_io.direct_read_lat = match fiop.nr_direct_reads {
+            0 => 0.0,
+            nr => fiop.direct_read_time / nr as f64,
+        };
+        self.file_io.direct_write_lat = match fiop.nr_direct_writes {
+            0 => 0.0,
+            nr => fiop.direct_write_time / nr as f64,
+        };
+        self.file_io.sync_lat = match fiop.nr_syncs {
+            0 => 0.0,
+            nr => fiop.sync_time / nr as f64,
+        };
+        self.file_io.cached_lat = match fiop.nr_cached_reads {
+            0 => 0.0,
+            nr => fiop.cached_time / nr as f64,
+        };
+        let nr_uring = fiop.nr_uring_reads + fiop.nr_uring_writes;
+        self.file_io.uring_lat = match nr_uring {
+            0 => 0.0,
+            nr => fiop.uring_time / nr as f64,
+        };
+
         self.reset_lat_rps(now);
         true
     }
@@ -684,7 +1460,7 @@ impl DispatchThread {
     /// Two pid controllers work in conjunction to determine the concurrency
     /// level. The latency one caps the max concurrency to keep latency within
     /// the target. The rps one tries to converge on the target rps.
-    fn update_control(&mut self) {
+    fn update_concurrency(&mut self) {
         let out = self
             .lat_pid
             .next_control_output(self.lat.ctl / self.params.lat_target);
@@ -702,7 +1478,7 @@ impl DispatchThread {
 
         let adj = self
             .rps_pid
-            .next_control_output(self.rps / self.params.rps_target as f64)
+            .next_control_output(self.rps / self.rps_target)
             .output;
         self.concurrency = (self.concurrency * (1.0 + adj)).max(1.0);
 
@@ -722,6 +1498,34 @@ impl DispatchThread {
         if out.i.is_sign_negative() && (self.lat.ctl <= self.params.lat_target) {
             self.lat_pid.reset_integral_term();
         }
+    }
+
+    fn update_hot_set(&mut self, now: Instant) {
+        let dur = now.duration_since(self.hot_set_at).as_secs_f64();
+        self.hot_set_at = now;
+        self.hot_set_pos = (self.hot_set_pos + self.params.hot_set_drift * dur).rem_euclid(1.0);
+
+        let intv = self.params.hot_set_jump_intv;
+        if intv > 0.0 && now.duration_since(self.hot_set_jump_at).as_secs_f64() >= intv {
+            let from = self.hot_set_pos;
+            self.hot_set_pos = SmallRng::from_entropy().gen_range(0.0..1.0);
+            self.hot_set_jump_at = now;
+            self.nr_hot_set_jumps += 1;
+            info!("Hot set jumped from {:.3} to {:.3}", from, self.hot_set_pos);
+        }
+    }
+
+    fn update_control(&mut self) {
+        self.rps_target =
+            (self.params.rps_target as f64 * self.profiler.frac_at(Instant::now())).max(1.0);
+
+        if self.params.open_loop || self.serve {
+            // Requests are dispatched on arrival, concurrency is only reported.
+            self.concurrency_max = self.params.concurrency_max as f64;
+            self.concurrency = self.nr_in_flight as f64;
+        } else {
+            self.update_concurrency();
+        }
 
         let rps_max = self.params.rps_max as f64;
         let file_base = self.params.file_addr_rps_base_frac;
@@ -730,8 +1534,8 @@ impl DispatchThread {
         self.anon_addr_frac = (anon_base + (1.0 - anon_base) * (self.rps / rps_max)).min(1.0);
 
         debug!(
-            "p50={:.1} p84={:.1} p90={:.1} p95={:.1} p99={:.1} ctl={:.1} rps={:.1} con={:.1}/{:.1} \
-             ffrac={:.2} aafrac={:.2}",
+            "p50={:.1} p84={:.1} p90={:.1} p95={:.1} p99={:.1} ctl={:.1} rps={:.1}/{:.1} \
+             con={:.1}/{:.1} ffrac={:.2} aafrac={:.2}",
             self.lat.p50 * TO_MSEC,
             self.lat.p84 * TO_MSEC,
             self.lat.p90 * TO_MSEC,
@@ -739,18 +1543,38 @@ impl DispatchThread {
             self.lat.p99 * TO_MSEC,
             self.lat.ctl * TO_MSEC,
             self.rps,
+            self.rps_target,
             self.concurrency,
             self.concurrency_max,
             self.file_addr_frac,
             self.anon_addr_frac,
         );
+
+        self.update_hot_set(Instant::now());
+
+        if let Some(Trace::Record(writer)) = self.trace.as_mut() {
+            if let Err(e) = writer.flush() {
+                warn!("Failed to flush trace ({:#})", &e);
+            }
+        }
     }
 
     pub fn run(&mut self) {
         self.params_updated();
         loop {
-            // Launch hashers to fill target concurrency.
-            self.launch_hashers();
+            // Launch hashers to fill target concurrency or, in open loop
+            // mode, dispatch the arrived requests and wake up for the next.
+            // RPC requests wake us up but keep refreshing stats.
+            let timeout = if self.serve {
+                self.dispatch_arrivals();
+                Duration::from_secs_f64(self.params.control_period)
+            } else if self.params.open_loop {
+                self.dispatch_arrivals();
+                self.next_arrival_at.saturating_duration_since(Instant::now())
+            } else {
+                self.launch_hashers();
+                Duration::MAX
+            };
 
             // Handle user commands and hasher completions.
             select! {
@@ -767,12 +1591,23 @@ impl DispatchThread {
 
                             ch.send(Stat { lat: self.lat.clone(),
                                            rps: self.rps,
+                                           rps_target: self.rps_target,
                                            concurrency: self.concurrency,
                                            concurrency_max: self.concurrency_max,
                                            file_addr_frac: self.file_addr_frac,
                                            anon_addr_frac: self.anon_addr_frac,
+                                           hot_set_pos: self.hot_set_pos,
+                                           nr_hot_set_jumps: self.nr_hot_set_jumps,
+                                           classes: self.classes.iter().map(|cs| {
+                                               (cs.name.to_string(), ClassStat { rps: cs.rps,
+                                                                             nr_done: cs.nr_done,
+                                                                             lat: cs.lat.clone() })
+                                           }).collect(),
+                                           file_io: self.file_io.clone(),
                                            nr_in_flight: self.nr_in_flight,
                                            nr_done: self.nr_done,
+                                           queue_depth: self.arrivals.len() as u32,
+                                           nr_dropped: self.nr_dropped,
                                            nr_workers: self.wq.nr_workers(),
                                            nr_idle_workers: self.wq.nr_idle_workers(),
                                            file_size: self.tf.size,
@@ -782,6 +1617,9 @@ impl DispatchThread {
                             })
                                 .unwrap();
                         }
+                        Ok(DispatchCmd::Request(reply)) => {
+                            self.queue_arrival(Arrival { at: Instant::now(), reply: Some(reply) });
+                        }
                         Ok(DispatchCmd::FillAnon) => {
                             let aa = self.anon_area.read().unwrap();
                             for i in 0 .. aa.size() / *PAGE_SIZE {
@@ -796,17 +1634,42 @@ impl DispatchThread {
                 },
                 recv(self.cmpl_rx) -> cmpl => {
                     match cmpl {
-                        Ok(HashCompletion {digest, started_at, file_dist, anon_dist}) => {
+                        Ok(HashCompletion {digest, started_at, class, reply, file_dist,
+                                           anon_dist, file_io, trace}) => {
                             self.nr_in_flight -= 1;
                             self.nr_done += 1;
                             let dur = Instant::now().duration_since(started_at).as_secs_f64();
                             self.lat_min = self.lat_min.min(dur);
                             self.lat_max = self.lat_max.max(dur);
                             self.ckms.insert(dur);
+                            // Classes may have been rebuilt since the launch.
+                            // Look up by name.
+                            if let Some(cs) = class.and_then(|name| {
+                                self.classes.iter_mut().find(|cs| cs.name == name)
+                            }) {
+                                cs.nr_done += 1;
+                                cs.lat_min = cs.lat_min.min(dur);
+                                cs.lat_max = cs.lat_max.max(dur);
+                                cs.ckms.insert(dur);
+                            }
+                            self.file_io.nr_cached_reads += file_io.nr_cached_reads;
+                            self.file_io.nr_cached_writes += file_io.nr_cached_writes;
+                            self.file_io.nr_uring_reads += file_io.nr_uring_reads;
+                            self.file_io.nr_uring_writes += file_io.nr_uring_writes;
+                            self.file_io.nr_direct_reads += file_io.nr_direct_reads;
+                            self.file_io.nr_direct_writes += file_io.nr_direct_writes;
+                            self.file_io.nr_syncs += file_io.nr_syncs;
+                            self.file_io_period.add(&file_io);
                             if let Some(logger) = self.logger.as_mut() {
                                 logger.log(&format!("{} {:.2}ms",
                                                     digest, dur * TO_MSEC));
                             }
+                            if let Some(reply) = reply {
+                                let _ = reply.send(Some(digest));
+                            }
+                            if let Some((seq, rec)) = trace {
+                                self.record_trace(seq, rec);
+                            }
                             if file_dist.len() == self.file_dist.len() {
                                 for i in 0..file_dist.len() {
                                     self.file_dist[i] += file_dist[i];
@@ -823,7 +1686,8 @@ impl DispatchThread {
                             return;
                         }
                     }
-                }
+                },
+                default(timeout) => {}
             }
 
             // Refresh stat and update control parameters.  Params
@@ -858,11 +1722,22 @@ impl Dispatch {
         params: &Params,
         anon_comp: f64,
         logger: Option<Logger>,
+        trace: Option<Trace>,
+        serve: bool,
     ) -> Self {
         let params_copy = params.clone();
         let (cmd_tx, cmd_rx) = channel::unbounded();
         let dispatch_jh = Option::Some(spawn(move || {
-            let mut dt = DispatchThread::new(max_size, tf, params_copy, anon_comp, logger, cmd_rx);
+            let mut dt = DispatchThread::new(
+                max_size,
+                tf,
+                params_copy,
+                anon_comp,
+                logger,
+                trace,
+                cmd_rx,
+                serve,
+            );
             dt.run();
         }));
         let (stat_tx, stat_rx) = channel::unbounded();
@@ -892,6 +1767,11 @@ impl Dispatch {
         self.stat_rx.recv().unwrap()
     }
 
+    /// Channel to submit RPC requests with `DispatchCmd::Request`.
+    pub fn request_tx(&self) -> Sender<DispatchCmd> {
+        self.cmd_tx.as_ref().unwrap().clone()
+    }
+
     pub fn fill_anon(&self) {
         self.cmd_tx
             .as_ref()
@@ -915,6 +1795,10 @@ mod tests {
     use quantiles::ckms::CKMS;
     use rand::rngs::SmallRng;
     use rand::SeedableRng;
+    use rd_hashd_intf::{ArrivalDist, Params, ReqClass, WorkKernel};
+    use std::collections::VecDeque;
+    use std::convert::TryInto;
+    use std::time::{Duration, Instant};
 
     const CKMS_ERROR: f64 = 0.001;
 
@@ -964,4 +1848,143 @@ mod tests {
         assert!(p50 >= -0.1 && p50 <= 0.1);
         assert!(p75 >= 0.4 && p75 <= 0.6);
     }
+
+    #[test]
+    fn test_shift_page_idx() {
+        assert_eq!(super::shift_page_idx(0, 100, 0.0), 0);
+        assert_eq!(super::shift_page_idx(0, 100, 0.25), 25);
+        assert_eq!(super::shift_page_idx(80, 100, 0.25), 5);
+        assert_eq!(super::shift_page_idx(99, 100, 0.999), 98);
+        assert_eq!(super::shift_page_idx(0, 0, 0.5), 0);
+    }
+
+    #[test]
+    fn test_arrival_intv() {
+        let mut rng = SmallRng::from_entropy();
+
+        assert_eq!(
+            super::arrival_intv(ArrivalDist::Uniform, 100.0, &mut rng),
+            0.01
+        );
+
+        // Poisson arrivals should average out to the rps.
+        let nr = 16384;
+        let mut sum = 0.0;
+        for _ in 0..nr {
+            let intv = super::arrival_intv(ArrivalDist::Poisson, 100.0, &mut rng);
+            assert!(intv >= 0.0);
+            sum += intv;
+        }
+        let mean = sum / nr as f64;
+        println!("poisson mean={:.5}", mean);
+        assert!((0.0095..=0.0105).contains(&mean));
+    }
+
+    #[test]
+    fn test_arrival_intv_zero_rps() {
+        let mut rng = SmallRng::from_entropy();
+
+        // 0, negative and NaN rps are treated as 1 instead of producing an
+        // infinite interval which can't be converted to Duration.
+        for rps in &[0.0, -1.0, f64::NAN] {
+            assert_eq!(
+                super::arrival_intv(ArrivalDist::Uniform, *rps, &mut rng),
+                1.0
+            );
+            for _ in 0..1024 {
+                let intv = super::arrival_intv(ArrivalDist::Poisson, *rps, &mut rng);
+                assert!(intv.is_finite() && intv >= 0.0);
+                let _ = Duration::from_secs_f64(intv);
+            }
+        }
+    }
+
+    #[test]
+    fn test_expire_arrivals() {
+        let now = Instant::now();
+        let mut arrivals: VecDeque<super::Arrival> = [3.0, 2.0, 1.0, 0.5]
+            .iter()
+            .map(|ago| super::Arrival {
+                at: now - Duration::from_secs_f64(*ago),
+                reply: None,
+            })
+            .collect();
+
+        assert_eq!(super::expire_arrivals(&mut arrivals, now, 0.0), 0);
+        assert_eq!(arrivals.len(), 4);
+        assert_eq!(super::expire_arrivals(&mut arrivals, now, 1.0), 3);
+        assert_eq!(arrivals.len(), 1);
+        assert_eq!(super::expire_arrivals(&mut arrivals, now, 1.0), 0);
+        assert_eq!(super::expire_arrivals(&mut arrivals, now, 0.1), 1);
+        assert!(arrivals.is_empty());
+    }
+
+    #[test]
+    fn test_class_states_reload() {
+        let class = |name: &str| ReqClass {
+            name: name.into(),
+            ..Default::default()
+        };
+        let mut params = Params {
+            classes: vec![class("a"), class("b")],
+            ..Default::default()
+        };
+
+        let (mut classes, _) = super::DispatchThread::class_states(&params, vec![]);
+        classes[0].nr_done = 5;
+        classes[1].nr_done = 3;
+
+        // Reordered, removed and added classes should keep the counters of
+        // the matching names.
+        params.classes = vec![class("c"), class("a")];
+        let (classes, pick) = super::DispatchThread::class_states(&params, classes);
+        assert!(pick.is_some());
+        let names: Vec<&str> = classes.iter().map(|cs| &*cs.name).collect();
+        assert_eq!(names, vec!["c", "a"]);
+        assert_eq!(classes[0].nr_done, 0);
+        assert_eq!(classes[1].nr_done, 5);
+    }
+
+    #[test]
+    fn test_load_direct_unaligned_write() {
+        let page_size = *rd_util::PAGE_SIZE;
+        let path =
+            std::env::temp_dir().join(format!("rd-hashd-test-direct-{}", std::process::id()));
+        let file_size = 2 * page_size + 100;
+        let orig: Vec<u8> = (0..file_size).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&path, &orig).unwrap();
+
+        let mut hasher = super::Hasher::new(1.0, 0.0, WorkKernel::default());
+        let res = hasher.load(&path, 0, page_size + 100, true, true, false);
+        if let Err(e) = res.as_ref() {
+            // O_DIRECT isn't supported on all filesystems.
+            let _ = std::fs::remove_file(&path);
+            println!("Skipping, O_DIRECT failed ({:#})", e);
+            return;
+        }
+        assert_eq!(res.unwrap(), page_size + 100);
+        assert_eq!(hasher.io.nr_direct_reads, 1);
+        assert_eq!(hasher.io.nr_direct_writes, 1);
+
+        // At the end of the file, only the full page is written back.
+        let res = hasher.load(&path, page_size as u64, 2 * page_size, true, true, false);
+        assert_eq!(res.unwrap(), page_size + 100);
+        assert_eq!(hasher.io.nr_direct_reads, 2);
+        assert_eq!(hasher.io.nr_direct_writes, 2);
+
+        let data = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(data.len(), file_size);
+
+        // The page heads should have been bumped once per write and the
+        // rest including the padding and the trailing partial page intact.
+        let bumped = |off: usize, by: u64| {
+            let val = u64::from_ne_bytes(orig[off..off + 8].try_into().unwrap());
+            val.wrapping_add(by).to_ne_bytes()
+        };
+        assert_eq!(data[..8], bumped(0, 1));
+        assert_eq!(data[8..page_size], orig[8..page_size]);
+        assert_eq!(data[page_size..page_size + 8], bumped(page_size, 2));
+        assert_eq!(data[page_size + 8..], orig[page_size + 8..]);
+    }
 }
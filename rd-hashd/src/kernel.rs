@@ -0,0 +1,143 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+use sha1_smol::Sha1;
+use std::convert::TryInto;
+use std::io::Write;
+
+use rd_hashd_intf::WorkKernel;
+
+/// CPU work performed on the bytes loaded by a hasher worker. `update()` is
+/// called once for each pass over the buffer and `digest()` returns the
+/// summary which is recorded in the log.
+pub trait Kernel {
+    fn update(&mut self, buf: &[u8]);
+    fn digest(&mut self) -> String;
+}
+
+pub fn new_kernel(kernel: WorkKernel) -> Box<dyn Kernel> {
+    match kernel {
+        WorkKernel::Sha1 => Box::new(Sha1Kernel(Sha1::new())),
+        WorkKernel::Deflate => Box::new(DeflateKernel::default()),
+        WorkKernel::PointerChase => Box::new(PointerChaseKernel::default()),
+        WorkKernel::MemStream => Box::new(MemStreamKernel::default()),
+    }
+}
+
+fn word_at(buf: &[u8], idx: usize) -> u64 {
+    u64::from_ne_bytes(buf[idx * 8..idx * 8 + 8].try_into().unwrap())
+}
+
+struct Sha1Kernel(Sha1);
+
+impl Kernel for Sha1Kernel {
+    fn update(&mut self, buf: &[u8]) {
+        self.0.update(buf);
+    }
+
+    fn digest(&mut self) -> String {
+        self.0.digest().to_string()
+    }
+}
+
+/// Deflate compresses the buffer. Compression ratio depends on the
+/// compressibility of testfiles and anon area.
+#[derive(Default)]
+struct DeflateKernel {
+    in_bytes: u64,
+    out_bytes: u64,
+}
+
+impl Kernel for DeflateKernel {
+    fn update(&mut self, buf: &[u8]) {
+        let mut enc = libflate::deflate::Encoder::new(Vec::new());
+        enc.write_all(buf).unwrap();
+        let out = enc.finish().into_result().unwrap();
+        self.in_bytes += buf.len() as u64;
+        self.out_bytes += out.len() as u64;
+    }
+
+    fn digest(&mut self) -> String {
+        format!("deflate {}/{}", self.out_bytes, self.in_bytes)
+    }
+}
+
+/// Follows a chain of data-dependent indices across the buffer so that each
+/// load depends on the previous one. Once the buffer exceeds the cache, most
+/// steps are cache misses which can't be overlapped.
+#[derive(Default)]
+struct PointerChaseKernel {
+    acc: u64,
+}
+
+impl Kernel for PointerChaseKernel {
+    fn update(&mut self, buf: &[u8]) {
+        let nr_words = buf.len() / 8;
+        if nr_words == 0 {
+            return;
+        }
+
+        let mut acc = self.acc;
+        let mut idx = (acc % nr_words as u64) as usize;
+        for _ in 0..nr_words {
+            let word = word_at(buf, idx);
+            acc = acc.wrapping_mul(6364136223846793005).wrapping_add(word | 1);
+            idx = ((acc >> 17) % nr_words as u64) as usize;
+        }
+        self.acc = acc;
+    }
+
+    fn digest(&mut self) -> String {
+        format!("chase {:016x}", self.acc)
+    }
+}
+
+/// Sequentially reads the buffer and copies it into a scratch buffer of the
+/// same size, consuming memory bandwidth in both directions.
+#[derive(Default)]
+struct MemStreamKernel {
+    scratch: Vec<u8>,
+    sum: u64,
+}
+
+impl Kernel for MemStreamKernel {
+    fn update(&mut self, buf: &[u8]) {
+        self.scratch.clear();
+        self.scratch.extend_from_slice(buf);
+        for idx in 0..self.scratch.len() / 8 {
+            self.sum = self.sum.wrapping_add(word_at(&self.scratch, idx));
+        }
+    }
+
+    fn digest(&mut self) -> String {
+        format!("stream {:016x}", self.sum)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kernels() {
+        let buf: Vec<u8> = (0..64 << 10).map(|i| (i % 251) as u8).collect();
+
+        let mut k = new_kernel(WorkKernel::Sha1);
+        k.update(&buf);
+        assert_eq!(k.digest(), Sha1::from(&buf).digest().to_string());
+
+        let mut k = DeflateKernel::default();
+        k.update(&buf);
+        assert!(k.out_bytes < k.in_bytes / 4);
+
+        for kernel in [WorkKernel::PointerChase, WorkKernel::MemStream].iter() {
+            let mut a = new_kernel(*kernel);
+            let mut b = new_kernel(*kernel);
+            a.update(&buf);
+            b.update(&buf);
+            assert_eq!(a.digest(), b.digest());
+
+            // Tail bytes which don't fill a word are ignored.
+            a.update(&buf[..13]);
+            a.update(&[]);
+        }
+    }
+}
@@ -8,24 +8,29 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread::sleep;
 use std::time::{Duration, Instant, SystemTime};
 
-use rd_hashd_intf::{Args, Params, Phase, Report, Stat};
+use rd_hashd_intf::{Args, Params, Phase, Report, RpcAddr, Stat};
 use rd_util::*;
 
 mod bench;
 mod hasher;
+mod kernel;
 mod logger;
+mod profile;
+mod server;
 mod testfiles;
+mod trace;
+mod uring;
 mod workqueue;
 
 use logger::Logger;
 use testfiles::TestFiles;
+use trace::{Trace, TraceReader, TraceWriter};
 
 lazy_static::lazy_static! {
     pub static ref VERSION: &'static str = env!("CARGO_PKG_VERSION");
     pub static ref FULL_VERSION: String = full_version(*VERSION);
 }
 
-const TESTFILE_UNIT_SIZE: u64 = 32 << 20;
 const LOGFILE_UNIT_SIZE: u64 = 1 << 30;
 const LOGGER_HOLD_SEC: f64 = 300.0;
 
@@ -157,6 +162,31 @@ fn create_logger(args: &Args, params: &Params) -> Option<Logger> {
     }
 }
 
+fn create_trace(args: &Args) -> Option<Trace> {
+    let trace = match (args.record_trace.as_ref(), args.replay_trace.as_ref()) {
+        (None, None) => return None,
+        (Some(_), Some(_)) => {
+            error!("--record-trace and --replay-trace can't be used together");
+            panic!();
+        }
+        (Some(path), None) => {
+            info!("Recording accesses to {:?}", path);
+            TraceWriter::create(path).map(Trace::Record)
+        }
+        (None, Some(path)) => {
+            info!("Replaying accesses from {:?}", path);
+            TraceReader::open(path).map(Trace::Replay)
+        }
+    };
+    match trace {
+        Ok(trace) => Some(trace),
+        Err(e) => {
+            error!("Failed to set up access trace ({:#})", &e);
+            panic!();
+        }
+    }
+}
+
 fn main() {
     assert_eq!(*VERSION, *rd_hashd_intf::VERSION);
     Args::set_help_body(std::str::from_utf8(include_bytes!("../README.md")).unwrap());
@@ -200,7 +230,7 @@ fn main() {
     //
     let mut tf = TestFiles::new(
         tf_path,
-        TESTFILE_UNIT_SIZE,
+        args.testfile_layout,
         args.file_max_size(),
         args.compressibility,
     );
@@ -242,10 +272,10 @@ fn main() {
 
     if args.prepare_testfiles {
         let greet = format!(
-            "Populating {} with {} {}M files ({:.2}G)",
+            "Populating {} with {} {:.2}M files ({:.2}G)",
             tf_path,
             tf.nr_files,
-            to_mb(TESTFILE_UNIT_SIZE),
+            to_mb(tf.unit_size),
             to_gb(args.file_max_size())
         );
 
@@ -305,8 +335,17 @@ fn main() {
         &params,
         args.compressibility,
         create_logger(args, &params),
+        create_trace(args),
+        args.listen.is_some(),
     );
 
+    if let Some(listen) = args.listen.as_ref() {
+        if let Err(e) = server::start(&RpcAddr::parse(listen), dispatch.request_tx()) {
+            error!("Failed to start RPC server ({:#})", &e);
+            panic!();
+        }
+    }
+
     //
     // Monitor and report.
     //
@@ -343,12 +382,15 @@ fn main() {
             if args.verbosity > 0 {
                 write!(
                     buf,
-                    "/{:.1} infl:{} workers:{}/{} done:{}",
+                    "/{:.1} tgt:{:.1} infl:{} queued:{} workers:{}/{} done:{} dropped:{}",
                     stat.concurrency_max,
+                    stat_sum.rps_target,
                     stat.nr_in_flight,
+                    stat.queue_depth,
                     stat.nr_workers - stat.nr_idle_workers,
                     stat.nr_workers,
                     stat.nr_done,
+                    stat.nr_dropped,
                 )
                 .unwrap();
             }
@@ -0,0 +1,212 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+use anyhow::{bail, Result};
+use log::{info, warn};
+use std::f64::consts::PI;
+use std::time::Instant;
+
+use rd_hashd_intf::LoadProfile;
+
+/// Parse an RPS trace. Each line is "SECS,RPS" with blank lines, "#"
+/// comments and a leading header ignored. The returned RPS values are
+/// normalized so that the maximum is 1.0.
+pub fn parse_trace(input: &str) -> Result<Vec<(f64, f64)>> {
+    let mut trace: Vec<(f64, f64)> = vec![];
+
+    for (idx, line) in input.lines().enumerate() {
+        let line = line.trim();
+        if line.len() == 0 || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(|x| x.trim()).collect();
+        let parsed = match fields.len() {
+            2 => fields[0]
+                .parse::<f64>()
+                .and_then(|t| fields[1].parse::<f64>().map(|v| (t, v))),
+            _ => bail!("line {}: expected \"SECS,RPS\"", idx + 1),
+        };
+        let (t, v) = match parsed {
+            Ok(v) => v,
+            Err(_) if trace.len() == 0 => continue,
+            Err(e) => bail!("line {}: {}", idx + 1, &e),
+        };
+        if v < 0.0 {
+            bail!("line {}: negative RPS", idx + 1);
+        }
+        if let Some(last) = trace.last() {
+            if t <= last.0 {
+                bail!("line {}: timestamps must increase", idx + 1);
+            }
+        }
+        trace.push((t, v));
+    }
+
+    let max = trace.iter().fold(0.0_f64, |acc, x| acc.max(x.1));
+    if max == 0.0 {
+        bail!("no non-zero RPS in trace");
+    }
+    for pt in trace.iter_mut() {
+        pt.1 /= max;
+    }
+    Ok(trace)
+}
+
+fn trace_frac(trace: &[(f64, f64)], t: f64) -> f64 {
+    let (first, last) = (trace[0], trace[trace.len() - 1]);
+    let span = last.0 - first.0;
+    if span <= 0.0 {
+        return first.1;
+    }
+
+    let t = first.0 + t % span;
+    let idx = trace
+        .iter()
+        .position(|pt| pt.0 > t)
+        .unwrap_or(trace.len() - 1);
+    let (left, right) = (trace[idx - 1], trace[idx]);
+    left.1 + (right.1 - left.1) * (t - left.0) / (right.0 - left.0)
+}
+
+/// Follows `Params::load_profile` and yields the RPS target fraction.
+pub struct LoadProfiler {
+    profile: LoadProfile,
+    trace: Vec<(f64, f64)>,
+    started_at: Instant,
+}
+
+impl LoadProfiler {
+    pub fn new(profile: &LoadProfile) -> Self {
+        let mut trace = vec![];
+        if let LoadProfile::Replay { path } = profile {
+            match std::fs::read_to_string(path)
+                .map_err(|e| e.into())
+                .and_then(|buf| parse_trace(&buf))
+            {
+                Ok(v) => {
+                    info!("profile: Loaded {} points from {:?}", v.len(), path);
+                    trace = v;
+                }
+                Err(e) => warn!("profile: Failed to load {:?}, ignoring ({:#})", path, &e),
+            }
+        }
+
+        Self {
+            profile: profile.clone(),
+            trace,
+            started_at: Instant::now(),
+        }
+    }
+
+    /// Restart if the profile changed.
+    pub fn update(&mut self, profile: &LoadProfile) {
+        if *profile != self.profile {
+            *self = Self::new(profile);
+        }
+    }
+
+    fn frac(&self, t: f64) -> f64 {
+        match &self.profile {
+            LoadProfile::Flat => 1.0,
+            LoadProfile::Diurnal {
+                period,
+                min_frac,
+                max_frac,
+            } => {
+                if *period <= 0.0 {
+                    return *max_frac;
+                }
+                min_frac + (max_frac - min_frac) * (1.0 - (2.0 * PI * t / period).cos()) / 2.0
+            }
+            LoadProfile::Step { steps } => {
+                let total: f64 = steps.iter().map(|x| x.0.max(0.0)).sum();
+                if total <= 0.0 {
+                    return 1.0;
+                }
+                let mut t = t % total;
+                for (dur, frac) in steps.iter() {
+                    if t < *dur {
+                        return *frac;
+                    }
+                    t -= dur.max(0.0);
+                }
+                steps[steps.len() - 1].1
+            }
+            LoadProfile::Spike {
+                period,
+                duration,
+                base_frac,
+                spike_frac,
+            } => {
+                if *period <= 0.0 {
+                    return *base_frac;
+                }
+                match t % period >= period - duration {
+                    true => *spike_frac,
+                    false => *base_frac,
+                }
+            }
+            LoadProfile::Replay { .. } => match self.trace.len() {
+                0 => 1.0,
+                _ => trace_frac(&self.trace, t),
+            },
+        }
+    }
+
+    pub fn frac_at(&self, now: Instant) -> f64 {
+        self.frac(now.duration_since(self.started_at).as_secs_f64())
+            .max(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 0.000_001
+    }
+
+    #[test]
+    fn test_load_profiles() {
+        let prof = |p: LoadProfile| LoadProfiler::new(&p);
+
+        let p = prof(LoadProfile::Diurnal {
+            period: 100.0,
+            min_frac: 0.2,
+            max_frac: 1.0,
+        });
+        assert!(close(p.frac(0.0), 0.2));
+        assert!(close(p.frac(25.0), 0.6));
+        assert!(close(p.frac(50.0), 1.0));
+        assert!(close(p.frac(100.0), 0.2));
+
+        let p = prof(LoadProfile::Step {
+            steps: vec![(10.0, 0.5), (5.0, 1.0)],
+        });
+        assert_eq!(p.frac(0.0), 0.5);
+        assert_eq!(p.frac(12.0), 1.0);
+        assert_eq!(p.frac(16.0), 0.5);
+
+        let p = prof(LoadProfile::Spike {
+            period: 60.0,
+            duration: 10.0,
+            base_frac: 0.5,
+            spike_frac: 2.0,
+        });
+        assert_eq!(p.frac(0.0), 0.5);
+        assert_eq!(p.frac(55.0), 2.0);
+        assert_eq!(p.frac(65.0), 0.5);
+    }
+
+    #[test]
+    fn test_trace() {
+        let trace = parse_trace("secs,rps\n0,100\n\n# comment\n10,200\n20,50\n").unwrap();
+        assert_eq!(trace, vec![(0.0, 0.5), (10.0, 1.0), (20.0, 0.25)]);
+        assert!(close(trace_frac(&trace, 5.0), 0.75));
+        assert!(close(trace_frac(&trace, 15.0), 0.625));
+        assert!(close(trace_frac(&trace, 25.0), 0.75));
+
+        assert!(parse_trace("0,100\n0,200\n").is_err());
+        assert!(parse_trace("0,100\n10,abc\n").is_err());
+        assert!(parse_trace("0,0\n").is_err());
+    }
+}
@@ -0,0 +1,210 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+use anyhow::{bail, Context, Result};
+use crossbeam::channel::{self, Sender};
+use log::{debug, info, warn};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixListener;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::spawn;
+
+use rd_hashd_intf::{RpcAddr, RPC_DROPPED, RPC_LINE_MAX};
+
+use super::hasher::DispatchCmd;
+
+/// Each connection is served by its own thread. Connections beyond this
+/// many are closed right away.
+const MAX_CONNS: usize = 1024;
+
+/// Execute the requests on a connection one by one and write back the
+/// replies. See rd_hashd_intf::rpc for the protocol.
+fn serve_conn<R: Read, W: Write>(rd: R, mut wr: W, req_tx: Sender<DispatchCmd>) -> Result<()> {
+    let mut rd = BufReader::new(rd);
+    let mut line = String::new();
+    loop {
+        // Don't let a line without the newline grow without bound.
+        line.clear();
+        let len = (&mut rd).take(RPC_LINE_MAX as u64).read_line(&mut line)?;
+        if len == 0 {
+            break;
+        }
+        if len == RPC_LINE_MAX && !line.ends_with('\n') {
+            bail!("Request line longer than {} bytes", RPC_LINE_MAX);
+        }
+
+        let (reply_tx, reply_rx) = channel::bounded(1);
+        req_tx.send(DispatchCmd::Request(reply_tx))?;
+        let digest = reply_rx.recv()?;
+        let reply = format!(
+            "{} {}\n",
+            line.trim_end(),
+            digest.as_deref().unwrap_or(RPC_DROPPED)
+        );
+        wr.write_all(reply.as_bytes())?;
+    }
+    Ok(())
+}
+
+fn spawn_conn<R, W>(
+    name: String,
+    rd: R,
+    wr: W,
+    req_tx: Sender<DispatchCmd>,
+    nr_conns: &Arc<AtomicUsize>,
+) where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    if nr_conns.fetch_add(1, Ordering::Relaxed) >= MAX_CONNS {
+        nr_conns.fetch_sub(1, Ordering::Relaxed);
+        warn!("rpc: Too many connections, closing {}", &name);
+        return;
+    }
+
+    debug!("rpc: Accepted {}", &name);
+    let nr_conns = nr_conns.clone();
+    spawn(move || {
+        match serve_conn(rd, wr, req_tx) {
+            Ok(()) => debug!("rpc: {} closed", &name),
+            Err(e) => debug!("rpc: {} failed ({:#})", &name, &e),
+        }
+        nr_conns.fetch_sub(1, Ordering::Relaxed);
+    });
+}
+
+/// Remove the socket left behind by an earlier instance. Anything else at
+/// `path` is left alone.
+fn remove_stale_socket(path: &str) -> Result<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(md) if md.file_type().is_socket() => {
+            std::fs::remove_file(path).with_context(|| format!("Removing {:?}", path))
+        }
+        Ok(_) => bail!("{:?} exists and isn't a socket", path),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e).with_context(|| format!("Checking {:?}", path)),
+    }
+}
+
+/// Listen on `addr` and forward the received requests to the dispatch
+/// thread through `req_tx`. Each connection is served by its own thread.
+pub fn start(addr: &RpcAddr, req_tx: Sender<DispatchCmd>) -> Result<()> {
+    let nr_conns = Arc::new(AtomicUsize::new(0));
+    match addr {
+        RpcAddr::Tcp(host_port) => {
+            let listener = TcpListener::bind(host_port)
+                .with_context(|| format!("Binding to {:?}", host_port))?;
+            spawn(move || {
+                for stream in listener.incoming() {
+                    let res = stream.and_then(|stream| {
+                        let _ = stream.set_nodelay(true);
+                        Ok((stream.peer_addr()?, stream.try_clone()?, stream))
+                    });
+                    match res {
+                        Ok((peer, rd, wr)) => {
+                            spawn_conn(format!("{}", peer), rd, wr, req_tx.clone(), &nr_conns)
+                        }
+                        Err(e) => warn!("rpc: Failed to accept connection ({})", &e),
+                    }
+                }
+            });
+        }
+        RpcAddr::Unix(path) => {
+            remove_stale_socket(path)?;
+            let listener =
+                UnixListener::bind(path).with_context(|| format!("Binding to {:?}", path))?;
+            spawn(move || {
+                for (idx, stream) in listener.incoming().enumerate() {
+                    match stream.and_then(|stream| Ok((stream.try_clone()?, stream))) {
+                        Ok((rd, wr)) => {
+                            spawn_conn(format!("unix#{}", idx), rd, wr, req_tx.clone(), &nr_conns)
+                        }
+                        Err(e) => warn!("rpc: Failed to accept connection ({})", &e),
+                    }
+                }
+            });
+        }
+    }
+    info!("rpc: Serving hash requests on {}", addr);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_serve_conn() {
+        let (req_tx, req_rx) = channel::unbounded();
+        let dispatcher = spawn(move || {
+            let replies = [Some("digest0".to_string()), None];
+            for reply in replies.iter() {
+                match req_rx.recv().unwrap() {
+                    DispatchCmd::Request(reply_tx) => reply_tx.send(reply.clone()).unwrap(),
+                    _ => panic!("unexpected command"),
+                }
+            }
+        });
+
+        let mut out = vec![];
+        serve_conn(Cursor::new("req0\nreq1 \n"), &mut out, req_tx).unwrap();
+        dispatcher.join().unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("req0 digest0\nreq1 {}\n", RPC_DROPPED)
+        );
+    }
+
+    #[test]
+    fn test_serve_conn_line_max() {
+        let (req_tx, req_rx) = channel::unbounded();
+        let dispatcher = spawn(move || {
+            let mut nr = 0;
+            while let Ok(DispatchCmd::Request(reply_tx)) = req_rx.recv() {
+                reply_tx.send(Some(format!("digest{}", nr))).unwrap();
+                nr += 1;
+            }
+            nr
+        });
+
+        // The longest allowed line is served, a longer one closes the
+        // connection without being dispatched.
+        let ok = "a".repeat(RPC_LINE_MAX - 1);
+        let input = format!("{}\n{}\nreq2\n", &ok, "b".repeat(RPC_LINE_MAX));
+        let mut out = vec![];
+        assert!(serve_conn(Cursor::new(input), &mut out, req_tx).is_err());
+        assert_eq!(dispatcher.join().unwrap(), 1);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("{} digest0\n", &ok)
+        );
+    }
+
+    #[test]
+    fn test_remove_stale_socket() {
+        let dir = std::env::temp_dir();
+        let path = |name: &str| {
+            dir.join(format!("rd-hashd-test-{}-{}", std::process::id(), name))
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+
+        let missing = path("missing");
+        assert!(remove_stale_socket(&missing).is_ok());
+
+        let file = path("file");
+        std::fs::write(&file, "data").unwrap();
+        assert!(remove_stale_socket(&file).is_err());
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "data");
+        std::fs::remove_file(&file).unwrap();
+
+        let sock = path("sock");
+        let listener = UnixListener::bind(&sock).unwrap();
+        drop(listener);
+        remove_stale_socket(&sock).unwrap();
+        assert!(std::fs::symlink_metadata(&sock).is_err());
+    }
+}
@@ -7,13 +7,20 @@ use rand::SeedableRng;
 use std::convert::TryInto;
 use std::ffi::OsStr;
 use std::fs;
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::fs::FileExt;
 use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
 
+use rd_hashd_intf::TestFileLayout;
 use rd_util::*;
 
+pub const UNIT_SIZE: u64 = 32 << 20;
+const SMALL_UNIT_SIZE: u64 = 64 << 10;
+const WRITE_CHUNK_SIZE: u64 = 32 << 20;
+
 const DFL_PREFIX: &str = "rdh-";
+const LAYOUT_FILE: &str = "rdh-layout";
 const FILE_BITS: usize = 28;
 const FILE_DIGITS: usize = FILE_BITS / 4;
 const DIR_BITS: usize = 16;
@@ -22,6 +29,7 @@ const DIR_DIGITS: usize = DIR_BITS / 4;
 #[derive(Debug)]
 pub struct TestFiles {
     base_path: PathBuf,
+    pub layout: TestFileLayout,
     pub unit_size: u64,
     pub size: u64,
     pub nr_files: u64,
@@ -30,9 +38,17 @@ pub struct TestFiles {
 }
 
 impl TestFiles {
-    pub fn new<P: AsRef<Path>>(base_path: P, unit_size: u64, size: u64, comp: f64) -> Self {
+    pub fn new<P: AsRef<Path>>(base_path: P, layout: TestFileLayout, size: u64, comp: f64) -> Self {
+        let unit_size = match layout {
+            TestFileLayout::Units | TestFileLayout::Sparse => UNIT_SIZE,
+            TestFileLayout::Small => SMALL_UNIT_SIZE,
+            TestFileLayout::Single => {
+                Integer::div_ceil(&size.max(1), &(*PAGE_SIZE as u64)) * *PAGE_SIZE as u64
+            }
+        };
         TestFiles {
             base_path: PathBuf::from(base_path.as_ref()),
+            layout,
             unit_size,
             size,
             nr_files: Integer::div_ceil(&size, &unit_size),
@@ -55,6 +71,10 @@ impl TestFiles {
     pub fn clear(&mut self) -> Result<()> {
         self.prep_base_dir()?;
 
+        if self.layout_path().exists() {
+            fs::remove_file(self.layout_path())?;
+        }
+
         // walk base dir and remove children with the matching prefix
         for entry in self
             .base_path
@@ -97,6 +117,110 @@ impl TestFiles {
         Ok(f64::from_le_bytes(buf))
     }
 
+    fn parse_idx(&self, path: &Path) -> Option<u64> {
+        let name = path.file_name()?.to_str()?;
+        u64::from_str_radix(name.strip_prefix(&self.prefix)?, 16).ok()
+    }
+
+    /// Remove testfiles beyond `nr_files` which are left behind by a
+    /// larger size or a different layout.
+    fn remove_stale(&self) -> Result<()> {
+        for dpath in self
+            .base_path
+            .read_dir()?
+            .filter_map(|r| r.ok())
+            .map(|e| e.path())
+            .filter(|p| p.is_dir() && self.parse_idx(p).is_some())
+        {
+            for fpath in dpath.read_dir()?.filter_map(|r| r.ok()).map(|e| e.path()) {
+                if let Some(idx) = self.parse_idx(&fpath) {
+                    if idx >= self.nr_files {
+                        debug!("testfiles: removing stale {:?}", &fpath);
+                        fs::remove_file(&fpath)?;
+                    }
+                }
+            }
+            if dpath.read_dir()?.next().is_none() {
+                fs::remove_dir(&dpath)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The layout of the existing testfiles is recorded in LAYOUT_FILE as
+    /// it can't be reliably told from the allocated blocks.
+    fn layout_path(&self) -> PathBuf {
+        self.base_path.join(LAYOUT_FILE)
+    }
+
+    fn read_layout(&self) -> Option<TestFileLayout> {
+        let line = fs::read_to_string(self.layout_path()).ok()?;
+        TestFileLayout::parse(line.trim()).ok()
+    }
+
+    /// Split `nr_pages` starting at `page` at testfile boundaries into
+    /// (page, file index, offset, size) pieces so that a chunk can span
+    /// multiple small testfiles.
+    pub fn chunk_pieces(&self, mut page: u64, nr_pages: u64) -> Vec<(u64, u64, u64, usize)> {
+        let pages_per_unit = self.unit_size / *PAGE_SIZE as u64;
+        let end = (page + nr_pages).min(self.nr_files * pages_per_unit);
+        let mut pieces = vec![];
+        while page < end {
+            let pg_off = page % pages_per_unit;
+            let len = (pages_per_unit - pg_off).min(end - page);
+            pieces.push((
+                page,
+                page / pages_per_unit,
+                pg_off * *PAGE_SIZE as u64,
+                len as usize * *PAGE_SIZE,
+            ));
+            page += len;
+        }
+        pieces
+    }
+
+    fn create_file<F: FnMut(u64)>(
+        &self,
+        fpath: &Path,
+        pos: u64,
+        rng: &mut SmallRng,
+        progress: &mut F,
+    ) -> Result<()> {
+        let f = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(fpath)?;
+
+        let page_size = *PAGE_SIZE as u64;
+        let mut buf = vec![0u8; WRITE_CHUNK_SIZE.min(self.unit_size) as usize];
+        let mut off = 0;
+        while off < self.unit_size {
+            let len = (self.unit_size - off).min(buf.len() as u64) as usize;
+            fill_area_with_random(&mut buf[..len], self.comp, rng);
+            if off == 0 {
+                buf[0..8].copy_from_slice(&self.comp.to_ne_bytes());
+            }
+
+            if self.layout == TestFileLayout::Sparse {
+                for page_off in (0..len as u64).step_by(2 * page_size as usize) {
+                    let end = (page_off + page_size).min(len as u64) as usize;
+                    f.write_all_at(&buf[page_off as usize..end], off + page_off)?;
+                }
+            } else {
+                f.write_all_at(&buf[..len], off)?;
+            }
+
+            off += len as u64;
+            if off < self.unit_size {
+                progress(pos + off);
+            }
+        }
+
+        // Trailing hole in sparse files.
+        f.set_len(self.unit_size)?;
+        Ok(())
+    }
+
     pub fn setup<F: FnMut(u64)>(&mut self, mut progress: F) -> Result<()> {
         let mut rng = SmallRng::from_entropy();
 
@@ -105,6 +229,14 @@ impl TestFiles {
         }
 
         self.prep_base_dir()?;
+        self.remove_stale()?;
+
+        // Files of a different or unknown layout can't be reused. Drop the
+        // marker until all files are in the new layout.
+        let layout_matches = self.read_layout() == Some(self.layout);
+        if !layout_matches && self.layout_path().exists() {
+            fs::remove_file(self.layout_path())?;
+        }
 
         for i in 0..self.nr_files {
             let (_di, fi, dname, fname) = self.idx_to_dfnames(i);
@@ -127,6 +259,7 @@ impl TestFiles {
                     Ok(ref md)
                         if md.is_file()
                             && md.len() == self.unit_size
+                            && layout_matches
                             && Self::read_comp(&fpath).unwrap_or(-1.0) == self.comp =>
                     {
                         trace!("testfiles: using existing {:?}", &fpath);
@@ -144,17 +277,12 @@ impl TestFiles {
 
             // create a new one
             debug!("testfiles: creating {}", &fname);
-            let mut f = fs::OpenOptions::new()
-                .write(true)
-                .create_new(true)
-                .open(&fpath)?;
-
-            let mut buf = vec![0u8; self.unit_size as usize];
-            fill_area_with_random(&mut buf, self.comp, &mut rng);
-            buf[0..8].copy_from_slice(&self.comp.to_ne_bytes());
-            f.write_all(&buf)?;
+            self.create_file(&fpath, i * self.unit_size, &mut rng, &mut progress)?;
 
-            progress(i * self.unit_size);
+            progress((i + 1) * self.unit_size);
+        }
+        if !layout_matches {
+            fs::write(self.layout_path(), format!("{}\n", self.layout.name()))?;
         }
         unsafe { libc::sync() };
         progress(self.nr_files * self.unit_size);
@@ -198,11 +326,14 @@ impl TestFiles {
     }
 
     pub fn preload<F: FnMut(u64)>(&self, size: usize, mut progress: F) {
-        let nr_files = (size as u64 / self.unit_size).min(self.nr_files);
+        let size = (size as u64).min(self.nr_files * self.unit_size);
+        let nr_files = Integer::div_ceil(&size, &self.unit_size);
+        let mut done = 0;
 
         // Populate the units in the reverse order so that the hotter a unit
         // the later it gets loaded.
         for fi in (0..nr_files).rev() {
+            let file_size = (size - fi * self.unit_size).min(self.unit_size);
             let path = self.path(fi);
             let mut f = match fs::File::open(&path) {
                 Ok(f) => f,
@@ -211,7 +342,7 @@ impl TestFiles {
                     continue;
                 }
             };
-            for pi in 0..(self.unit_size / *PAGE_SIZE as u64) {
+            for pi in 0..(file_size / *PAGE_SIZE as u64) {
                 if let Err(e) = f.seek(SeekFrom::Start(pi * *PAGE_SIZE as u64)) {
                     warn!("Failed to seek to {:?}:{} for preheat ({})", &path, pi, &e);
                     continue;
@@ -222,7 +353,119 @@ impl TestFiles {
                     continue;
                 }
             }
-            progress((nr_files - fi) * self.unit_size);
+            done += file_size;
+            progress(done);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_pieces() {
+        let pg = *PAGE_SIZE as u64;
+
+        // Units and Sparse split at 32M boundaries and clip at the end.
+        for layout in &[TestFileLayout::Units, TestFileLayout::Sparse] {
+            let tf = TestFiles::new("/nonexistent", *layout, 3 * UNIT_SIZE + 1, 0.0);
+            assert_eq!(tf.nr_files, 4);
+            let ppu = UNIT_SIZE / pg;
+            assert_eq!(
+                tf.chunk_pieces(ppu - 2, 4),
+                vec![
+                    (ppu - 2, 0, (ppu - 2) * pg, 2 * pg as usize),
+                    (ppu, 1, 0, 2 * pg as usize)
+                ]
+            );
+            assert_eq!(
+                tf.chunk_pieces(ppu + 1, 2),
+                vec![(ppu + 1, 1, pg, 2 * pg as usize)]
+            );
+            assert_eq!(
+                tf.chunk_pieces(4 * ppu - 1, 4),
+                vec![(4 * ppu - 1, 3, (ppu - 1) * pg, pg as usize)]
+            );
+            assert_eq!(tf.chunk_pieces(4 * ppu, 4), vec![]);
+        }
+
+        // Small can span several whole files.
+        let tf = TestFiles::new(
+            "/nonexistent",
+            TestFileLayout::Small,
+            4 * SMALL_UNIT_SIZE,
+            0.0,
+        );
+        let ppu = SMALL_UNIT_SIZE / pg;
+        let usz = SMALL_UNIT_SIZE as usize;
+        assert_eq!(
+            tf.chunk_pieces(ppu - 1, 2 * ppu + 2),
+            vec![
+                (ppu - 1, 0, (ppu - 1) * pg, pg as usize),
+                (ppu, 1, 0, usz),
+                (2 * ppu, 2, 0, usz),
+                (3 * ppu, 3, 0, pg as usize),
+            ]
+        );
+
+        // Single is one page-aligned file.
+        let tf = TestFiles::new("/nonexistent", TestFileLayout::Single, 10 * pg + 1, 0.0);
+        assert_eq!((tf.nr_files, tf.unit_size), (1, 11 * pg));
+        assert_eq!(
+            tf.chunk_pieces(3, 16),
+            vec![(3, 0, 3 * pg, 8 * pg as usize)]
+        );
+    }
+
+    #[test]
+    fn test_setup_layout() {
+        let dir = std::env::temp_dir().join(format!("rd-hashd-test-layout-{}", std::process::id()));
+        let layout = |tf: &TestFiles| fs::read_to_string(tf.layout_path()).unwrap();
+        // Recreated files are filled with new random data, tag to tell.
+        const TAG: &[u8] = b"rdh-test";
+        let tag = |tf: &TestFiles| {
+            let f = fs::OpenOptions::new().write(true).open(tf.path(0)).unwrap();
+            f.write_all_at(TAG, 8).unwrap();
+        };
+        let tagged = |tf: &TestFiles| {
+            let mut buf = [0u8; 8];
+            let f = fs::File::open(tf.path(0)).unwrap();
+            f.read_exact_at(&mut buf, 8).unwrap();
+            buf == TAG
+        };
+
+        let mut tf = TestFiles::new(&dir, TestFileLayout::Sparse, UNIT_SIZE, 0.5);
+        tf.setup(|_| {}).unwrap();
+        assert_eq!(layout(&tf), "sparse\n");
+
+        // Filling the holes doesn't make the files look dense.
+        tag(&tf);
+        let f = fs::OpenOptions::new().write(true).open(tf.path(0)).unwrap();
+        let pg = *PAGE_SIZE;
+        for off in (pg..UNIT_SIZE as usize).step_by(2 * pg) {
+            f.write_all_at(&vec![0xa5; pg], off as u64).unwrap();
+        }
+        drop(f);
+        tf.setup(|_| {}).unwrap();
+        assert!(tagged(&tf));
+
+        // A different layout of the same size recreates the files.
+        let mut tf = TestFiles::new(&dir, TestFileLayout::Units, UNIT_SIZE, 0.5);
+        tf.setup(|_| {}).unwrap();
+        assert_eq!(layout(&tf), "units\n");
+        assert!(!tagged(&tf));
+
+        // The files are recreated too if the marker is missing.
+        tag(&tf);
+        tf.setup(|_| {}).unwrap();
+        assert!(tagged(&tf));
+        fs::remove_file(tf.layout_path()).unwrap();
+        tf.setup(|_| {}).unwrap();
+        assert!(!tagged(&tf));
+
+        tf.clear().unwrap();
+        assert!(!tf.layout_path().exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
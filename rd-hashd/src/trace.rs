@@ -0,0 +1,328 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// Access traces for recording and replaying requests. A trace file starts
+// with TRACE_MAGIC followed by records in the following little-endian
+// format.
+//
+//  u32 chunk_pages
+//  f64 sleep_dur
+//  u32 nr_file
+//  u32 nr_anon
+//  u64 x nr_file: file page index | FILE_WRITE | FILE_DIRECT | FILE_SYNC
+//  u64 x nr_anon: anon page index | ANON_WRITE
+//
+// Records are written in the order the requests were dispatched.
+use anyhow::{bail, Result};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const TRACE_MAGIC: &[u8; 8] = b"RDHTRC01";
+
+const FILE_WRITE: u64 = 1 << 63;
+const FILE_DIRECT: u64 = 1 << 62;
+const FILE_SYNC: u64 = 1 << 61;
+const ANON_WRITE: u64 = 1 << 63;
+const PAGE_MASK: u64 = (1 << 61) - 1;
+
+// Sanity limit on nr_file and nr_anon so that a corrupt record can't make
+// the reader allocate the world.
+const NR_ACCESSES_MAX: u32 = 1 << 20;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileAccess {
+    pub page: u64,
+    pub is_write: bool,
+    pub direct: bool,
+    pub sync: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnonAccess {
+    pub page: u64,
+    pub is_write: bool,
+}
+
+/// The accesses a request makes, either sampled or replayed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TraceRecord {
+    pub chunk_pages: u32,
+    pub sleep_dur: f64,
+    pub file: Vec<FileAccess>,
+    pub anon: Vec<AnonAccess>,
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+impl TraceRecord {
+    pub fn write_to<W: Write>(&self, w: &mut W) -> Result<()> {
+        w.write_all(&self.chunk_pages.to_le_bytes())?;
+        w.write_all(&self.sleep_dur.to_le_bytes())?;
+        w.write_all(&(self.file.len() as u32).to_le_bytes())?;
+        w.write_all(&(self.anon.len() as u32).to_le_bytes())?;
+        for fa in self.file.iter() {
+            let mut v = fa.page & PAGE_MASK;
+            if fa.is_write {
+                v |= FILE_WRITE;
+            }
+            if fa.direct {
+                v |= FILE_DIRECT;
+            }
+            if fa.sync {
+                v |= FILE_SYNC;
+            }
+            w.write_all(&v.to_le_bytes())?;
+        }
+        for aa in self.anon.iter() {
+            let mut v = aa.page & PAGE_MASK;
+            if aa.is_write {
+                v |= ANON_WRITE;
+            }
+            w.write_all(&v.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn read_record<R: Read>(r: &mut R) -> io::Result<Self> {
+        let chunk_pages = read_u32(r)?;
+        let sleep_dur = f64::from_bits(read_u64(r)?);
+        let nr_file = read_u32(r)?;
+        let nr_anon = read_u32(r)?;
+        if nr_file > NR_ACCESSES_MAX || nr_anon > NR_ACCESSES_MAX {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "corrupt trace record, nr_file={} nr_anon={} (max {})",
+                    nr_file, nr_anon, NR_ACCESSES_MAX
+                ),
+            ));
+        }
+
+        let mut rec = Self {
+            chunk_pages,
+            sleep_dur,
+            file: Vec::with_capacity(nr_file as usize),
+            anon: Vec::with_capacity(nr_anon as usize),
+        };
+        for _ in 0..nr_file {
+            let v = read_u64(r)?;
+            rec.file.push(FileAccess {
+                page: v & PAGE_MASK,
+                is_write: v & FILE_WRITE != 0,
+                direct: v & FILE_DIRECT != 0,
+                sync: v & FILE_SYNC != 0,
+            });
+        }
+        for _ in 0..nr_anon {
+            let v = read_u64(r)?;
+            rec.anon.push(AnonAccess {
+                page: v & PAGE_MASK,
+                is_write: v & ANON_WRITE != 0,
+            });
+        }
+        Ok(rec)
+    }
+
+    /// Read the next record. Returns None at the end of the trace. A
+    /// truncated trailing record, e.g. from an interrupted recording, is
+    /// treated as the end.
+    pub fn read_from<R: Read>(r: &mut R) -> Result<Option<Self>> {
+        match Self::read_record(r) {
+            Ok(rec) => Ok(Some(rec)),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Requests complete out of order. Each request is assigned a sequence
+/// number with next_seq() when dispatched and its record is held until all
+/// the preceding ones are written.
+pub struct TraceWriter {
+    writer: BufWriter<File>,
+    seq: u64,
+    next_write_seq: u64,
+    pending: BTreeMap<u64, TraceRecord>,
+}
+
+impl TraceWriter {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(TRACE_MAGIC)?;
+        Ok(Self {
+            writer,
+            seq: 0,
+            next_write_seq: 0,
+            pending: BTreeMap::new(),
+        })
+    }
+
+    /// Assign the dispatch sequence number of a request.
+    pub fn next_seq(&mut self) -> u64 {
+        self.seq += 1;
+        self.seq - 1
+    }
+
+    /// Write `rec` of the request `seq` along with the following ones
+    /// which completed earlier.
+    pub fn write(&mut self, seq: u64, rec: TraceRecord) -> Result<()> {
+        self.pending.insert(seq, rec);
+        while let Some(rec) = self.pending.remove(&self.next_write_seq) {
+            rec.write_to(&mut self.writer)?;
+            self.next_write_seq += 1;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+pub struct TraceReader {
+    reader: BufReader<File>,
+    pub nr_wraps: u64,
+}
+
+impl TraceReader {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != TRACE_MAGIC {
+            bail!("not an rd-hashd trace file");
+        }
+        let mut tr = Self {
+            reader,
+            nr_wraps: 0,
+        };
+        if TraceRecord::read_from(&mut tr.reader)?.is_none() {
+            bail!("trace file is empty");
+        }
+        tr.rewind()?;
+        Ok(tr)
+    }
+
+    fn rewind(&mut self) -> Result<()> {
+        self.reader
+            .seek(SeekFrom::Start(TRACE_MAGIC.len() as u64))?;
+        Ok(())
+    }
+
+    /// Return the next record, wrapping around at the end of the trace.
+    pub fn next(&mut self) -> Result<TraceRecord> {
+        if let Some(rec) = TraceRecord::read_from(&mut self.reader)? {
+            return Ok(rec);
+        }
+        self.rewind()?;
+        self.nr_wraps += 1;
+        match TraceRecord::read_from(&mut self.reader)? {
+            Some(rec) => Ok(rec),
+            None => bail!("trace file is empty"),
+        }
+    }
+}
+
+/// A trace being either recorded or replayed.
+pub enum Trace {
+    Record(TraceWriter),
+    Replay(TraceReader),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace_record_roundtrip() {
+        let recs = [
+            TraceRecord {
+                chunk_pages: 25,
+                sleep_dur: 0.0123,
+                file: vec![
+                    FileAccess {
+                        page: 12345,
+                        is_write: true,
+                        direct: false,
+                        sync: true,
+                    },
+                    FileAccess {
+                        page: PAGE_MASK,
+                        is_write: false,
+                        direct: true,
+                        sync: false,
+                    },
+                ],
+                anon: vec![AnonAccess {
+                    page: 7,
+                    is_write: true,
+                }],
+            },
+            TraceRecord::default(),
+        ];
+
+        let mut buf = vec![];
+        for rec in recs.iter() {
+            rec.write_to(&mut buf).unwrap();
+        }
+        let mut cursor = io::Cursor::new(buf.clone());
+        for rec in recs.iter() {
+            assert_eq!(&TraceRecord::read_from(&mut cursor).unwrap().unwrap(), rec);
+        }
+        assert!(TraceRecord::read_from(&mut cursor).unwrap().is_none());
+
+        // A truncated trailing record marks the end.
+        buf.truncate(buf.len() - 3);
+        let mut cursor = io::Cursor::new(buf);
+        assert_eq!(
+            &TraceRecord::read_from(&mut cursor).unwrap().unwrap(),
+            &recs[0]
+        );
+        assert!(TraceRecord::read_from(&mut cursor).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_trace_record_corrupt_counts() {
+        let mut buf = vec![];
+        TraceRecord::default().write_to(&mut buf).unwrap();
+        // Overwrite nr_file.
+        buf[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(TraceRecord::read_from(&mut io::Cursor::new(buf)).is_err());
+    }
+
+    #[test]
+    fn test_trace_writer_dispatch_order() {
+        let path = std::env::temp_dir().join(format!("rd-hashd-test-trace-{}", std::process::id()));
+        let rec = |chunk_pages| TraceRecord {
+            chunk_pages,
+            ..Default::default()
+        };
+
+        let mut writer = TraceWriter::create(&path).unwrap();
+        let seqs: Vec<u64> = (0..4).map(|_| writer.next_seq()).collect();
+        assert_eq!(seqs, vec![0, 1, 2, 3]);
+        // Complete in the order of 2, 0, 3, 1.
+        for seq in [2, 0, 3, 1].iter() {
+            writer.write(*seq, rec(*seq as u32)).unwrap();
+        }
+        writer.flush().unwrap();
+
+        let mut reader = TraceReader::open(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        for seq in 0..4 {
+            assert_eq!(reader.next().unwrap(), rec(seq));
+        }
+        assert_eq!(reader.nr_wraps, 0);
+    }
+}
@@ -0,0 +1,387 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// Minimal io_uring interface which is just enough for issuing file reads and
+// writes from hasher workers. See include/uapi/linux/io_uring.h.
+use anyhow::{bail, Context, Result};
+use std::io;
+use std::os::unix::io::RawFd;
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+const IORING_OFF_SQ_RING: libc::off_t = 0;
+const IORING_OFF_CQ_RING: libc::off_t = 0x8000000;
+const IORING_OFF_SQES: libc::off_t = 0x10000000;
+const IORING_ENTER_GETEVENTS: u32 = 1;
+
+pub const IORING_OP_READ: u8 = 22;
+pub const IORING_OP_WRITE: u8 = 23;
+
+#[repr(C)]
+#[derive(Default)]
+struct SqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct UringParams {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqringOffsets,
+    cq_off: CqringOffsets,
+}
+
+#[repr(C)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    rw_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    addr3: u64,
+    pad: u64,
+}
+
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+struct Mmap {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Mmap {
+    fn new(fd: RawFd, len: usize, off: libc::off_t) -> Result<Self> {
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd,
+                off,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            bail!("mmap failed ({})", io::Error::last_os_error());
+        }
+        Ok(Self {
+            ptr: ptr as *mut u8,
+            len,
+        })
+    }
+
+    fn at<T>(&self, off: u32) -> *mut T {
+        unsafe { self.ptr.add(off as usize) as *mut T }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+/// Whether IoUring::new() failed because io_uring isn't supported or allowed
+/// at all, in which case retrying is pointless.
+pub fn is_unavailable(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<io::Error>()
+            .and_then(|e| e.raw_os_error()),
+        Some(libc::ENOSYS) | Some(libc::EPERM)
+    )
+}
+
+pub struct IoUring {
+    fd: RawFd,
+    depth: u32,
+    sq_ring: Mmap,
+    cq_ring: Mmap,
+    sqes: Mmap,
+    params: UringParams,
+    nr_to_submit: u32,
+}
+
+// The rings are only accessed through &mut self.
+unsafe impl Send for IoUring {}
+
+impl IoUring {
+    pub fn new(depth: u32) -> Result<Self> {
+        let mut params = UringParams::default();
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_setup,
+                depth,
+                &mut params as *mut UringParams,
+            )
+        } as RawFd;
+        if fd < 0 {
+            return Err(io::Error::last_os_error()).context("io_uring_setup failed");
+        }
+
+        let maps = Mmap::new(
+            fd,
+            params.sq_off.array as usize + params.sq_entries as usize * 4,
+            IORING_OFF_SQ_RING,
+        )
+        .and_then(|sq_ring| {
+            let cq_ring = Mmap::new(
+                fd,
+                params.cq_off.cqes as usize
+                    + params.cq_entries as usize * std::mem::size_of::<Cqe>(),
+                IORING_OFF_CQ_RING,
+            )?;
+            let sqes = Mmap::new(
+                fd,
+                params.sq_entries as usize * std::mem::size_of::<Sqe>(),
+                IORING_OFF_SQES,
+            )?;
+            Ok((sq_ring, cq_ring, sqes))
+        });
+        let (sq_ring, cq_ring, sqes) = match maps {
+            Ok(v) => v,
+            Err(e) => {
+                unsafe { libc::close(fd) };
+                return Err(e);
+            }
+        };
+
+        Ok(Self {
+            fd,
+            depth,
+            sq_ring,
+            cq_ring,
+            sqes,
+            params,
+            nr_to_submit: 0,
+        })
+    }
+
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    fn atomic<'a>(ptr: *mut u32) -> &'a AtomicU32 {
+        unsafe { &*(ptr as *const AtomicU32) }
+    }
+
+    /// Queue a read or write of `len` bytes at `buf` and file offset `off`.
+    /// Returns false if the submission queue is full.
+    ///
+    /// # Safety
+    ///
+    /// `fd` and `buf` must stay valid until the completion is reaped.
+    pub unsafe fn push(
+        &mut self,
+        opcode: u8,
+        fd: RawFd,
+        buf: *mut u8,
+        len: u32,
+        off: u64,
+        user_data: u64,
+    ) -> bool {
+        let so = &self.params.sq_off;
+        let head = Self::atomic(self.sq_ring.at(so.head)).load(Ordering::Acquire);
+        let tail_ptr = Self::atomic(self.sq_ring.at(so.tail));
+        let tail = tail_ptr.load(Ordering::Relaxed);
+        if tail.wrapping_sub(head) >= self.params.sq_entries {
+            return false;
+        }
+
+        let idx = tail & *self.sq_ring.at::<u32>(so.ring_mask);
+        self.sqes.at::<Sqe>(0).add(idx as usize).write(Sqe {
+            opcode,
+            flags: 0,
+            ioprio: 0,
+            fd,
+            off,
+            addr: buf as u64,
+            len,
+            rw_flags: 0,
+            user_data,
+            buf_index: 0,
+            personality: 0,
+            splice_fd_in: 0,
+            addr3: 0,
+            pad: 0,
+        });
+        *self.sq_ring.at::<u32>(so.array).add(idx as usize) = idx;
+        tail_ptr.store(tail.wrapping_add(1), Ordering::Release);
+        self.nr_to_submit += 1;
+        true
+    }
+
+    /// Submit the queued entries and wait for at least `min_complete`
+    /// completions.
+    pub fn submit_and_wait(&mut self, min_complete: u32) -> Result<()> {
+        loop {
+            let ret = unsafe {
+                libc::syscall(
+                    libc::SYS_io_uring_enter,
+                    self.fd,
+                    self.nr_to_submit,
+                    min_complete,
+                    IORING_ENTER_GETEVENTS,
+                    ptr::null::<libc::sigset_t>(),
+                    0,
+                )
+            };
+            if ret >= 0 {
+                self.nr_to_submit -= ret as u32;
+                return Ok(());
+            }
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                bail!("io_uring_enter failed ({})", &err);
+            }
+        }
+    }
+
+    /// Reap a completion, returns (user_data, res).
+    pub fn pop(&mut self) -> Option<(u64, i32)> {
+        let co = &self.params.cq_off;
+        let head_ptr = Self::atomic(self.cq_ring.at(co.head));
+        let head = head_ptr.load(Ordering::Relaxed);
+        let tail = Self::atomic(self.cq_ring.at(co.tail)).load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+
+        let cqe = unsafe {
+            let idx = head & *self.cq_ring.at::<u32>(co.ring_mask);
+            &*self.cq_ring.at::<Cqe>(co.cqes).add(idx as usize)
+        };
+        let res = (cqe.user_data, cqe.res);
+        head_ptr.store(head.wrapping_add(1), Ordering::Release);
+        Some(res)
+    }
+}
+
+impl Drop for IoUring {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::io::AsRawFd;
+
+    fn run_one(ring: &mut IoUring, opcode: u8, fd: RawFd, buf: &mut [u8], off: u64) -> i32 {
+        assert!(unsafe { ring.push(opcode, fd, buf.as_mut_ptr(), buf.len() as u32, off, 7) });
+        ring.submit_and_wait(1).unwrap();
+        let (user_data, res) = ring.pop().unwrap();
+        assert_eq!(user_data, 7);
+        assert!(ring.pop().is_none());
+        res
+    }
+
+    #[test]
+    fn test_uring_read_write() {
+        let mut ring = match IoUring::new(4) {
+            Ok(v) => v,
+            Err(e) => {
+                println!("Skipping, io_uring unavailable ({:#})", &e);
+                return;
+            }
+        };
+        assert_eq!(ring.depth(), 4);
+
+        let path = std::env::temp_dir().join(format!("rd-hashd-test-uring-{}", std::process::id()));
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        let _ = std::fs::remove_file(&path);
+        let fd = file.as_raw_fd();
+
+        let mut wbuf: Vec<u8> = (0..8192).map(|i| (i % 253) as u8).collect();
+        assert_eq!(
+            run_one(&mut ring, IORING_OP_WRITE, fd, &mut wbuf, 4096),
+            8192
+        );
+        assert_eq!(file.metadata().unwrap().len(), 4096 + 8192);
+
+        let mut rbuf = vec![0u8; 8192];
+        assert_eq!(
+            run_one(&mut ring, IORING_OP_READ, fd, &mut rbuf, 4096),
+            8192
+        );
+        assert_eq!(rbuf, wbuf);
+
+        // Short read at the end of the file.
+        let mut rbuf = vec![0u8; 8192];
+        assert_eq!(
+            run_one(&mut ring, IORING_OP_READ, fd, &mut rbuf, 8192),
+            4096
+        );
+        assert_eq!(rbuf[..4096], wbuf[4096..]);
+
+        // Errors come back as negative errnos.
+        assert_eq!(
+            run_one(&mut ring, IORING_OP_READ, -1, &mut rbuf, 0),
+            -libc::EBADF
+        );
+    }
+
+    #[test]
+    fn test_uring_invalid_depth() {
+        if let Err(e) = IoUring::new(1) {
+            println!("Skipping, io_uring unavailable ({:#})", &e);
+            return;
+        }
+        // 0 depth fails with EINVAL which shouldn't be taken as io_uring
+        // being unavailable.
+        match IoUring::new(0) {
+            Ok(_) => panic!("io_uring with 0 depth should fail"),
+            Err(e) => assert!(!is_unavailable(&e)),
+        }
+    }
+}
@@ -8,7 +8,7 @@ use std::path::Path;
 use std::process::exit;
 use std::sync::Mutex;
 
-use super::{IoCostQoSOvr, JobSpec};
+use super::{Assertion, IoCostQoSOvr, JobSpec};
 use rd_agent_intf;
 use rd_util::*;
 
@@ -36,6 +36,7 @@ lazy_static::lazy_static! {
                  --iocost-from-sys        'Uses parameters from io.cost.{{model,qos}} instead of bench.json'
                  --keep-reports           'Prevents deleting expired report files'
                  --clear-reports          'Removes existing report files'
+                 --rep-seg                'Store reports in compressed segment files'
                  --force                  'Ignore missing system requirements and proceed'
                  --force-shadow-inode-prot-test 'Force shadow inode protection test'
                  --skip-shadow-inode-prot-test 'Assume shadow inodes are protected without testing'
@@ -119,10 +120,18 @@ pub enum Mode {
     Upload,
     Pack,
     Merge,
+    Compare,
     Deps,
     Doc,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Csv,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct Args {
@@ -152,6 +161,8 @@ pub struct Args {
     #[serde(skip)]
     pub clear_reports: bool,
     #[serde(skip)]
+    pub rep_seg: bool,
+    #[serde(skip)]
     pub force: bool,
     #[serde(skip)]
     pub force_shadow_inode_prot_test: bool,
@@ -166,6 +177,8 @@ pub struct Args {
     #[serde(skip)]
     pub rstat: u32,
     #[serde(skip)]
+    pub output_format: OutputFormat,
+    #[serde(skip)]
     pub merge_srcs: Vec<String>,
     #[serde(skip)]
     pub merge_by_id: bool,
@@ -176,6 +189,14 @@ pub struct Args {
     #[serde(skip)]
     pub merge_multiple: bool,
     #[serde(skip)]
+    pub compare_srcs: Vec<String>,
+    #[serde(skip)]
+    pub compare_threshold: f64,
+    #[serde(skip)]
+    pub compare_stdevs: f64,
+    #[serde(skip)]
+    pub compare_all: bool,
+    #[serde(skip)]
     pub upload_email: Option<String>,
     #[serde(skip)]
     pub upload_github: Option<String>,
@@ -207,6 +228,7 @@ impl Default for Args {
             iocost_from_sys: false,
             keep_reports: false,
             clear_reports: false,
+            rep_seg: false,
             force: false,
             force_shadow_inode_prot_test: false,
             skip_shadow_inode_prot_test: false,
@@ -214,11 +236,16 @@ impl Default for Args {
             verbosity: 0,
             logfile: None,
             rstat: 0,
+            output_format: OutputFormat::Text,
             merge_srcs: vec![],
             merge_by_id: false,
             merge_ignore_versions: false,
             merge_ignore_sysreqs: false,
             merge_multiple: false,
+            compare_srcs: vec![],
+            compare_threshold: Self::DFL_COMPARE_THRESHOLD,
+            compare_stdevs: Self::DFL_COMPARE_STDEVS,
+            compare_all: false,
             upload_email: None,
             upload_github: None,
             upload_url: None,
@@ -230,6 +257,8 @@ impl Default for Args {
 impl Args {
     pub const RB_BENCH_FILENAME: &'static str = "rb-bench.json";
     pub const DFL_MEM_PROFILE: u32 = 16;
+    pub const DFL_COMPARE_THRESHOLD: f64 = 0.05;
+    pub const DFL_COMPARE_STDEVS: f64 = 2.0;
 
     pub fn set_help_body(help: &'static str) {
         *HELP_BODY.lock().unwrap() = help;
@@ -261,6 +290,31 @@ impl Args {
         propset
     }
 
+    /// Split "KEY=VAL,...,assert=COND,COND..." into the regular properties
+    /// and the assertions. As assertions may contain ',' and '=', `assert`
+    /// consumes the rest of the group.
+    fn split_asserts(group: &str) -> (&str, Option<&str>) {
+        const KEY: &str = "assert=";
+        if group.starts_with(KEY) {
+            return ("", Some(&group[KEY.len()..]));
+        }
+        match group.find(&format!(",{}", KEY)) {
+            Some(pos) => (&group[..pos], Some(&group[pos + 1 + KEY.len()..])),
+            None => (group, None),
+        }
+    }
+
+    fn load_asserts_file(path: &str) -> Result<Vec<String>> {
+        let body = std::fs::read_to_string(path)
+            .with_context(|| format!("Reading assertions file {:?}", path))?;
+        Ok(body
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| line.len() > 0 && !line.starts_with('#'))
+            .map(|line| line.to_string())
+            .collect())
+    }
+
     pub fn parse_job_spec(spec: &str) -> Result<JobSpec> {
         let mut groups = spec.split(':');
 
@@ -272,20 +326,49 @@ impl Args {
         let mut props = vec![];
         let mut id = None;
         let mut passive = None;
+        let mut asserts = vec![];
 
         for group in groups {
+            let (group, group_asserts) = Self::split_asserts(group);
             let mut propset = Self::parse_propset(group);
+
+            let mut has_asserts = false;
+            if let Some(path) = propset.remove("assert-file") {
+                asserts.append(&mut Self::load_asserts_file(&path)?);
+                has_asserts = true;
+            }
+            if let Some(group_asserts) = group_asserts {
+                for assert in group_asserts.split(',').map(|x| x.trim()) {
+                    if assert.len() > 0 {
+                        asserts.push(assert.to_string());
+                    }
+                }
+                has_asserts = true;
+            }
+
+            // Don't let a group which only carried assertions show up as
+            // an empty property group or reset id and passive.
+            if has_asserts && propset.len() == 0 && props.len() > 0 {
+                continue;
+            }
+
             id = propset.remove("id");
             passive = propset.remove("passive");
             props.push(propset);
         }
 
+        for assert in asserts.iter() {
+            Assertion::parse(assert)?;
+        }
+
         // Make sure there always is the first group.
         if props.len() == 0 {
             props.push(Default::default());
         }
 
-        Ok(JobSpec::new(kind, id.as_deref(), passive.as_deref(), props))
+        let mut job_spec = JobSpec::new(kind, id.as_deref(), passive.as_deref(), props);
+        job_spec.asserts = asserts;
+        Ok(job_spec)
     }
 
     fn parse_job_specs(subm: &clap::ArgMatches) -> Result<Vec<JobSpec>> {
@@ -354,6 +437,18 @@ impl Args {
             _ => {}
         }
 
+        if let Mode::Format | Mode::Summary = mode {
+            self.output_format = match subm.value_of("output-format") {
+                None | Some("text") => OutputFormat::Text,
+                Some("json") => OutputFormat::Json,
+                Some("csv") => OutputFormat::Csv,
+                Some(v) => {
+                    error!("Unknown output format {:?}", v);
+                    exit(1);
+                }
+            };
+        }
+
         match Self::parse_job_specs(subm) {
             Ok(job_specs) => {
                 if job_specs.len() > 0 {
@@ -392,6 +487,12 @@ impl JsonArgs for Args {
             .takes_value(true)
             .number_of_values(1)
             .help("Benchmark job file");
+        let output_format_arg = clap::Arg::with_name("output-format")
+            .long("output-format")
+            .short("O")
+            .takes_value(true)
+            .possible_values(&["text", "json", "csv"])
+            .help("Output format (default: text)");
         let job_spec_arg = clap::Arg::with_name("spec")
             .multiple(true)
             .help("Benchmark job spec - \"BENCH_TYPE[:KEY[=VAL][,KEY[=VAL]...]]...\"");
@@ -431,6 +532,7 @@ impl JsonArgs for Args {
             .subcommand(
                 clap::SubCommand::with_name("format")
                     .about("Formats benchmark results")
+                    .arg(output_format_arg.clone())
                     .arg(
                         clap::Arg::with_name("rstat")
                             .long("rstat")
@@ -446,6 +548,7 @@ impl JsonArgs for Args {
             .subcommand(
                 clap::SubCommand::with_name("summary")
                     .about("Summarizes benchmark results")
+                    .arg(output_format_arg.clone())
                     .arg(job_file_arg.clone())
                     .arg(job_spec_arg.clone()),
             )
@@ -482,6 +585,42 @@ impl JsonArgs for Args {
                             .help("Allow more than one result per kind (and optionally id)")
                     )
             )
+            .subcommand(
+                clap::SubCommand::with_name("compare")
+                    .about("Compares the results of matching benchmarks in two result files")
+                    .arg(
+                        clap::Arg::with_name("A")
+                            .required(true)
+                            .help("Base result file")
+                    )
+                    .arg(
+                        clap::Arg::with_name("B")
+                            .required(true)
+                            .help("Result file to compare against the base")
+                    )
+                    .arg(
+                        clap::Arg::with_name("threshold")
+                            .long("threshold")
+                            .short("t")
+                            .takes_value(true)
+                            .number_of_values(1)
+                            .help("Flag deltas larger than the fraction (default: 5%)")
+                    )
+                    .arg(
+                        clap::Arg::with_name("stdevs")
+                            .long("stdevs")
+                            .short("s")
+                            .takes_value(true)
+                            .number_of_values(1)
+                            .help("Flag deltas larger than the multiple of stdev if known (default: 2.0)")
+                    )
+                    .arg(
+                        clap::Arg::with_name("all")
+                            .long("all")
+                            .short("a")
+                            .help("Show unchanged metrics too")
+                    )
+            )
             .subcommand(
                 clap::App::new("upload")
                     .about("Upload results to community database")
@@ -660,6 +799,7 @@ impl JsonArgs for Args {
         self.iocost_from_sys = matches.is_present("iocost-from-sys");
         self.keep_reports = matches.is_present("keep-reports");
         self.clear_reports = matches.is_present("clear-reports");
+        self.rep_seg = matches.is_present("rep-seg");
         self.force = matches.is_present("force");
         self.force_shadow_inode_prot_test = matches.is_present("force-shadow-inode-prot-test");
         self.skip_shadow_inode_prot_test = matches.is_present("skip-shadow-inode-prot-test");
@@ -699,6 +839,23 @@ impl JsonArgs for Args {
                     .collect();
                 false
             }
+            ("compare", Some(subm)) => {
+                self.mode = Mode::Compare;
+                self.compare_srcs = vec![
+                    subm.value_of("A").unwrap().to_string(),
+                    subm.value_of("B").unwrap().to_string(),
+                ];
+                self.compare_threshold = match subm.value_of("threshold") {
+                    Some(v) => parse_frac(v).expect("Invalid --threshold"),
+                    None => Self::DFL_COMPARE_THRESHOLD,
+                };
+                self.compare_stdevs = match subm.value_of("stdevs") {
+                    Some(v) => v.parse::<f64>().expect("Invalid --stdevs"),
+                    None => Self::DFL_COMPARE_STDEVS,
+                };
+                self.compare_all = subm.is_present("all");
+                false
+            }
             ("deps", Some(_subm)) => {
                 self.mode = Mode::Deps;
                 false
@@ -715,7 +872,11 @@ impl JsonArgs for Args {
             _ => false,
         };
 
-        if self.mode != Mode::Doc && self.mode != Mode::Deps && self.result.len() == 0 {
+        if self.mode != Mode::Doc
+            && self.mode != Mode::Deps
+            && self.mode != Mode::Compare
+            && self.result.len() == 0
+        {
             error!("{:?} requires --result", &self.mode);
             exit(1);
         }
@@ -723,3 +884,43 @@ impl JsonArgs for Args {
         updated
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_job_spec_asserts() {
+        let spec = Args::parse_job_spec(
+            "protection:id=b:scenario=mem-hog,load=0.8,id=a:assert=isol-50>=0.9,lat-imp<=0.2",
+        )
+        .unwrap();
+        assert_eq!(spec.id.as_deref(), Some("a"));
+        assert_eq!(spec.props.len(), 2);
+        assert_eq!(spec.props[1]["load"], "0.8");
+        assert!(!spec.props[1].contains_key("id"));
+        assert_eq!(spec.asserts, vec!["isol-50>=0.9", "lat-imp<=0.2"]);
+
+        let spec = Args::parse_job_spec("storage:loops=1,assert=mof>=1.2").unwrap();
+        assert_eq!(spec.props.len(), 1);
+        assert_eq!(spec.props[0]["loops"], "1");
+        assert_eq!(spec.asserts, vec!["mof>=1.2"]);
+
+        assert!(Args::parse_job_spec("storage:assert=mof").is_err());
+    }
+
+    #[test]
+    fn test_parse_job_spec_id_passive() {
+        // The last property group determines id and passive.
+        let spec = Args::parse_job_spec("storage:id=a,passive=all:loops=1").unwrap();
+        assert_eq!(spec.id, None);
+        assert_eq!(spec.passive, None);
+        assert_eq!(spec.props.len(), 2);
+
+        let spec = Args::parse_job_spec("storage:loops=1:id=a,passive=all").unwrap();
+        assert_eq!(spec.id.as_deref(), Some("a"));
+        assert_eq!(spec.passive.as_deref(), Some("all"));
+        assert_eq!(spec.props.len(), 2);
+        assert_eq!(spec.props[1].len(), 0);
+    }
+}
@@ -0,0 +1,121 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use rd_util::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AssertOp {
+    Ge,
+    Le,
+    Eq,
+    Ne,
+    Gt,
+    Lt,
+}
+
+impl AssertOp {
+    // Two-char operators must come first.
+    const OPS: [(&'static str, AssertOp); 6] = [
+        (">=", AssertOp::Ge),
+        ("<=", AssertOp::Le),
+        ("==", AssertOp::Eq),
+        ("!=", AssertOp::Ne),
+        (">", AssertOp::Gt),
+        ("<", AssertOp::Lt),
+    ];
+
+    pub fn test(&self, lhs: f64, rhs: f64) -> bool {
+        match self {
+            Self::Ge => lhs >= rhs,
+            Self::Le => lhs <= rhs,
+            Self::Eq => lhs == rhs,
+            Self::Ne => lhs != rhs,
+            Self::Gt => lhs > rhs,
+            Self::Lt => lhs < rhs,
+        }
+    }
+}
+
+impl std::fmt::Display for AssertOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let tok = Self::OPS.iter().find(|(_, op)| op == self).unwrap().0;
+        write!(f, "{}", tok)
+    }
+}
+
+/// A threshold assertion on a result metric, e.g. "isol-50>=0.9". The
+/// threshold may be a plain number, a percentage or a duration.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Assertion {
+    pub metric: String,
+    pub op: AssertOp,
+    pub thr: f64,
+}
+
+impl Assertion {
+    fn parse_thr(input: &str) -> Result<f64> {
+        if input.ends_with('%') {
+            return parse_frac(input);
+        }
+        if let Ok(v) = input.parse::<f64>() {
+            return Ok(v);
+        }
+        parse_duration(input)
+    }
+
+    pub fn parse(input: &str) -> Result<Self> {
+        for (tok, op) in AssertOp::OPS.iter() {
+            if let Some(pos) = input.find(tok) {
+                let metric = input[..pos].trim();
+                let thr = input[pos + tok.len()..].trim();
+                if metric.len() == 0 || thr.len() == 0 {
+                    bail!("invalid assertion {:?}", input);
+                }
+                let thr = match Self::parse_thr(thr) {
+                    Ok(v) => v,
+                    Err(_) => bail!("invalid threshold {:?} in assertion {:?}", thr, input),
+                };
+                return Ok(Self {
+                    metric: metric.to_string(),
+                    op: *op,
+                    thr,
+                });
+            }
+        }
+        bail!("no comparison operator in assertion {:?}", input)
+    }
+
+    pub fn test(&self, val: f64) -> bool {
+        self.op.test(val, self.thr)
+    }
+}
+
+impl std::fmt::Display for Assertion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}{}", &self.metric, self.op, self.thr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assertion_parse() {
+        let a = Assertion::parse("isol-50>=0.9").unwrap();
+        assert_eq!(a.metric, "isol-50");
+        assert_eq!(a.op, AssertOp::Ge);
+        assert_eq!(a.thr, 0.9);
+        assert!(a.test(0.9) && !a.test(0.89));
+
+        let a = Assertion::parse(" lat-imp < 20% ").unwrap();
+        assert_eq!((a.op, a.thr), (AssertOp::Lt, 0.2));
+        let a = Assertion::parse("base_lat<=150m").unwrap();
+        assert!((a.thr - 0.15).abs() < 0.000_001);
+
+        assert!(Assertion::parse("isol-50").is_err());
+        assert!(Assertion::parse(">=0.9").is_err());
+        assert!(Assertion::parse("isol-50>=high").is_err());
+    }
+}
@@ -37,6 +37,8 @@ pub struct JobSpec {
     pub id: Option<String>,
     pub passive: Option<String>,
     pub props: JobProps,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub asserts: Vec<String>,
 }
 
 impl JobSpec {
@@ -62,6 +64,7 @@ impl JobSpec {
             id: id.map(Into::into),
             passive: passive.map(Into::into),
             props,
+            asserts: vec![],
         }
     }
 
@@ -70,6 +73,10 @@ impl JobSpec {
         let mut left = self.clone();
         let mut right = other.clone();
 
+        // Assertions only affect how the result is judged.
+        left.asserts.clear();
+        right.asserts.clear();
+
         for key in IGN_PROP_KEYS.iter() {
             left.props[0].remove(*key);
             right.props[0].remove(*key);
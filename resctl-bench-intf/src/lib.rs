@@ -2,10 +2,12 @@
 use rd_util::*;
 
 pub mod args;
+pub mod assertion;
 pub mod iocost;
 pub mod jobspec;
 
-pub use args::{set_bench_list, Args, Mode, GITHUB_DOC_LINK};
+pub use args::{set_bench_list, Args, Mode, OutputFormat, GITHUB_DOC_LINK};
+pub use assertion::{AssertOp, Assertion};
 pub use iocost::IoCostQoSOvr;
 pub use jobspec::{format_job_props, JobProps, JobSpec};
 
@@ -0,0 +1,128 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+use log::warn;
+use serde::Serialize;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+use super::compare::flatten_result;
+use super::job::JobCtx;
+use resctl_bench_intf::Assertion;
+
+/// Exit status when one or more assertions are violated.
+pub const EXIT_ASSERT_VIOLATED: i32 = 3;
+
+lazy_static::lazy_static! {
+    static ref VIOLATIONS: Mutex<Vec<Outcome>> = Mutex::new(vec![]);
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Outcome {
+    pub job: String,
+    pub kind: String,
+    pub id: Option<String>,
+    pub assertion: String,
+    pub metric: String,
+    pub value: Option<f64>,
+    pub passed: bool,
+    pub error: Option<String>,
+}
+
+/// Evaluate the assertions of a completed job. Metrics are looked up in
+/// the job's key metrics first and then in the flattened result. A
+/// missing metric fails the assertion.
+pub fn evaluate(jctx: &JobCtx) -> Vec<Outcome> {
+    let spec = &jctx.data.spec;
+    let result = match (spec.asserts.len(), jctx.data.result.as_ref()) {
+        (0, _) | (_, None) => return vec![],
+        (_, Some(v)) => v,
+    };
+
+    let key_metrics = match jctx.job.as_ref().map(|job| job.key_metrics(&jctx.data)) {
+        Some(Ok(v)) => v,
+        Some(Err(e)) => {
+            warn!(
+                "assertion: Failed to determine key metrics of {} ({:#})",
+                spec, &e
+            );
+            Default::default()
+        }
+        None => Default::default(),
+    };
+    let (flat, _) = flatten_result(result);
+
+    let mut outcomes = vec![];
+    for input in spec.asserts.iter() {
+        let mut outcome = Outcome {
+            job: format!("{}", spec),
+            kind: spec.kind.clone(),
+            id: spec.id.clone(),
+            assertion: input.clone(),
+            metric: String::new(),
+            value: None,
+            passed: false,
+            error: None,
+        };
+
+        match Assertion::parse(input) {
+            Ok(asrt) => {
+                outcome.metric = asrt.metric.clone();
+                outcome.value = key_metrics
+                    .get(&asrt.metric)
+                    .cloned()
+                    .or_else(|| flat.get(&asrt.metric).map(|m| m.val));
+                match outcome.value {
+                    Some(v) => outcome.passed = asrt.test(v),
+                    None => outcome.error = Some(format!("unknown metric {:?}", &asrt.metric)),
+                }
+            }
+            Err(e) => outcome.error = Some(format!("{:#}", &e)),
+        }
+        outcomes.push(outcome);
+    }
+    outcomes
+}
+
+pub fn format_outcomes<'a>(out: &mut Box<dyn Write + 'a>, outcomes: &[Outcome]) {
+    if outcomes.len() == 0 {
+        return;
+    }
+
+    writeln!(out, "\nAssertions:\n").unwrap();
+    for oc in outcomes.iter() {
+        let detail = match (oc.value, oc.error.as_ref()) {
+            (_, Some(e)) => e.clone(),
+            (Some(v), None) => format!("{}={}", &oc.metric, v),
+            (None, None) => "".to_string(),
+        };
+        writeln!(
+            out,
+            "  {} {:<32} {}",
+            if oc.passed { "PASS" } else { "FAIL" },
+            &oc.assertion,
+            &detail
+        )
+        .unwrap();
+    }
+}
+
+pub fn record_violations(outcomes: Vec<Outcome>) {
+    VIOLATIONS
+        .lock()
+        .unwrap()
+        .extend(outcomes.into_iter().filter(|oc| !oc.passed));
+}
+
+/// If any assertion was violated, print the machine readable report and
+/// exit with EXIT_ASSERT_VIOLATED.
+pub fn exit_on_violations() {
+    let violations = VIOLATIONS.lock().unwrap();
+    if violations.len() == 0 {
+        return;
+    }
+
+    println!(
+        "ASSERT-REPORT {}",
+        serde_json::to_string(&serde_json::json!({ "violations": &*violations })).unwrap()
+    );
+    std::process::exit(EXIT_ASSERT_VIOLATED);
+}
@@ -239,6 +239,7 @@ mod iocost_qos;
 mod iocost_tune;
 mod merge_info;
 mod protection;
+mod scenario;
 mod storage;
 
 pub fn init_benchs() -> () {
@@ -249,4 +250,5 @@ pub fn init_benchs() -> () {
     register_bench(Box::new(iocost_qos::IoCostQoSBench {}));
     register_bench(Box::new(iocost_tune::IoCostTuneBench {}));
     register_bench(Box::new(merge_info::MergeInfoBench {}));
+    register_bench(Box::new(scenario::ScenarioBench {}));
 }
@@ -455,6 +455,44 @@ impl IoCostQoSJob {
             nr_reports,
         })
     }
+
+    pub fn key_metrics_of(rec: &IoCostQoSRecord, res: &IoCostQoSResult) -> BTreeMap<String, f64> {
+        let mut metrics = BTreeMap::new();
+        for (i, (recr, resr)) in rec.runs.iter().zip(res.runs.iter()).enumerate() {
+            let (recr, resr) = match (recr, resr) {
+                (Some(recr), Some(resr)) => (recr, resr),
+                _ => continue,
+            };
+            let pfx = format!("run[{}].", i);
+            let mut insert = |key: &str, v: f64| {
+                metrics.insert(format!("{}{}", &pfx, key), v);
+            };
+
+            if let Some(qos) = recr.qos.as_ref() {
+                insert("min", qos.min);
+                insert("max", qos.max);
+            }
+            for (k, v) in StorageJob::key_metrics_of(&resr.stor) {
+                insert(&k, v);
+            }
+            for (k, v) in ProtectionJob::key_metrics_of(&resr.prot) {
+                insert(&k, v);
+            }
+            if let Some(v) = resr.adjusted_mem_offload_factor {
+                insert("amof", v);
+            }
+            if let Some(v) = resr.adjusted_mem_offload_delta {
+                insert("amof-delta", v);
+            }
+            for (pct, v) in resr.vrate.iter() {
+                match pct.as_str() {
+                    "mean" => insert("vrate", *v),
+                    pct => insert(&format!("vrate-{}", pct), *v),
+                }
+            }
+        }
+        metrics
+    }
 }
 
 impl Job for IoCostQoSJob {
@@ -879,4 +917,11 @@ impl Job for IoCostQoSJob {
 
         Ok(())
     }
+
+    fn key_metrics(&self, data: &JobData) -> Result<BTreeMap<String, f64>> {
+        Ok(Self::key_metrics_of(
+            &data.parse_record()?,
+            &data.parse_result()?,
+        ))
+    }
 }
@@ -2850,6 +2850,36 @@ impl Job for IoCostTuneJob {
         self.format_remarks(out, &res);
         Ok(())
     }
+
+    fn key_metrics(&self, data: &JobData) -> Result<BTreeMap<String, f64>> {
+        let res: IoCostTuneResult = data.parse_result()?;
+        let mut metrics = BTreeMap::new();
+        for (name, sol) in res.solutions.iter() {
+            let (qos, model) = (&sol.qos, &sol.model);
+            for (key, v) in &[
+                ("isol", sol.isol),
+                ("mof", sol.mem_offload_factor),
+                ("amof", sol.adjusted_mem_offload_factor),
+                ("amof-delta", sol.adjusted_mem_offload_delta),
+                ("scale-factor", sol.scale_factor),
+                ("qos.rpct", qos.rpct),
+                ("qos.rlat", qos.rlat as f64),
+                ("qos.wpct", qos.wpct),
+                ("qos.wlat", qos.wlat as f64),
+                ("qos.min", qos.min),
+                ("qos.max", qos.max),
+                ("model.rbps", model.rbps as f64),
+                ("model.rseqiops", model.rseqiops as f64),
+                ("model.rrandiops", model.rrandiops as f64),
+                ("model.wbps", model.wbps as f64),
+                ("model.wseqiops", model.wseqiops as f64),
+                ("model.wrandiops", model.wrandiops as f64),
+            ] {
+                metrics.insert(format!("solution.{}.{}", name, key), *v);
+            }
+        }
+        Ok(metrics)
+    }
 }
 
 #[cfg(test)]
@@ -1,9 +1,16 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
 use super::*;
+use std::cell::RefCell;
 use std::collections::BTreeMap;
+use std::ops::{Deref, DerefMut};
 
+mod cpu_hog;
+mod io_hog;
 mod mem_hog;
 pub mod mem_hog_tune;
+pub use cpu_hog::{CpuHog, CpuHogRecord, CpuHogResult, CpuHogSpeed};
+pub use io_hog::{IoHog, IoHogKind, IoHogRecord, IoHogResult};
+use mem_hog::FailAcc;
 pub use mem_hog::{MemHog, MemHogRecord, MemHogResult, MemHogSpeed};
 pub use mem_hog_tune::{MemHogTune, MemHogTuneRecord, MemHogTuneResult};
 
@@ -63,16 +70,268 @@ fn ws_status(mon: &WorkloadMon, af: &AgentFiles) -> Result<(bool, String)> {
     Ok((false, status))
 }
 
+/// The failure of a hog run - (target_duration, failed_after, fail_msg).
+type HogFailure = Option<(f64, f64, String)>;
+
+trait HogRun {
+    fn failed(&self) -> &HogFailure;
+}
+
+/// Stabilize hashd at `load` for a hog run. On the first run, also hold
+/// for the baseline and return its period.
+fn prepare_hog_run(
+    rctx: &mut RunCtx,
+    load: f64,
+    run_desc: &str,
+    do_base_hold: bool,
+) -> Result<Option<(u64, u64)>> {
+    info!(
+        "protection: Stabilizing hashd at {}% for {}",
+        format_pct(load),
+        run_desc
+    );
+    warm_up_hashd(rctx, load).context("Warming up hashd")?;
+
+    match do_base_hold {
+        true => Ok(Some(baseline_hold(rctx)?)),
+        false => Ok(None),
+    }
+}
+
+// A hog runs as a sideload under the sideloader in Slice::Side and as a
+// sysload otherwise.
+fn hog_svc_name(name: &str, slice: Slice) -> String {
+    match slice {
+        Slice::Side => rd_agent_intf::sideload_svc_name(name),
+        _ => rd_agent_intf::sysload_svc_name(name),
+    }
+}
+
+/// Run a hog which keeps running until stopped for `timeout` and return
+/// how it failed, if it did, and the period it ran. The hog exiting early
+/// is a failure too.
+fn run_cont_hog<F>(
+    rctx: &mut RunCtx,
+    name: &str,
+    def_id: &str,
+    slice: Slice,
+    timeout: f64,
+    status_fn: F,
+) -> Result<(HogFailure, (u64, u64))>
+where
+    F: FnMut(&WorkloadMon, &AgentFiles) -> Result<(bool, String)>,
+{
+    info!(
+        "protection: Starting {} in {} for {}",
+        name,
+        slice.name(),
+        format_duration(timeout)
+    );
+    let started_at = unix_now();
+    let mut mon = WorkloadMon::default().hashd();
+    match slice {
+        Slice::Side => {
+            rctx.start_sideload(name, def_id)?;
+            mon = mon.sideload(name);
+        }
+        _ => {
+            rctx.start_sysload(name, def_id)?;
+            mon = mon.sysload(name);
+        }
+    }
+
+    let mut failed = None;
+    match mon
+        .timeout(Duration::from_secs_f64(timeout))
+        .monitor_with_status(rctx, status_fn)
+    {
+        Ok(false) => {}
+        res => {
+            let msg = match res {
+                Ok(_) => format!("{} exited prematurely", name),
+                Err(e) => format!("{}", &e),
+            };
+            failed = Some((
+                timeout,
+                ((unix_now() - started_at) as f64).min(timeout),
+                msg,
+            ));
+        }
+    }
+    let period = (started_at, unix_now());
+
+    match slice {
+        Slice::Side => rctx.stop_sideload(name),
+        _ => rctx.stop_sysload(name),
+    }
+
+    Ok((failed, period))
+}
+
+/// Hog runs can fail in two ways - either from agent or hashd not being
+/// protected enough or some other failures. This function wraps the result
+/// of a run to perform failure handling for both cases.
+fn recover_hog_run<T: HogRun>(
+    rctx: &mut RunCtx,
+    run_name: &str,
+    res: Result<(T, Option<(u64, u64)>)>,
+) -> Result<(T, Option<(u64, u64)>)> {
+    match res {
+        Ok((hog_run, bper)) => {
+            if let Some((target_dur, failed_after, fail_msg)) = hog_run.failed().as_ref() {
+                info!(
+                    "protection: {} failed after {}% ({})",
+                    run_name,
+                    format_pct(failed_after / target_dur),
+                    fail_msg
+                );
+                rctx.restart_agent()?;
+            }
+            Ok((hog_run, bper))
+        }
+        Err(e) => {
+            info!("protection: {} failed ({:#})", run_name, &e);
+            rctx.restart_agent()?;
+            Err(e)
+        }
+    }
+}
+
+type RestoreFn = Box<dyn FnOnce(&RunCtx) -> Result<()>>;
+
+/// Restores the agent configurations which a scenario changed when
+/// dropped so that they're restored on the error paths too. The scenario
+/// runs through the guard which derefs to the RunCtx.
+struct RestoreGuard<'x, 'a, 'b> {
+    rctx: &'x mut RunCtx<'a, 'b>,
+    restores: Vec<(&'static str, RestoreFn)>,
+}
+
+impl<'x, 'a, 'b> RestoreGuard<'x, 'a, 'b> {
+    fn new(rctx: &'x mut RunCtx<'a, 'b>) -> Self {
+        Self {
+            rctx,
+            restores: vec![],
+        }
+    }
+
+    fn on_restore<F>(&mut self, what: &'static str, restore: F)
+    where
+        F: FnOnce(&RunCtx) -> Result<()> + 'static,
+    {
+        self.restores.push((what, Box::new(restore)));
+    }
+
+    fn restore_all(&mut self) -> Result<()> {
+        let mut res = Ok(());
+        while let Some((what, restore)) = self.restores.pop() {
+            if !self.rctx.agent_running() {
+                warn!("protection: Agent not running, can't restore {}", what);
+                continue;
+            }
+            if let Err(e) = restore(self.rctx) {
+                warn!("protection: Failed to restore {} ({:#})", what, &e);
+                if res.is_ok() {
+                    res = Err(e);
+                }
+            }
+        }
+        res
+    }
+
+    /// Restore explicitly on success so that the failures are reported.
+    fn finish(mut self) -> Result<()> {
+        self.restore_all()
+    }
+}
+
+impl<'x, 'a, 'b> Deref for RestoreGuard<'x, 'a, 'b> {
+    type Target = RunCtx<'a, 'b>;
+    fn deref(&self) -> &Self::Target {
+        self.rctx
+    }
+}
+
+impl<'x, 'a, 'b> DerefMut for RestoreGuard<'x, 'a, 'b> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.rctx
+    }
+}
+
+impl<'x, 'a, 'b> Drop for RestoreGuard<'x, 'a, 'b> {
+    fn drop(&mut self) {
+        let _ = self.restore_all();
+    }
+}
+
+/// Determine the baseline latency. We need it for the latency impact study.
+/// Run it first.
+fn study_base_lat(rctx: &RunCtx, base_period: (u64, u64)) -> Result<(f64, f64)> {
+    let mut study_base_lat = StudyMean::new(|arg| [arg.rep.hashd[0].lat.ctl].repeat(arg.cnt));
+    Studies::new()
+        .add(&mut study_base_lat)
+        .run(rctx, base_period)?;
+    let (base_lat, base_lat_stdev, _, _) = study_base_lat.result();
+    Ok((base_lat, base_lat_stdev))
+}
+
+type PctsStudy<'a> = StudyMeanPcts<f64, Box<dyn FnMut(&SelArg) -> Vec<f64> + 'a>>;
+
+/// Study work isolation and latency impact. The former is defined as
+/// observed rps divided by the baseline, [0.0, 1.0] with 1.0 indicating the
+/// perfect isolation. The latter is defined as the proportion of the
+/// latency increase over the baseline, [0.0, 1.0] with 0.0 indicating no
+/// latency impact.
+///
+/// If a run failed because agent or hashd couldn't be kept healthy enough,
+/// the rest of the run is considered to have completely failed isolation -
+/// 0% isol, 100% lat-imp.
+fn isol_lat_imp_studies<'a>(
+    last_nr_done: &'a RefCell<Option<u64>>,
+    base_rps: f64,
+    base_lat: f64,
+    fail_acc: &FailAcc,
+) -> (PctsStudy<'a>, PctsStudy<'a>) {
+    let mut study_isol: PctsStudy = StudyMeanPcts::new(
+        Box::new(sel_delta_calc(
+            |arg| arg.rep.hashd[0].nr_done,
+            move |arg, cur, last| MemHog::calc_isol((cur - last) as f64 / arg.dur, base_rps),
+            last_nr_done,
+        )),
+        None,
+    );
+    let mut study_lat_imp: PctsStudy = StudyMeanPcts::new(
+        Box::new(move |arg: &SelArg| {
+            [MemHog::calc_lat_imp(
+                arg.rep.hashd[0].lat.ctl.max(base_lat),
+                base_lat,
+            )]
+            .repeat(arg.cnt)
+        }),
+        None,
+    );
+
+    for _ in 0..fail_acc.secs() {
+        study_isol.study_data(&[0.0]).unwrap();
+        study_lat_imp.study_data(&[1.0]).unwrap();
+    }
+    (study_isol, study_lat_imp)
+}
+
 #[derive(Clone, Debug)]
 pub enum Scenario {
     MemHog(MemHog),
     MemHogTune(MemHogTune),
+    CpuHog(CpuHog),
+    IoHog(IoHog),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum ScenarioRecord {
     MemHog(MemHogRecord),
     MemHogTune(MemHogTuneRecord),
+    CpuHog(CpuHogRecord),
+    IoHog(IoHogRecord),
 }
 
 impl ScenarioRecord {
@@ -91,12 +350,30 @@ impl ScenarioRecord {
             _ => None,
         }
     }
+
+    #[allow(dead_code)]
+    pub fn as_cpu_hog<'a>(&'a self) -> Option<&'a CpuHogRecord> {
+        match self {
+            Self::CpuHog(hog) => Some(hog),
+            _ => None,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn as_io_hog<'a>(&'a self) -> Option<&'a IoHogRecord> {
+        match self {
+            Self::IoHog(hog) => Some(hog),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum ScenarioResult {
     MemHog(MemHogResult),
     MemHogTune(MemHogTuneResult),
+    CpuHog(CpuHogResult),
+    IoHog(IoHogResult),
 }
 
 impl ScenarioResult {
@@ -115,6 +392,22 @@ impl ScenarioResult {
             _ => None,
         }
     }
+
+    #[allow(dead_code)]
+    pub fn as_cpu_hog<'a>(&'a self) -> Option<&'a CpuHogResult> {
+        match self {
+            Self::CpuHog(hog) => Some(hog),
+            _ => None,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn as_io_hog<'a>(&'a self) -> Option<&'a IoHogResult> {
+        match self {
+            Self::IoHog(hog) => Some(hog),
+            _ => None,
+        }
+    }
 }
 
 impl Scenario {
@@ -134,6 +427,22 @@ impl Scenario {
         }
     }
 
+    #[allow(dead_code)]
+    pub fn as_cpu_hog<'a>(&'a self) -> Option<&'a CpuHog> {
+        match self {
+            Self::CpuHog(hog) => Some(hog),
+            _ => None,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn as_io_hog<'a>(&'a self) -> Option<&'a IoHog> {
+        match self {
+            Self::IoHog(hog) => Some(hog),
+            _ => None,
+        }
+    }
+
     fn parse(mut props: BTreeMap<String, String>) -> Result<Self> {
         match props.remove("scenario").as_deref() {
             Some("mem-hog") => {
@@ -180,6 +489,80 @@ impl Scenario {
                 }
                 Ok(Self::MemHogTune(tune))
             }
+            Some("cpu-hog") => {
+                let mut hog = CpuHog::default();
+                for (k, v) in props.iter() {
+                    match k.as_str() {
+                        "loops" => hog.loops = v.parse::<u32>()?,
+                        "load" => hog.load = parse_frac(v)?,
+                        "speed" => hog.speed = CpuHogSpeed::from_str(v)?,
+                        "slice" => {
+                            hog.slice = match v.as_str() {
+                                "side" => Slice::Side,
+                                "sys" => Slice::Sys,
+                                v => bail!("\"slice\" should be either side or sys, not {:?}", v),
+                            }
+                        }
+                        "headroom" => hog.headroom = Some(parse_frac(v)?),
+                        "weight" => hog.weight = Some(v.parse::<u32>()?),
+                        "dur" => hog.dur = parse_duration(v)?,
+                        k => bail!("unknown cpu-hog property {:?}", k),
+                    }
+                }
+                if hog.loops == 0 || hog.load == 0.0 || hog.dur == 0.0 {
+                    bail!("\"loops\", \"load\" and \"dur\" can't be 0");
+                }
+                if let Some(weight) = hog.weight {
+                    if weight < 1 || weight > 10000 {
+                        bail!("\"weight\" should be in [1, 10000]");
+                    }
+                }
+                Ok(Self::CpuHog(hog))
+            }
+            Some("io-hog") => {
+                let mut hog = IoHog::default();
+                for (k, v) in props.iter() {
+                    match k.as_str() {
+                        "loops" => hog.loops = v.parse::<u32>()?,
+                        "load" => hog.load = parse_frac(v)?,
+                        "kind" => hog.kind = IoHogKind::from_str(v)?,
+                        "slice" => {
+                            hog.slice = match v.as_str() {
+                                "side" => Slice::Side,
+                                "sys" => Slice::Sys,
+                                v => bail!("\"slice\" should be either side or sys, not {:?}", v),
+                            }
+                        }
+                        "iocost" => {
+                            hog.iocost = match v.as_str() {
+                                "on" => vec![true],
+                                "off" => vec![false],
+                                "both" => vec![true, false],
+                                v => bail!(
+                                    "\"iocost\" should be one of on, off or both, not {:?}",
+                                    v
+                                ),
+                            }
+                        }
+                        "weights" => {
+                            hog.weights = vec![];
+                            for w in v.split('/').filter(|w| w.len() > 0) {
+                                let w = w.parse::<u32>()?;
+                                if w < 1 || w > 10000 {
+                                    bail!("\"weights\" should be in [1, 10000]");
+                                }
+                                hog.weights.push(w);
+                            }
+                        }
+                        "dur" => hog.dur = parse_duration(v)?,
+                        k => bail!("unknown io-hog property {:?}", k),
+                    }
+                }
+                if hog.loops == 0 || hog.load == 0.0 || hog.dur == 0.0 {
+                    bail!("\"loops\", \"load\" and \"dur\" can't be 0");
+                }
+                Ok(Self::IoHog(hog))
+            }
             _ => bail!("\"scenario\" invalid or missing"),
         }
     }
@@ -188,6 +571,8 @@ impl Scenario {
         Ok(match self {
             Self::MemHog(hog) => ScenarioRecord::MemHog(hog.run(rctx)?),
             Self::MemHogTune(tune) => ScenarioRecord::MemHogTune(tune.run(rctx)?),
+            Self::CpuHog(hog) => ScenarioRecord::CpuHog(hog.run(rctx)?),
+            Self::IoHog(hog) => ScenarioRecord::IoHog(hog.run(rctx)?),
         })
     }
 
@@ -199,6 +584,12 @@ impl Scenario {
             (Self::MemHogTune(tune), ScenarioRecord::MemHogTune(rec)) => {
                 ScenarioResult::MemHogTune(tune.study(rctx, rec)?)
             }
+            (Self::CpuHog(hog), ScenarioRecord::CpuHog(rec)) => {
+                ScenarioResult::CpuHog(hog.study(rctx, rec)?)
+            }
+            (Self::IoHog(hog), ScenarioRecord::IoHog(rec)) => {
+                ScenarioResult::IoHog(hog.study(rctx, rec)?)
+            }
             _ => panic!("Unsupported (scenario, record) pair"),
         })
     }
@@ -283,6 +674,79 @@ impl ProtectionJob {
         Ok(job)
     }
 
+    fn insert_key_metrics(
+        metrics: &mut BTreeMap<String, f64>,
+        prefix: &str,
+        isol: &BTreeMap<String, f64>,
+        lat_imp: &BTreeMap<String, f64>,
+        fail_ratio: f64,
+    ) {
+        for (name, pcts) in &[("isol", isol), ("lat-imp", lat_imp)] {
+            for (pct, v) in pcts.iter() {
+                let key = match pct.as_str() {
+                    "mean" => format!("{}{}", prefix, name),
+                    pct => format!("{}{}-{}", prefix, name, pct),
+                };
+                metrics.insert(key, *v);
+            }
+        }
+        metrics.insert(format!("{}fail-ratio", prefix), fail_ratio);
+    }
+
+    fn insert_mem_hog_key_metrics(
+        metrics: &mut BTreeMap<String, f64>,
+        prefix: &str,
+        res: &MemHogResult,
+    ) {
+        Self::insert_key_metrics(metrics, prefix, &res.isol, &res.lat_imp, res.fail_ratio);
+        metrics.insert(format!("{}work-csv", prefix), res.work_csv);
+    }
+
+    pub fn key_metrics_of(res: &ProtectionResult) -> BTreeMap<String, f64> {
+        let mut metrics = BTreeMap::new();
+
+        if let Some(hog) = res.combined_mem_hog.as_ref() {
+            Self::insert_mem_hog_key_metrics(&mut metrics, "", hog);
+        }
+
+        for (idx, scn) in res.scenarios.iter().enumerate() {
+            let pfx = format!("scenario[{}].", idx);
+            match scn {
+                ScenarioResult::MemHog(hog) => {
+                    Self::insert_mem_hog_key_metrics(&mut metrics, &pfx, hog);
+                }
+                ScenarioResult::MemHogTune(tune) => {
+                    if let Some(hog) = tune.final_run.as_ref() {
+                        Self::insert_mem_hog_key_metrics(&mut metrics, &pfx, hog);
+                    }
+                }
+                ScenarioResult::CpuHog(hog) => {
+                    Self::insert_key_metrics(
+                        &mut metrics,
+                        &pfx,
+                        &hog.isol,
+                        &hog.lat_imp,
+                        hog.fail_ratio,
+                    );
+                    metrics.insert(format!("{}work-csv", &pfx), hog.work_csv);
+                }
+                ScenarioResult::IoHog(hog) => {
+                    for (cidx, cfg) in hog.cfgs.iter().enumerate() {
+                        Self::insert_key_metrics(
+                            &mut metrics,
+                            &format!("{}cfg[{}].", &pfx, cidx),
+                            &cfg.isol,
+                            &cfg.lat_imp,
+                            cfg.fail_ratio,
+                        );
+                    }
+                }
+            }
+        }
+
+        metrics
+    }
+
     pub fn format_result<'a>(
         &self,
         mut out: &mut Box<dyn Write + 'a>,
@@ -344,6 +808,22 @@ impl ProtectionJob {
                     writeln!(out, "").unwrap();
                     scn.format_result(&mut out, rec, res, opts);
                 }
+                (
+                    Scenario::CpuHog(scn),
+                    ScenarioRecord::CpuHog(rec),
+                    ScenarioResult::CpuHog(res),
+                ) => {
+                    print_header(&mut out, idx, "CPU Hog");
+                    scn.format_params(&mut out);
+                    writeln!(out, "").unwrap();
+                    CpuHog::format_result(out, rec, res, opts);
+                }
+                (Scenario::IoHog(scn), ScenarioRecord::IoHog(rec), ScenarioResult::IoHog(res)) => {
+                    print_header(&mut out, idx, "IO Hog");
+                    scn.format_params(&mut out);
+                    writeln!(out, "").unwrap();
+                    IoHog::format_result(out, rec, res, opts);
+                }
                 _ => panic!("Unsupported (scenario, record, result) tuple"),
             }
         }
@@ -422,4 +902,8 @@ impl Job for ProtectionJob {
         self.format_result(out, &rec, &res, opts, "");
         Ok(())
     }
+
+    fn key_metrics(&self, data: &JobData) -> Result<BTreeMap<String, f64>> {
+        Ok(Self::key_metrics_of(&data.parse_result()?))
+    }
 }
@@ -0,0 +1,611 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+use super::super::*;
+use super::mem_hog::FailAcc;
+use super::{HogRun, RestoreGuard};
+use rd_agent_intf::Slice;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+#[derive(Clone, Copy, Debug)]
+pub enum CpuHogSpeed {
+    Burn50Pct,
+    Burn1x,
+    Burn2x,
+}
+
+impl CpuHogSpeed {
+    pub fn from_str(input: &str) -> Result<Self> {
+        Ok(match input {
+            "50%" => Self::Burn50Pct,
+            "1x" => Self::Burn1x,
+            "2x" => Self::Burn2x,
+            _ => bail!("\"speed\" should be one of 50%, 1x or 2x"),
+        })
+    }
+
+    pub fn to_sideload_name(&self) -> &'static str {
+        match self {
+            Self::Burn50Pct => "burn-cpus-50pct",
+            Self::Burn1x => "burn-cpus-1x",
+            Self::Burn2x => "burn-cpus-2x",
+        }
+    }
+}
+
+impl std::fmt::Display for CpuHogSpeed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Burn50Pct => "50%",
+                Self::Burn1x => "1x",
+                Self::Burn2x => "2x",
+            }
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CpuHog {
+    pub loops: u32,
+    pub load: f64,
+    pub speed: CpuHogSpeed,
+    // Slice::Side runs the hog as a sideload under the sideloader,
+    // Slice::Sys as a sysload competing through cpu.weight.
+    pub slice: Slice,
+    pub headroom: Option<f64>,
+    pub weight: Option<u32>,
+    pub dur: f64,
+}
+
+impl Default for CpuHog {
+    fn default() -> Self {
+        Self {
+            loops: 2,
+            load: 1.0,
+            speed: CpuHogSpeed::Burn2x,
+            slice: Slice::Side,
+            headroom: None,
+            weight: None,
+            dur: 120.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CpuHogRun {
+    pub failed: Option<(f64, f64, String)>, // (target_duration, failed_after, fail_msg)
+    pub hog_period: (u64, u64),
+}
+
+impl HogRun for CpuHogRun {
+    fn failed(&self) -> &Option<(f64, f64, String)> {
+        &self.failed
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CpuHogRecord {
+    pub period: (u64, u64),
+    pub base_period: (u64, u64),
+    pub base_rps: f64,
+    pub headroom: f64,
+    pub weight: u32,
+    pub runs: Vec<CpuHogRun>,
+    #[serde(skip)]
+    pub result: RefCell<Option<CpuHogResult>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CpuHogResult {
+    pub base_rps: f64,
+    pub base_lat: f64,
+    pub base_lat_stdev: f64,
+
+    pub isol: BTreeMap<String, f64>,
+    pub lat_imp: BTreeMap<String, f64>,
+    pub fail_ratio: f64,
+    pub fail_msgs: Vec<String>,
+    pub work_csv: f64,
+
+    pub root_rstat: ResourceStat,
+    pub work_rstat: ResourceStat,
+    pub hog_rstat: ResourceStat,
+
+    pub nr_reports: (u64, u64),
+    pub hog_periods: Vec<(u64, u64)>,
+    pub root_cpu_util: f64,
+    pub work_cpu_util: f64,
+    pub hog_cpu_util: f64,
+}
+
+impl CpuHog {
+    const NAME: &'static str = "cpu-hog";
+
+    fn hog_svc_name(&self) -> String {
+        super::hog_svc_name(Self::NAME, self.slice)
+    }
+
+    fn apply_cfg(&self, rctx: &RunCtx) -> Result<()> {
+        if let Some(headroom) = self.headroom {
+            rctx.update_sideloader_cpu_headroom(headroom)?;
+        }
+        if let Some(weight) = self.weight {
+            rctx.update_slice_cpu_weight(self.slice, weight)?;
+        }
+        Ok(())
+    }
+
+    fn status(&self, mon: &WorkloadMon, af: &AgentFiles) -> Result<(bool, String)> {
+        let rep = &af.report.data;
+        let hog_cpu = match rep.usages.get(&self.hog_svc_name()) {
+            Some(usage) => usage.cpu_util,
+            None => 0.0,
+        };
+        Ok((
+            false,
+            format!(
+                "load:{:>4}% lat:{:>5} cpu root/work/hog:{:>4}%/{:>4}%/{:>4}%",
+                format4_pct(mon.hashd_loads[0]),
+                format_duration(rep.hashd[0].lat.ctl),
+                format4_pct(rep.usages[ROOT_SLICE].cpu_util),
+                format4_pct(rep.usages[Slice::Work.name()].cpu_util),
+                format4_pct(hog_cpu),
+            ),
+        ))
+    }
+
+    fn run_one_int(
+        &self,
+        rctx: &mut RunCtx,
+        run_name: &str,
+        do_base_hold: bool,
+    ) -> Result<(CpuHogRun, Option<(u64, u64)>)> {
+        // The agent may have been restarted after a failure. Apply every
+        // time.
+        self.apply_cfg(rctx)?;
+
+        let base_period = super::prepare_hog_run(rctx, self.load, run_name, do_base_hold)?;
+
+        let timeout = match rctx.test {
+            true => 10.0,
+            false => self.dur,
+        };
+
+        // The cpu hog keeps running. Monitor until the timeout expires.
+        let (failed, hog_period) = super::run_cont_hog(
+            rctx,
+            Self::NAME,
+            self.speed.to_sideload_name(),
+            self.slice,
+            timeout,
+            |wm, af| self.status(wm, af),
+        )?;
+
+        if failed.is_none() {
+            info!("protection: Stopped cpu hog, {} finished", run_name);
+        }
+
+        Ok((CpuHogRun { failed, hog_period }, base_period))
+    }
+
+    fn run_one(
+        &self,
+        rctx: &mut RunCtx,
+        run_name: &str,
+        do_base_hold: bool,
+    ) -> Result<(CpuHogRun, Option<(u64, u64)>)> {
+        let res = self.run_one_int(rctx, run_name, do_base_hold);
+        super::recover_hog_run(rctx, run_name, res)
+    }
+
+    pub fn run(&mut self, rctx: &mut RunCtx) -> Result<CpuHogRecord> {
+        let started_at = unix_now();
+        let (prev_headroom, prev_weight) = rctx.access_agent_files(|af| {
+            (
+                af.cmd.data.sideloader.cpu_headroom,
+                af.slices.data[self.slice].cpu_weight,
+            )
+        });
+
+        // Restore the knobs on the way out whether the runs succeed or not.
+        let mut guard = RestoreGuard::new(rctx);
+        if self.headroom.is_some() {
+            guard.on_restore("sideloader cpu headroom", move |rctx| {
+                rctx.update_sideloader_cpu_headroom(prev_headroom)?;
+                Ok(())
+            });
+        }
+        if self.weight.is_some() {
+            let slice = self.slice;
+            guard.on_restore("cpu weight", move |rctx| {
+                rctx.update_slice_cpu_weight(slice, prev_weight)?;
+                Ok(())
+            });
+        }
+
+        let mut base_period = (0, 0);
+        let mut runs = vec![];
+        for run_idx in 0..self.loops {
+            let (hog_run, bper) = self.run_one(
+                &mut guard,
+                &format!("run {}/{}", run_idx + 1, self.loops),
+                run_idx == 0,
+            )?;
+            if run_idx == 0 {
+                base_period = bper.unwrap();
+            }
+            runs.push(hog_run);
+        }
+        guard.finish()?;
+
+        let rec = CpuHogRecord {
+            period: (started_at, unix_now()),
+            base_period,
+            base_rps: rctx.bench_knobs().hashd.rps_max as f64 * self.load,
+            headroom: self.headroom.unwrap_or(prev_headroom),
+            weight: self.weight.unwrap_or(prev_weight),
+            runs,
+            result: RefCell::new(None),
+        };
+
+        let result = self.study(rctx, &rec)?;
+        info!(
+            "protection: isol={}%:{} lat_imp={}%:{} work_csv={}% missing={}%",
+            format_pct(result.isol["mean"]),
+            format_pct(result.isol["stdev"]),
+            format_pct(result.lat_imp["mean"]),
+            format_pct(result.lat_imp["stdev"]),
+            format_pct(result.work_csv),
+            format_pct(Studies::reports_missing(result.nr_reports)),
+        );
+
+        rec.result.replace(Some(result));
+        Ok(rec)
+    }
+
+    pub fn study(&self, rctx: &RunCtx, rec: &CpuHogRecord) -> Result<CpuHogResult> {
+        if let Some(res) = rec.result.replace(None) {
+            return Ok(res);
+        }
+
+        let (base_lat, base_lat_stdev) = super::study_base_lat(rctx, rec.base_period)?;
+
+        let mut fail_acc = FailAcc::default();
+        for run in rec.runs.iter() {
+            fail_acc.add(&run.failed);
+        }
+
+        let last_nr_done = RefCell::new(None);
+        let (mut study_isol, mut study_lat_imp) =
+            super::isol_lat_imp_studies(&last_nr_done, rec.base_rps, base_lat, &fail_acc);
+
+        // The hog always wants more CPU. If work conservation is 100%, it
+        // would have consumed all the CPU cycles left over by hashd. The
+        // conservation factor is defined as the busy CPU time divided by
+        // the total CPU time.
+        let (mut cpu_busy, mut cpu_total) = (0.0_f64, 0.0_f64);
+        let last_cpu_usage = RefCell::new(None);
+        let mut study_cpu_usage = StudyMutFn::new(|arg| {
+            let root = &arg.rep.usages[ROOT_SLICE];
+            let cur = (root.cpu_usage, root.cpu_usage_base);
+            if let Some(last) = last_cpu_usage.replace(Some(cur)) {
+                if cur.1 > last.1 {
+                    cpu_busy += (cur.0 - last.0).max(0.0);
+                    cpu_total += cur.1 - last.1;
+                }
+            }
+        });
+
+        let hog_svc_name = self.hog_svc_name();
+        let mut study_root_cpu =
+            StudyMean::new(|arg| [arg.rep.usages[ROOT_SLICE].cpu_util].repeat(arg.cnt));
+        let mut study_work_cpu =
+            StudyMean::new(|arg| [arg.rep.usages[Slice::Work.name()].cpu_util].repeat(arg.cnt));
+        let mut study_hog_cpu = StudyMean::new(|arg| match arg.rep.usages.get(&hog_svc_name) {
+            Some(usage) => [usage.cpu_util].repeat(arg.cnt),
+            None => vec![],
+        });
+
+        let root_rstat_study_ctx = ResourceStatStudyCtx::new();
+        let work_rstat_study_ctx = ResourceStatStudyCtx::new();
+        let hog_rstat_study_ctx = ResourceStatStudyCtx::new();
+        let mut root_rstat_study = ResourceStatStudy::new(ROOT_SLICE, &root_rstat_study_ctx);
+        let mut work_rstat_study =
+            ResourceStatStudy::new(Slice::Work.name(), &work_rstat_study_ctx);
+        let mut hog_rstat_study = ResourceStatStudy::new(self.slice.name(), &hog_rstat_study_ctx);
+
+        let mut studies = Studies::new()
+            .add(&mut study_isol)
+            .add(&mut study_lat_imp)
+            .add(&mut study_cpu_usage)
+            .add(&mut study_root_cpu)
+            .add(&mut study_work_cpu)
+            .add(&mut study_hog_cpu)
+            .add_multiple(&mut root_rstat_study.studies())
+            .add_multiple(&mut work_rstat_study.studies())
+            .add_multiple(&mut hog_rstat_study.studies());
+
+        let hog_periods: Vec<(u64, u64)> = rec.runs.iter().map(|run| run.hog_period).collect();
+        let mut nr_reports = (0, 0);
+        for per in hog_periods.iter() {
+            last_nr_done.replace(None);
+            last_cpu_usage.replace(None);
+            root_rstat_study_ctx.reset();
+            work_rstat_study_ctx.reset();
+            hog_rstat_study_ctx.reset();
+
+            let nr = studies.run(rctx, *per)?;
+            nr_reports.0 += nr.0;
+            nr_reports.1 += nr.1;
+        }
+        drop(studies);
+
+        let work_csv = if cpu_total > 0.0 {
+            (cpu_busy / cpu_total).min(1.0)
+        } else {
+            1.0
+        };
+
+        Ok(CpuHogResult {
+            base_rps: rec.base_rps,
+            base_lat,
+            base_lat_stdev,
+
+            isol: study_isol.result(None),
+            lat_imp: study_lat_imp.result(None),
+            fail_ratio: fail_acc.ratio(),
+            fail_msgs: fail_acc.msgs(),
+            work_csv,
+
+            root_rstat: root_rstat_study.result(None),
+            work_rstat: work_rstat_study.result(None),
+            hog_rstat: hog_rstat_study.result(None),
+
+            nr_reports,
+            hog_periods,
+            root_cpu_util: study_root_cpu.result().0,
+            work_cpu_util: study_work_cpu.result().0,
+            hog_cpu_util: study_hog_cpu.result().0,
+        })
+    }
+
+    pub fn format_params<'a>(&self, out: &mut Box<dyn Write + 'a>) {
+        let mut params = format!(
+            "Params: loops={} load={} speed={} slice={} dur={}",
+            self.loops,
+            self.load,
+            self.speed,
+            self.slice.name(),
+            format_duration(self.dur)
+        );
+        if let Some(headroom) = self.headroom {
+            write!(params, " headroom={}%", format_pct(headroom)).unwrap();
+        }
+        if let Some(weight) = self.weight {
+            write!(params, " weight={}", weight).unwrap();
+        }
+        writeln!(out, "{}", &params).unwrap();
+    }
+
+    pub fn format_result<'a>(
+        out: &mut Box<dyn Write + 'a>,
+        rec: &CpuHogRecord,
+        result: &CpuHogResult,
+        opts: &FormatOpts,
+    ) {
+        if opts.full {
+            writeln!(
+                out,
+                "Info: baseline_rps={:.2} baseline_lat={}:{} headroom={}% weight={}",
+                result.base_rps,
+                format_duration(result.base_lat),
+                format_duration(result.base_lat_stdev),
+                format_pct(rec.headroom),
+                rec.weight,
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "      cpu_util root={}% work={}% hog={}%",
+                format_pct(result.root_cpu_util),
+                format_pct(result.work_cpu_util),
+                format_pct(result.hog_cpu_util),
+            )
+            .unwrap();
+
+            writeln!(out, "\nSlice resource stat:\n").unwrap();
+            result.root_rstat.format(out, "ROOT", opts);
+            writeln!(out, "").unwrap();
+            result.work_rstat.format(out, "WORKLOAD", opts);
+            writeln!(out, "").unwrap();
+            result.hog_rstat.format(out, "HOG", opts);
+        }
+
+        writeln!(
+            out,
+            "\nIsolation and Request Latency Impact Distributions:\n"
+        )
+        .unwrap();
+
+        print_pcts_header(out, 8, "", None);
+        print_pcts_line(out, 8, "isol%", &result.isol, format_pct, None);
+        print_pcts_line(out, 8, "lat-imp%", &result.lat_imp, format_pct, None);
+
+        let fail_str = if result.fail_ratio != 0.0 {
+            format!("FAIL={}% ", format_pct(result.fail_ratio))
+        } else {
+            "".to_string()
+        };
+
+        writeln!(
+            out,
+            "\nResult: {}isol={}:{}% lat_imp={}%:{} work_csv={}% missing={}%",
+            &fail_str,
+            format_pct(result.isol["mean"]),
+            format_pct(result.isol["stdev"]),
+            format_pct(result.lat_imp["mean"]),
+            format_pct(result.lat_imp["stdev"]),
+            format_pct(result.work_csv),
+            format_pct(Studies::reports_missing(result.nr_reports)),
+        )
+        .unwrap();
+
+        for msg in result.fail_msgs.iter() {
+            writeln!(out, "        [error] {}", &msg).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Scenario;
+    use super::*;
+
+    fn parse(props: &[(&str, &str)]) -> Result<CpuHog> {
+        let mut props: BTreeMap<String, String> = props
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        props.insert("scenario".into(), "cpu-hog".into());
+        Ok(Scenario::parse(props)?.as_cpu_hog().unwrap().clone())
+    }
+
+    fn format_to_string<F: FnOnce(&mut Box<dyn Write + '_>)>(f: F) -> String {
+        let mut buf = String::new();
+        {
+            let mut out: Box<dyn Write> = Box::new(&mut buf);
+            f(&mut out);
+        }
+        buf
+    }
+
+    #[test]
+    fn test_cpu_hog_speed() {
+        for speed in &["50%", "1x", "2x"] {
+            assert_eq!(&format!("{}", CpuHogSpeed::from_str(speed).unwrap()), speed);
+        }
+        assert_eq!(
+            CpuHogSpeed::from_str("50%").unwrap().to_sideload_name(),
+            "burn-cpus-50pct"
+        );
+        assert!(CpuHogSpeed::from_str("3x").is_err());
+    }
+
+    #[test]
+    fn test_cpu_hog_parse() {
+        let hog = parse(&[]).unwrap();
+        assert_eq!((hog.loops, hog.slice), (2, Slice::Side));
+        assert_eq!((hog.headroom, hog.weight), (None, None));
+
+        let hog = parse(&[
+            ("loops", "3"),
+            ("load", "0.5"),
+            ("speed", "1x"),
+            ("slice", "sys"),
+            ("headroom", "0.2"),
+            ("weight", "50"),
+            ("dur", "30"),
+        ])
+        .unwrap();
+        assert_eq!((hog.loops, hog.load, hog.dur), (3, 0.5, 30.0));
+        assert_eq!(hog.speed.to_sideload_name(), "burn-cpus-1x");
+        assert_eq!(hog.slice, Slice::Sys);
+        assert_eq!((hog.headroom, hog.weight), (Some(0.2), Some(50)));
+
+        for props in &[
+            vec![("loops", "0")],
+            vec![("slice", "work")],
+            vec![("weight", "0")],
+            vec![("weight", "10001")],
+            vec![("nope", "1")],
+        ] {
+            assert!(parse(props).is_err(), "{:?}", props);
+        }
+    }
+
+    #[test]
+    fn test_cpu_hog_format() {
+        let hog = CpuHog {
+            headroom: Some(0.2),
+            weight: Some(50),
+            ..Default::default()
+        };
+        let params = format_to_string(|out| hog.format_params(out));
+        assert_eq!(
+            params,
+            format!(
+                "Params: loops=2 load=1 speed=2x slice=sideload.slice dur={} \
+                 headroom={}% weight=50\n",
+                format_duration(120.0),
+                format_pct(0.2)
+            )
+        );
+
+        let stat = |mean: f64| -> BTreeMap<String, f64> {
+            let mut map: BTreeMap<String, f64> =
+                DFL_PCTS.iter().map(|pct| (pct.to_string(), mean)).collect();
+            map.insert("stdev".into(), 0.01);
+            map
+        };
+        let rec = CpuHogRecord::default();
+        let result = CpuHogResult {
+            isol: stat(0.9),
+            lat_imp: stat(0.1),
+            fail_ratio: 0.5,
+            fail_msgs: vec!["hashd failed".into()],
+            work_csv: 0.75,
+            nr_reports: (100, 0),
+            ..Default::default()
+        };
+        let opts = FormatOpts {
+            full: false,
+            undecorated: false,
+            rstat: 0,
+            result_path: "",
+            output: resctl_bench_intf::OutputFormat::Text,
+        };
+        let out = format_to_string(|out| CpuHog::format_result(out, &rec, &result, &opts));
+        assert!(out.contains(&format!(
+            "Result: FAIL={}% isol={}:{}% lat_imp={}%:{} work_csv={}% missing={}%\n",
+            format_pct(0.5),
+            format_pct(0.9),
+            format_pct(0.01),
+            format_pct(0.1),
+            format_pct(0.01),
+            format_pct(0.75),
+            format_pct(0.0),
+        )));
+        assert!(out.contains("        [error] hashd failed\n"));
+        assert!(!out.contains("Info:"));
+    }
+
+    #[test]
+    fn test_cpu_hog_record_serde() {
+        let rec = CpuHogRecord {
+            base_rps: 1000.0,
+            headroom: 0.2,
+            weight: 50,
+            runs: vec![
+                CpuHogRun {
+                    failed: None,
+                    hog_period: (10, 20),
+                },
+                CpuHogRun {
+                    failed: Some((120.0, 30.0, "oops".into())),
+                    hog_period: (30, 40),
+                },
+            ],
+            result: RefCell::new(Some(Default::default())),
+            ..Default::default()
+        };
+        let json = serde_json::to_string(&rec).unwrap();
+        let parsed: CpuHogRecord = serde_json::from_str(&json).unwrap();
+        assert_eq!((parsed.base_rps, parsed.weight), (1000.0, 50));
+        assert_eq!(parsed.runs.len(), 2);
+        assert_eq!(parsed.runs[0].hog_period, (10, 20));
+        assert_eq!(parsed.runs[1].failed().as_ref().unwrap().2, "oops");
+        assert!(parsed.result.borrow().is_none());
+    }
+}
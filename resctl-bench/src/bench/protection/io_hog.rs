@@ -0,0 +1,539 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+use super::super::*;
+use super::mem_hog::FailAcc;
+use super::{HogRun, RestoreGuard};
+use rd_agent_intf::Slice;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IoHogKind {
+    ReadBomb,
+    WriteBomb,
+}
+
+impl IoHogKind {
+    pub fn from_str(input: &str) -> Result<Self> {
+        Ok(match input {
+            "read" => Self::ReadBomb,
+            "write" => Self::WriteBomb,
+            _ => bail!("\"kind\" should be either read or write"),
+        })
+    }
+
+    pub fn to_sideload_name(&self) -> &'static str {
+        match self {
+            Self::ReadBomb => "read-bomb",
+            Self::WriteBomb => "write-bomb",
+        }
+    }
+}
+
+impl std::fmt::Display for IoHogKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::ReadBomb => "read",
+                Self::WriteBomb => "write",
+            }
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct IoHog {
+    pub loops: u32,
+    pub load: f64,
+    pub kind: IoHogKind,
+    // Slice::Sys runs the hog as a sysload, Slice::Side as a sideload.
+    pub slice: Slice,
+    pub iocost: Vec<bool>,
+    // io.weight's of the hog slice to try while iocost is enabled. Empty
+    // means the current configuration.
+    pub weights: Vec<u32>,
+    pub dur: f64,
+}
+
+impl Default for IoHog {
+    fn default() -> Self {
+        Self {
+            loops: 1,
+            load: 1.0,
+            kind: IoHogKind::ReadBomb,
+            slice: Slice::Sys,
+            iocost: vec![true, false],
+            weights: vec![],
+            dur: 60.0,
+        }
+    }
+}
+
+/// A single point in the configuration matrix. `weight` is None if the
+/// scenario didn't specify io.weight's or iocost is disabled.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IoHogCfg {
+    pub iocost: bool,
+    pub weight: Option<u32>,
+}
+
+impl std::fmt::Display for IoHogCfg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.iocost, self.weight) {
+            (false, _) => write!(f, "iocost=off"),
+            (true, None) => write!(f, "iocost=on"),
+            (true, Some(weight)) => write!(f, "iocost=on weight={}", weight),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct IoHogRun {
+    pub cfg: IoHogCfg,
+    pub failed: Option<(f64, f64, String)>, // (target_duration, failed_after, fail_msg)
+    pub hog_period: (u64, u64),
+}
+
+impl HogRun for IoHogRun {
+    fn failed(&self) -> &Option<(f64, f64, String)> {
+        &self.failed
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct IoHogRecord {
+    pub period: (u64, u64),
+    pub base_period: (u64, u64),
+    pub base_rps: f64,
+    pub work_weight: u32,
+    pub hog_weight: u32,
+    pub runs: Vec<IoHogRun>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IoHogCfgResult {
+    pub cfg: IoHogCfg,
+    pub isol: BTreeMap<String, f64>,
+    pub lat_imp: BTreeMap<String, f64>,
+    pub fail_ratio: f64,
+    pub fail_msgs: Vec<String>,
+    pub iolat: [TimePctsMap; 2],
+    pub hog_rbps: f64,
+    pub hog_wbps: f64,
+    pub nr_reports: (u64, u64),
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IoHogResult {
+    pub base_rps: f64,
+    pub base_lat: f64,
+    pub base_lat_stdev: f64,
+    pub cfgs: Vec<IoHogCfgResult>,
+}
+
+impl IoHog {
+    const NAME: &'static str = "io-hog";
+
+    fn hog_svc_name(&self) -> String {
+        super::hog_svc_name(Self::NAME, self.slice)
+    }
+
+    fn cfgs(&self) -> Vec<IoHogCfg> {
+        let mut cfgs = vec![];
+        for iocost in self.iocost.iter() {
+            // io.weight doesn't mean anything without iocost.
+            if *iocost && self.weights.len() > 0 {
+                for weight in self.weights.iter() {
+                    cfgs.push(IoHogCfg {
+                        iocost: true,
+                        weight: Some(*weight),
+                    });
+                }
+            } else {
+                cfgs.push(IoHogCfg {
+                    iocost: *iocost,
+                    weight: None,
+                });
+            }
+        }
+        cfgs
+    }
+
+    fn apply_cfg(&self, rctx: &RunCtx, cfg: &IoHogCfg, hog_weight: u32) -> Result<()> {
+        rctx.update_io_control(cfg.iocost)?;
+        rctx.update_slice_io_weight(self.slice, cfg.weight.unwrap_or(hog_weight))?;
+        Ok(())
+    }
+
+    fn status(&self, mon: &WorkloadMon, af: &AgentFiles) -> Result<(bool, String)> {
+        let rep = &af.report.data;
+        let (hog_rbps, hog_wbps) = match rep.usages.get(&self.hog_svc_name()) {
+            Some(usage) => (usage.io_rbps, usage.io_wbps),
+            None => (0, 0),
+        };
+        Ok((
+            false,
+            format!(
+                "load:{:>4}% lat:{:>5} iolat r/w:{:>5}/{:>5} hog r/w:{:>5}/{:>5}",
+                format4_pct(mon.hashd_loads[0]),
+                format_duration(rep.hashd[0].lat.ctl),
+                format_duration_dashed(rep.iolat.map["read"]["99"]),
+                format_duration_dashed(rep.iolat.map["write"]["99"]),
+                format_size_dashed(hog_rbps),
+                format_size_dashed(hog_wbps),
+            ),
+        ))
+    }
+
+    fn run_one_int(
+        &self,
+        rctx: &mut RunCtx,
+        cfg: &IoHogCfg,
+        hog_weight: u32,
+        run_name: &str,
+        do_base_hold: bool,
+    ) -> Result<(IoHogRun, Option<(u64, u64)>)> {
+        // The agent may have been restarted after a failure. Apply every
+        // time.
+        self.apply_cfg(rctx, cfg, hog_weight)?;
+
+        let base_period = super::prepare_hog_run(
+            rctx,
+            self.load,
+            &format!("{} ({})", run_name, cfg),
+            do_base_hold,
+        )?;
+
+        let timeout = match rctx.test {
+            true => 10.0,
+            false => self.dur,
+        };
+
+        // The IO hog keeps running. Monitor until the timeout expires.
+        let (failed, hog_period) = super::run_cont_hog(
+            rctx,
+            Self::NAME,
+            self.kind.to_sideload_name(),
+            self.slice,
+            timeout,
+            |wm, af| self.status(wm, af),
+        )?;
+
+        if failed.is_none() {
+            info!("protection: Stopped io hog, {} finished", run_name);
+        }
+
+        Ok((
+            IoHogRun {
+                cfg: cfg.clone(),
+                failed,
+                hog_period,
+            },
+            base_period,
+        ))
+    }
+
+    fn run_one(
+        &self,
+        rctx: &mut RunCtx,
+        cfg: &IoHogCfg,
+        hog_weight: u32,
+        run_name: &str,
+        do_base_hold: bool,
+    ) -> Result<(IoHogRun, Option<(u64, u64)>)> {
+        let res = self.run_one_int(rctx, cfg, hog_weight, run_name, do_base_hold);
+        super::recover_hog_run(rctx, run_name, res)
+    }
+
+    pub fn run(&mut self, rctx: &mut RunCtx) -> Result<IoHogRecord> {
+        let started_at = unix_now();
+        let (work_weight, hog_weight) = rctx.access_agent_files(|af| {
+            (
+                af.slices.data[Slice::Work].io_weight,
+                af.slices.data[self.slice].io_weight,
+            )
+        });
+        let iocost_enabled = rctx.update_io_control(true)?;
+
+        // Restore the knobs on the way out whether the runs succeed or not.
+        let mut guard = RestoreGuard::new(rctx);
+        guard.on_restore("iocost", move |rctx| {
+            rctx.update_io_control(iocost_enabled)?;
+            Ok(())
+        });
+        let slice = self.slice;
+        guard.on_restore("io weight", move |rctx| {
+            rctx.update_slice_io_weight(slice, hog_weight)?;
+            Ok(())
+        });
+
+        // Take the baseline with iocost enabled and run all the
+        // configurations in the matrix.
+        let cfgs = self.cfgs();
+        let nr_runs = cfgs.len() as u32 * self.loops;
+        let mut base_period = (0, 0);
+        let mut runs = vec![];
+        for (cfg_idx, cfg) in cfgs.iter().enumerate() {
+            for loop_idx in 0..self.loops {
+                let run_idx = cfg_idx as u32 * self.loops + loop_idx;
+                let (hog_run, bper) = self.run_one(
+                    &mut guard,
+                    cfg,
+                    hog_weight,
+                    &format!("run {}/{}", run_idx + 1, nr_runs),
+                    run_idx == 0,
+                )?;
+                if run_idx == 0 {
+                    base_period = bper.unwrap();
+                }
+                runs.push(hog_run);
+            }
+        }
+
+        guard.finish()?;
+
+        Ok(IoHogRecord {
+            period: (started_at, unix_now()),
+            base_period,
+            base_rps: rctx.bench_knobs().hashd.rps_max as f64 * self.load,
+            work_weight,
+            hog_weight,
+            runs,
+        })
+    }
+
+    fn study_cfg(
+        &self,
+        rctx: &RunCtx,
+        rec: &IoHogRecord,
+        cfg: &IoHogCfg,
+        base_lat: f64,
+    ) -> Result<IoHogCfgResult> {
+        let runs: Vec<&IoHogRun> = rec.runs.iter().filter(|run| run.cfg == *cfg).collect();
+        let mut fail_acc = FailAcc::default();
+        for run in runs.iter() {
+            fail_acc.add(&run.failed);
+        }
+
+        let last_nr_done = RefCell::new(None);
+        let (mut study_isol, mut study_lat_imp) =
+            super::isol_lat_imp_studies(&last_nr_done, rec.base_rps, base_lat, &fail_acc);
+
+        let hog_svc_name = self.hog_svc_name();
+        let mut study_hog_rbps = StudyMean::new(|arg| match arg.rep.usages.get(&hog_svc_name) {
+            Some(usage) => [usage.io_rbps as f64].repeat(arg.cnt),
+            None => vec![],
+        });
+        let mut study_hog_wbps = StudyMean::new(|arg| match arg.rep.usages.get(&hog_svc_name) {
+            Some(usage) => [usage.io_wbps as f64].repeat(arg.cnt),
+            None => vec![],
+        });
+        let mut study_read_lat_pcts = StudyIoLatPcts::new("read", None);
+        let mut study_write_lat_pcts = StudyIoLatPcts::new("write", None);
+
+        let mut studies = Studies::new()
+            .add(&mut study_isol)
+            .add(&mut study_lat_imp)
+            .add(&mut study_hog_rbps)
+            .add(&mut study_hog_wbps)
+            .add_multiple(&mut study_read_lat_pcts.studies())
+            .add_multiple(&mut study_write_lat_pcts.studies());
+
+        let mut nr_reports = (0, 0);
+        for run in runs.iter() {
+            last_nr_done.replace(None);
+            let nr = studies.run(rctx, run.hog_period)?;
+            nr_reports.0 += nr.0;
+            nr_reports.1 += nr.1;
+        }
+        drop(studies);
+
+        Ok(IoHogCfgResult {
+            cfg: cfg.clone(),
+            isol: study_isol.result(None),
+            lat_imp: study_lat_imp.result(None),
+            fail_ratio: fail_acc.ratio(),
+            fail_msgs: fail_acc.msgs(),
+            iolat: [
+                study_read_lat_pcts.result(None),
+                study_write_lat_pcts.result(None),
+            ],
+            hog_rbps: study_hog_rbps.result().0,
+            hog_wbps: study_hog_wbps.result().0,
+            nr_reports,
+        })
+    }
+
+    pub fn study(&self, rctx: &RunCtx, rec: &IoHogRecord) -> Result<IoHogResult> {
+        let (base_lat, base_lat_stdev) = super::study_base_lat(rctx, rec.base_period)?;
+
+        // Use the configurations from the record so that the result can be
+        // studied again even if the matrix changes.
+        let mut cfgs: Vec<IoHogCfg> = vec![];
+        for run in rec.runs.iter() {
+            if !cfgs.contains(&run.cfg) {
+                cfgs.push(run.cfg.clone());
+            }
+        }
+
+        let mut result = IoHogResult {
+            base_rps: rec.base_rps,
+            base_lat,
+            base_lat_stdev,
+            cfgs: vec![],
+        };
+        for cfg in cfgs.iter() {
+            result.cfgs.push(self.study_cfg(rctx, rec, cfg, base_lat)?);
+        }
+        Ok(result)
+    }
+
+    pub fn format_params<'a>(&self, out: &mut Box<dyn Write + 'a>) {
+        let mut params = format!(
+            "Params: loops={} load={} kind={} slice={} dur={} iocost={}",
+            self.loops,
+            self.load,
+            self.kind,
+            self.slice.name(),
+            format_duration(self.dur),
+            self.iocost
+                .iter()
+                .map(|v| if *v { "on" } else { "off" })
+                .collect::<Vec<&str>>()
+                .join("/"),
+        );
+        if self.weights.len() > 0 {
+            write!(
+                params,
+                " weights={}",
+                self.weights
+                    .iter()
+                    .map(|w| w.to_string())
+                    .collect::<Vec<String>>()
+                    .join("/")
+            )
+            .unwrap();
+        }
+        writeln!(out, "{}", &params).unwrap();
+    }
+
+    pub fn format_result<'a>(
+        out: &mut Box<dyn Write + 'a>,
+        rec: &IoHogRecord,
+        result: &IoHogResult,
+        opts: &FormatOpts,
+    ) {
+        writeln!(
+            out,
+            "Info: baseline_rps={:.2} baseline_lat={}:{} io_weight work/hog={}/{}",
+            result.base_rps,
+            format_duration(result.base_lat),
+            format_duration(result.base_lat_stdev),
+            rec.work_weight,
+            rec.hog_weight,
+        )
+        .unwrap();
+
+        for cr in result.cfgs.iter() {
+            writeln!(out, "\n[{}]\n", &cr.cfg).unwrap();
+
+            if opts.full {
+                print_pcts_header(out, 8, "", None);
+                print_pcts_line(out, 8, "isol%", &cr.isol, format_pct, None);
+                print_pcts_line(out, 8, "lat-imp%", &cr.lat_imp, format_pct, None);
+                writeln!(out, "").unwrap();
+            }
+
+            StudyIoLatPcts::format_rw(out, &cr.iolat, opts, None);
+
+            let fail_str = if cr.fail_ratio != 0.0 {
+                format!("FAIL={}% ", format_pct(cr.fail_ratio))
+            } else {
+                "".to_string()
+            };
+            writeln!(
+                out,
+                "Result: {}isol={}:{}% lat_imp={}%:{} hog_bps={}/{} missing={}%",
+                &fail_str,
+                format_pct(cr.isol["mean"]),
+                format_pct(cr.isol["stdev"]),
+                format_pct(cr.lat_imp["mean"]),
+                format_pct(cr.lat_imp["stdev"]),
+                format_size_dashed(cr.hog_rbps),
+                format_size_dashed(cr.hog_wbps),
+                format_pct(Studies::reports_missing(cr.nr_reports)),
+            )
+            .unwrap();
+            for msg in cr.fail_msgs.iter() {
+                writeln!(out, "        [error] {}", &msg).unwrap();
+            }
+        }
+
+        writeln!(out, "\nSummary:\n").unwrap();
+        writeln!(
+            out,
+            "{:<24} {:>7} {:>7} {:>8} {:>8} {:>6} {:>6} {:>6}",
+            "", "isol", "isol-01", "lat-imp", "lat-99", "rlat99", "wlat99", "fail"
+        )
+        .unwrap();
+        for cr in result.cfgs.iter() {
+            writeln!(
+                out,
+                "{:<24} {:>6}% {:>6}% {:>7}% {:>7}% {:>6} {:>6} {:>5}%",
+                &format!("{}", &cr.cfg),
+                format_pct(cr.isol["mean"]),
+                format_pct(cr.isol["01"]),
+                format_pct(cr.lat_imp["mean"]),
+                format_pct(cr.lat_imp["99"]),
+                format_duration(cr.iolat[READ]["99"]["mean"]),
+                format_duration(cr.iolat[WRITE]["99"]["mean"]),
+                format_pct(cr.fail_ratio),
+            )
+            .unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_io_hog_cfgs() {
+        let mut hog = IoHog::default();
+        assert_eq!(
+            hog.cfgs(),
+            vec![
+                IoHogCfg {
+                    iocost: true,
+                    weight: None
+                },
+                IoHogCfg {
+                    iocost: false,
+                    weight: None
+                },
+            ]
+        );
+
+        hog.weights = vec![50, 10];
+        hog.iocost = vec![false, true];
+        assert_eq!(
+            hog.cfgs(),
+            vec![
+                IoHogCfg {
+                    iocost: false,
+                    weight: None
+                },
+                IoHogCfg {
+                    iocost: true,
+                    weight: Some(50)
+                },
+                IoHogCfg {
+                    iocost: true,
+                    weight: Some(10)
+                },
+            ]
+        );
+    }
+}
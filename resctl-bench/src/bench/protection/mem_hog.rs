@@ -1,5 +1,6 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
 use super::super::*;
+use super::HogRun;
 use rd_agent_intf::{bandit_report::BanditMemHogReport, Report, Slice};
 use std::cell::RefCell;
 use std::collections::{BTreeMap, VecDeque};
@@ -60,6 +61,12 @@ pub struct MemHogRun {
     pub last_hog_mem: usize,
 }
 
+impl HogRun for MemHogRun {
+    fn failed(&self) -> &Option<(f64, f64, String)> {
+        &self.failed
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct MemHog {
     pub loops: u32,
@@ -88,28 +95,29 @@ pub struct MemHogRecord {
 }
 
 #[derive(Default)]
-struct FailAcc {
+pub(super) struct FailAcc {
     target_dur_sum: f64,
     failed_after_sum: f64,
     fail_msgs: Vec<String>,
 }
 
 impl FailAcc {
-    fn add(&mut self, run: &MemHogRun) {
-        if let Some((target_dur, failed_after, fail_msg)) = run.failed.as_ref() {
+    // (target_duration, failed_after, fail_msg)
+    pub(super) fn add(&mut self, failed: &Option<(f64, f64, String)>) {
+        if let Some((target_dur, failed_after, fail_msg)) = failed.as_ref() {
             self.target_dur_sum += target_dur;
             self.failed_after_sum += failed_after;
             self.fail_msgs.push(fail_msg.clone());
         }
     }
 
-    fn secs(&self) -> usize {
+    pub(super) fn secs(&self) -> usize {
         (self.target_dur_sum - self.failed_after_sum)
             .max(0.0)
             .ceil() as usize
     }
 
-    fn ratio(&self) -> f64 {
+    pub(super) fn ratio(&self) -> f64 {
         if self.target_dur_sum > 0.0 {
             ((self.target_dur_sum - self.failed_after_sum) / self.target_dur_sum).clamp(0.0001, 1.0)
         } else {
@@ -117,7 +125,7 @@ impl FailAcc {
         }
     }
 
-    fn msgs(self) -> Vec<String> {
+    pub(super) fn msgs(self) -> Vec<String> {
         self.fail_msgs
     }
 }
@@ -179,17 +187,7 @@ impl MemHog {
     where
         F: FnMut(&AgentFiles, &rd_agent_intf::UsageReport, &BanditMemHogReport) -> bool,
     {
-        info!(
-            "protection: Stabilizing hashd at {}% for {}",
-            format_pct(hashd_load),
-            run_name
-        );
-        super::warm_up_hashd(rctx, hashd_load).context("Warming up hashd")?;
-
-        let mut base_period = None;
-        if do_base_hold {
-            base_period = Some(super::baseline_hold(rctx)?);
-        }
+        let base_period = super::prepare_hog_run(rctx, hashd_load, run_name, do_base_hold)?;
 
         info!("protection: Starting memory hog");
         let hog_started_at = unix_now();
@@ -290,10 +288,7 @@ impl MemHog {
     where
         F: FnMut(&AgentFiles, &rd_agent_intf::UsageReport, &BanditMemHogReport) -> bool,
     {
-        // mem-hog runs can fail in two ways - either from agent or hashd
-        // not being protected enough or some other failures. This function
-        // wraps run_one_int() to perform failure handling for both cases.
-        match Self::run_one_int(
+        let res = Self::run_one_int(
             rctx,
             run_name,
             hashd_load,
@@ -301,25 +296,8 @@ impl MemHog {
             do_base_hold,
             timeout,
             is_done,
-        ) {
-            Ok((hog_run, bper)) => {
-                if let Some((target_dur, failed_after, fail_msg)) = hog_run.failed.as_ref() {
-                    info!(
-                        "protection: {} failed after {}% ({})",
-                        run_name,
-                        format_pct(failed_after / target_dur),
-                        fail_msg
-                    );
-                    rctx.restart_agent()?;
-                }
-                Ok((hog_run, bper))
-            }
-            Err(e) => {
-                info!("protection: {} failed ({:#})", run_name, &e);
-                rctx.restart_agent()?;
-                Err(e)
-            }
-        }
+        );
+        super::recover_hog_run(rctx, run_name, res)
     }
 
     pub fn run(&mut self, rctx: &mut RunCtx) -> Result<MemHogRecord> {
@@ -368,11 +346,11 @@ impl MemHog {
         Ok(rec)
     }
 
-    fn calc_isol(rps: f64, base_rps: f64) -> f64 {
+    pub(super) fn calc_isol(rps: f64, base_rps: f64) -> f64 {
         (rps / base_rps).min(1.0)
     }
 
-    fn calc_lat_imp(lat: f64, base_lat: f64) -> f64 {
+    pub(super) fn calc_lat_imp(lat: f64, base_lat: f64) -> f64 {
         (lat / base_lat - 1.0).max(0.0)
     }
 
@@ -390,51 +368,17 @@ impl MemHog {
 
         // Determine the baseline latency. We need it for the latency impact
         // study. Run it first.
-        let mut study_base_lat = StudyMean::new(|arg| [arg.rep.hashd[0].lat.ctl].repeat(arg.cnt));
-
-        Studies::new()
-            .add(&mut study_base_lat)
-            .run(rctx, rec.base_period)?;
-
-        let (base_lat, base_lat_stdev, _, _) = study_base_lat.result();
-
-        // Study work isolation and latency impact. The former is defined as
-        // observed rps divided by the baseline, [0.0, 1.0] with 1.0
-        // indicating the perfect isolation. The latter is defined as the
-        // proportion of the latency increase over the baseline, [0.0, 1.0]
-        // with 0.0 indicating no latency impact.
-        let last_nr_done = RefCell::new(None);
-        let mut study_isol = StudyMeanPcts::new(
-            sel_delta_calc(
-                |arg| arg.rep.hashd[0].nr_done,
-                |arg, cur, last| Self::calc_isol((cur - last) as f64 / arg.dur, rec.base_rps),
-                &last_nr_done,
-            ),
-            None,
-        );
-        let mut study_lat_imp = StudyMeanPcts::new(
-            |arg| {
-                [Self::calc_lat_imp(
-                    arg.rep.hashd[0].lat.ctl.max(base_lat),
-                    base_lat,
-                )]
-                .repeat(arg.cnt)
-            },
-            None,
-        );
+        let (base_lat, base_lat_stdev) = super::study_base_lat(rctx, rec.base_period)?;
 
-        // If the run failed because agent or hashd couldn't be kept healthy
-        // enoungh, consider the rest of the run to have completely failed
-        // isolation - 0% isol, 100% lat-imp.
         let mut fail_acc = FailAcc::default();
         for run in rec.runs.iter() {
-            fail_acc.add(run)
-        }
-        for _ in 0..fail_acc.secs() {
-            study_isol.study_data(&[0.0]).unwrap();
-            study_lat_imp.study_data(&[1.0]).unwrap();
+            fail_acc.add(&run.failed)
         }
 
+        let last_nr_done = RefCell::new(None);
+        let (mut study_isol, mut study_lat_imp) =
+            super::isol_lat_imp_studies(&last_nr_done, rec.base_rps, base_lat, &fail_acc);
+
         // Collect IO usage and unused budgets which will be used to
         // calculate work conservation factor.
         let hog_svc_name = rd_agent_intf::sysload_svc_name(Self::NAME);
@@ -689,7 +633,7 @@ impl MemHog {
         let mut fail_acc = FailAcc::default();
         for (rec, _) in rrs.iter() {
             for run in rec.runs.iter() {
-                fail_acc.add(run)
+                fail_acc.add(&run.failed)
             }
         }
         for _ in 0..fail_acc.secs() {
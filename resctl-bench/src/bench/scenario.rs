@@ -0,0 +1,534 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+use super::*;
+use rd_agent_intf::HASHD_A_SVC_NAME;
+use std::collections::BTreeMap;
+
+mod cond;
+pub use cond::Cond;
+
+use crate::run::RunCtxErr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WaitExpect {
+    Any,
+    Met,
+    Timeout,
+}
+
+impl Default for WaitExpect {
+    fn default() -> Self {
+        Self::Any
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub enum StepAction {
+    Hashd {
+        load: f64,
+    },
+    StopHashd,
+    Sysload {
+        name: String,
+        kind: String,
+    },
+    StopSysload {
+        name: String,
+    },
+    Sideload {
+        name: String,
+        kind: String,
+    },
+    StopSideload {
+        name: String,
+    },
+    Sleep {
+        dur: f64,
+    },
+    WaitUntil {
+        cond: String,
+        timeout: f64,
+        #[serde(default)]
+        expect: WaitExpect,
+    },
+    Check {
+        cond: String,
+    },
+}
+
+impl std::fmt::Display for StepAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Hashd { load } => write!(f, "hashd load={}%", format_pct(*load)),
+            Self::StopHashd => write!(f, "stop hashd"),
+            Self::Sysload { name, kind } => write!(f, "sysload {}:{}", name, kind),
+            Self::StopSysload { name } => write!(f, "stop sysload {}", name),
+            Self::Sideload { name, kind } => write!(f, "sideload {}:{}", name, kind),
+            Self::StopSideload { name } => write!(f, "stop sideload {}", name),
+            Self::Sleep { dur } => write!(f, "sleep {}", format_duration(*dur)),
+            Self::WaitUntil {
+                cond,
+                timeout,
+                expect,
+            } => {
+                write!(f, "wait until {:?} for {}", cond, format_duration(*timeout))?;
+                match expect {
+                    WaitExpect::Any => Ok(()),
+                    WaitExpect::Met => write!(f, ", expect met"),
+                    WaitExpect::Timeout => write!(f, ", expect timeout"),
+                }
+            }
+            Self::Check { cond } => write!(f, "check {:?}", cond),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScenarioStep {
+    // Seconds since the start of the scenario. The step waits until then.
+    #[serde(default)]
+    pub at: Option<f64>,
+    #[serde(flatten)]
+    pub action: StepAction,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScenarioFile {
+    pub name: String,
+    pub steps: Vec<ScenarioStep>,
+}
+
+impl JsonLoad for ScenarioFile {}
+
+impl ScenarioFile {
+    fn verify(&self) -> Result<()> {
+        if self.steps.len() == 0 {
+            bail!("scenario has no steps");
+        }
+        for (idx, step) in self.steps.iter().enumerate() {
+            let ctx = || format!("step {} ({})", idx, &step.action);
+            match &step.action {
+                StepAction::Hashd { load } if *load <= 0.0 => {
+                    bail!("{}: load must be positive", ctx())
+                }
+                StepAction::WaitUntil { cond, .. } | StepAction::Check { cond } => {
+                    Cond::parse(cond).with_context(ctx)?;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn needs_linux_build(&self) -> bool {
+        self.steps.iter().any(|step| match &step.action {
+            StepAction::Sysload { kind, .. } | StepAction::Sideload { kind, .. } => {
+                kind.starts_with("build-linux")
+            }
+            _ => false,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StepRecord {
+    pub at: f64,
+    pub dur: f64,
+    pub msg: String,
+    pub failed: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ScenarioRecord {
+    pub scenario: ScenarioFile,
+    pub period: (u64, u64),
+    pub steps: Vec<StepRecord>,
+    pub aborted: bool,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ScenarioResult {
+    pub passed: bool,
+    pub nr_steps: usize,
+    pub nr_failed: usize,
+    pub nr_skipped: usize,
+}
+
+#[derive(Default)]
+struct RunState {
+    hashd: bool,
+    sysloads: BTreeMap<String, String>,
+    sideloads: BTreeMap<String, String>,
+}
+
+pub struct ScenarioJob {
+    file: Option<String>,
+    scenario: ScenarioFile,
+}
+
+pub struct ScenarioBench {}
+
+impl Bench for ScenarioBench {
+    fn desc(&self) -> BenchDesc {
+        BenchDesc::new("scenario", "Run a scripted rd-agent scenario").takes_run_props()
+    }
+
+    fn parse(&self, spec: &JobSpec, prev_data: Option<&JobData>) -> Result<Box<dyn Job>> {
+        let mut file = None;
+
+        for (k, v) in spec.props[0].iter() {
+            match k.as_str() {
+                "file" => file = Some(v.to_owned()),
+                k => bail!("unknown property key {:?}", k),
+            }
+        }
+
+        // The record carries the scenario so that the result can be
+        // formatted without the original file.
+        let prev_scn = prev_data
+            .and_then(|pd| pd.parse_record::<ScenarioRecord>().ok())
+            .map(|rec| rec.scenario);
+
+        let scenario = match (file.as_ref(), prev_scn) {
+            (Some(file), prev_scn) => match ScenarioFile::load(file) {
+                Ok(scn) => scn,
+                Err(e) => match prev_scn {
+                    Some(scn) => scn,
+                    None => {
+                        return Err(e.context(format!("Loading scenario file {:?}", file)));
+                    }
+                },
+            },
+            (None, Some(scn)) => scn,
+            (None, None) => bail!("\"file\" property missing"),
+        };
+        scenario.verify()?;
+
+        Ok(Box::new(ScenarioJob { file, scenario }))
+    }
+
+    fn doc<'a>(&self, out: &mut Box<dyn Write + 'a>) -> Result<()> {
+        const DOC: &[u8] = include_bytes!("../../doc/scenario.md");
+        write!(out, "{}", String::from_utf8_lossy(DOC))?;
+        Ok(())
+    }
+}
+
+impl ScenarioJob {
+    fn report_json(af: &AgentFiles) -> serde_json::Value {
+        serde_json::to_value(&af.report.data).unwrap()
+    }
+
+    fn sleep(rctx: &RunCtx, dur: f64, status: &str) -> Result<()> {
+        if dur <= 0.0 {
+            return Ok(());
+        }
+        let mut progress = BenchProgress::new().monitor_systemd_unit(HASHD_A_SVC_NAME);
+        progress.set_status(status);
+        match rctx.wait_cond(
+            |_, _| false,
+            Some(Duration::from_secs_f64(dur)),
+            Some(progress),
+        ) {
+            Ok(()) => Ok(()),
+            Err(e) => match e.downcast_ref::<RunCtxErr>() {
+                Some(RunCtxErr::WaitCondTimeout { .. }) => Ok(()),
+                _ => Err(e),
+            },
+        }
+    }
+
+    fn wait_until(rctx: &RunCtx, cond: &Cond, timeout: f64, status: &str) -> Result<Option<f64>> {
+        let started_at = unix_now_f64();
+        let mut eval_err = None;
+        let mut progress = BenchProgress::new().monitor_systemd_unit(HASHD_A_SVC_NAME);
+        progress.set_status(status);
+
+        let res = rctx.wait_cond(
+            |af, _| match cond.eval(&Self::report_json(af)) {
+                Ok(v) => v,
+                Err(e) => {
+                    eval_err = Some(e);
+                    true
+                }
+            },
+            Some(Duration::from_secs_f64(timeout)),
+            Some(progress),
+        );
+        if let Some(e) = eval_err {
+            return Err(e);
+        }
+        match res {
+            Ok(()) => Ok(Some(unix_now_f64() - started_at)),
+            Err(e) => match e.downcast_ref::<RunCtxErr>() {
+                Some(RunCtxErr::WaitCondTimeout { .. }) => Ok(None),
+                _ => Err(e),
+            },
+        }
+    }
+
+    // Returns (msg, failure). Err() aborts the scenario.
+    fn run_step(
+        rctx: &mut RunCtx,
+        state: &mut RunState,
+        action: &StepAction,
+    ) -> Result<(String, Option<String>)> {
+        let status = format!("{}", action);
+        match action {
+            StepAction::Hashd { load } => {
+                rctx.start_hashd(*load)?;
+                state.hashd = true;
+            }
+            StepAction::StopHashd => {
+                rctx.stop_hashd()?;
+                state.hashd = false;
+            }
+            StepAction::Sysload { name, kind } => {
+                rctx.start_sysload(name, kind)?;
+                state.sysloads.insert(name.clone(), kind.clone());
+            }
+            StepAction::StopSysload { name } => {
+                rctx.stop_sysload(name);
+                state.sysloads.remove(name);
+            }
+            StepAction::Sideload { name, kind } => {
+                rctx.start_sideload(name, kind)?;
+                state.sideloads.insert(name.clone(), kind.clone());
+            }
+            StepAction::StopSideload { name } => {
+                rctx.stop_sideload(name);
+                state.sideloads.remove(name);
+            }
+            StepAction::Sleep { dur } => Self::sleep(rctx, *dur, &status)?,
+            StepAction::WaitUntil {
+                cond,
+                timeout,
+                expect,
+            } => {
+                let met = Self::wait_until(rctx, &Cond::parse(cond)?, *timeout, &status)?;
+                let msg = match met {
+                    Some(after) => format!("met after {}", format_duration(after)),
+                    None => format!("timed out after {}", format_duration(*timeout)),
+                };
+                let failed = match (expect, met) {
+                    (WaitExpect::Met, None) => Some(format!("{:?} not met", cond)),
+                    (WaitExpect::Timeout, Some(_)) => Some(format!("{:?} met", cond)),
+                    _ => None,
+                };
+                return Ok((msg, failed));
+            }
+            StepAction::Check { cond } => {
+                let cond = Cond::parse(cond)?;
+                let res = rctx.access_agent_files(|af| cond.eval(&Self::report_json(af)))?;
+                return Ok(match res {
+                    true => ("true".into(), None),
+                    false => ("false".into(), Some("check failed".into())),
+                });
+            }
+        }
+        Ok(("done".into(), None))
+    }
+
+    fn cleanup(rctx: &mut RunCtx, state: &RunState) {
+        for name in state.sideloads.keys() {
+            rctx.stop_sideload(name);
+        }
+        for name in state.sysloads.keys() {
+            rctx.stop_sysload(name);
+        }
+        if state.hashd {
+            if let Err(e) = rctx.stop_hashd() {
+                warn!("scenario: Failed to stop hashd ({:#})", &e);
+            }
+        }
+    }
+}
+
+impl Job for ScenarioJob {
+    fn sysreqs(&self) -> BTreeSet<SysReq> {
+        match self.scenario.needs_linux_build() {
+            true => ALL_SYSREQS.clone(),
+            false => ALL_BUT_LINUX_BUILD_SYSREQS.clone(),
+        }
+    }
+
+    fn run(&mut self, rctx: &mut RunCtx) -> Result<serde_json::Value> {
+        rctx.maybe_run_nested_iocost_params()?;
+        rctx.maybe_run_nested_hashd_params()?;
+        if self.scenario.needs_linux_build() {
+            rctx.set_need_linux_tar();
+        }
+        rctx.set_prep_testfiles().start_agent(vec![])?;
+
+        info!(
+            "scenario: Running {:?} ({} steps) from {:?}",
+            &self.scenario.name,
+            self.scenario.steps.len(),
+            self.file.as_deref().unwrap_or("-")
+        );
+
+        let mut rec = ScenarioRecord {
+            scenario: self.scenario.clone(),
+            ..Default::default()
+        };
+        let mut state = RunState::default();
+        let started_at = unix_now_f64();
+        let nr_steps = self.scenario.steps.len();
+
+        for (idx, step) in self.scenario.steps.iter().enumerate() {
+            if let Some(at) = step.at {
+                let dur = started_at + at - unix_now_f64();
+                if let Err(e) = Self::sleep(
+                    rctx,
+                    dur,
+                    &format!("[{}/{}] waiting for t+{}", idx + 1, nr_steps, at),
+                ) {
+                    Self::cleanup(rctx, &state);
+                    return Err(e);
+                }
+            }
+
+            let step_at = unix_now_f64();
+            info!(
+                "scenario: [{}/{}] t+{} {}",
+                idx + 1,
+                nr_steps,
+                format_duration(step_at - started_at),
+                &step.action
+            );
+
+            let (msg, failed) = match Self::run_step(rctx, &mut state, &step.action) {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("scenario: {} failed ({:#})", &step.action, &e);
+                    rec.aborted = true;
+                    ("error".into(), Some(format!("{:#}", &e)))
+                }
+            };
+            if let Some(failed) = failed.as_ref() {
+                warn!("scenario: {} failed ({})", &step.action, failed);
+            }
+
+            rec.steps.push(StepRecord {
+                at: step_at - started_at,
+                dur: unix_now_f64() - step_at,
+                msg,
+                failed,
+            });
+
+            if rec.aborted {
+                break;
+            }
+        }
+
+        rec.period = (started_at as u64, unix_now());
+        Self::cleanup(rctx, &state);
+
+        Ok(serde_json::to_value(&rec).unwrap())
+    }
+
+    fn study(&self, _rctx: &mut RunCtx, rec_json: serde_json::Value) -> Result<serde_json::Value> {
+        let rec: ScenarioRecord = parse_json_value_or_dump(rec_json)?;
+        let nr_steps = rec.scenario.steps.len();
+        let nr_failed = rec.steps.iter().filter(|s| s.failed.is_some()).count();
+        let nr_skipped = nr_steps - rec.steps.len();
+
+        Ok(serde_json::to_value(&ScenarioResult {
+            passed: nr_failed == 0 && nr_skipped == 0,
+            nr_steps,
+            nr_failed,
+            nr_skipped,
+        })
+        .unwrap())
+    }
+
+    fn format<'a>(
+        &self,
+        out: &mut Box<dyn Write + 'a>,
+        data: &JobData,
+        _opts: &FormatOpts,
+        _props: &JobProps,
+    ) -> Result<()> {
+        let rec: ScenarioRecord = data.parse_record()?;
+        let res: ScenarioResult = data.parse_result()?;
+
+        writeln!(
+            out,
+            "Scenario: {:?} ({} steps)",
+            &rec.scenario.name,
+            rec.scenario.steps.len()
+        )
+        .unwrap();
+        writeln!(out, "Period: {}\n", format_period(rec.period)).unwrap();
+
+        for (idx, step) in rec.scenario.steps.iter().enumerate() {
+            let (state, detail) = match rec.steps.get(idx) {
+                Some(srec) => (
+                    match srec.failed {
+                        Some(_) => "FAIL",
+                        None => "ok",
+                    },
+                    format!(
+                        "t+{:<6} {:>6}  {}",
+                        format_duration(srec.at),
+                        format_duration(srec.dur),
+                        &srec.msg
+                    ),
+                ),
+                None => ("skip", String::new()),
+            };
+            writeln!(out, "[{:>2}] {:<4} {}", idx + 1, state, &step.action).unwrap();
+            if detail.len() > 0 {
+                writeln!(out, "          {}", &detail).unwrap();
+            }
+            if let Some(Some(failed)) = rec.steps.get(idx).map(|s| s.failed.as_ref()) {
+                writeln!(out, "          {}", failed).unwrap();
+            }
+        }
+
+        writeln!(
+            out,
+            "\nResult: {} (steps={} failed={} skipped={})",
+            match res.passed {
+                true => "PASS",
+                false => "FAIL",
+            },
+            res.nr_steps,
+            res.nr_failed,
+            res.nr_skipped
+        )
+        .unwrap();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scenario_file() {
+        let scn: ScenarioFile = serde_json::from_str(
+            r#"{
+              "name": "mem-hog-2x",
+              "steps": [
+                { "action": "hashd", "load": 0.6 },
+                { "action": "sideload", "at": 30, "name": "hog", "kind": "mem-hog-2x" },
+                { "action": "wait-until", "cond": "hashd[0].lat.p99 > 150ms",
+                  "timeout": 120, "expect": "timeout" },
+                { "action": "stop-hashd" }
+              ]
+            }"#,
+        )
+        .unwrap();
+        scn.verify().unwrap();
+        assert_eq!(scn.steps[1].at, Some(30.0));
+        match &scn.steps[2].action {
+            StepAction::WaitUntil { expect, .. } => assert_eq!(*expect, WaitExpect::Timeout),
+            v => panic!("unexpected action {:?}", v),
+        }
+        assert!(!scn.needs_linux_build());
+    }
+}
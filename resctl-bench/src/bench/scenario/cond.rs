@@ -0,0 +1,244 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// Conditions on rd-agent reports, e.g. "hashd[0].lat.p99 > 150ms". A
+// condition is a list of comparisons joined with "&&" and "||" with the
+// usual precedence. The left side of each comparison is the path to a
+// field of the JSON report. Object keys and array indices are separated by
+// "." and keys which contain "." can be wrapped in "[]", e.g.
+// "usages[workload.slice].cpu_util". The right side is a number with an
+// optional unit suffix - "%", "ns", "us", "ms", "s" or a size suffix such
+// as "K", "M" and "G". String fields can be tested for equality, e.g.
+// "hashd[0].svc.state == Running".
+use anyhow::{anyhow, bail, Result};
+use rd_util::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CmpOp {
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Eq,
+    Ne,
+}
+
+impl CmpOp {
+    const OPS: [(&'static str, CmpOp); 6] = [
+        (">=", CmpOp::Ge),
+        ("<=", CmpOp::Le),
+        ("==", CmpOp::Eq),
+        ("!=", CmpOp::Ne),
+        (">", CmpOp::Gt),
+        ("<", CmpOp::Lt),
+    ];
+
+    fn test(&self, lhs: f64, rhs: f64) -> bool {
+        match self {
+            Self::Gt => lhs > rhs,
+            Self::Ge => lhs >= rhs,
+            Self::Lt => lhs < rhs,
+            Self::Le => lhs <= rhs,
+            Self::Eq => lhs == rhs,
+            Self::Ne => lhs != rhs,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Cmp {
+    path: Vec<String>,
+    op: CmpOp,
+    rhs_str: String,
+    rhs: Option<f64>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Cond {
+    // OR of ANDs
+    terms: Vec<Vec<Cmp>>,
+}
+
+fn parse_path(input: &str) -> Result<Vec<String>> {
+    let mut path = vec![];
+    let mut cur = String::new();
+    let mut chars = input.trim().chars();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '.' => {
+                if cur.len() > 0 {
+                    path.push(cur.split_off(0));
+                }
+            }
+            '[' => {
+                if cur.len() > 0 {
+                    path.push(cur.split_off(0));
+                }
+                loop {
+                    match chars.next() {
+                        Some(']') => break,
+                        Some(ch) => cur.push(ch),
+                        None => bail!("unterminated '[' in {:?}", input),
+                    }
+                }
+                let key = cur.trim().trim_matches('"').to_string();
+                cur.clear();
+                if key.len() == 0 {
+                    bail!("empty \"[]\" in {:?}", input);
+                }
+                path.push(key);
+            }
+            ch if ch.is_whitespace() => bail!("whitespace in path {:?}", input),
+            ch => cur.push(ch),
+        }
+    }
+    if cur.len() > 0 {
+        path.push(cur);
+    }
+    if path.len() == 0 {
+        bail!("empty path");
+    }
+    Ok(path)
+}
+
+pub fn parse_value(input: &str) -> Result<f64> {
+    let input = input.trim();
+    if let Some(v) = input.strip_suffix('%') {
+        return Ok(v.trim().parse::<f64>()? / 100.0);
+    }
+    for (sfx, mult) in &[("ns", 0.000_000_001), ("us", 0.000_001), ("ms", 0.001)] {
+        if let Some(v) = input.strip_suffix(sfx) {
+            return Ok(v.trim().parse::<f64>()? * mult);
+        }
+    }
+    if let Some(v) = input.strip_suffix('s') {
+        return Ok(v.trim().parse::<f64>()?);
+    }
+    let input = match input.strip_suffix('B') {
+        Some(v) if v.ends_with(|c: char| c.is_ascii_alphabetic()) => v,
+        _ => input,
+    };
+    if input.ends_with(|c: char| c.is_ascii_alphabetic()) {
+        return Ok(parse_size(input)? as f64);
+    }
+    Ok(input.parse::<f64>()?)
+}
+
+impl Cmp {
+    fn parse(input: &str) -> Result<Self> {
+        for (tok, op) in CmpOp::OPS.iter() {
+            if let Some((lhs, rhs)) = input.split_once(tok) {
+                let rhs_str = rhs.trim().to_string();
+                if rhs_str.len() == 0 {
+                    bail!("missing value in {:?}", input);
+                }
+                let rhs = parse_value(&rhs_str).ok();
+                if rhs.is_none() && *op != CmpOp::Eq && *op != CmpOp::Ne {
+                    bail!("invalid value {:?} in {:?}", &rhs_str, input);
+                }
+                return Ok(Self {
+                    path: parse_path(lhs)?,
+                    op: *op,
+                    rhs_str,
+                    rhs,
+                });
+            }
+        }
+        bail!("no comparison operator in {:?}", input)
+    }
+
+    fn eval(&self, rep: &serde_json::Value) -> Result<bool> {
+        let mut cur = rep;
+        for key in self.path.iter() {
+            cur = match cur {
+                serde_json::Value::Array(arr) => key.parse::<usize>().ok().and_then(|i| arr.get(i)),
+                serde_json::Value::Object(obj) => obj.get(key),
+                _ => None,
+            }
+            .ok_or_else(|| anyhow!("{:?} not found in report", &self.path.join(".")))?;
+        }
+
+        let lhs = match cur {
+            serde_json::Value::Number(v) => v.as_f64().unwrap_or(0.0),
+            serde_json::Value::Bool(v) => *v as u32 as f64,
+            serde_json::Value::String(v) => match self.op {
+                CmpOp::Eq => return Ok(*v == self.rhs_str),
+                CmpOp::Ne => return Ok(*v != self.rhs_str),
+                _ => bail!("{:?} is not a number", &self.path.join(".")),
+            },
+            v => bail!("{:?} is not a number ({:?})", &self.path.join("."), v),
+        };
+        match self.rhs {
+            Some(rhs) => Ok(self.op.test(lhs, rhs)),
+            None => bail!("{:?} is not a number", &self.rhs_str),
+        }
+    }
+}
+
+impl Cond {
+    pub fn parse(input: &str) -> Result<Self> {
+        let mut terms = vec![];
+        for or_term in input.split("||") {
+            let mut and_terms = vec![];
+            for and_term in or_term.split("&&") {
+                and_terms.push(Cmp::parse(and_term)?);
+            }
+            terms.push(and_terms);
+        }
+        Ok(Self { terms })
+    }
+
+    pub fn eval(&self, rep: &serde_json::Value) -> Result<bool> {
+        for and_terms in self.terms.iter() {
+            let mut all = true;
+            for cmp in and_terms.iter() {
+                if !cmp.eval(rep)? {
+                    all = false;
+                    break;
+                }
+            }
+            if all {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cond() {
+        let rep = serde_json::json!({
+            "hashd": [
+                { "lat": { "p99": 0.120 }, "svc": { "state": "Running" } },
+                { "lat": { "p99": 0.200 }, "svc": { "state": "Idle" } },
+            ],
+            "usages": { "workload.slice": { "cpu_util": 0.5, "mem_bytes": 2147483648u64 } },
+        });
+
+        let t = |cond: &str| Cond::parse(cond).unwrap().eval(&rep).unwrap();
+        assert!(!t("hashd[0].lat.p99 > 150ms"));
+        assert!(t("hashd[1].lat.p99 > 150ms"));
+        assert!(t("hashd.1.lat.p99 >= 0.2"));
+        assert!(t(
+            "hashd[0].svc.state == Running && usages[workload.slice].cpu_util < 60%"
+        ));
+        assert!(t(
+            "hashd[1].svc.state == Running || usages[workload.slice].mem_bytes >= 2G"
+        ));
+        assert!(!t(
+            "hashd[1].svc.state != Idle || usages[workload.slice].mem_bytes > 2GB"
+        ));
+
+        assert!(Cond::parse("hashd[0].lat.p99").is_err());
+        assert!(Cond::parse("hashd[0].lat.p99 > fast").is_err());
+        assert!(Cond::parse("hashd[0.lat.p99 > 1").is_err());
+        assert!(Cond::parse("hashd[0].lat.p42 > 1")
+            .unwrap()
+            .eval(&rep)
+            .is_err());
+    }
+}
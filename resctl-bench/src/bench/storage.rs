@@ -324,6 +324,23 @@ impl StorageJob {
         }
     }
 
+    pub fn key_metrics_of(res: &StorageResult) -> BTreeMap<String, f64> {
+        let mut metrics = BTreeMap::new();
+        metrics.insert("mof".into(), res.mem_offload_factor);
+        metrics.insert("mem-usage".into(), res.mem_usage as f64);
+        metrics.insert("mem-usage-stdev".into(), res.mem_usage_stdev as f64);
+        metrics.insert("mem-size".into(), res.mem_size as f64);
+        metrics.insert("mem-size-stdev".into(), res.mem_size_stdev as f64);
+        for (rw, iolat) in ["rlat", "wlat"].iter().zip(res.iolat.iter()) {
+            for (lat_pct, time_pcts) in iolat.iter() {
+                if let Some(v) = time_pcts.get("mean") {
+                    metrics.insert(format!("{}-{}", rw, lat_pct), *v);
+                }
+            }
+        }
+        metrics
+    }
+
     pub fn format_result<'a>(
         &self,
         out: &mut Box<dyn Write + 'a>,
@@ -518,4 +535,8 @@ impl Job for StorageJob {
         self.format_result(out, &rec, &res, true, opts);
         Ok(())
     }
+
+    fn key_metrics(&self, data: &JobData) -> Result<BTreeMap<String, f64>> {
+        Ok(Self::key_metrics_of(&data.parse_result()?))
+    }
 }
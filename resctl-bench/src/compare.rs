@@ -0,0 +1,361 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use super::job::{JobCtx, JobCtxs};
+use rd_util::*;
+use resctl_bench_intf::{format_job_props, Args};
+
+// Result fields which aren't metrics and would only add noise.
+const IGN_KEYS: &[&str] = &["period", "periods", "hog_periods", "nr_reports"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metric {
+    pub val: f64,
+    pub stdev: Option<f64>,
+}
+
+fn flatten_value(
+    path: &str,
+    val: &serde_json::Value,
+    metrics: &mut BTreeMap<String, Metric>,
+    order: &mut Vec<String>,
+) {
+    let join = |key: &str| match path.len() {
+        0 => key.to_string(),
+        _ => format!("{}.{}", path, key),
+    };
+
+    match val {
+        serde_json::Value::Number(v) => {
+            if let Some(v) = v.as_f64() {
+                metrics.insert(
+                    path.to_string(),
+                    Metric {
+                        val: v,
+                        stdev: None,
+                    },
+                );
+                order.push(path.to_string());
+            }
+        }
+        serde_json::Value::Bool(v) => {
+            metrics.insert(
+                path.to_string(),
+                Metric {
+                    val: *v as u32 as f64,
+                    stdev: None,
+                },
+            );
+            order.push(path.to_string());
+        }
+        serde_json::Value::Array(arr) => {
+            for (idx, v) in arr.iter().enumerate() {
+                flatten_value(&format!("{}[{}]", path, idx), v, metrics, order);
+            }
+        }
+        serde_json::Value::Object(obj) => {
+            for (k, v) in obj.iter() {
+                if IGN_KEYS.contains(&k.as_str()) {
+                    continue;
+                }
+                flatten_value(&join(k), v, metrics, order);
+            }
+
+            // Pair up "mean" with "stdev" and "X" with "X_stdev" so that
+            // deltas can be judged against the run-to-run variance.
+            let stdev_of = |key: &str| obj.get(key).and_then(|v| v.as_f64());
+            if let Some(stdev) = stdev_of("stdev") {
+                if let Some(m) = metrics.get_mut(&join("mean")) {
+                    m.stdev = Some(stdev);
+                }
+            }
+            for k in obj.keys() {
+                if let Some(stdev) = stdev_of(&format!("{}_stdev", k)) {
+                    if let Some(m) = metrics.get_mut(&join(k)) {
+                        m.stdev = Some(stdev);
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Flatten the numeric leaves of a result into "a.b[0].c" -> Metric. The
+/// second return value lists the paths in the original order.
+pub fn flatten_result(result: &serde_json::Value) -> (BTreeMap<String, Metric>, Vec<String>) {
+    let mut metrics = BTreeMap::new();
+    let mut order = vec![];
+    flatten_value("", result, &mut metrics, &mut order);
+    (metrics, order)
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct DeltaFlags {
+    pub thr: bool,
+    pub stdev: bool,
+}
+
+impl DeltaFlags {
+    pub fn any(&self) -> bool {
+        self.thr || self.stdev
+    }
+}
+
+impl std::fmt::Display for DeltaFlags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.thr, self.stdev) {
+            (true, true) => write!(f, "THR,SD"),
+            (true, false) => write!(f, "THR"),
+            (false, true) => write!(f, "SD"),
+            (false, false) => Ok(()),
+        }
+    }
+}
+
+pub fn delta_flags(a: &Metric, b: &Metric, threshold: f64, nr_stdevs: f64) -> DeltaFlags {
+    let delta = (b.val - a.val).abs();
+    let thr = match a.val {
+        v if v == 0.0 => delta > 0.0,
+        v => delta / v.abs() > threshold,
+    };
+    let stdev = match (a.stdev, b.stdev) {
+        (None, None) => false,
+        (sa, sb) => {
+            let stdev = sa.unwrap_or(0.0).max(sb.unwrap_or(0.0));
+            stdev > 0.0 && delta > nr_stdevs * stdev
+        }
+    };
+    DeltaFlags { thr, stdev }
+}
+
+fn format_val(v: f64) -> String {
+    if v != 0.0 && (v.abs() >= 1_000_000.0 || v.abs() < 0.001) {
+        format!("{:.3e}", v)
+    } else {
+        format!("{:.3}", v)
+    }
+}
+
+fn format_delta(a: f64, b: f64) -> String {
+    if a == b {
+        "0".to_string()
+    } else if a == 0.0 {
+        "-".to_string()
+    } else {
+        format!("{:+.2}%", (b - a) / a.abs() * 100.0)
+    }
+}
+
+fn format_job<'a>(out: &mut Box<dyn Write + 'a>, a: &JobCtx, b: &JobCtx, args: &Args) -> usize {
+    let (am, order) = flatten_result(a.data.result.as_ref().unwrap());
+    let (bm, border) = flatten_result(b.data.result.as_ref().unwrap());
+
+    let mut lines = vec![];
+    let mut nr_flagged = 0;
+    for path in order.iter() {
+        let (av, bv) = match (am.get(path), bm.get(path)) {
+            (Some(av), Some(bv)) => (av, bv),
+            _ => continue,
+        };
+        let flags = delta_flags(av, bv, args.compare_threshold, args.compare_stdevs);
+        if flags.any() {
+            nr_flagged += 1;
+        } else if !args.compare_all && av.val == bv.val {
+            continue;
+        }
+        lines.push((path, av.val, bv.val, flags));
+    }
+
+    let only_a: Vec<&String> = order.iter().filter(|p| !bm.contains_key(*p)).collect();
+    let only_b: Vec<&String> = border.iter().filter(|p| !am.contains_key(*p)).collect();
+
+    let props = format_job_props(&a.data.spec.props);
+    writeln!(
+        out,
+        "\n{}",
+        underline(&format!(
+            "{}{}{}",
+            &a.data.spec,
+            if props.len() > 0 { " " } else { "" },
+            &props
+        ))
+    )
+    .unwrap();
+
+    if lines.len() == 0 {
+        writeln!(out, "No differences").unwrap();
+    } else {
+        let width = lines.iter().map(|l| l.0.len()).max().unwrap().max(6);
+        writeln!(
+            out,
+            "{:<width$} {:>10} {:>10} {:>9}  {}",
+            "METRIC",
+            "A",
+            "B",
+            "DELTA",
+            "FLAGS",
+            width = width
+        )
+        .unwrap();
+        for (path, av, bv, flags) in lines.iter() {
+            writeln!(
+                out,
+                "{:<width$} {:>10} {:>10} {:>9}  {}",
+                path,
+                format_val(*av),
+                format_val(*bv),
+                format_delta(*av, *bv),
+                flags,
+                width = width
+            )
+            .unwrap();
+        }
+    }
+
+    for (name, paths) in &[("A", &only_a), ("B", &only_b)] {
+        if paths.len() > 0 {
+            writeln!(out, "\nOnly in {}: {}", name, paths.len()).unwrap();
+            for path in paths.iter() {
+                writeln!(out, "  {}", path).unwrap();
+            }
+        }
+    }
+
+    writeln!(
+        out,
+        "\nFlagged: {} (threshold={}% stdevs={})",
+        nr_flagged,
+        format_pct(args.compare_threshold),
+        args.compare_stdevs
+    )
+    .unwrap();
+
+    nr_flagged
+}
+
+fn load_completed(path: &str) -> Result<Vec<JobCtx>> {
+    Ok(JobCtxs::load_results(path)
+        .with_context(|| format!("Loading {:?}", path))?
+        .vec
+        .into_iter()
+        .filter(|jctx| jctx.data.result.is_some())
+        .collect())
+}
+
+pub fn compare(args: &Args) -> Result<()> {
+    if args.compare_srcs.len() != 2 {
+        bail!("Two result files are required");
+    }
+
+    let a_jctxs = load_completed(&args.compare_srcs[0])?;
+    let mut b_jctxs = load_completed(&args.compare_srcs[1])?;
+
+    // Match jobs by kind, id and compatible spec. If there are multiple
+    // matches, pair them up in order.
+    let mut pairs = vec![];
+    let mut a_only = vec![];
+    for a in a_jctxs.into_iter() {
+        let spec = &a.data.spec;
+        match b_jctxs.iter().position(|b| {
+            let bs = &b.data.spec;
+            bs.kind == spec.kind && bs.id == spec.id && bs.compatible(spec)
+        }) {
+            Some(idx) => pairs.push((a, b_jctxs.remove(idx))),
+            None => a_only.push(a),
+        }
+    }
+
+    let mut buf = String::new();
+    let mut out = Box::new(&mut buf) as Box<dyn Write>;
+    writeln!(
+        out,
+        "A: {}\nB: {}",
+        &args.compare_srcs[0], &args.compare_srcs[1]
+    )
+    .unwrap();
+
+    let mut nr_flagged = 0;
+    for (a, b) in pairs.iter() {
+        nr_flagged += format_job(&mut out, a, b, args);
+    }
+
+    for (name, jctxs) in &[("A", &spec_names(&a_only)), ("B", &spec_names(&b_jctxs))] {
+        if jctxs.len() > 0 {
+            writeln!(
+                out,
+                "\nNo match for jobs only in {}: {}",
+                name,
+                jctxs.join(" ")
+            )
+            .unwrap();
+        }
+    }
+
+    writeln!(
+        out,
+        "\nCompared {} job(s), {} flagged metric(s)",
+        pairs.len(),
+        nr_flagged
+    )
+    .unwrap();
+
+    drop(out);
+    print!("{}", &buf);
+    Ok(())
+}
+
+fn spec_names(jctxs: &[JobCtx]) -> Vec<String> {
+    jctxs
+        .iter()
+        .map(|jctx| format!("{}", &jctx.data.spec))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flatten_and_flags() {
+        let res = serde_json::json!({
+            "mem_offload_factor": 2.5,
+            "base_lat": 0.01,
+            "base_lat_stdev": 0.001,
+            "period": [1, 2],
+            "isol": { "01": 0.9, "mean": 0.95, "stdev": 0.02 },
+            "iolat": [{ "99": { "mean": 0.005 } }],
+        });
+        let (metrics, order) = flatten_result(&res);
+        assert_eq!(
+            order,
+            vec![
+                "base_lat",
+                "base_lat_stdev",
+                "iolat[0].99.mean",
+                "isol.01",
+                "isol.mean",
+                "isol.stdev",
+                "mem_offload_factor",
+            ]
+        );
+        assert_eq!(metrics["base_lat"].stdev, Some(0.001));
+        assert_eq!(metrics["isol.mean"].stdev, Some(0.02));
+        assert_eq!(metrics["isol.01"].stdev, None);
+
+        let m = |val, stdev| Metric { val, stdev };
+        assert!(!delta_flags(&m(1.0, None), &m(1.04, None), 0.05, 2.0).any());
+        assert!(delta_flags(&m(1.0, None), &m(1.06, None), 0.05, 2.0).thr);
+        assert_eq!(
+            delta_flags(&m(1.0, Some(0.01)), &m(1.03, Some(0.01)), 0.05, 2.0),
+            DeltaFlags {
+                thr: false,
+                stdev: true
+            }
+        );
+        assert!(!delta_flags(&m(1.0, Some(0.1)), &m(1.06, Some(0.1)), 0.05, 2.0).stdev);
+        assert!(delta_flags(&m(0.0, None), &m(0.1, None), 0.05, 2.0).thr);
+    }
+}
@@ -3,7 +3,7 @@ use anyhow::{anyhow, bail, Context, Result};
 use chrono::{DateTime, Local};
 use log::{debug, warn};
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Write;
 use std::fs;
 use std::io::{Read, Write as IoWrite};
@@ -16,7 +16,7 @@ use super::parse_json_value_or_dump;
 use super::run::RunCtx;
 use rd_agent_intf::{EnforceConfig, MissedSysReqs, SysReq, SysReqsReport};
 use rd_util::*;
-use resctl_bench_intf::{JobProps, JobSpec, Mode};
+use resctl_bench_intf::{JobProps, JobSpec, Mode, OutputFormat};
 
 #[derive(Debug, Clone)]
 pub struct FormatOpts<'a> {
@@ -24,6 +24,7 @@ pub struct FormatOpts<'a> {
     pub undecorated: bool,
     pub rstat: u32,
     pub result_path: &'a str,
+    pub output: OutputFormat,
 }
 
 pub trait Job {
@@ -54,6 +55,12 @@ pub trait Job {
         opts: &FormatOpts,
         props: &JobProps,
     ) -> Result<()>;
+
+    /// Named headline metrics of the result which assertions can refer to
+    /// in addition to the flattened result paths.
+    fn key_metrics(&self, _data: &JobData) -> Result<BTreeMap<String, f64>> {
+        Ok(Default::default())
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -400,10 +407,13 @@ impl JobCtx {
         let pdata = rctx.prev_job_data();
 
         if rctx.studying() || (pdata.is_some() && !self.incremental) {
+            // Assertions come from the current invocation.
+            let asserts = self.data.spec.asserts.clone();
             self.data = pdata.ok_or(anyhow!(
                 "study or solve mode but {} isn't complete",
                 &self.data.spec
             ))?;
+            self.data.spec.asserts = asserts;
         } else {
             let job = self.job.as_mut().unwrap();
             let data = &mut self.data;
@@ -481,6 +491,10 @@ impl JobCtx {
     }
 
     pub fn format(&self, opts: &FormatOpts, props: &JobProps) -> Result<String> {
+        if opts.output != OutputFormat::Text {
+            return super::structured::format(&[self], opts.output);
+        }
+
         let mut buf = String::new();
         let mut out = Box::new(&mut buf) as Box<dyn Write>;
 
@@ -492,6 +506,7 @@ impl JobCtx {
             .as_ref()
             .unwrap()
             .format(&mut out, &self.data, opts, props)?;
+        super::assertion::format_outcomes(&mut out, &super::assertion::evaluate(self));
         drop(out);
         Ok(buf)
     }
@@ -503,6 +518,7 @@ impl JobCtx {
                 print!("{}\n\n", "=".repeat(90));
             }
             println!("{}", &self.format(opts, props)?);
+            super::assertion::record_violations(super::assertion::evaluate(self));
         }
         Ok(())
     }
@@ -14,6 +14,7 @@ use std::path::Path;
 use url::Url;
 
 use rd_util::{LambdaRequest as Request, LambdaResponse as Response};
+use resctl_bench_intf::OutputFormat;
 
 use crate::job::{FormatOpts, JobCtxs};
 
@@ -276,6 +277,7 @@ impl LambdaHelper {
             undecorated: false,
             rstat: 0,
             result_path: RESULT_PATH,
+            output: OutputFormat::Text,
         };
         let empty_props = vec![Default::default()];
 
@@ -3,18 +3,21 @@ use anyhow::{bail, Context, Error, Result};
 use base64::prelude::*;
 use indicatif::{ProgressBar, ProgressStyle};
 use log::{debug, error, info, warn};
+use std::collections::HashSet;
 use std::fmt::Write;
 use std::io::{Write as IoWrite};
 use std::path::Path;
 use std::process::{exit, Command};
 use std::sync::{Arc, Mutex};
 
-use rd_agent_intf::MissedSysReqs;
+use rd_agent_intf::{report_store, MissedSysReqs};
 use rd_util::*;
-use resctl_bench_intf::{Args, Mode};
+use resctl_bench_intf::{Args, Mode, OutputFormat};
 
+mod assertion;
 mod base;
 mod bench;
+mod compare;
 mod iocost;
 mod job;
 #[cfg(feature = "lambda")]
@@ -22,10 +25,11 @@ mod lambda;
 mod merge;
 mod progress;
 mod run;
+mod structured;
 mod study;
 
 use bench::ALL_BUT_LINUX_BUILD_SYSREQS;
-use job::{FormatOpts, JobCtxs};
+use job::{FormatOpts, JobCtx, JobCtxs};
 use run::RunCtx;
 
 lazy_static::lazy_static! {
@@ -197,7 +201,7 @@ impl Program {
             to_format = jctxs.vec.into_iter().map(|x| (x, &empty_props)).collect();
         } else {
             for spec in specs.iter() {
-                let jctx = match jctxs.pop_matching_jctx(&spec) {
+                let mut jctx = match jctxs.pop_matching_jctx(&spec) {
                     Some(v) => v,
                     None => {
                         error!("No matching result for {}", &spec);
@@ -220,10 +224,29 @@ impl Program {
                     );
                     exit(1);
                 }
+                if spec.asserts.len() > 0 {
+                    jctx.data.spec.asserts = spec.asserts.clone();
+                }
                 to_format.push((jctx, &spec.props));
             }
         }
 
+        if opts.output != OutputFormat::Text {
+            let jctxs: Vec<&JobCtx> = to_format.iter().map(|(jctx, _)| jctx).collect();
+            match structured::format(&jctxs, opts.output) {
+                Ok(buf) => print!("{}", &buf),
+                Err(e) => {
+                    error!("Failed to format ({:#})", &e);
+                    panic!();
+                }
+            }
+            for jctx in jctxs.into_iter() {
+                assertion::record_violations(assertion::evaluate(jctx));
+            }
+            self.commit_args();
+            return;
+        }
+
         for (jctx, props) in to_format.iter() {
             let mut is_high_level = false;
             let mut is_hwdb = false;
@@ -370,7 +393,23 @@ impl Program {
 
         let mut nr_packed = 0;
         let mut nr_skipped = 0;
+        let mut packed_segs = HashSet::new();
         for per in pers.iter() {
+            for path in report_store::report_seg_paths(&rctx.report_path(), *per) {
+                if packed_segs.contains(&path) {
+                    continue;
+                }
+                let target_path = format!(
+                    "{}/{}",
+                    &repdir,
+                    path.file_name().unwrap().to_str().unwrap()
+                );
+                debug!("Packing {:?} as {:?}", &path, &target_path);
+                tgz.append_path_with_name(&path, &target_path)
+                    .with_context(|| format!("Packing {:?}", &path))?;
+                packed_segs.insert(path);
+            }
+
             for (path, _at) in rctx.report_path_iter(*per) {
                 if !path.exists() {
                     nr_skipped += 1;
@@ -395,7 +434,12 @@ impl Program {
         }
         pgbar.finish_and_clear();
 
-        info!("Packed {}/{} reports", nr_packed, nr_packed + nr_skipped);
+        info!(
+            "Packed {}/{} reports and {} report segments",
+            nr_packed,
+            nr_packed + nr_skipped,
+            packed_segs.len()
+        );
 
         let gz = tgz.into_inner().context("Finishing up archive")?;
         gz.finish().into_result().context("Finishing up gzip")?;
@@ -491,6 +535,7 @@ impl Program {
 
         let rstat = args.rstat;
         let result_path = args.result.clone();
+        let output = args.output_format;
         match args.mode {
             Mode::Run | Mode::Study | Mode::Solve => self.do_run(),
             Mode::Format => self.do_format(&FormatOpts {
@@ -498,12 +543,14 @@ impl Program {
                 undecorated: false,
                 rstat,
                 result_path: &result_path,
+                output,
             }),
             Mode::Summary => self.do_format(&FormatOpts {
                 full: false,
                 undecorated: false,
                 rstat: 0,
                 result_path: &result_path,
+                output,
             }),
             #[cfg(feature = "lambda")]
             Mode::Lambda => lambda::run().unwrap(),
@@ -515,6 +562,12 @@ impl Program {
                     panic!();
                 }
             }
+            Mode::Compare => {
+                if let Err(e) = compare::compare(&self.args_file.data) {
+                    error!("Failed to compare ({:#})", &e);
+                    panic!();
+                }
+            }
             Mode::Deps => {
                 if let Err(e) = self.do_deps() {
                     error!("Failed to test dependencies ({:#})", &e);
@@ -529,6 +582,8 @@ impl Program {
                 }
             }
         }
+
+        assertion::exit_on_violations();
     }
 }
 
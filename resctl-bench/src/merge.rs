@@ -10,7 +10,7 @@ pub mod info;
 use super::job::{FormatOpts, JobCtx, JobCtxs, JobData, SysInfo};
 use info::{MergeEntry, MergeInfo};
 use rd_util::*;
-use resctl_bench_intf::{Args, JobSpec};
+use resctl_bench_intf::{Args, JobSpec, OutputFormat};
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct MergeId {
@@ -217,6 +217,7 @@ pub fn merge(args: &Args) -> Result<()> {
                 undecorated: false,
                 rstat: 0,
                 result_path: &args.result,
+                output: OutputFormat::Text,
             },
             &vec![Default::default()],
         )
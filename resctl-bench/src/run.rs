@@ -16,13 +16,13 @@ use super::progress::BenchProgress;
 use super::{Program, AGENT_BIN};
 use crate::job::{FormatOpts, JobCtx, JobCtxs, JobData, SysInfo};
 use rd_agent_intf::{
-    AgentFiles, EnforceConfig, HashdKnobs, IoCostKnobs, MemoryKnob, MissedSysReqs, ReportIter,
-    ReportPathIter, RunnerState, Slice, SvcStateReport, SysReq, AGENT_SVC_NAME, HASHD_A_SVC_NAME,
-    HASHD_BENCH_SVC_NAME, HASHD_B_SVC_NAME, IOCOST_BENCH_SVC_NAME, SIDELOAD_SVC_PREFIX,
-    SYSLOAD_SVC_PREFIX,
+    hashd_name, hashd_svc_name, AgentFiles, EnforceConfig, HashdKnobs, IoCostKnobs, MemoryKnob,
+    MissedSysReqs, ReportIter, ReportPathIter, RunnerState, Slice, SvcStateReport, SysReq,
+    AGENT_SVC_NAME, HASHD_A_SVC_NAME, HASHD_BENCH_SVC_NAME, IOCOST_BENCH_SVC_NAME,
+    SIDELOAD_SVC_PREFIX, SYSLOAD_SVC_PREFIX,
 };
 use rd_util::*;
-use resctl_bench_intf::{JobSpec, Mode};
+use resctl_bench_intf::{JobSpec, Mode, OutputFormat};
 
 const MINDER_AGENT_TIMEOUT: Duration = Duration::from_secs(120);
 const CMD_TIMEOUT: Duration = Duration::from_secs(120);
@@ -148,6 +148,7 @@ struct RunCtxInner {
     systemd_timeout: f64,
     dev: Option<String>,
     linux_tar: Option<String>,
+    rep_seg: bool,
     verbosity: u32,
     sysreqs: BTreeSet<SysReq>,
     missed_sysreqs: MissedSysReqs,
@@ -174,6 +175,9 @@ impl RunCtxInner {
         )?);
         args.push("--reset".into());
         args.push("--keep-reports".into());
+        if self.rep_seg {
+            args.push("--rep-seg".into());
+        }
 
         if self.cfg.need_linux_tar {
             if self.linux_tar.is_some() {
@@ -287,6 +291,7 @@ impl<'a, 'b> RunCtx<'a, 'b> {
                 systemd_timeout: args.systemd_timeout,
                 dev: args.dev.clone(),
                 linux_tar: args.linux_tar.clone(),
+                rep_seg: args.rep_seg,
                 verbosity: args.verbosity,
                 sysreqs: Default::default(),
                 missed_sysreqs: Default::default(),
@@ -432,6 +437,52 @@ impl<'a, 'b> RunCtx<'a, 'b> {
         })
     }
 
+    pub fn update_slice_cpu_weight(&self, slice: Slice, weight: u32) -> Result<u32> {
+        assert!(self.agent_running());
+        self.access_agent_files(|af| {
+            let prev = af.slices.data[slice].cpu_weight;
+            af.slices.data[slice].cpu_weight = weight;
+            af.slices.save()?;
+            Ok(prev)
+        })
+    }
+
+    pub fn update_slice_io_weight(&self, slice: Slice, weight: u32) -> Result<u32> {
+        assert!(self.agent_running());
+        self.access_agent_files(|af| {
+            let prev = af.slices.data[slice].io_weight;
+            af.slices.data[slice].io_weight = weight;
+            af.slices.save()?;
+            Ok(prev)
+        })
+    }
+
+    /// Enable or disable IO control. Returns whether it was enabled before.
+    pub fn update_io_control(&self, enable: bool) -> Result<bool> {
+        assert!(self.agent_running());
+        self.access_agent_files(|af| {
+            let slices = &mut af.slices.data;
+            let rep = &af.report.data;
+            let prev = slices.disable_seqs.io < rep.seq;
+            slices.disable_seqs.io = if enable { 0 } else { rep.seq };
+            af.slices.save()?;
+            Ok(prev)
+        })
+    }
+
+    pub fn update_sideloader_cpu_headroom(&self, headroom: f64) -> Result<f64> {
+        assert!(self.agent_running());
+        let prev = self.access_agent_files(|af| -> Result<f64> {
+            let prev = af.cmd.data.sideloader.cpu_headroom;
+            af.cmd.data.sideloader.cpu_headroom = headroom;
+            af.cmd.save()?;
+            Ok(prev)
+        })?;
+        self.cmd_barrier()
+            .context("Waiting for sideloader cpu_headroom update ack")?;
+        Ok(prev)
+    }
+
     pub fn update_incremental_jctx(&mut self, jctx: &JobCtx) {
         static UPDATE_SEQ: AtomicU64 = AtomicU64::new(1);
 
@@ -1183,6 +1234,58 @@ impl<'a, 'b> RunCtx<'a, 'b> {
         Self::stop_svc(&rd_agent_intf::sysload_svc_name(name));
     }
 
+    pub fn start_sideload(&mut self, name: &str, kind: &str) -> Result<()> {
+        debug!("Starting sideload {}:{}", name, kind);
+        self.svcs.insert(rd_agent_intf::sideload_svc_name(name));
+
+        self.access_agent_files(|af| {
+            af.cmd.data.cmd_seq += 1;
+            af.cmd
+                .data
+                .sideloads
+                .insert(name.to_owned(), kind.to_owned());
+            af.cmd.save().unwrap();
+        });
+        self.cmd_barrier()
+            .context("Waiting for sideload start ack")?;
+        let mut state = SvcStateReport::Other;
+        self.wait_cond(
+            |af, _| match af.report.data.sideloads.get(name) {
+                Some(rep) => {
+                    state = rep.svc.state;
+                    true
+                }
+                None => false,
+            },
+            Some(CMD_TIMEOUT),
+            None,
+        )
+        .context("Waiting for sideload to start")?;
+
+        if state != SvcStateReport::Running {
+            self.stop_sideload(name);
+            bail!(
+                "Failed to start sideload {}:{}, state={:?}",
+                name,
+                kind,
+                state
+            );
+        }
+        Ok(())
+    }
+
+    pub fn stop_sideload(&self, name: &str) {
+        debug!("Stopping sideload {}", name);
+
+        self.access_agent_files(|af| {
+            af.cmd.data.cmd_seq += 1;
+            af.cmd.data.sideloads.remove(&name.to_owned());
+            af.cmd.save().unwrap();
+        });
+
+        Self::stop_svc(&rd_agent_intf::sideload_svc_name(name));
+    }
+
     pub fn prev_job_data(&self) -> Option<JobData> {
         let jobs = self.jobs.lock().unwrap();
         let prev = jobs.by_uid(self.uid).unwrap();
@@ -1253,6 +1356,7 @@ impl<'a, 'b> RunCtx<'a, 'b> {
                 undecorated: false,
                 rstat: 0,
                 result_path: &self.args.result,
+                output: OutputFormat::Text,
             },
             &vec![Default::default()],
         )
@@ -1402,13 +1506,13 @@ impl Drop for RunCtx<'_, '_> {
 
 #[derive(Default)]
 pub struct WorkloadMon {
-    hashd: [bool; 2],
+    hashd: BTreeSet<usize>,
     sysloads: Vec<String>,
     sideloads: Vec<String>,
     timeout: Option<Duration>,
     exit_on_any: bool,
 
-    pub hashd_loads: [f64; 2],
+    pub hashd_loads: Vec<f64>,
     pub nr_sys_total: usize,
     pub nr_sys_running: usize,
     pub nr_side_total: usize,
@@ -1418,7 +1522,7 @@ pub struct WorkloadMon {
 
 impl WorkloadMon {
     pub fn hashd(mut self) -> Self {
-        self.hashd[0] = true;
+        self.hashd.insert(0);
         self
     }
 
@@ -1428,7 +1532,7 @@ impl WorkloadMon {
     }
 
     pub fn sideload(mut self, name: &str) -> Self {
-        self.sysloads.push(name.to_owned());
+        self.sideloads.push(name.to_owned());
         self
     }
 
@@ -1448,11 +1552,8 @@ impl WorkloadMon {
     {
         let mut progress = BenchProgress::new();
 
-        if self.hashd[0] {
-            progress = progress.monitor_systemd_unit(HASHD_A_SVC_NAME);
-        }
-        if self.hashd[1] {
-            progress = progress.monitor_systemd_unit(HASHD_B_SVC_NAME);
+        for idx in self.hashd.iter() {
+            progress = progress.monitor_systemd_unit(&hashd_svc_name(*idx));
         }
         for name in self.sysloads.iter() {
             progress =
@@ -1477,15 +1578,26 @@ impl WorkloadMon {
                 let rep = &af.report.data;
                 let bench = &af.bench.data;
 
-                if (self.hashd[0] && rep.hashd[0].svc.state != SvcStateReport::Running)
-                    || (self.hashd[1] && rep.hashd[1].svc.state != SvcStateReport::Running)
+                let hashd_state = |idx: usize| {
+                    rep.hashd
+                        .get(idx)
+                        .map(|hrep| hrep.svc.state)
+                        .unwrap_or_default()
+                };
+                if self
+                    .hashd
+                    .iter()
+                    .any(|idx| hashd_state(*idx) != SvcStateReport::Running)
                 {
                     let mut states = String::new();
-                    if self.hashd[0] {
-                        write!(states, ", hashd-A {:?}", rep.hashd[0].svc.state).unwrap();
-                    }
-                    if self.hashd[1] {
-                        write!(states, ", hashd-B {:?}", rep.hashd[1].svc.state).unwrap();
+                    for idx in self.hashd.iter() {
+                        write!(
+                            states,
+                            ", hashd-{} {:?}",
+                            hashd_name(*idx),
+                            hashd_state(*idx)
+                        )
+                        .unwrap();
                     }
                     result = Err(anyhow!("hashd failed while waiting{}", &states));
                     return true;
@@ -1521,10 +1633,11 @@ impl WorkloadMon {
                     _ => {}
                 }
 
-                self.hashd_loads = [
-                    rep.hashd[0].rps / bench.hashd.rps_max as f64,
-                    rep.hashd[1].rps / bench.hashd.rps_max as f64,
-                ];
+                self.hashd_loads = rep
+                    .hashd
+                    .iter()
+                    .map(|hrep| hrep.rps / bench.hashd.rps_max as f64)
+                    .collect();
                 self.time_remaining = match self.timeout.as_ref() {
                     Some(timeout) => {
                         let passed = SystemTime::now().duration_since(started_at).unwrap();
@@ -1568,31 +1681,18 @@ impl WorkloadMon {
     fn dfl_status(mon: &WorkloadMon, af: &AgentFiles) -> Result<(bool, String)> {
         let rep = &af.report.data;
         let mut status = String::new();
-        match (mon.hashd[0], mon.hashd[1]) {
-            (true, false) => write!(
-                status,
-                "load:{:>4}% lat:{:>5} ",
-                format4_pct(mon.hashd_loads[0]),
-                format_duration(rep.hashd[0].lat.ctl)
-            )
-            .unwrap(),
-            (false, true) => write!(
-                status,
-                "load:{:>4}% lat:{:>5}",
-                format4_pct(mon.hashd_loads[1]),
-                format_duration(rep.hashd[1].lat.ctl)
-            )
-            .unwrap(),
-            (true, true) => write!(
-                status,
-                "load:{:>4}%/{:>4}% lat:{:>5}/{:>5}",
-                format4_pct(mon.hashd_loads[0]),
-                format4_pct(mon.hashd_loads[1]),
-                format_duration(rep.hashd[0].lat.ctl),
-                format_duration(rep.hashd[1].lat.ctl),
-            )
-            .unwrap(),
-            _ => {}
+        if !mon.hashd.is_empty() {
+            let loads: Vec<String> = mon
+                .hashd
+                .iter()
+                .map(|idx| format!("{:>4}%", format4_pct(mon.hashd_loads[*idx])))
+                .collect();
+            let lats: Vec<String> = mon
+                .hashd
+                .iter()
+                .map(|idx| format!("{:>5}", format_duration(rep.hashd[*idx].lat.ctl)))
+                .collect();
+            write!(status, "load:{} lat:{} ", loads.join("/"), lats.join("/")).unwrap();
         }
         if mon.nr_sys_total > 0 {
             write!(
@@ -0,0 +1,213 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+use anyhow::{bail, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use super::assertion;
+use super::compare::flatten_result;
+use super::job::JobCtx;
+use resctl_bench_intf::OutputFormat;
+
+const CSV_HEADER: &str = "kind,id,metric,value";
+
+/// Benches which don't define key metrics export all numeric fields of the
+/// result instead.
+pub fn job_metrics(jctx: &JobCtx) -> Result<BTreeMap<String, f64>> {
+    let metrics = match jctx.job.as_ref() {
+        Some(job) => job.key_metrics(&jctx.data)?,
+        None => Default::default(),
+    };
+    if metrics.len() > 0 {
+        return Ok(metrics);
+    }
+    Ok(match jctx.data.result.as_ref() {
+        Some(res) => flatten_result(res)
+            .0
+            .into_iter()
+            .map(|(k, m)| (k, m.val))
+            .collect(),
+        None => Default::default(),
+    })
+}
+
+fn job_json(jctx: &JobCtx) -> Result<serde_json::Value> {
+    let spec = &jctx.data.spec;
+    let mut val = serde_json::json!({
+        "spec": format!("{}", spec),
+        "kind": &spec.kind,
+        "id": &spec.id,
+        "period": jctx.data.period,
+        "metrics": job_metrics(jctx)?,
+    });
+
+    let outcomes = assertion::evaluate(jctx);
+    if outcomes.len() > 0 {
+        val["assertions"] = serde_json::to_value(&outcomes)?;
+    }
+    Ok(val)
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\n'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn job_csv<'a>(out: &mut Box<dyn Write + 'a>, jctx: &JobCtx) -> Result<()> {
+    let spec = &jctx.data.spec;
+    let (kind, id) = (
+        csv_field(&spec.kind),
+        csv_field(spec.id.as_deref().unwrap_or("")),
+    );
+    for (metric, val) in job_metrics(jctx)?.iter() {
+        writeln!(out, "{},{},{},{}", &kind, &id, csv_field(metric), val).unwrap();
+    }
+    Ok(())
+}
+
+/// Format the key metrics of completed jobs. JSON output is an array with
+/// an object per job. CSV output is flat with a row per metric.
+pub fn format(jctxs: &[&JobCtx], output: OutputFormat) -> Result<String> {
+    let jctxs = jctxs.iter().filter(|jctx| jctx.data.result.is_some());
+
+    match output {
+        OutputFormat::Json => {
+            let mut vals = vec![];
+            for jctx in jctxs {
+                vals.push(job_json(jctx)?);
+            }
+            Ok(serde_json::to_string_pretty(&vals)?)
+        }
+        OutputFormat::Csv => {
+            let mut buf = String::new();
+            let mut out = Box::new(&mut buf) as Box<dyn Write>;
+            writeln!(out, "{}", CSV_HEADER).unwrap();
+            for jctx in jctxs {
+                job_csv(&mut out, jctx)?;
+            }
+            drop(out);
+            Ok(buf)
+        }
+        OutputFormat::Text => bail!("text isn't a structured output format"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::job::{FormatOpts, Job, JobData};
+    use crate::run::RunCtx;
+    use rd_agent_intf::SysReq;
+    use resctl_bench_intf::{JobProps, JobSpec};
+    use std::collections::BTreeSet;
+
+    struct KeyMetricsJob;
+
+    impl Job for KeyMetricsJob {
+        fn sysreqs(&self) -> BTreeSet<SysReq> {
+            Default::default()
+        }
+
+        fn run(&mut self, _rctx: &mut RunCtx) -> Result<serde_json::Value> {
+            unreachable!()
+        }
+
+        fn format<'a>(
+            &self,
+            _out: &mut Box<dyn Write + 'a>,
+            _data: &JobData,
+            _opts: &FormatOpts,
+            _props: &JobProps,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        fn key_metrics(&self, data: &JobData) -> Result<BTreeMap<String, f64>> {
+            let res = data.result.as_ref().unwrap();
+            let mut metrics = BTreeMap::new();
+            metrics.insert("isol".into(), res["isol"]["mean"].as_f64().unwrap());
+            Ok(metrics)
+        }
+    }
+
+    fn jctx(kind: &str, id: Option<&str>, result: Option<serde_json::Value>) -> JobCtx {
+        let mut jctx = JobCtx::new(&JobSpec::new(kind, id, None, JobSpec::props(&[])));
+        jctx.data.period = (100, 200);
+        jctx.data.result = result;
+        jctx
+    }
+
+    fn results() -> Vec<JobCtx> {
+        let result = serde_json::json!({ "isol": { "mean": 0.9, "stdev": 0.1 } });
+
+        // key metrics
+        let mut prot = jctx("protection", Some("a,b"), Some(result.clone()));
+        prot.job = Some(Box::new(KeyMetricsJob));
+        prot.data.spec.asserts = vec!["isol>=0.8".into(), "nope<1".into()];
+
+        // flattened result
+        let flat = jctx("iocost-qos", None, Some(result));
+
+        // incomplete, skipped
+        let pending = jctx("storage", None, None);
+
+        vec![prot, flat, pending]
+    }
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(super::csv_field("isol-50"), "isol-50");
+        assert_eq!(super::csv_field("a,b"), "\"a,b\"");
+        assert_eq!(super::csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn test_format_csv() {
+        let jctxs = results();
+        let refs: Vec<&JobCtx> = jctxs.iter().collect();
+        let out = format(&refs, OutputFormat::Csv).unwrap();
+        assert_eq!(
+            out.lines().collect::<Vec<&str>>(),
+            vec![
+                CSV_HEADER,
+                "protection,\"a,b\",isol,0.9",
+                "iocost-qos,,isol.mean,0.9",
+                "iocost-qos,,isol.stdev,0.1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_json() {
+        let jctxs = results();
+        let refs: Vec<&JobCtx> = jctxs.iter().collect();
+        let out = format(&refs, OutputFormat::Json).unwrap();
+        let vals: Vec<serde_json::Value> = serde_json::from_str(&out).unwrap();
+        assert_eq!(vals.len(), 2);
+
+        let prot = &vals[0];
+        assert_eq!(prot["kind"], "protection");
+        assert_eq!(prot["id"], "a,b");
+        assert_eq!(prot["period"], serde_json::json!([100, 200]));
+        assert_eq!(prot["metrics"], serde_json::json!({ "isol": 0.9 }));
+        let asserts = prot["assertions"].as_array().unwrap();
+        assert_eq!(asserts.len(), 2);
+        assert_eq!(asserts[0]["passed"], true);
+        assert_eq!(asserts[0]["value"], 0.9);
+        assert_eq!(asserts[1]["passed"], false);
+        assert!(asserts[1]["error"].as_str().unwrap().contains("nope"));
+
+        let flat = &vals[1];
+        assert_eq!(flat["kind"], "iocost-qos");
+        assert_eq!(flat["id"], serde_json::Value::Null);
+        assert_eq!(
+            flat["metrics"],
+            serde_json::json!({ "isol.mean": 0.9, "isol.stdev": 0.1 })
+        );
+        assert!(flat.get("assertions").is_none());
+
+        assert!(format(&refs, OutputFormat::Text).is_err());
+    }
+}
@@ -81,6 +81,10 @@ impl AgentFilesWrapper {
     pub fn bench(&self) -> rd_agent_intf::BenchKnobs {
         self.files.lock().unwrap().bench.data.clone()
     }
+
+    pub fn slices(&self) -> rd_agent_intf::SliceKnobs {
+        self.files.lock().unwrap().slices.data.clone()
+    }
 }
 
 pub struct AgentMinder {
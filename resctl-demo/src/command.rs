@@ -22,7 +22,7 @@ pub struct CmdState {
     pub bench_hashd_cur: u64,
     pub bench_iocost_cur: u64,
 
-    pub hashd: [HashdCmd; 2],
+    pub hashd: Vec<HashdCmd>,
     pub sideloads: BTreeMap<String, String>,
     pub sysloads: BTreeMap<String, String>,
 
@@ -50,6 +50,21 @@ impl CmdState {
         cs
     }
 
+    /// The command of the hashd instance `idx`. The ones which aren't in
+    /// cmd.json yet are inactive with the default parameters.
+    pub fn hashd_cmd(&self, idx: usize) -> HashdCmd {
+        self.hashd.get(idx).cloned().unwrap_or_default()
+    }
+
+    /// Mutable access to the command of the hashd instance `idx`,
+    /// extending hashd[] as necessary.
+    pub fn hashd_mut(&mut self, idx: usize) -> &mut HashdCmd {
+        if self.hashd.len() <= idx {
+            self.hashd.resize_with(idx + 1, Default::default);
+        }
+        &mut self.hashd[idx]
+    }
+
     pub fn refresh(&mut self) {
         AGENT_FILES.refresh();
         let af = AGENT_FILES.files.lock().unwrap();
@@ -113,11 +128,10 @@ impl CmdState {
         cmd.bench_iocost_seq = self.bench_iocost_next;
 
         cmd.hashd = self.hashd.clone();
-        if cmd.hashd[0].rps_target_ratio == 1.0 {
-            cmd.hashd[0].rps_target_ratio = 10.0;
-        }
-        if cmd.hashd[1].rps_target_ratio == 1.0 {
-            cmd.hashd[1].rps_target_ratio = 10.0;
+        for hashd in cmd.hashd.iter_mut() {
+            if hashd.rps_target_ratio == 1.0 {
+                hashd.rps_target_ratio = 10.0;
+            }
         }
         cmd.sideloads = self.sideloads.clone();
         cmd.sysloads = self.sysloads.clone();
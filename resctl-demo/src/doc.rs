@@ -1,4 +1,5 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
+use anyhow::{bail, Result};
 use cursive::direction::Orientation;
 use cursive::utils::markup::StyledString;
 use cursive::view::{Nameable, Resizable, Scrollable, SizeConstraint, View};
@@ -9,11 +10,12 @@ use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::sync::{Mutex, RwLock};
 
 mod index;
-mod markup_rd;
+pub mod markup_rd;
 
 use super::agent::AGENT_FILES;
 use super::command::{CmdState, CMD_STATE};
-use super::graph::{clear_main_graph, set_main_graph, GraphTag};
+use super::graph::{all_graph_tags, clear_main_graph, parse_graph_tag, set_main_graph};
+use super::replay;
 use super::{get_layout, COLOR_ACTIVE, COLOR_ALERT};
 use markup_rd::{RdCmd, RdDoc, RdKnob, RdPara, RdReset, RdSwitch};
 use rd_agent_intf::{Cmd, HashdCmd, SliceConfig, SysReq};
@@ -93,7 +95,8 @@ fn load_docs() -> BTreeMap<String, &'static str> {
 
     let mut nr_missing = 0;
 
-    let graph_tags: HashSet<String> = enum_iterator::all::<GraphTag>()
+    let graph_tags: HashSet<String> = all_graph_tags()
+        .into_iter()
         .map(|x| format!("{:?}", x))
         .collect();
     for tag in graphs.iter() {
@@ -186,6 +189,10 @@ fn format_markup_tags(tag: &str) -> Option<StyledString> {
 }
 
 fn exec_one_cmd(siv: &mut Cursive, cmd: &RdCmd) {
+    if replay::replaying() {
+        info!("replaying, ignoring {:?}", cmd);
+        return;
+    }
     info!("executing {:?}", cmd);
 
     let mut cs = CMD_STATE.lock().unwrap();
@@ -224,8 +231,7 @@ fn exec_one_cmd(siv: &mut Cursive, cmd: &RdCmd) {
                         cs.bench_iocost_next = 1;
                     }
                 }
-                RdSwitch::HashdA => cs.hashd[0].active = is_on,
-                RdSwitch::HashdB => cs.hashd[1].active = is_on,
+                RdSwitch::Hashd(idx) => cs.hashd_mut(*idx).active = is_on,
                 RdSwitch::Sideload(tag, id) => {
                     if is_on {
                         cs.sideloads.insert(tag.clone(), id.clone());
@@ -251,34 +257,20 @@ fn exec_one_cmd(siv: &mut Cursive, cmd: &RdCmd) {
             }
         }
         RdCmd::Knob(knob, val) => match knob {
-            RdKnob::HashdALoad => cs.hashd[0].rps_target_ratio = *val,
-            RdKnob::HashdBLoad => cs.hashd[1].rps_target_ratio = *val,
-            RdKnob::HashdALatTargetPct => cs.hashd[0].lat_target_pct = *val,
-            RdKnob::HashdBLatTargetPct => cs.hashd[1].lat_target_pct = *val,
-            RdKnob::HashdALatTarget => cs.hashd[0].lat_target = *val,
-            RdKnob::HashdBLatTarget => cs.hashd[1].lat_target = *val,
-            RdKnob::HashdAMem => cs.hashd[0].mem_ratio = Some(*val),
-            RdKnob::HashdBMem => cs.hashd[1].mem_ratio = Some(*val),
-            RdKnob::HashdAFileAddrStdev => {
-                cs.hashd[0].file_addr_stdev = Some(if *val < 1.0 { *val } else { 100.0 });
-            }
-            RdKnob::HashdAAnonAddrStdev => {
-                cs.hashd[0].anon_addr_stdev = Some(if *val < 1.0 { *val } else { 100.0 });
+            RdKnob::HashdLoad(idx) => cs.hashd_mut(*idx).rps_target_ratio = *val,
+            RdKnob::HashdLatTargetPct(idx) => cs.hashd_mut(*idx).lat_target_pct = *val,
+            RdKnob::HashdLatTarget(idx) => cs.hashd_mut(*idx).lat_target = *val,
+            RdKnob::HashdMem(idx) => cs.hashd_mut(*idx).mem_ratio = Some(*val),
+            RdKnob::HashdFileAddrStdev(idx) => {
+                cs.hashd_mut(*idx).file_addr_stdev = Some(if *val < 1.0 { *val } else { 100.0 });
             }
-            RdKnob::HashdBFileAddrStdev => {
-                cs.hashd[1].file_addr_stdev = Some(if *val < 1.0 { *val } else { 100.0 });
+            RdKnob::HashdAnonAddrStdev(idx) => {
+                cs.hashd_mut(*idx).anon_addr_stdev = Some(if *val < 1.0 { *val } else { 100.0 });
             }
-            RdKnob::HashdBAnonAddrStdev => {
-                cs.hashd[1].anon_addr_stdev = Some(if *val < 1.0 { *val } else { 100.0 });
-            }
-            RdKnob::HashdAFile => cs.hashd[0].file_ratio = *val,
-            RdKnob::HashdBFile => cs.hashd[1].file_ratio = *val,
-            RdKnob::HashdAFileMax => cs.hashd[0].file_max_ratio = *val,
-            RdKnob::HashdBFileMax => cs.hashd[1].file_max_ratio = *val,
-            RdKnob::HashdALogBps => cs.hashd[0].log_bps = (wbps * *val).round() as u64,
-            RdKnob::HashdBLogBps => cs.hashd[1].log_bps = (wbps * *val).round() as u64,
-            RdKnob::HashdAWeight => cs.hashd[0].weight = *val,
-            RdKnob::HashdBWeight => cs.hashd[1].weight = *val,
+            RdKnob::HashdFile(idx) => cs.hashd_mut(*idx).file_ratio = *val,
+            RdKnob::HashdFileMax(idx) => cs.hashd_mut(*idx).file_max_ratio = *val,
+            RdKnob::HashdLogBps(idx) => cs.hashd_mut(*idx).log_bps = (wbps * *val).round() as u64,
+            RdKnob::HashdWeight(idx) => cs.hashd_mut(*idx).weight = *val,
             RdKnob::SysCpuRatio => cs.sys_cpu_ratio = *val,
             RdKnob::SysIoRatio => cs.sys_io_ratio = *val,
             RdKnob::MemMargin => cs.mem_margin = *val,
@@ -287,11 +279,10 @@ fn exec_one_cmd(siv: &mut Cursive, cmd: &RdCmd) {
         },
         RdCmd::Graph(tag_name) => {
             if tag_name.len() > 0 {
-                let tag = enum_iterator::all::<GraphTag>()
-                    .filter(|x| &format!("{:?}", x) == tag_name)
-                    .next()
-                    .unwrap();
-                set_main_graph(siv, tag);
+                match parse_graph_tag(tag_name) {
+                    Some(tag) => set_main_graph(siv, tag),
+                    None => warn!("doc: invalid graph tag {:?}", tag_name),
+                }
             } else {
                 clear_main_graph(siv);
             }
@@ -302,18 +293,17 @@ fn exec_one_cmd(siv: &mut Cursive, cmd: &RdCmd) {
                 cs.bench_iocost_next = cs.bench_iocost_cur;
             };
             let reset_hashds = |cs: &mut CmdState| {
-                cs.hashd[0].active = false;
-                cs.hashd[1].active = false;
+                for hashd in cs.hashd.iter_mut() {
+                    hashd.active = false;
+                }
             };
             let reset_hashd_params = |cs: &mut CmdState| {
-                cs.hashd[0] = HashdCmd {
-                    active: cs.hashd[0].active,
-                    ..Default::default()
-                };
-                cs.hashd[1] = HashdCmd {
-                    active: cs.hashd[1].active,
-                    ..Default::default()
-                };
+                for hashd in cs.hashd.iter_mut() {
+                    *hashd = HashdCmd {
+                        active: hashd.active,
+                        ..Default::default()
+                    };
+                }
             };
             let reset_secondaries = |cs: &mut CmdState| {
                 cs.sideloads.clear();
@@ -430,17 +420,13 @@ fn exec_toggle(siv: &mut Cursive, cmd: &RdCmd, val: bool) {
     }
 }
 
-fn format_knob_val(knob: &RdKnob, ratio: f64) -> String {
+pub fn format_knob_val(knob: &RdKnob, ratio: f64) -> String {
     let bench = AGENT_FILES.bench();
 
     let v = match knob {
-        RdKnob::HashdALatTarget | RdKnob::HashdBLatTarget => {
-            format!("{}m", (ratio * 1000.0).round())
-        }
-        RdKnob::HashdAMem | RdKnob::HashdBMem => format_size(ratio * bench.hashd.mem_size as f64),
-        RdKnob::HashdALogBps | RdKnob::HashdBLogBps => {
-            format_size(ratio * bench.iocost.model.wbps as f64)
-        }
+        RdKnob::HashdLatTarget(_) => format!("{}m", (ratio * 1000.0).round()),
+        RdKnob::HashdMem(_) => format_size(ratio * bench.hashd.mem_size as f64),
+        RdKnob::HashdLogBps(_) => format_size(ratio * bench.iocost.model.wbps as f64),
         RdKnob::MemMargin => format_size(ratio * total_memory() as f64),
         RdKnob::Balloon => format_size(ratio * total_memory() as f64),
         _ => format4_pct(ratio) + "%",
@@ -450,8 +436,11 @@ fn format_knob_val(knob: &RdKnob, ratio: f64) -> String {
 }
 
 fn exec_knob(siv: &mut Cursive, cmd: &RdCmd, val: usize, range: usize) {
+    exec_knob_ratio(siv, cmd, val as f64 / (range - 1) as f64);
+}
+
+fn exec_knob_ratio(siv: &mut Cursive, cmd: &RdCmd, ratio: f64) {
     if let RdCmd::Knob(knob, _) = cmd {
-        let ratio = val as f64 / (range - 1) as f64;
         siv.call_on_all_named(&format!("{:?}-digit", knob), |t: &mut TextView| {
             t.set_content(format_knob_val(knob, ratio))
         });
@@ -462,38 +451,44 @@ fn exec_knob(siv: &mut Cursive, cmd: &RdCmd, val: usize, range: usize) {
     }
 }
 
-fn refresh_toggles(siv: &mut Cursive, doc: &RdDoc, cs: &CmdState) {
-    for sw in doc.toggles.iter() {
-        let val = match sw {
-            RdSwitch::BenchHashd => cs.bench_hashd_next > cs.bench_hashd_cur,
-            RdSwitch::BenchHashdLoop => cs.bench_hashd_next == std::u64::MAX,
-            RdSwitch::BenchIoCost => cs.bench_iocost_next > cs.bench_iocost_cur,
-            RdSwitch::BenchNeeded => cs.bench_hashd_cur == 0 || cs.bench_iocost_cur == 0,
-            RdSwitch::HashdA => cs.hashd[0].active,
-            RdSwitch::HashdB => cs.hashd[1].active,
-            RdSwitch::Sideload(tag, _) => cs.sideloads.contains_key(tag),
-            RdSwitch::Sysload(tag, _) => cs.sysloads.contains_key(tag),
-            RdSwitch::CpuResCtl => cs.cpu,
-            RdSwitch::MemResCtl => cs.mem,
-            RdSwitch::IoResCtl => cs.io,
-            RdSwitch::Oomd => cs.oomd,
-            RdSwitch::OomdWorkMemPressure => cs.oomd_work_mempress,
-            RdSwitch::OomdWorkSenpai => cs.oomd_work_senpai,
-            RdSwitch::OomdSysMemPressure => cs.oomd_sys_mempress,
-            RdSwitch::OomdSysSenpai => cs.oomd_sys_senpai,
-        };
+/// Name of the checkbox view for a toggle. Sideload and sysload toggles
+/// share the view regardless of the job ID.
+pub fn toggle_name(sw: &RdSwitch) -> String {
+    match sw {
+        RdSwitch::Sideload(tag, _) => {
+            format!("{:?}", RdSwitch::Sideload(tag.into(), "ID".into()))
+        }
+        RdSwitch::Sysload(tag, _) => {
+            format!("{:?}", RdSwitch::Sysload(tag.into(), "ID".into()))
+        }
+        _ => format!("{:?}", sw),
+    }
+}
 
-        let name = match sw {
-            RdSwitch::Sideload(tag, _) => {
-                format!("{:?}", RdSwitch::Sideload(tag.into(), "ID".into()))
-            }
-            RdSwitch::Sysload(tag, _) => {
-                format!("{:?}", RdSwitch::Sysload(tag.into(), "ID".into()))
-            }
-            _ => format!("{:?}", sw),
-        };
+pub fn toggle_val(sw: &RdSwitch, cs: &CmdState) -> bool {
+    match sw {
+        RdSwitch::BenchHashd => cs.bench_hashd_next > cs.bench_hashd_cur,
+        RdSwitch::BenchHashdLoop => cs.bench_hashd_next == std::u64::MAX,
+        RdSwitch::BenchIoCost => cs.bench_iocost_next > cs.bench_iocost_cur,
+        RdSwitch::BenchNeeded => cs.bench_hashd_cur == 0 || cs.bench_iocost_cur == 0,
+        RdSwitch::Hashd(idx) => cs.hashd_cmd(*idx).active,
+        RdSwitch::Sideload(tag, _) => cs.sideloads.contains_key(tag),
+        RdSwitch::Sysload(tag, _) => cs.sysloads.contains_key(tag),
+        RdSwitch::CpuResCtl => cs.cpu,
+        RdSwitch::MemResCtl => cs.mem,
+        RdSwitch::IoResCtl => cs.io,
+        RdSwitch::Oomd => cs.oomd,
+        RdSwitch::OomdWorkMemPressure => cs.oomd_work_mempress,
+        RdSwitch::OomdWorkSenpai => cs.oomd_work_senpai,
+        RdSwitch::OomdSysMemPressure => cs.oomd_sys_mempress,
+        RdSwitch::OomdSysSenpai => cs.oomd_sys_senpai,
+    }
+}
 
-        siv.call_on_all_named(&name, |c: &mut Checkbox| {
+fn refresh_toggles(siv: &mut Cursive, doc: &RdDoc, cs: &CmdState) {
+    for sw in doc.toggles.iter() {
+        let val = toggle_val(sw, cs);
+        siv.call_on_all_named(&toggle_name(sw), |c: &mut Checkbox| {
             c.set_checked(val);
         });
     }
@@ -534,39 +529,32 @@ fn hashd_cmd_anon_addr_stdev(hashd: &HashdCmd) -> f64 {
     }
 }
 
-fn refresh_knobs(siv: &mut Cursive, doc: &RdDoc, cs: &CmdState) {
+/// Current value of a knob as the 0.0 - 1.0 ratio shown on the slider.
+pub fn knob_val(knob: &RdKnob, cs: &CmdState) -> f64 {
     let wbps = AGENT_FILES.bench().iocost.model.wbps as f64;
 
-    for knob in doc.knobs.iter() {
-        let val = match knob {
-            RdKnob::HashdALoad => cs.hashd[0].rps_target_ratio,
-            RdKnob::HashdBLoad => cs.hashd[1].rps_target_ratio,
-            RdKnob::HashdALatTargetPct => cs.hashd[0].lat_target_pct,
-            RdKnob::HashdBLatTargetPct => cs.hashd[1].lat_target_pct,
-            RdKnob::HashdALatTarget => cs.hashd[0].lat_target,
-            RdKnob::HashdBLatTarget => cs.hashd[1].lat_target,
-            RdKnob::HashdAMem => hmem_ratio(cs.hashd[0].mem_ratio),
-            RdKnob::HashdBMem => hmem_ratio(cs.hashd[1].mem_ratio),
-            RdKnob::HashdAFileAddrStdev => hashd_cmd_file_addr_stdev(&cs.hashd[0]),
-            RdKnob::HashdAAnonAddrStdev => hashd_cmd_anon_addr_stdev(&cs.hashd[0]),
-            RdKnob::HashdBFileAddrStdev => hashd_cmd_file_addr_stdev(&cs.hashd[1]),
-            RdKnob::HashdBAnonAddrStdev => hashd_cmd_anon_addr_stdev(&cs.hashd[1]),
-            RdKnob::HashdAFile => cs.hashd[0].file_ratio,
-            RdKnob::HashdBFile => cs.hashd[1].file_ratio,
-            RdKnob::HashdAFileMax => cs.hashd[0].file_max_ratio,
-            RdKnob::HashdBFileMax => cs.hashd[1].file_max_ratio,
-            RdKnob::HashdALogBps => cs.hashd[0].log_bps as f64 / wbps,
-            RdKnob::HashdBLogBps => cs.hashd[1].log_bps as f64 / wbps,
-            RdKnob::HashdAWeight => cs.hashd[0].weight,
-            RdKnob::HashdBWeight => cs.hashd[1].weight,
-            RdKnob::SysCpuRatio => cs.sys_cpu_ratio,
-            RdKnob::SysIoRatio => cs.sys_io_ratio,
-            RdKnob::MemMargin => cs.mem_margin,
-            RdKnob::Balloon => cs.balloon_ratio,
-            RdKnob::CpuHeadroom => cs.cpu_headroom,
-        };
+    match knob {
+        RdKnob::HashdLoad(idx) => cs.hashd_cmd(*idx).rps_target_ratio,
+        RdKnob::HashdLatTargetPct(idx) => cs.hashd_cmd(*idx).lat_target_pct,
+        RdKnob::HashdLatTarget(idx) => cs.hashd_cmd(*idx).lat_target,
+        RdKnob::HashdMem(idx) => hmem_ratio(cs.hashd_cmd(*idx).mem_ratio),
+        RdKnob::HashdFileAddrStdev(idx) => hashd_cmd_file_addr_stdev(&cs.hashd_cmd(*idx)),
+        RdKnob::HashdAnonAddrStdev(idx) => hashd_cmd_anon_addr_stdev(&cs.hashd_cmd(*idx)),
+        RdKnob::HashdFile(idx) => cs.hashd_cmd(*idx).file_ratio,
+        RdKnob::HashdFileMax(idx) => cs.hashd_cmd(*idx).file_max_ratio,
+        RdKnob::HashdLogBps(idx) => cs.hashd_cmd(*idx).log_bps as f64 / wbps,
+        RdKnob::HashdWeight(idx) => cs.hashd_cmd(*idx).weight,
+        RdKnob::SysCpuRatio => cs.sys_cpu_ratio,
+        RdKnob::SysIoRatio => cs.sys_io_ratio,
+        RdKnob::MemMargin => cs.mem_margin,
+        RdKnob::Balloon => cs.balloon_ratio,
+        RdKnob::CpuHeadroom => cs.cpu_headroom,
+    }
+}
 
-        refresh_one_knob(siv, knob, val);
+fn refresh_knobs(siv: &mut Cursive, doc: &RdDoc, cs: &CmdState) {
+    for knob in doc.knobs.iter() {
+        refresh_one_knob(siv, knob, knob_val(knob, cs));
     }
 }
 
@@ -620,6 +608,41 @@ pub fn show_doc(siv: &mut Cursive, target: &str, jump: bool, back: bool) {
     refresh_cur_doc(siv);
 }
 
+/// Execute the `idx`'th prompt of document `id` as if it were activated in
+/// the document pane. `val` is the new state for toggles and the ratio for
+/// sliders. Fails if `id` is no longer the current document.
+pub fn exec_prompt(siv: &mut Cursive, id: &str, idx: usize, val: Option<f64>) -> Result<()> {
+    let cur_doc = CUR_DOC.read().unwrap();
+    if cur_doc.id != id {
+        bail!("document {:?} is not current", id);
+    }
+    let cmd = match cur_doc
+        .body
+        .iter()
+        .filter_map(|para| match para {
+            RdPara::Prompt(_, cmd) => Some(cmd),
+            _ => None,
+        })
+        .nth(idx)
+    {
+        Some(v) => v.clone(),
+        None => bail!("document {:?} doesn't have prompt {}", id, idx),
+    };
+    drop(cur_doc);
+
+    match (&cmd, val) {
+        (RdCmd::Toggle(_), Some(v)) => exec_toggle(siv, &cmd, v > 0.0),
+        (RdCmd::Knob(_, knob_val), Some(v)) if *knob_val < 0.0 => {
+            exec_knob_ratio(siv, &cmd, v.clamp(0.0, 1.0))
+        }
+        (RdCmd::Toggle(_), None) => bail!("toggle requires a value"),
+        (RdCmd::Knob(_, knob_val), None) if *knob_val < 0.0 => bail!("slider requires a value"),
+        (RdCmd::Jump(target), _) => show_doc(siv, target, true, false),
+        _ => exec_cmd(siv, &cmd),
+    }
+    Ok(())
+}
+
 fn create_button<F>(prompt: &str, cb: F) -> impl View
 where
     F: 'static + Fn(&mut Cursive) + std::marker::Sync + std::marker::Send,
@@ -641,16 +664,7 @@ fn render_cmd(prompt: &str, cmd: &RdCmd) -> impl View {
             view = view.child(create_button(prompt, move |siv| exec_cmd(siv, &cmdc)));
         }
         RdCmd::Toggle(sw) => {
-            let name = match sw {
-                RdSwitch::Sideload(tag, _id) => {
-                    format!("{:?}", RdSwitch::Sideload(tag.into(), "ID".into()))
-                }
-                RdSwitch::Sysload(tag, _id) => {
-                    format!("{:?}", RdSwitch::Sysload(tag.into(), "ID".into()))
-                }
-                _ => format!("{:?}", sw),
-            };
-
+            let name = toggle_name(sw);
             view = view.child(
                 LinearLayout::horizontal()
                     .child(
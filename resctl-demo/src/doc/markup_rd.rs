@@ -10,6 +10,7 @@ use std::mem::swap;
 
 use super::format_markup_tags;
 use crate::{COLOR_ALERT, COLOR_DFL, STYLE_ALERT};
+use rd_agent_intf::{hashd_idx, HASHD_MAX_INSTANCES};
 
 const RD_PRE_CMD_PREFIX: &str = "%% ";
 const RD_POST_CMD_PREFIX: &str = "$$ ";
@@ -23,8 +24,7 @@ pub enum RdSwitch {
     BenchHashdLoop,
     BenchIoCost,
     BenchNeeded,
-    HashdA,
-    HashdB,
+    Hashd(usize),
     Sideload(String, String),
     Sysload(String, String),
     CpuResCtl,
@@ -39,26 +39,16 @@ pub enum RdSwitch {
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum RdKnob {
-    HashdALoad,
-    HashdBLoad,
-    HashdALatTargetPct,
-    HashdBLatTargetPct,
-    HashdALatTarget,
-    HashdBLatTarget,
-    HashdAMem,
-    HashdBMem,
-    HashdAFileAddrStdev,
-    HashdAAnonAddrStdev,
-    HashdBFileAddrStdev,
-    HashdBAnonAddrStdev,
-    HashdAFile,
-    HashdBFile,
-    HashdAFileMax,
-    HashdBFileMax,
-    HashdALogBps,
-    HashdBLogBps,
-    HashdAWeight,
-    HashdBWeight,
+    HashdLoad(usize),
+    HashdLatTargetPct(usize),
+    HashdLatTarget(usize),
+    HashdMem(usize),
+    HashdFileAddrStdev(usize),
+    HashdAnonAddrStdev(usize),
+    HashdFile(usize),
+    HashdFileMax(usize),
+    HashdLogBps(usize),
+    HashdWeight(usize),
     SysCpuRatio,
     SysIoRatio,
     MemMargin,
@@ -224,6 +214,23 @@ fn parse_markup_text(input: &str) -> Option<StyledString> {
     Some(parsed)
 }
 
+/// Split a hashd switch or knob target into the instance index and the
+/// rest, e.g. "hashd-C-load" into (2, "load"). The instance can be omitted
+/// for hashd-A, e.g. "hashd" and "hashd-load".
+fn parse_hashd_target(target: &str) -> Option<(usize, &str)> {
+    let rest = match target {
+        "hashd" => return Some((0, "")),
+        t if t.starts_with("hashd-") => &t["hashd-".len()..],
+        _ => return None,
+    };
+    let mut toks = rest.splitn(2, '-');
+    match hashd_idx(toks.next().unwrap()) {
+        Some(idx) if idx < HASHD_MAX_INSTANCES => Some((idx, toks.next().unwrap_or(""))),
+        Some(_) => None,
+        None => Some((0, rest)),
+    }
+}
+
 impl RdCmd {
     fn parse(input: &str) -> Result<(RdCmd, Option<String>)> {
         let mut args: Vec<&str> = input.split_whitespace().collect();
@@ -253,8 +260,6 @@ impl RdCmd {
                     "bench-hashd-loop" => RdSwitch::BenchHashdLoop,
                     "bench-iocost" => RdSwitch::BenchIoCost,
                     "bench-needed" => RdSwitch::BenchNeeded,
-                    "hashd" | "hashd-A" => RdSwitch::HashdA,
-                    "hashd-B" => RdSwitch::HashdB,
                     "sideload" | "sysload" => {
                         if (args[0] == "off" && args.len() != 3)
                             || (args[0] != "off" && args.len() != 4)
@@ -279,7 +284,10 @@ impl RdCmd {
                     "oomd-work-senpai" => RdSwitch::OomdWorkSenpai,
                     "oomd-sys-mem-pressure" => RdSwitch::OomdSysMemPressure,
                     "oomd-sys-senpai" => RdSwitch::OomdSysSenpai,
-                    _ => bail!("invalid switch target"),
+                    target => match parse_hashd_target(target) {
+                        Some((idx, "")) => RdSwitch::Hashd(idx),
+                        _ => bail!("invalid switch target"),
+                    },
                 };
                 match &sw {
                     RdSwitch::Sideload(_, _) | RdSwitch::Sysload(_, _) => {}
@@ -304,36 +312,24 @@ impl RdCmd {
                     _ => bail!("invalid number of arguments"),
                 };
                 let knob = match args[1] {
-                    "hashd-load" | "hashd-A-load" => RdKnob::HashdALoad,
-                    "hashd-lat-target-pct" | "hashd-A-lat-target-pct" => RdKnob::HashdALatTargetPct,
-                    "hashd-lat-target" | "hashd-A-lat-target" => RdKnob::HashdALatTarget,
-                    "hashd-mem" | "hashd-A-mem" => RdKnob::HashdAMem,
-                    "hashd-file-addr-stdev" | "hashd-A-file-addr-stdev" => {
-                        RdKnob::HashdAFileAddrStdev
-                    }
-                    "hashd-anon-addr-stdev" | "hashd-A-anon-addr-stdev" => {
-                        RdKnob::HashdAAnonAddrStdev
-                    }
-                    "hashd-file" | "hashd-A-file" => RdKnob::HashdAFile,
-                    "hashd-file-max" | "hashd-A-file-max" => RdKnob::HashdAFileMax,
-                    "hashd-log-bps" | "hashd-A-write" => RdKnob::HashdALogBps,
-                    "hashd-weight" | "hashd-A-weight" => RdKnob::HashdAWeight,
-                    "hashd-B-load" => RdKnob::HashdBLoad,
-                    "hashd-B-lat-target-pct" => RdKnob::HashdBLatTargetPct,
-                    "hashd-B-lat-target" => RdKnob::HashdBLatTarget,
-                    "hashd-B-mem" => RdKnob::HashdBMem,
-                    "hashd-B-file-addr-stdev" => RdKnob::HashdBFileAddrStdev,
-                    "hashd-B-anon-addr-stdev" => RdKnob::HashdBAnonAddrStdev,
-                    "hashd-B-file" => RdKnob::HashdBFile,
-                    "hashd-B-file-max" => RdKnob::HashdBFileMax,
-                    "hashd-B-log-bps" => RdKnob::HashdBLogBps,
-                    "hashd-B-weight" => RdKnob::HashdBWeight,
                     "sys-cpu-ratio" => RdKnob::SysCpuRatio,
                     "sys-io-ratio" => RdKnob::SysIoRatio,
                     "mem-margin" => RdKnob::MemMargin,
                     "balloon" => RdKnob::Balloon,
                     "cpu-headroom" => RdKnob::CpuHeadroom,
-                    _ => bail!("invalid knob target"),
+                    target => match parse_hashd_target(target) {
+                        Some((idx, "load")) => RdKnob::HashdLoad(idx),
+                        Some((idx, "lat-target-pct")) => RdKnob::HashdLatTargetPct(idx),
+                        Some((idx, "lat-target")) => RdKnob::HashdLatTarget(idx),
+                        Some((idx, "mem")) => RdKnob::HashdMem(idx),
+                        Some((idx, "file-addr-stdev")) => RdKnob::HashdFileAddrStdev(idx),
+                        Some((idx, "anon-addr-stdev")) => RdKnob::HashdAnonAddrStdev(idx),
+                        Some((idx, "file")) => RdKnob::HashdFile(idx),
+                        Some((idx, "file-max")) => RdKnob::HashdFileMax(idx),
+                        Some((idx, "log-bps")) | Some((idx, "write")) => RdKnob::HashdLogBps(idx),
+                        Some((idx, "weight")) => RdKnob::HashdWeight(idx),
+                        _ => bail!("invalid knob target"),
+                    },
                 };
                 RdCmd::Knob(knob, val)
             }
@@ -702,4 +698,26 @@ $$ off hashd
         siv.add_layer(Dialog::around(view.scrollable()).button("quit", |siv| siv.quit()));
         siv.run();
     }
+
+    #[test]
+    fn test_parse_hashd_target() {
+        assert_eq!(parse_hashd_target("hashd"), Some((0, "")));
+        assert_eq!(parse_hashd_target("hashd-B"), Some((1, "")));
+        assert_eq!(parse_hashd_target("hashd-load"), Some((0, "load")));
+        assert_eq!(
+            parse_hashd_target("hashd-A-lat-target"),
+            Some((0, "lat-target"))
+        );
+        assert_eq!(
+            parse_hashd_target("hashd-D-file-max"),
+            Some((3, "file-max"))
+        );
+        assert_eq!(parse_hashd_target("bench-hashd"), None);
+
+        match RdCmd::parse("knob hashd-C-weight 0.5").unwrap().0 {
+            RdCmd::Knob(RdKnob::HashdWeight(2), v) => assert_eq!(v, 0.5),
+            cmd => panic!("unexpected {:?}", &cmd),
+        }
+        assert!(RdCmd::parse("on hashd-C-load").is_err());
+    }
 }
@@ -8,6 +8,7 @@ use cursive::Cursive;
 use cursive_tabs::TabView;
 use enum_iterator::Sequence;
 use log::error;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt;
 use std::fs;
@@ -19,7 +20,10 @@ use std::sync::Mutex;
 use std::thread::{spawn, JoinHandle};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use super::graph_cfg::{self, GraphSel, RateTracker, SeriesCfg};
+use super::replay;
 use super::report_ring::ReportDataSet;
+use super::web;
 use super::{
     get_layout, kick_refresh, Layout, AGENT_FILES, COLOR_ACTIVE, COLOR_ALERT, COLOR_GRAPH_1,
     COLOR_GRAPH_2, COLOR_GRAPH_3, COLOR_INACTIVE, TEMP_DIR,
@@ -31,14 +35,63 @@ const GRAPH_X_ADJ: usize = 20;
 const GRAPH_INTVS: &[u64] = &[1, 5, 15, 30, 60];
 const GRAPH_NR_TABS: usize = 4;
 const GRAPH_TAB_NAMES: &[&'static str] = &["rps/psi", "utilization", "IO", "iocost/psi-some"];
+const GRAPH_CUSTOM_PER_TAB: usize = 4;
+const GRAPH_TAB_TAGS: [[GraphTag; 4]; GRAPH_NR_TABS] = [
+    [
+        GraphTag::HashdA,
+        GraphTag::MemPsiFull,
+        GraphTag::CpuPsiSome,
+        GraphTag::IoPsiFull,
+    ],
+    [
+        GraphTag::SwapUtil,
+        GraphTag::MemUtil,
+        GraphTag::CpuUtil,
+        GraphTag::IoUtil,
+    ],
+    [
+        GraphTag::ReadBps,
+        GraphTag::WriteBps,
+        GraphTag::ReadLat,
+        GraphTag::WriteLat,
+    ],
+    [
+        GraphTag::IoCost,
+        GraphTag::MemPsiSome,
+        GraphTag::CpuPsiSome2,
+        GraphTag::IoPsiSome,
+    ],
+];
+// number of data points in the graphs served by the web front-end
+const WEB_GRAPH_POINTS: u64 = 120;
 
 lazy_static::lazy_static! {
     static ref GRAPH_INTV_IDX: Mutex<usize> = Mutex::new(0);
     static ref GRAPH_MAIN_TAG: Mutex<GraphTag> = Mutex::new(GraphTag::HashdA);
     static ref GRAPH_TAB_IDX: Mutex<usize> = Mutex::new(0);
+    static ref WEB_GRAPHS: Mutex<HashMap<GraphTag, WebGraph>> = Mutex::new(HashMap::new());
+}
+
+/// Latest data of a graph for the web front-end. `points` are the
+/// timestamps relative to `at` followed by the value of each series.
+#[derive(Clone, Debug, Serialize)]
+pub struct WebGraph {
+    pub title: String,
+    pub at: u64,
+    pub intv: u64,
+    pub span: u64,
+    pub series: Vec<WebSeries>,
+    pub points: Vec<(i64, Vec<f64>)>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct WebSeries {
+    pub title: String,
+    pub min: f64,
+    pub max: f64,
 }
 
-fn graph_intv() -> u64 {
+pub fn graph_intv() -> u64 {
     GRAPH_INTVS[*GRAPH_INTV_IDX.lock().unwrap()]
 }
 
@@ -78,8 +131,29 @@ pub fn clear_main_graph(siv: &mut Cursive) {
     kick_refresh();
 }
 
+fn graph_tab_name(pos: usize) -> String {
+    match pos {
+        pos if pos < GRAPH_NR_TABS => GRAPH_TAB_NAMES[pos].to_string(),
+        GRAPH_NR_TABS => "custom".to_string(),
+        pos => format!("custom-{}", pos - GRAPH_NR_TABS + 1),
+    }
+}
+
+/// The graphs shown in the `pos`'th tab of the full screen graph view.
+pub fn graph_tab_tags(pos: usize) -> Vec<GraphTag> {
+    if pos < GRAPH_NR_TABS {
+        GRAPH_TAB_TAGS[pos].to_vec()
+    } else {
+        let start = (pos - GRAPH_NR_TABS) * GRAPH_CUSTOM_PER_TAB;
+        let end = (start + GRAPH_CUSTOM_PER_TAB).min(graph_cfg::panels().len());
+        (start..end)
+            .map(|idx| GraphTag::Custom(idx as u8))
+            .collect()
+    }
+}
+
 fn graph_tab_id(pos: usize) -> String {
-    format!("graph-tab-{}", GRAPH_TAB_NAMES[pos])
+    format!("graph-tab-{}", graph_tab_name(pos))
 }
 
 fn graph_tab_focus(siv: &mut Cursive, idx: usize) {
@@ -88,8 +162,56 @@ fn graph_tab_focus(siv: &mut Cursive, idx: usize) {
     });
 }
 
+pub fn graph_tab_names() -> Vec<String> {
+    (0..graph_nr_active_tabs()).map(graph_tab_name).collect()
+}
+
+/// All existing graph tags. GraphTag::Custom is limited to the configured
+/// panels.
+pub fn all_graph_tags() -> Vec<GraphTag> {
+    let nr_custom = graph_cfg::panels().len();
+    enum_iterator::all::<GraphTag>()
+        .filter(|tag| match tag {
+            GraphTag::Custom(idx) => (*idx as usize) < nr_custom,
+            _ => true,
+        })
+        .collect()
+}
+
+pub fn parse_graph_tag(name: &str) -> Option<GraphTag> {
+    all_graph_tags()
+        .into_iter()
+        .find(|tag| format!("{:?}", tag) == name)
+}
+
+pub fn graph_title(tag: GraphTag) -> String {
+    match tag {
+        GraphTag::Custom(idx) => match graph_cfg::panels().get(idx as usize) {
+            Some(panel) => panel.title.clone(),
+            None => format!("{:?}", tag),
+        },
+        tag => ALL_GRAPHS
+            .iter()
+            .find(|x| x.0 == tag)
+            .map(|x| x.1.to_string())
+            .unwrap(),
+    }
+}
+
+pub fn main_graph_tag() -> GraphTag {
+    *GRAPH_MAIN_TAG.lock().unwrap()
+}
+
+pub fn web_graph(tag: GraphTag) -> Option<WebGraph> {
+    WEB_GRAPHS.lock().unwrap().get(&tag).cloned()
+}
+
+fn graph_nr_custom_tabs() -> usize {
+    (graph_cfg::panels().len() + GRAPH_CUSTOM_PER_TAB - 1) / GRAPH_CUSTOM_PER_TAB
+}
+
 fn graph_nr_active_tabs() -> usize {
-    GRAPH_NR_TABS
+    GRAPH_NR_TABS + graph_nr_custom_tabs()
 }
 
 pub fn graph_tab_next(siv: &mut Cursive) {
@@ -108,12 +230,20 @@ pub fn graph_tab_prev(siv: &mut Cursive) {
     graph_tab_focus(siv, *idx);
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PlotDataAggr {
+    #[serde(rename = "avg")]
     AVG,
+    #[serde(rename = "max")]
     MAX,
 }
 
+impl Default for PlotDataAggr {
+    fn default() -> Self {
+        Self::AVG
+    }
+}
+
 pub struct PlotSpec {
     pub sel: Box<dyn 'static + Send + Fn(&Report) -> f64>,
     pub aggr: PlotDataAggr,
@@ -352,6 +482,45 @@ impl UpdateWorker {
         )
     }
 
+    fn publish_web_graph(&mut self, now: u64, span: u64) -> Result<()> {
+        let intv = graph_intv();
+        self.data.fill(now, intv, span)?;
+
+        // the third series of some graphs is a placeholder without title
+        let idxs: Vec<usize> = (0..self.specs.len())
+            .filter(|&i| i == 0 || (self.specs[i].title)().len() > 0)
+            .collect();
+        let latest_at = self.data.latest_at();
+        let mut points = vec![];
+        for (at, val) in self.data.iter() {
+            if let Some(v) = val {
+                let vals = [v.0, v.1, v.2];
+                points.push((
+                    at as i64 - latest_at as i64,
+                    idxs.iter().map(|&i| vals[i]).collect(),
+                ));
+            }
+        }
+
+        let graph = WebGraph {
+            title: graph_title(self.tag),
+            at: latest_at,
+            intv,
+            span,
+            series: idxs
+                .iter()
+                .map(|&i| WebSeries {
+                    title: (self.specs[i].title)(),
+                    min: (self.specs[i].min)(),
+                    max: (self.specs[i].max)(),
+                })
+                .collect(),
+            points,
+        };
+        WEB_GRAPHS.lock().unwrap().insert(self.tag, graph);
+        Ok(())
+    }
+
     fn refresh_graph(siv: &mut Cursive, tag: GraphTag, graph: StyledString) {
         if *GRAPH_MAIN_TAG.lock().unwrap() == tag {
             siv.call_on_name("graph-main", |v: &mut TextView| {
@@ -366,7 +535,7 @@ impl UpdateWorker {
 
     fn run_inner(mut self) {
         let mut wait_dur = Duration::from_secs(0);
-        let mut now = unix_now();
+        let mut now = replay::demo_now();
         let mut next_at = now;
 
         loop {
@@ -376,10 +545,17 @@ impl UpdateWorker {
                 ProgState::Exiting => break,
             };
 
-            now = unix_now();
+            now = replay::demo_now();
             let intv = graph_intv();
 
-            if force || now >= next_at {
+            if web::serving() {
+                if force || now >= next_at {
+                    if let Err(e) = self.publish_web_graph(now, WEB_GRAPH_POINTS * intv) {
+                        error!("Failed to update graph data ({:?})", &e);
+                    }
+                    next_at = now + intv;
+                }
+            } else if force || now >= next_at {
                 let mut size = get_layout().graph;
                 size.x -= 2;
                 let span = (size.x - GRAPH_X_ADJ) as u64 * intv;
@@ -399,7 +575,7 @@ impl UpdateWorker {
                 next_at = now + intv;
             }
 
-            let sleep_till = UNIX_EPOCH + Duration::from_secs(now + 1);
+            let sleep_till = UNIX_EPOCH + Duration::from_secs(unix_now() + 1);
             match sleep_till.duration_since(SystemTime::now()) {
                 Ok(v) => wait_dur = v,
                 _ => wait_dur = Duration::from_millis(100),
@@ -695,6 +871,8 @@ fn plot_spec_factory(id: PlotId) -> PlotSpec {
 
 #[derive(Copy, Clone, Hash, PartialEq, Eq, Debug, Sequence)]
 pub enum GraphTag {
+    // user-defined panels from graph_cfg, indexed in the order of definition
+    Custom(u8),
     HashdA,
     HashdB,
     CpuUtil,
@@ -842,8 +1020,47 @@ static ALL_GRAPHS: &[(GraphTag, &str, &[PlotId])] = &[
     ),
 ];
 
+fn custom_plot_spec(cfg: &SeriesCfg) -> PlotSpec {
+    // validated when the config was loaded
+    let sel = GraphSel::parse(&cfg.sel).unwrap();
+    let scale = cfg.scale;
+    let rate = match cfg.rate {
+        true => Some(Mutex::new(RateTracker::default())),
+        false => None,
+    };
+    let title = match cfg.title.len() {
+        0 => cfg.sel.clone(),
+        _ => cfg.title.clone(),
+    };
+    let (min, max) = (cfg.min, cfg.max);
+
+    PlotSpec {
+        sel: Box::new(move |rep: &Report| {
+            let val = match sel.select(rep) {
+                Some(v) => v,
+                None => return f64::NAN,
+            };
+            let val = match rate.as_ref() {
+                Some(rt) => {
+                    let at = rep.timestamp.timestamp() as u64;
+                    match rt.lock().unwrap().rate(at, val) {
+                        Some(v) => v,
+                        None => return f64::NAN,
+                    }
+                }
+                None => val,
+            };
+            val * scale
+        }),
+        aggr: cfg.aggr,
+        title: Box::new(move || title.clone()),
+        min: Box::new(move || min),
+        max: Box::new(move || max),
+    }
+}
+
 pub fn updater_factory(cb_sink: cursive::CbSink) -> Vec<Updater> {
-    ALL_GRAPHS
+    let mut updaters: Vec<Updater> = ALL_GRAPHS
         .iter()
         .map(|&(tag, _title, ids)| {
             Updater::new(
@@ -853,19 +1070,35 @@ pub fn updater_factory(cb_sink: cursive::CbSink) -> Vec<Updater> {
             )
             .unwrap()
         })
-        .collect()
+        .collect();
+
+    for (idx, panel) in graph_cfg::panels().iter().enumerate() {
+        updaters.push(
+            Updater::new(
+                cb_sink.clone(),
+                GraphTag::Custom(idx as u8),
+                panel.series.iter().map(custom_plot_spec).collect(),
+            )
+            .unwrap(),
+        );
+    }
+    updaters
 }
 
 fn all_graph_panels() -> HashMap<GraphTag, impl View> {
-    ALL_GRAPHS
+    let panel = |tag: GraphTag, title: &str| {
+        Panel::new(TextView::new("").with_name(format!("graph-{:?}", tag))).title(title)
+    };
+
+    let mut panels: HashMap<GraphTag, _> = ALL_GRAPHS
         .iter()
-        .map(|&(tag, title, _ids)| {
-            (
-                tag,
-                Panel::new(TextView::new("").with_name(format!("graph-{:?}", tag))).title(title),
-            )
-        })
-        .collect()
+        .map(|&(tag, title, _ids)| (tag, panel(tag, title)))
+        .collect();
+    for (idx, cfg) in graph_cfg::panels().iter().enumerate() {
+        let tag = GraphTag::Custom(idx as u8);
+        panels.insert(tag, panel(tag, &cfg.title));
+    }
+    panels
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
@@ -887,14 +1120,14 @@ pub fn layout_factory(id: GraphSetId) -> Box<dyn View> {
 
     fn graph_tab_title(focus: usize) -> impl View {
         let mut buf = StyledString::new();
-        let mut styles: [Style; GRAPH_NR_TABS] = [(*COLOR_INACTIVE).into(); GRAPH_NR_TABS];
+        let mut styles: Vec<Style> = vec![(*COLOR_INACTIVE).into(); graph_nr_active_tabs()];
         styles[focus] = (*COLOR_ACTIVE).into();
 
         for i in 0..graph_nr_active_tabs() {
             if i > 0 {
                 buf.append_plain(" | ");
             }
-            buf.append_styled(&format!(" {} ", GRAPH_TAB_NAMES[i]), styles[i]);
+            buf.append_styled(&format!(" {} ", graph_tab_name(i)), styles[i]);
         }
 
         LinearLayout::vertical()
@@ -919,66 +1152,18 @@ pub fn layout_factory(id: GraphSetId) -> Box<dyn View> {
             };
             let mut tabs = TabView::new();
 
-            tabs.add_tab(
-                LinearLayout::vertical()
-                    .child(graph_tab_title(0))
-                    .child(
-                        horiz_or_vert()
-                            .child(graph(GraphTag::HashdA))
-                            .child(graph(GraphTag::MemPsiFull)),
-                    )
-                    .child(
-                        horiz_or_vert()
-                            .child(graph(GraphTag::CpuPsiSome))
-                            .child(graph(GraphTag::IoPsiFull)),
-                    )
-                    .with_name(graph_tab_id(0)),
-            );
-            tabs.add_tab(
-                LinearLayout::vertical()
-                    .child(graph_tab_title(1))
-                    .child(
-                        horiz_or_vert()
-                            .child(graph(GraphTag::SwapUtil))
-                            .child(graph(GraphTag::MemUtil)),
-                    )
-                    .child(
-                        horiz_or_vert()
-                            .child(graph(GraphTag::CpuUtil))
-                            .child(graph(GraphTag::IoUtil)),
-                    )
-                    .with_name(graph_tab_id(1)),
-            );
-            tabs.add_tab(
-                LinearLayout::vertical()
-                    .child(graph_tab_title(2))
-                    .child(
-                        horiz_or_vert()
-                            .child(graph(GraphTag::ReadBps))
-                            .child(graph(GraphTag::WriteBps)),
-                    )
-                    .child(
-                        horiz_or_vert()
-                            .child(graph(GraphTag::ReadLat))
-                            .child(graph(GraphTag::WriteLat)),
-                    )
-                    .with_name(graph_tab_id(2)),
-            );
-            tabs.add_tab(
-                LinearLayout::vertical()
-                    .child(graph_tab_title(3))
-                    .child(
-                        horiz_or_vert()
-                            .child(graph(GraphTag::IoCost))
-                            .child(graph(GraphTag::MemPsiSome)),
-                    )
-                    .child(
-                        horiz_or_vert()
-                            .child(graph(GraphTag::CpuPsiSome2))
-                            .child(graph(GraphTag::IoPsiSome)),
-                    )
-                    .with_name(graph_tab_id(3)),
-            );
+            for pos in 0..graph_nr_active_tabs() {
+                let tags = graph_tab_tags(pos);
+                let mut rows = LinearLayout::vertical().child(graph_tab_title(pos));
+                for row in tags.chunks(2) {
+                    let mut line = horiz_or_vert();
+                    for &tag in row {
+                        line.add_child(graph(tag));
+                    }
+                    rows.add_child(line);
+                }
+                tabs.add_tab(rows.with_name(graph_tab_id(pos)));
+            }
 
             let _ = tabs.set_active_tab(&graph_tab_id(*GRAPH_TAB_IDX.lock().unwrap()));
 
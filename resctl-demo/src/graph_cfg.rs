@@ -0,0 +1,415 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use rd_agent_intf::{hashd_idx, HashdReport, Report, UsageReport, HASHD_MAX_INSTANCES};
+use rd_util::*;
+
+use super::graph::PlotDataAggr;
+
+const GRAPHS_DOC: &str = "\
+//
+// resctl-demo user-defined graph panels
+//
+// Each panel plots up to three series and is shown in the \"custom\" tabs
+// of the graph view ('g'). SEL selects the value to plot from the rd-agent
+// report. Keys are separated by \".\" and keys which contain \".\" can be
+// wrapped in \"[]\".
+//
+//  usages[SLICE].FIELD: UsageReport field of the slice, e.g.
+//                       usages[workload.slice].mem_bytes. The two
+//                       elements of the pressure and stall fields are
+//                       selected with \".0\" and \".1\".
+//  mem_stat[SLICE].KEY: memory.stat key of the slice
+//  io_stat[SLICE].KEY: io.stat key of the slice
+//  vmstat.KEY: /proc/vmstat key
+//  iolat.IOTYPE[PCT]: IO latency percentile in seconds, e.g. iolat.read[99]
+//  iolat_cum.IOTYPE[PCT]: Cumulative IO latency percentile in seconds
+//  hashd.NAME.FIELD: rd-hashd instance report field, e.g. hashd.C.rps.
+//                    FIELD is one of load, rps, lat (control percentile
+//                    latency in seconds), nr_in_flight, nr_done,
+//                    nr_workers and nr_idle_workers.
+//
+//  panels[].title: Panel title
+//  panels[].series[].sel: SEL as above
+//  panels[].series[].title: Series title, SEL if empty
+//  panels[].series[].aggr: avg or max when multiple reports are merged
+//  panels[].series[].scale: The selected value is multiplied by this
+//  panels[].series[].rate: Plot the per-second rate of change, e.g. for
+//                          cumulative counters such as pgscan
+//  panels[].series[].min: Y axis minimum
+//  panels[].series[].max: Y axis maximum, auto if not larger than min
+//
+// The first series uses the left Y axis. If there are two series, the
+// second uses the right Y axis. Otherwise, all share the left Y axis.
+//
+";
+
+pub const MAX_PANELS: usize = 256;
+pub const MAX_SERIES: usize = 3;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SeriesCfg {
+    pub sel: String,
+    pub title: String,
+    pub aggr: PlotDataAggr,
+    pub scale: f64,
+    pub rate: bool,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Default for SeriesCfg {
+    fn default() -> Self {
+        Self {
+            sel: "".into(),
+            title: "".into(),
+            aggr: Default::default(),
+            scale: 1.0,
+            rate: false,
+            min: 0.0,
+            max: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PanelCfg {
+    pub title: String,
+    pub series: Vec<SeriesCfg>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GraphsCfg {
+    pub panels: Vec<PanelCfg>,
+}
+
+impl Default for GraphsCfg {
+    fn default() -> Self {
+        Self {
+            panels: vec![PanelCfg {
+                title: "Page cache refaults / scans (per sec)".into(),
+                series: vec![
+                    SeriesCfg {
+                        sel: "mem_stat[workload.slice].workingset_refault_file".into(),
+                        title: "workload-refault-file".into(),
+                        rate: true,
+                        ..Default::default()
+                    },
+                    SeriesCfg {
+                        sel: "vmstat.pgscan_kswapd".into(),
+                        rate: true,
+                        ..Default::default()
+                    },
+                    SeriesCfg {
+                        sel: "vmstat.pgscan_direct".into(),
+                        rate: true,
+                        ..Default::default()
+                    },
+                ],
+            }],
+        }
+    }
+}
+
+impl JsonLoad for GraphsCfg {
+    fn loaded(&mut self, _prev: Option<&mut Self>) -> Result<()> {
+        if self.panels.len() > MAX_PANELS {
+            bail!("too many panels, at most {} allowed", MAX_PANELS);
+        }
+        for panel in self.panels.iter() {
+            if panel.series.len() == 0 || panel.series.len() > MAX_SERIES {
+                bail!(
+                    "panel {:?} should have 1 to {} series",
+                    &panel.title,
+                    MAX_SERIES
+                );
+            }
+            for series in panel.series.iter() {
+                GraphSel::parse(&series.sel)
+                    .with_context(|| format!("panel {:?}", &panel.title))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl JsonSave for GraphsCfg {
+    fn preamble() -> Option<String> {
+        Some(GRAPHS_DOC.to_string())
+    }
+}
+
+fn parse_path(input: &str) -> Result<Vec<String>> {
+    let mut path = vec![];
+    let mut cur = String::new();
+    let mut chars = input.trim().chars();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '.' => {
+                if cur.len() > 0 {
+                    path.push(cur.split_off(0));
+                }
+            }
+            '[' => {
+                if cur.len() > 0 {
+                    path.push(cur.split_off(0));
+                }
+                loop {
+                    match chars.next() {
+                        Some(']') => break,
+                        Some(ch) => cur.push(ch),
+                        None => bail!("unterminated '[' in {:?}", input),
+                    }
+                }
+                if cur.trim().len() == 0 {
+                    bail!("empty \"[]\" in {:?}", input);
+                }
+                path.push(cur.trim().to_string());
+                cur.clear();
+            }
+            ch => cur.push(ch),
+        }
+    }
+    if cur.len() > 0 {
+        path.push(cur);
+    }
+    Ok(path)
+}
+
+type UsageFieldFn = fn(&UsageReport) -> f64;
+
+fn usage_field(field: &str, idx: Option<&str>) -> Option<UsageFieldFn> {
+    let f: UsageFieldFn = match (field, idx) {
+        ("cpu_util", None) => |u| u.cpu_util,
+        ("cpu_sys", None) => |u| u.cpu_sys,
+        ("cpu_usage", None) => |u| u.cpu_usage,
+        ("cpu_usage_sys", None) => |u| u.cpu_usage_sys,
+        ("cpu_usage_base", None) => |u| u.cpu_usage_base,
+        ("mem_bytes", None) => |u| u.mem_bytes as f64,
+        ("swap_bytes", None) => |u| u.swap_bytes as f64,
+        ("swap_free", None) => |u| u.swap_free as f64,
+        ("io_rbytes", None) => |u| u.io_rbytes as f64,
+        ("io_wbytes", None) => |u| u.io_wbytes as f64,
+        ("io_rbps", None) => |u| u.io_rbps as f64,
+        ("io_wbps", None) => |u| u.io_wbps as f64,
+        ("io_usage", None) => |u| u.io_usage,
+        ("io_util", None) => |u| u.io_util,
+        ("cpu_stalls", Some("0")) => |u| u.cpu_stalls.0,
+        ("cpu_stalls", Some("1")) => |u| u.cpu_stalls.1,
+        ("mem_stalls", Some("0")) => |u| u.mem_stalls.0,
+        ("mem_stalls", Some("1")) => |u| u.mem_stalls.1,
+        ("io_stalls", Some("0")) => |u| u.io_stalls.0,
+        ("io_stalls", Some("1")) => |u| u.io_stalls.1,
+        ("cpu_pressures", Some("0")) => |u| u.cpu_pressures.0,
+        ("cpu_pressures", Some("1")) => |u| u.cpu_pressures.1,
+        ("mem_pressures", Some("0")) => |u| u.mem_pressures.0,
+        ("mem_pressures", Some("1")) => |u| u.mem_pressures.1,
+        ("io_pressures", Some("0")) => |u| u.io_pressures.0,
+        ("io_pressures", Some("1")) => |u| u.io_pressures.1,
+        _ => return None,
+    };
+    Some(f)
+}
+
+type HashdFieldFn = fn(&HashdReport) -> f64;
+
+fn hashd_field(field: &str) -> Option<HashdFieldFn> {
+    let f: HashdFieldFn = match field {
+        "load" => |h| h.load,
+        "rps" => |h| h.rps,
+        "lat" => |h| h.lat.ctl,
+        "nr_in_flight" => |h| h.nr_in_flight as f64,
+        "nr_done" => |h| h.nr_done as f64,
+        "nr_workers" => |h| h.nr_workers as f64,
+        "nr_idle_workers" => |h| h.nr_idle_workers as f64,
+        _ => return None,
+    };
+    Some(f)
+}
+
+/// A value in the rd-agent report selected by a SEL string.
+#[derive(Clone)]
+pub enum GraphSel {
+    Usage(String, UsageFieldFn),
+    MemStat(String, String),
+    IoStat(String, String),
+    VmStat(String),
+    IoLat(bool, String, String),
+    Hashd(usize, HashdFieldFn),
+}
+
+impl GraphSel {
+    pub fn parse(input: &str) -> Result<Self> {
+        let path = parse_path(input)?;
+        let path: Vec<&str> = path.iter().map(|x| x.as_str()).collect();
+        Ok(match path.as_slice() {
+            ["usages", slice, field] => match usage_field(field, None) {
+                Some(f) => Self::Usage(slice.to_string(), f),
+                None => bail!("unknown usage field {:?} in {:?}", field, input),
+            },
+            ["usages", slice, field, idx] => match usage_field(field, Some(idx)) {
+                Some(f) => Self::Usage(slice.to_string(), f),
+                None => bail!("unknown usage field \"{}.{}\" in {:?}", field, idx, input),
+            },
+            ["mem_stat", slice, key] => Self::MemStat(slice.to_string(), key.to_string()),
+            ["io_stat", slice, key] => Self::IoStat(slice.to_string(), key.to_string()),
+            ["vmstat", key] => Self::VmStat(key.to_string()),
+            [which @ "iolat", iotype, pct] | [which @ "iolat_cum", iotype, pct] => {
+                Self::IoLat(*which == "iolat_cum", iotype.to_string(), pct.to_string())
+            }
+            ["hashd", name, field] => {
+                let idx = match hashd_idx(name) {
+                    Some(idx) if idx < HASHD_MAX_INSTANCES => idx,
+                    _ => bail!("invalid hashd instance {:?} in {:?}", name, input),
+                };
+                match hashd_field(field) {
+                    Some(f) => Self::Hashd(idx, f),
+                    None => bail!("unknown hashd field {:?} in {:?}", field, input),
+                }
+            }
+            _ => bail!("invalid selector {:?}", input),
+        })
+    }
+
+    /// The selected value, None if the report doesn't have it.
+    pub fn select(&self, rep: &Report) -> Option<f64> {
+        match self {
+            Self::Usage(slice, f) => rep.usages.get(slice).map(f),
+            Self::MemStat(slice, key) => rep.mem_stat.get(slice)?.get(key).copied(),
+            Self::IoStat(slice, key) => rep.io_stat.get(slice)?.get(key).copied(),
+            Self::VmStat(key) => rep.vmstat.get(key).copied(),
+            Self::IoLat(cum, iotype, pct) => {
+                let iolat = if *cum { &rep.iolat_cum } else { &rep.iolat };
+                iolat.map.get(iotype)?.get(pct).copied()
+            }
+            Self::Hashd(idx, f) => rep.hashd.get(*idx).map(f),
+        }
+    }
+}
+
+// Number of recent samples to remember for rate calculation.
+const RATE_HIST_LEN: usize = 16;
+
+/// Converts cumulative samples to per-second rates. The reports don't
+/// arrive in order - the per-minute ring is filled separately from the
+/// per-second one and the replay can seek - so the rate is calculated
+/// against the closest preceding sample seen within the last ten minutes.
+#[derive(Default)]
+pub struct RateTracker {
+    hist: BTreeMap<u64, f64>,
+}
+
+impl RateTracker {
+    pub fn rate(&mut self, at: u64, val: f64) -> Option<f64> {
+        let rate = match self.hist.range(at.saturating_sub(600)..at).next_back() {
+            Some((&pat, &pval)) if val >= pval => Some((val - pval) / (at - pat) as f64),
+            _ => None,
+        };
+        self.hist.insert(at, val);
+
+        // drop the neighbors which won't be used anymore and then the oldest
+        let stale: Vec<u64> = self
+            .hist
+            .range(at.saturating_sub(600)..at)
+            .rev()
+            .skip(1)
+            .map(|(k, _)| *k)
+            .collect();
+        for k in stale {
+            self.hist.remove(&k);
+        }
+        while self.hist.len() > RATE_HIST_LEN {
+            let oldest = *self.hist.keys().next().unwrap();
+            self.hist.remove(&oldest);
+        }
+        rate
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref GRAPHS_CFG: Mutex<GraphsCfg> = Mutex::new(GraphsCfg { panels: vec![] });
+}
+
+/// Load the user-defined graph panels from `path`, which is created with
+/// an example panel if it doesn't exist.
+pub fn load(path: &str) -> Result<()> {
+    let cfg = JsonConfigFile::<GraphsCfg>::load_or_create(Some(path))
+        .with_context(|| format!("Loading {:?}", path))?;
+    *GRAPHS_CFG.lock().unwrap() = cfg.data;
+    Ok(())
+}
+
+pub fn panels() -> Vec<PanelCfg> {
+    GRAPHS_CFG.lock().unwrap().panels.clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_graph_sel() {
+        let mut rep = Report::default();
+        let usage = UsageReport {
+            mem_bytes: 4096,
+            io_pressures: (0.25, 0.5),
+            ..Default::default()
+        };
+        rep.usages.insert("workload.slice".into(), usage);
+        rep.mem_stat.insert(
+            "workload.slice".into(),
+            [("workingset_refault_file".to_string(), 7.0)]
+                .iter()
+                .cloned()
+                .collect(),
+        );
+        rep.vmstat.insert("pgscan_kswapd".into(), 11.0);
+        rep.iolat.map.insert(
+            "read".into(),
+            [("99.9".to_string(), 0.003)].iter().cloned().collect(),
+        );
+
+        let sel = |s: &str| GraphSel::parse(s).unwrap().select(&rep);
+        assert_eq!(sel("usages[workload.slice].mem_bytes"), Some(4096.0));
+        assert_eq!(sel("usages[workload.slice].io_pressures.1"), Some(0.5));
+        assert_eq!(sel("usages[sideload.slice].mem_bytes"), None);
+        assert_eq!(
+            sel("mem_stat[workload.slice].workingset_refault_file"),
+            Some(7.0)
+        );
+        assert_eq!(sel("vmstat.pgscan_kswapd"), Some(11.0));
+        assert_eq!(sel("iolat.read[99.9]"), Some(0.003));
+        assert_eq!(sel("hashd.B.rps"), Some(0.0));
+        assert_eq!(sel("hashd[C].lat"), None);
+
+        assert!(GraphSel::parse("usages[workload.slice].nope").is_err());
+        assert!(GraphSel::parse("usages[workload.slice].io_pressures.2").is_err());
+        assert!(GraphSel::parse("vmstat").is_err());
+        assert!(GraphSel::parse("usages[workload.slice.mem_bytes").is_err());
+        assert!(GraphSel::parse("hashd.c.rps").is_err());
+        assert!(GraphSel::parse("hashd.C.nope").is_err());
+    }
+
+    #[test]
+    fn test_rate_tracker() {
+        let mut rt = RateTracker::default();
+        assert_eq!(rt.rate(1000, 10.0), None);
+        assert_eq!(rt.rate(1001, 15.0), Some(5.0));
+        assert_eq!(rt.rate(1002, 25.0), Some(10.0));
+        // an older sample from the per-minute ring
+        assert_eq!(rt.rate(100, 0.0), None);
+        assert_eq!(rt.rate(160, 60.0), Some(1.0));
+        // back to the per-second ring
+        assert_eq!(rt.rate(1003, 26.0), Some(1.0));
+        // counter reset
+        assert_eq!(rt.rate(1004, 0.0), None);
+        assert!(rt.hist.len() <= RATE_HIST_LEN);
+    }
+}
@@ -415,6 +415,22 @@ pub fn layout_factory(id: JournalViewId) -> Box<dyn View> {
     }
 }
 
+/// The contents of the default journal panels as currently displayed.
+pub fn journal_contents(siv: &mut Cursive) -> Vec<(&'static str, StyledString)> {
+    [
+        ("Management logs", "journal-top"),
+        ("Other logs", "journal-bot"),
+    ]
+    .iter()
+    .map(|&(title, name)| {
+        let content = siv
+            .call_on_name(name, |v: &mut TextView| v.get_content().clone())
+            .unwrap_or_default();
+        (title, content)
+    })
+    .collect()
+}
+
 pub fn post_zoomed_layout(siv: &mut Cursive) {
     let cur = FS_CUR.lock().unwrap().clone();
     update_fs_journal(siv, &cur);
@@ -19,15 +19,18 @@ mod agent;
 mod command;
 mod doc;
 mod graph;
+mod graph_cfg;
 mod journal;
+mod replay;
 mod report_ring;
 mod status;
+mod web;
 
 use agent::AGENT_FILES;
 use graph::GraphSetId;
 use journal::JournalViewId;
 use rd_agent_intf::{
-    AGENT_SVC_NAME, HASHD_A_SVC_NAME, HASHD_BENCH_SVC_NAME, HASHD_B_SVC_NAME,
+    hashd_svc_name, AGENT_SVC_NAME, HASHD_BENCH_SVC_NAME, HASHD_MAX_INSTANCES,
     IOCOST_BENCH_SVC_NAME, OOMD_SVC_NAME, SIDELOADER_SVC_NAME, SIDELOAD_SVC_PREFIX,
     SYSLOAD_SVC_PREFIX,
 };
@@ -72,7 +75,11 @@ lazy_static::lazy_static! {
          -l, --linux=[PATH]     'Path to linux.tar, downloaded automatically if not specified'
          -k, --keep             'Do not shutdown rd-agent on exit'
          -L, --no-iolat         'Disable bpf-based io latency stat monitoring'
-             --force            'Ignore startup check failures'",
+             --force            'Ignore startup check failures'
+             --graphs=[PATH]    'User-defined graph panels, created with an example if missing'
+             --replay=[START-END] 'Replay the reports in the period instead of running rd-agent'
+             --from-pack=[TARBALL] 'Replay the reports in a resctl-bench pack tarball'
+             --web=[HOST:PORT]  'Serve the demo to browsers instead of the terminal'",
         dfl_dir = rd_agent_intf::Args::default().dir,
     );
     pub static ref ARGS: Mutex<Option<Args>> = Mutex::new(None);
@@ -92,11 +99,10 @@ lazy_static::lazy_static! {
             AGENT_SVC_NAME.into(),
             OOMD_SVC_NAME.into(),
             SIDELOADER_SVC_NAME.into(),
-            HASHD_A_SVC_NAME.into(),
-            HASHD_B_SVC_NAME.into(),
-            HASHD_BENCH_SVC_NAME.into(),
-            IOCOST_BENCH_SVC_NAME.into(),
         ];
+        names.extend((0..HASHD_MAX_INSTANCES).map(hashd_svc_name));
+        names.push(HASHD_BENCH_SVC_NAME.into());
+        names.push(IOCOST_BENCH_SVC_NAME.into());
 
         for name in doc::SIDELOAD_NAMES
             .lock()
@@ -127,6 +133,10 @@ pub struct Args {
     pub keep: bool,
     pub no_iolat: bool,
     pub force: bool,
+    pub graphs: Option<String>,
+    pub replay: Option<String>,
+    pub pack: Option<String>,
+    pub web: Option<String>,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -373,15 +383,17 @@ impl Drop for ExitGuard {
         upd.status.take();
         upd.graphs.clear();
         upd.journal.clear();
-        agent::AGENT_MINDER.lock().unwrap().svc.take();
+        if !replay::replaying() {
+            agent::AGENT_MINDER.lock().unwrap().svc.take();
+        }
         let _ = fs::remove_dir_all(TEMP_DIR.path());
     }
 }
 
-fn startup_checks() -> Result<()> {
+fn startup_checks(replay: bool, web: bool) -> Result<()> {
     let mut nr_failed = 0;
 
-    if !verify_agent_and_hashd(&FULL_VERSION) {
+    if !replay && !verify_agent_and_hashd(&FULL_VERSION) {
         eprintln!(
             "Error: Failed to verify rd-agent and/or rd-hashd, install with \"cargo install\""
         );
@@ -389,14 +401,15 @@ fn startup_checks() -> Result<()> {
     }
 
     let euid = unsafe { libc::geteuid() };
-    if euid != 0 {
+    if !replay && euid != 0 {
         eprintln!("Error: must be run as root");
         nr_failed += 1;
     }
 
-    if !read_one_line("/proc/self/cgroup")
-        .unwrap()
-        .starts_with("0::/hostcritical.slice/")
+    if !replay
+        && !read_one_line("/proc/self/cgroup")
+            .unwrap()
+            .starts_with("0::/hostcritical.slice/")
     {
         eprintln!(
             "Error: must be under hostcritical.slice, start with \
@@ -406,7 +419,8 @@ fn startup_checks() -> Result<()> {
         nr_failed += 1;
     }
 
-    if let None = find_bin("gnuplot", Option::<&str>::None) {
+    // the browser draws the graphs
+    if !web && find_bin("gnuplot", Option::<&str>::None).is_none() {
         eprintln!("Error: gnuplot is missing");
         nr_failed += 1;
     }
@@ -509,9 +523,16 @@ fn main() {
         keep: matches.is_present("keep"),
         no_iolat: matches.is_present("no-iolat"),
         force: matches.is_present("force"),
+        graphs: matches.value_of("graphs").map(|v| v.to_string()),
+        replay: matches.value_of("replay").map(|v| v.to_string()),
+        pack: matches.value_of("from-pack").map(|v| v.to_string()),
+        web: matches.value_of("web").map(|v| v.to_string()),
     };
+    let replaying = args.replay.is_some() || args.pack.is_some();
 
-    if let Err(e) = startup_checks() {
+    let web_addr = args.web.clone();
+
+    if let Err(e) = startup_checks(replaying, web_addr.is_some()) {
         if args.force {
             error!("Ignoring startup check failure: {}", &e);
         } else {
@@ -519,9 +540,23 @@ fn main() {
         }
     }
 
+    let period = match args.replay.as_deref().map(replay::parse_period) {
+        Some(Ok(v)) => v,
+        Some(Err(e)) => panic!("Invalid --replay ({:#})", &e),
+        None => (None, None),
+    };
+    let pack = args.pack.clone();
+
+    if let Some(path) = args.graphs.as_ref() {
+        if let Err(e) = graph_cfg::load(path) {
+            panic!("Failed to load graph panels ({:#})", &e);
+        }
+    }
+
     ARGS.lock().unwrap().replace(args);
 
-    if std::env::var("RUST_LOG").is_ok() {
+    // there's no debug console without the terminal
+    if std::env::var("RUST_LOG").is_ok() || web_addr.is_some() {
         init_logging(0, "".to_string());
     } else {
         logger::init();
@@ -529,13 +564,34 @@ fn main() {
     log::set_max_level(log::LevelFilter::Info);
 
     info!("TEMP_DIR: {:?}", TEMP_DIR.path());
-    touch_units();
+
+    if replaying {
+        let index = AGENT_FILES.index();
+        if let Err(e) = replay::init(
+            period,
+            pack.as_deref(),
+            &index.report_d,
+            &index.report_1min_d,
+            TEMP_DIR.path(),
+        ) {
+            panic!("Failed to start replay ({:#})", &e);
+        }
+        AGENT_ZV_REQ.store(false, Ordering::Relaxed);
+    } else {
+        touch_units();
+    }
 
     let mut siv = cursive::default();
     set_cursive_theme(&mut siv);
 
     let _exit_guard = ExitGuard {};
 
+    if let Some(addr) = web_addr.as_ref() {
+        if let Err(e) = web::start(addr, siv.cb_sink().clone()) {
+            panic!("Failed to start web front-end ({:#})", &e);
+        }
+    }
+
     let mut upd = UPDATERS.lock().unwrap();
     upd.status
         .replace(status::Updater::new(siv.cb_sink().clone()));
@@ -575,6 +631,9 @@ fn main() {
         toggle_zoomed_view(siv, None)
     });
     siv.add_global_callback('a', |siv| {
+        if replay::replaying() {
+            return;
+        }
         let req = !AGENT_ZV_REQ.load(Ordering::Relaxed);
         AGENT_ZV_REQ.store(req, Ordering::Relaxed);
         update_agent_zoomed_view(siv);
@@ -592,6 +651,32 @@ fn main() {
         kick_refresh();
     });
 
+    if replaying {
+        siv.add_global_callback('p', |_siv| {
+            replay::toggle_pause();
+            kick_refresh();
+        });
+        for &(key, delta) in &[
+            ('[', -replay::SEEK_STEP),
+            (']', replay::SEEK_STEP),
+            ('{', -replay::SEEK_STEP_LONG),
+            ('}', replay::SEEK_STEP_LONG),
+        ] {
+            siv.add_global_callback(key, move |_siv| {
+                replay::seek(delta);
+                kick_refresh();
+            });
+        }
+        siv.add_global_callback('<', |_siv| {
+            replay::slow_down();
+            kick_refresh();
+        });
+        siv.add_global_callback('>', |_siv| {
+            replay::speed_up();
+            kick_refresh();
+        });
+    }
+
     siv.set_global_callback(event::Event::WindowResize, move |siv| {
         // see https://github.com/gyscos/cursive/issues/519#issuecomment-721966516
         siv.clear();
@@ -612,5 +697,9 @@ fn main() {
     refresh_layout_and_kick(&mut siv);
     update_agent_zoomed_view(&mut siv);
 
-    siv.run()
+    if web_addr.is_some() {
+        web::run(&mut siv);
+    } else {
+        siv.run();
+    }
 }
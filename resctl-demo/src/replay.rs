@@ -0,0 +1,277 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// Historical replay. Instead of following the live rd-agent reports up to
+// now, the graphs, usage table and status panels are driven from the
+// reports in a period, either in the local rd-agent report directories or
+// in a resctl-bench pack tarball. The replay clock can be paused, seeked
+// and sped up.
+use anyhow::{anyhow, bail, Context, Result};
+use log::info;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use rd_agent_intf::{Report, ReportReader};
+use rd_util::*;
+
+const SPEEDS: &[f64] = &[1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0];
+pub const SEEK_STEP: i64 = 60;
+pub const SEEK_STEP_LONG: i64 = 600;
+
+// The status panels show the latest report at or before the replay
+// position as long as it isn't older than this.
+const REPORT_LOOKBACK: u64 = 10;
+
+// Bumped on each seek so that the report rings and data sets, which
+// assume that time only moves forward, can start over.
+static SEEK_SEQ: AtomicU64 = AtomicU64::new(0);
+
+lazy_static::lazy_static! {
+    static ref REPLAY: Mutex<Option<Replay>> = Mutex::new(None);
+}
+
+struct Replay {
+    report_d: String,
+    report_1min_d: Option<String>,
+    period: (u64, u64),
+    pos: f64,
+    speed_idx: usize,
+    paused: bool,
+    updated_at: f64,
+    reader: ReportReader,
+}
+
+impl Replay {
+    fn advance(&mut self) {
+        let now = unix_now_f64();
+        if !self.paused {
+            self.pos += (now - self.updated_at) * SPEEDS[self.speed_idx];
+            if self.pos >= self.period.1 as f64 {
+                self.pos = self.period.1 as f64;
+                self.paused = true;
+            }
+        }
+        self.updated_at = now;
+    }
+}
+
+/// Parse "START-END" where START and END are UNIX timestamps. Either can
+/// be omitted to select from the first or up to the last report.
+pub fn parse_period(input: &str) -> Result<(Option<u64>, Option<u64>)> {
+    let mut toks = input.splitn(2, '-');
+    let parse = |tok: Option<&str>| -> Result<Option<u64>> {
+        match tok.map(|s| s.trim()) {
+            Some(v) if v.len() > 0 => Ok(Some(
+                v.parse::<u64>()
+                    .with_context(|| format!("invalid timestamp {:?}", v))?,
+            )),
+            _ => Ok(None),
+        }
+    };
+    let start = parse(toks.next())?;
+    let end = match toks.next() {
+        Some(v) => parse(Some(v))?,
+        None => bail!("replay period {:?} should be START-END", input),
+    };
+    if let (Some(s), Some(e)) = (start, end) {
+        if s >= e {
+            bail!("replay period {:?} is empty", input);
+        }
+    }
+    Ok((start, end))
+}
+
+/// Extract a resctl-bench pack tarball into `dest` and return the path of
+/// the contained report directory.
+fn unpack(tarball: &str, dest: &Path) -> Result<String> {
+    let f = fs::File::open(tarball).with_context(|| format!("Opening {:?}", tarball))?;
+    let gz = libflate::gzip::Decoder::new(f).context("Creating gzip decoder")?;
+    tar::Archive::new(gz)
+        .unpack(dest)
+        .with_context(|| format!("Unpacking {:?}", tarball))?;
+
+    for ent in fs::read_dir(dest)?.filter_map(|x| x.ok()) {
+        let path = ent.path();
+        if path.is_dir()
+            && path
+                .file_name()
+                .and_then(|x| x.to_str())
+                .map(|x| x.ends_with("-report.d"))
+                .unwrap_or(false)
+        {
+            return Ok(path.to_str().unwrap().to_string());
+        }
+    }
+    bail!("{:?} doesn't contain a report directory", tarball)
+}
+
+/// Enter replay mode. `period` is the result of parse_period(). If `pack`
+/// is specified, the reports are read from the pack tarball which is
+/// extracted into `tmp_dir`. Otherwise, `report_d` and `report_1min_d` of
+/// the local rd-agent are used.
+pub fn init(
+    period: (Option<u64>, Option<u64>),
+    pack: Option<&str>,
+    report_d: &str,
+    report_1min_d: &str,
+    tmp_dir: &Path,
+) -> Result<()> {
+    // pack tarballs only contain the per-second reports, the minute ring
+    // samples them at minute boundaries.
+    let (report_d, report_1min_d) = match pack {
+        Some(tarball) => (unpack(tarball, &tmp_dir.join("pack"))?, None),
+        None => {
+            if report_d.len() == 0 {
+                bail!("rd-agent report directory is unknown, specify --dir");
+            }
+            let min_d = if report_1min_d.len() > 0 {
+                Some(report_1min_d.to_string())
+            } else {
+                None
+            };
+            (report_d.to_string(), min_d)
+        }
+    };
+
+    let reader = ReportReader::new(&report_d);
+    let ats = reader
+        .list()
+        .with_context(|| format!("Listing reports in {:?}", &report_d))?;
+    let (first, last) = match (ats.first(), ats.last()) {
+        (Some(f), Some(l)) => (*f, *l),
+        _ => bail!("no reports found in {:?}", &report_d),
+    };
+
+    let start = period.0.unwrap_or(first).max(first);
+    let end = period.1.unwrap_or(last).min(last);
+    if start >= end {
+        return Err(anyhow!(
+            "no reports in the replay period, available: {}",
+            format_period((first, last))
+        ));
+    }
+
+    info!("replay: {:?} {}", &report_d, format_period((start, end)));
+
+    REPLAY.lock().unwrap().replace(Replay {
+        report_d,
+        report_1min_d,
+        period: (start, end),
+        pos: start as f64,
+        speed_idx: 0,
+        paused: false,
+        updated_at: unix_now_f64(),
+        reader,
+    });
+    Ok(())
+}
+
+pub fn replaying() -> bool {
+    REPLAY.lock().unwrap().is_some()
+}
+
+/// The current time as seen by the graphs and status panels - the replay
+/// position while replaying, the wall clock otherwise.
+pub fn demo_now() -> u64 {
+    match REPLAY.lock().unwrap().as_mut() {
+        Some(rp) => {
+            rp.advance();
+            rp.pos as u64
+        }
+        None => unix_now(),
+    }
+}
+
+/// The per-second and per-minute report directories to replay.
+pub fn report_dirs() -> Option<(String, String)> {
+    REPLAY.lock().unwrap().as_ref().map(|rp| {
+        (
+            rp.report_d.clone(),
+            rp.report_1min_d
+                .clone()
+                .unwrap_or_else(|| rp.report_d.clone()),
+        )
+    })
+}
+
+/// The latest report at the replay position. None if there's no recent
+/// enough report, e.g. in a gap between benchmark runs.
+pub fn report() -> Option<Report> {
+    let mut replay = REPLAY.lock().unwrap();
+    let rp = replay.as_mut()?;
+    rp.advance();
+    let pos = rp.pos as u64;
+    for at in (pos.saturating_sub(REPORT_LOOKBACK)..=pos).rev() {
+        if let Ok(rep) = rp.reader.load(at) {
+            return Some(rep);
+        }
+    }
+    None
+}
+
+pub fn toggle_pause() {
+    if let Some(rp) = REPLAY.lock().unwrap().as_mut() {
+        rp.advance();
+        if rp.paused && rp.pos as u64 >= rp.period.1 {
+            rp.pos = rp.period.0 as f64;
+            SEEK_SEQ.fetch_add(1, Ordering::Relaxed);
+        }
+        rp.paused = !rp.paused;
+    }
+}
+
+pub fn seek(delta: i64) {
+    if let Some(rp) = REPLAY.lock().unwrap().as_mut() {
+        rp.advance();
+        rp.pos = (rp.pos + delta as f64).clamp(rp.period.0 as f64, rp.period.1 as f64);
+        SEEK_SEQ.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub fn seek_seq() -> u64 {
+    SEEK_SEQ.load(Ordering::Relaxed)
+}
+
+pub fn speed_up() {
+    if let Some(rp) = REPLAY.lock().unwrap().as_mut() {
+        rp.advance();
+        rp.speed_idx = (rp.speed_idx + 1).min(SPEEDS.len() - 1);
+    }
+}
+
+pub fn slow_down() {
+    if let Some(rp) = REPLAY.lock().unwrap().as_mut() {
+        rp.advance();
+        rp.speed_idx = rp.speed_idx.saturating_sub(1);
+    }
+}
+
+/// One-line description of the replay state for the status panel.
+pub fn describe() -> Option<String> {
+    let mut replay = REPLAY.lock().unwrap();
+    let rp = replay.as_mut()?;
+    rp.advance();
+    Some(format!(
+        "{} {}x +{}/{}",
+        if rp.paused { "paused" } else { "replay" },
+        SPEEDS[rp.speed_idx],
+        format_duration((rp.pos as u64 - rp.period.0) as f64),
+        format_duration((rp.period.1 - rp.period.0) as f64),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_parse_period() {
+        use super::parse_period;
+        assert_eq!(parse_period("100-200").unwrap(), (Some(100), Some(200)));
+        assert_eq!(parse_period("100-").unwrap(), (Some(100), None));
+        assert_eq!(parse_period("-200").unwrap(), (None, Some(200)));
+        assert_eq!(parse_period("-").unwrap(), (None, None));
+        assert!(parse_period("100").is_err());
+        assert!(parse_period("200-100").is_err());
+        assert!(parse_period("abc-100").is_err());
+    }
+}
@@ -8,9 +8,10 @@ use std::iter::Iterator;
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 
-use rd_agent_intf::Report;
+use rd_agent_intf::{Report, ReportReader};
 use rd_util::*;
 
+use super::replay;
 use super::AGENT_FILES;
 
 lazy_static::lazy_static! {
@@ -79,25 +80,25 @@ impl ReportRing {
         }
 
         let load_from = match self.ring.back() {
-            Some(rec) => rec.at + self.cadence,
+            Some(rec) => (rec.at + self.cadence).max(start),
             None => start,
         };
 
         debug!("Loading {:?}..{:?}", load_from, now);
 
+        let mut reader = ReportReader::new(&dir);
         for at in (load_from..=now).step_by(self.cadence as usize) {
-            let path = format!("{}/{}.json", &dir, at);
-            let rep = match Report::load(&path) {
+            let rep = match reader.load(at) {
                 Ok(v) => v,
                 Err(e) => {
                     match e.downcast_ref::<io::Error>() {
                         Some(ie) if ie.raw_os_error() == Some(libc::ENOENT) => {}
-                        _ => warn!("Failed to load {:?} ({:?})", &path, &e),
+                        _ => warn!("Failed to load report {} from {:?} ({:?})", at, &dir, &e),
                     }
                     continue;
                 }
             };
-            debug!("Loaded {:?}", &path);
+            debug!("Loaded report {} from {:?}", at, &dir);
             self.ring.push_back(ReportRecord { at, rep });
         }
 
@@ -108,6 +109,7 @@ impl ReportRing {
 struct ReportRingSet {
     sec_ring: ReportRing,
     min_ring: ReportRing,
+    seek_seq: u64,
 }
 
 impl ReportRingSet {
@@ -115,6 +117,9 @@ impl ReportRingSet {
         Self {
             sec_ring: ReportRing::new(
                 Box::new(|| {
+                    if let Some((sec_d, _)) = replay::report_dirs() {
+                        return Some(sec_d);
+                    }
                     let path = AGENT_FILES.index().report_d;
                     if path.len() > 0 {
                         Some(path)
@@ -128,6 +133,9 @@ impl ReportRingSet {
             ),
             min_ring: ReportRing::new(
                 Box::new(|| {
+                    if let Some((_, min_d)) = replay::report_dirs() {
+                        return Some(min_d);
+                    }
                     let path = AGENT_FILES.index().report_1min_d;
                     if path.len() > 0 {
                         Some(path)
@@ -139,10 +147,18 @@ impl ReportRingSet {
                 60,
                 AGENT_FILES.args().rep_1min_retention,
             ),
+            seek_seq: 0,
         }
     }
 
     fn update(&mut self, now: u64) -> Result<()> {
+        let seek_seq = replay::seek_seq();
+        if seek_seq != self.seek_seq {
+            self.sec_ring.ring.clear();
+            self.min_ring.ring.clear();
+            self.seek_seq = seek_seq;
+        }
+
         self.sec_ring.update(now)?;
         self.min_ring.update(now - self.sec_ring.retention - 60)?;
         if self.sec_ring.ring.len() > 0 && self.min_ring.ring.len() > 0 {
@@ -333,6 +349,7 @@ pub struct ReportDataSet<T: ReportDataType<T>> {
     src_set: Arc<Mutex<ReportRingSet>>,
     sec_data: ReportData<T>,
     min_data: ReportData<T>,
+    seek_seq: u64,
 }
 
 impl<T: ReportDataType<T>> ReportDataSet<T> {
@@ -360,12 +377,20 @@ impl<T: ReportDataType<T>> ReportDataSet<T> {
                 Box::new(move |dacc, data| acc_clone(dacc, data)),
                 Box::new(move |dacc, nr| aggr_clone(dacc, nr)),
             ),
+            seek_seq: 0,
         }
     }
 
     pub fn fill(&mut self, now: u64, stride: u64, span: u64) -> Result<()> {
         let mut src_set = self.src_set.lock().unwrap();
 
+        let seek_seq = replay::seek_seq();
+        if seek_seq != self.seek_seq {
+            self.sec_data.clear();
+            self.min_data.clear();
+            self.seek_seq = seek_seq;
+        }
+
         src_set.update(now)?;
 
         debug!(
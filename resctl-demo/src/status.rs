@@ -1,6 +1,6 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
 use cursive::utils::markup::StyledString;
-use cursive::view::{Nameable, Resizable, SizeConstraint, View};
+use cursive::view::{Nameable, Resizable, Scrollable, SizeConstraint, View};
 use cursive::views::{DummyView, LinearLayout, Panel, TextView};
 use cursive::Cursive;
 use log::error;
@@ -11,16 +11,19 @@ use std::thread::{spawn, JoinHandle};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use rd_agent_intf::{
-    HashdReport, OomdReport, ResCtlReport, RunnerState, SideloadReport, SideloaderReport,
-    SvcStateReport, SysloadReport, UsageReport, HASHD_A_SVC_NAME, HASHD_B_SVC_NAME,
+    hashd_svc_name, HashdReport, OomdReport, Report, ResCtlReport, RunnerState, SideloadReport,
+    SideloaderReport, SvcStateReport, SysloadReport, UsageReport,
 };
 use rd_util::*;
 
 use super::agent::{refresh_agent_states, AGENT_FILES};
+use super::replay;
 use super::{get_layout, COLOR_ACTIVE, COLOR_ALERT, COLOR_DFL, COLOR_INACTIVE, STYLE_ALERT};
 
 pub static STATUS_INTV: AtomicU32 = AtomicU32::new(3);
 
+const USAGE_HEADER: &str = "  cpu%    mem   swap   rbps   wbps  cpuP%  memP%   ioP%";
+
 pub struct UpdateWorker {
     usages: BTreeMap<String, UsageReport>,
     nr_samples: u32,
@@ -218,30 +221,50 @@ impl UpdateWorker {
         });
     }
 
+    // The second line covers B and, if any is running, the instances after
+    // it. Their loads are averaged, the worst latency is shown and the
+    // usages are summed.
     fn refresh_hashd_status(
         siv: &mut Cursive,
-        rep: &HashdReport,
-        usage: &UsageReport,
+        reps: &[(&HashdReport, &UsageReport)],
         is_b: bool,
         use_ab: bool,
     ) {
         let mut line = StyledString::new();
-        let name = if is_b { "workload-B" } else { "workload-A" };
-        let running = rep.svc.state == SvcStateReport::Running;
-
-        if running || !is_b {
+        let view = if is_b { "workload-B" } else { "workload-A" };
+        let name = if reps.len() > 1 { "workload-B+" } else { view };
+        let running: Vec<&(&HashdReport, &UsageReport)> = reps
+            .iter()
+            .filter(|(rep, _)| rep.svc.state == SvcStateReport::Running)
+            .collect();
+
+        if running.len() > 0 || !is_b {
             line.append_plain("[");
             line.append_styled(
                 format!("{:^11}", if use_ab { name } else { "workload" }),
-                if running { *COLOR_ACTIVE } else { *COLOR_ALERT },
+                if running.len() > 0 {
+                    *COLOR_ACTIVE
+                } else {
+                    *COLOR_ALERT
+                },
             );
             line.append_plain("] ");
 
-            if running {
+            if running.len() > 0 {
+                let mut usage = UsageReport::default();
+                for (_, u) in running.iter() {
+                    usage += u;
+                }
                 line.append_plain(format!(
                     "load:{:>5}%  lat:{:4.0}ms  cpu:{:>5}%  mem:{:>6}  io:{:>6}",
-                    format_pct_dashed(rep.load),
-                    rep.lat.ctl * 1000.0,
+                    format_pct_dashed(
+                        running.iter().map(|(rep, _)| rep.load).sum::<f64>() / running.len() as f64
+                    ),
+                    running
+                        .iter()
+                        .map(|(rep, _)| rep.lat.ctl)
+                        .fold(0.0, f64::max)
+                        * 1000.0,
                     &format_pct_dashed(usage.cpu_util),
                     &format_size_dashed(usage.mem_bytes),
                     &format_size_dashed(usage.io_rbps + usage.io_wbps),
@@ -249,17 +272,29 @@ impl UpdateWorker {
             }
         }
 
-        siv.call_on_name(&format!("status-{}", name), |v: &mut TextView| {
+        siv.call_on_name(&format!("status-{}", view), |v: &mut TextView| {
             v.set_content(line);
         });
     }
 
-    fn refresh_status(siv: &mut Cursive) {
+    fn current_report() -> Report {
+        if replay::replaying() {
+            replay::report().unwrap_or_default()
+        } else {
+            AGENT_FILES.report()
+        }
+    }
+
+    fn refresh_status(siv: &mut Cursive, rep: Report) {
         let mut line = StyledString::new();
-        let rep = AGENT_FILES.report();
+        let replay_desc = replay::describe();
 
         let timestamp = SystemTime::from(rep.timestamp);
-        let stale = match SystemTime::now().duration_since(timestamp) {
+        let now = match replay_desc {
+            Some(_) => UNIX_EPOCH + Duration::from_secs(replay::demo_now()),
+            None => SystemTime::now(),
+        };
+        let stale = match now.duration_since(timestamp) {
             Ok(dur) => dur >= Duration::from_secs(3),
             Err(_) => true,
         };
@@ -281,7 +316,10 @@ impl UpdateWorker {
             if stale { *COLOR_ALERT } else { *COLOR_DFL },
         );
 
-        if stale {
+        if let Some(desc) = replay_desc {
+            line.append_plain(" - ");
+            line.append_styled(desc, *COLOR_ACTIVE);
+        } else if stale {
             line.append_plain(" - ");
             line.append_styled("'a': agent launcher", *STYLE_ALERT);
         }
@@ -295,17 +333,35 @@ impl UpdateWorker {
         Self::refresh_sideload_status(siv, &rep.sideloader, &rep.sideloads);
         Self::refresh_sysload_status(siv, &rep.sysloads);
 
-        let use_ab = rep.hashd[1].svc.state == SvcStateReport::Running;
-        if let Some(usage_a) = rep.usages.get(HASHD_A_SVC_NAME) {
-            Self::refresh_hashd_status(siv, &rep.hashd[0], usage_a, false, use_ab);
-        } else {
-            error!("Failed to find {:?} in usage report", HASHD_A_SVC_NAME);
-        }
-        if let Some(usage_b) = rep.usages.get(HASHD_B_SVC_NAME) {
-            Self::refresh_hashd_status(siv, &rep.hashd[1], usage_b, true, use_ab);
-        } else {
-            error!("Failed to find {:?} in usage report", HASHD_B_SVC_NAME);
+        let dfl_usage = UsageReport::default();
+        let mut reps = vec![];
+        for (idx, hashd) in rep.hashd.iter().enumerate() {
+            let svc_name = hashd_svc_name(idx);
+            let usage = match rep.usages.get(&svc_name) {
+                Some(v) => v,
+                None => {
+                    if hashd.svc.state == SvcStateReport::Running {
+                        error!("Failed to find {:?} in usage report", &svc_name);
+                    }
+                    &dfl_usage
+                }
+            };
+            reps.push((hashd, usage));
         }
+        let reps_rest = match reps
+            .iter()
+            .skip(2)
+            .any(|(hashd, _)| hashd.svc.state == SvcStateReport::Running)
+        {
+            true => &reps[1..],
+            false => &reps[1..2],
+        };
+        let use_ab = reps_rest
+            .iter()
+            .any(|(hashd, _)| hashd.svc.state == SvcStateReport::Running);
+
+        Self::refresh_hashd_status(siv, &reps[0..1], false, use_ab);
+        Self::refresh_hashd_status(siv, reps_rest, true, use_ab);
     }
 
     fn refresh_usage(siv: &mut Cursive, usages: BTreeMap<String, UsageReport>) {
@@ -318,9 +374,7 @@ impl UpdateWorker {
         }
     }
 
-    fn update_usage(&mut self) {
-        let rep = AGENT_FILES.report();
-
+    fn update_usage(&mut self, rep: &Report) {
         for (k, v) in rep.usages.iter() {
             match self.usages.get_mut(k) {
                 Some(cur) => *cur += v,
@@ -349,14 +403,18 @@ impl UpdateWorker {
         while wait_prog_state(dur) != ProgState::Exiting {
             let now = unix_now();
 
-            refresh_agent_states(&self.cb_sink);
+            // there's no agent to mind while replaying
+            if !replay::replaying() {
+                refresh_agent_states(&self.cb_sink);
+            }
+
+            let rep = Self::current_report();
+            self.update_usage(&rep);
 
             self.cb_sink
-                .send(Box::new(move |siv| Self::refresh_status(siv)))
+                .send(Box::new(move |siv| Self::refresh_status(siv, rep)))
                 .unwrap();
 
-            self.update_usage();
-
             let sleep_till = UNIX_EPOCH + Duration::from_secs(now + 1);
             match sleep_till.duration_since(SystemTime::now()) {
                 Ok(v) => dur = v,
@@ -393,27 +451,69 @@ impl Drop for Updater {
     }
 }
 
+const STATUS_VIEWS: &[&str] = &[
+    "status-state",
+    "status-cfg",
+    "status-oomd",
+    "status-sideload",
+    "status-sysload",
+    "status-workload-A",
+    "status-workload-B",
+];
+
+fn text_content(siv: &mut Cursive, name: &str) -> StyledString {
+    siv.call_on_name(name, |v: &mut TextView| v.get_content().clone())
+        .unwrap_or_default()
+}
+
+/// The lines of the status panel as currently displayed.
+pub fn status_lines(siv: &mut Cursive) -> Vec<StyledString> {
+    STATUS_VIEWS
+        .iter()
+        .map(|name| text_content(siv, name))
+        .collect()
+}
+
+/// The rows of the usage panel as currently displayed, prefixed with the
+/// header.
+pub fn usage_lines(siv: &mut Cursive) -> Vec<StyledString> {
+    let mut lines = vec![StyledString::styled(
+        format!("{:12}  {}", "", USAGE_HEADER),
+        *COLOR_INACTIVE,
+    )];
+    for name in usage_row_names() {
+        let mut line = StyledString::styled(format!("{:12}", name), *COLOR_INACTIVE);
+        line.append_plain(" ");
+        line.append(text_content(siv, &format!("usage-data-{}", name)));
+        lines.push(line);
+    }
+    lines
+}
+
 pub fn status_layout_factory() -> impl View {
     let layout = get_layout();
 
-    Panel::new(
-        LinearLayout::vertical()
-            .child(TextView::new("").with_name("status-state"))
-            .child(TextView::new("").with_name("status-cfg"))
-            .child(TextView::new("").with_name("status-oomd"))
-            .child(TextView::new("").with_name("status-sideload"))
-            .child(TextView::new("").with_name("status-sysload"))
-            .child(TextView::new("").with_name("status-workload-A"))
-            .child(TextView::new("").with_name("status-workload-B")),
-    )
-    .title(format!(
-        "Facebook Resource Control Demo v{} - 'q': quit",
-        &*super::VERSION
-    ))
-    .resized(
-        SizeConstraint::Fixed(layout.status.x),
-        SizeConstraint::Fixed(layout.status.y),
-    )
+    let mut lines = LinearLayout::vertical();
+    for name in STATUS_VIEWS.iter() {
+        lines.add_child(TextView::new("").with_name(*name));
+    }
+
+    Panel::new(lines)
+        .title(if replay::replaying() {
+            format!(
+                "Replay v{} - 'p': pause '[]{{}}': seek '<>': speed 'q': quit",
+                &*super::VERSION
+            )
+        } else {
+            format!(
+                "Facebook Resource Control Demo v{} - 'q': quit",
+                &*super::VERSION
+            )
+        })
+        .resized(
+            SizeConstraint::Fixed(layout.status.x),
+            SizeConstraint::Fixed(layout.status.y),
+        )
 }
 
 fn usage_top_row() -> LinearLayout {
@@ -421,7 +521,7 @@ fn usage_top_row() -> LinearLayout {
         .child(TextView::new(format!("{:12}", "")))
         .child(DummyView)
         .child(TextView::new(StyledString::styled(
-            "  cpu%    mem   swap   rbps   wbps  cpuP%  memP%   ioP%",
+            USAGE_HEADER,
             *COLOR_INACTIVE,
         )))
 }
@@ -451,22 +551,36 @@ fn usage_row(name: &str, rep: &UsageReport) -> LinearLayout {
         .child(TextView::new(format_row_data(rep)).with_name(format!("usage-data-{}", name)))
 }
 
+fn usage_row_names() -> Vec<String> {
+    let mut names: Vec<String> = ["workload", "sideload", "hostcritical", "system", "user"]
+        .iter()
+        .map(|x| x.to_string())
+        .collect();
+    // User-defined slices, including the workload.slice sub-slices, follow
+    // the built-in ones. The rows scroll if they don't fit in the panel.
+    for slice in AGENT_FILES.slices().custom_slices() {
+        names.push(slice.split(".").next().unwrap().to_string());
+    }
+    names.push("-".into());
+    names
+}
+
 pub fn usage_layout_factory() -> impl View {
     let layout = get_layout();
     let dfl_rep = Default::default();
 
+    let mut rows = LinearLayout::vertical();
+    for name in usage_row_names() {
+        rows.add_child(usage_row(&name, &dfl_rep));
+    }
+
     Panel::new(
         LinearLayout::horizontal()
             .child(DummyView)
             .child(
                 LinearLayout::vertical()
                     .child(usage_top_row())
-                    .child(usage_row("workload", &dfl_rep))
-                    .child(usage_row("sideload", &dfl_rep))
-                    .child(usage_row("hostcritical", &dfl_rep))
-                    .child(usage_row("system", &dfl_rep))
-                    .child(usage_row("user", &dfl_rep))
-                    .child(usage_row("-", &dfl_rep)),
+                    .child(rows.scrollable()),
             )
             .child(DummyView),
     )
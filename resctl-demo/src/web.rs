@@ -0,0 +1,743 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// Web front-end. With --web, the cursive views are kept up-to-date without
+// a terminal and a small HTTP server serves the status, graphs, journal
+// tails and the current document to browsers. Document prompts are
+// executed through the same paths as the document pane, so the state seen
+// through the browser is always the state of the demo. Requests must be
+// addressed to the listening address and POSTs must carry X-Resctl-Demo so
+// that other web pages can't drive the demo.
+use anyhow::{anyhow, bail, Context, Result};
+use cursive::backend::Backend;
+use cursive::event::Event;
+use cursive::theme::{Color, ColorPair, ColorType, Effect, EffectStatus, Style};
+use cursive::utils::markup::StyledString;
+use cursive::{CbSink, Cursive, Vec2};
+use log::{debug, info, warn};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::spawn;
+use std::time::Duration;
+
+use super::command::CMD_STATE;
+use super::doc::markup_rd::{RdCmd, RdDoc, RdPara};
+use super::doc::{self, CUR_DOC, DOC_HIST};
+use super::graph;
+use super::{
+    get_layout, journal, kick_refresh, replay, status, COLOR_ACTIVE, COLOR_ALERT, COLOR_INACTIVE,
+    VERSION,
+};
+use rd_util::*;
+
+const INDEX_HTML: &str = include_str!("web/index.html");
+const SIV_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_BODY_LEN: usize = 64 << 10;
+const CONN_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_CONNS: usize = 64;
+
+// POST requests must carry this header. Browsers don't allow cross-site
+// pages to set custom headers without a CORS preflight, which isn't
+// answered, so other pages can't forge commands.
+const CSRF_HEADER: &str = "x-resctl-demo";
+
+static SERVING: AtomicBool = AtomicBool::new(false);
+
+pub fn serving() -> bool {
+    SERVING.load(Ordering::Relaxed)
+}
+
+// Drives the cursive event loop without a terminal. The views are laid out
+// at the current layout size but nothing is drawn.
+struct HeadlessBackend;
+
+impl Backend for HeadlessBackend {
+    fn name(&self) -> &str {
+        "headless"
+    }
+    fn poll_event(&mut self) -> Option<Event> {
+        None
+    }
+    fn set_title(&mut self, _title: String) {}
+    fn refresh(&mut self) {}
+    fn has_colors(&self) -> bool {
+        false
+    }
+    fn screen_size(&self) -> Vec2 {
+        get_layout().screen
+    }
+    fn move_to(&self, _pos: Vec2) {}
+    fn print(&self, _text: &str) {}
+    fn clear(&self, _color: Color) {}
+    fn set_color(&self, colors: ColorPair) -> ColorPair {
+        colors
+    }
+    fn set_effect(&self, _effect: Effect) {}
+    fn unset_effect(&self, _effect: Effect) {}
+}
+
+fn escape_html(input: &str) -> String {
+    let mut buf = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '&' => buf.push_str("&amp;"),
+            '<' => buf.push_str("&lt;"),
+            '>' => buf.push_str("&gt;"),
+            '"' => buf.push_str("&quot;"),
+            '\'' => buf.push_str("&#39;"),
+            c => buf.push(c),
+        }
+    }
+    buf
+}
+
+fn style_classes(style: &Style) -> Vec<&'static str> {
+    let mut classes = vec![];
+    let has = |effect| {
+        matches!(
+            style.effects[effect],
+            EffectStatus::On | EffectStatus::OppositeParent
+        )
+    };
+    if has(Effect::Bold) {
+        classes.push("bold");
+    }
+    if has(Effect::Underline) {
+        classes.push("underline");
+    }
+    if has(Effect::Reverse) {
+        classes.push("reverse");
+    }
+    if let ColorType::Color(color) = style.color.front {
+        if color == *COLOR_ALERT {
+            classes.push("alert");
+        } else if color == *COLOR_ACTIVE {
+            classes.push("active");
+        } else if color == *COLOR_INACTIVE {
+            classes.push("inactive");
+        }
+    }
+    classes
+}
+
+/// Convert a cursive styled string into HTML spans with the style classes
+/// defined in index.html.
+fn styled_to_html(input: &StyledString) -> String {
+    let mut buf = String::new();
+    for span in input.spans() {
+        let classes = style_classes(span.attr);
+        if classes.is_empty() {
+            buf += &escape_html(span.content);
+        } else {
+            buf += &format!(
+                "<span class=\"{}\">{}</span>",
+                classes.join(" "),
+                escape_html(span.content)
+            );
+        }
+    }
+    buf
+}
+
+fn url_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+                match u8::from_str_radix(hex, 16) {
+                    Ok(v) => {
+                        out.push(v);
+                        i += 2;
+                    }
+                    Err(_) => out.push(b'%'),
+                }
+            }
+            v => out.push(v),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+/// Parse "k0=v0&k1=v1..." from query strings and form bodies.
+fn parse_params(input: &str, params: &mut BTreeMap<String, String>) {
+    for kv in input.split('&').filter(|x| x.len() > 0) {
+        let mut toks = kv.splitn(2, '=');
+        let key = url_decode(toks.next().unwrap());
+        let val = url_decode(toks.next().unwrap_or(""));
+        params.insert(key, val);
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    // Header names are lowercased.
+    headers: BTreeMap<String, String>,
+    params: BTreeMap<String, String>,
+}
+
+impl Request {
+    fn read(stream: &TcpStream) -> Result<Self> {
+        stream.set_read_timeout(Some(CONN_TIMEOUT))?;
+        stream.set_write_timeout(Some(CONN_TIMEOUT))?;
+
+        let mut reader = BufReader::new(stream.try_clone()?.take(MAX_BODY_LEN as u64 * 2));
+        let mut req_line = String::new();
+        reader.read_line(&mut req_line)?;
+
+        let mut headers = BTreeMap::new();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 || line.trim().len() == 0 {
+                break;
+            }
+            let mut toks = line.splitn(2, ':');
+            let key = toks.next().unwrap().trim().to_ascii_lowercase();
+            headers.insert(key, toks.next().unwrap_or("").trim().to_string());
+        }
+        let content_len = match headers.get("content-length") {
+            Some(v) => v.parse::<usize>()?,
+            None => 0,
+        };
+
+        let mut toks = req_line.split_whitespace();
+        let method = toks.next().unwrap_or("").to_string();
+        let target = toks.next().unwrap_or("");
+        let mut params = BTreeMap::new();
+        let path = match target.find('?') {
+            Some(pos) => {
+                parse_params(&target[pos + 1..], &mut params);
+                target[..pos].to_string()
+            }
+            None => target.to_string(),
+        };
+
+        if content_len > 0 {
+            let mut body = vec![0u8; content_len.min(MAX_BODY_LEN)];
+            reader.read_exact(&mut body)?;
+            parse_params(&String::from_utf8_lossy(&body), &mut params);
+        }
+
+        Ok(Self {
+            method,
+            path,
+            headers,
+            params,
+        })
+    }
+
+    /// Reject requests which weren't addressed to us - the Host and Origin
+    /// must be one of `hosts` - and POSTs without CSRF_HEADER.
+    fn verify(&self, hosts: &[String]) -> Result<()> {
+        match self.headers.get("host") {
+            Some(host) if hosts.iter().any(|h| h.eq_ignore_ascii_case(host)) => {}
+            host => return Err(anyhow!("host {:?} not allowed", host)),
+        }
+        if let Some(origin) = self.headers.get("origin") {
+            let origin_host = origin.strip_prefix("http://").unwrap_or("");
+            if !hosts.iter().any(|h| h.eq_ignore_ascii_case(origin_host)) {
+                return Err(anyhow!("origin {:?} not allowed", origin));
+            }
+        }
+        if self.method == "POST" && self.headers.get(CSRF_HEADER).map(|x| x.as_str()) != Some("1") {
+            return Err(anyhow!("{:?} header missing", CSRF_HEADER));
+        }
+        Ok(())
+    }
+
+    fn param(&self, key: &str) -> Result<&str> {
+        self.params
+            .get(key)
+            .map(|x| x.as_str())
+            .ok_or_else(|| anyhow!("parameter {:?} missing", key))
+    }
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn new(status: &'static str, content_type: &'static str, body: Vec<u8>) -> Self {
+        Self {
+            status,
+            content_type,
+            body,
+        }
+    }
+
+    fn json<T: Serialize>(data: &T) -> Self {
+        match serde_json::to_vec(data) {
+            Ok(body) => Self::new("200 OK", "application/json", body),
+            Err(e) => Self::error(anyhow!("failed to serialize ({})", &e)),
+        }
+    }
+
+    fn ok() -> Self {
+        Self::new("200 OK", "text/plain", b"OK\n".to_vec())
+    }
+
+    fn not_found() -> Self {
+        Self::new("404 Not Found", "text/plain", b"Not Found\n".to_vec())
+    }
+
+    fn forbidden(e: anyhow::Error) -> Self {
+        Self::new(
+            "403 Forbidden",
+            "text/plain",
+            format!("{:#}\n", &e).into_bytes(),
+        )
+    }
+
+    fn error(e: anyhow::Error) -> Self {
+        Self::new(
+            "400 Bad Request",
+            "text/plain",
+            format!("{:#}\n", &e).into_bytes(),
+        )
+    }
+
+    fn write_to(&self, stream: &mut TcpStream) -> Result<()> {
+        write!(
+            stream,
+            "HTTP/1.0 {}\r\nContent-Type: {}; charset=utf-8\r\nContent-Length: {}\r\n\
+             Cache-Control: no-store\r\n\r\n",
+            self.status,
+            self.content_type,
+            self.body.len()
+        )?;
+        stream.write_all(&self.body)?;
+        Ok(())
+    }
+}
+
+/// Run `f` on the cursive thread and return the result. All view and
+/// document accesses go through here so that they're serialized against
+/// the terminal side.
+fn call_siv<F, R>(cb_sink: &CbSink, f: F) -> Result<R>
+where
+    F: 'static + Send + FnOnce(&mut Cursive) -> R,
+    R: 'static + Send,
+{
+    let (tx, rx) = crossbeam::channel::bounded(1);
+    cb_sink
+        .send(Box::new(move |siv| {
+            let _ = tx.send(f(siv));
+        }))
+        .map_err(|_| anyhow!("event loop is gone"))?;
+    rx.recv_timeout(SIV_TIMEOUT)
+        .map_err(|_| anyhow!("timed out waiting for event loop"))
+}
+
+#[derive(Serialize)]
+struct StatusResp {
+    title: String,
+    replay: bool,
+    status: Vec<String>,
+    usage: Vec<String>,
+}
+
+fn status_resp(siv: &mut Cursive) -> StatusResp {
+    let replay = replay::replaying();
+    StatusResp {
+        title: match replay {
+            true => format!("Replay v{}", *VERSION),
+            false => format!("Facebook Resource Control Demo v{}", *VERSION),
+        },
+        replay,
+        status: status::status_lines(siv)
+            .iter()
+            .map(styled_to_html)
+            .collect(),
+        usage: status::usage_lines(siv)
+            .iter()
+            .map(styled_to_html)
+            .collect(),
+    }
+}
+
+#[derive(Serialize)]
+struct JournalResp {
+    title: String,
+    html: String,
+}
+
+fn journal_resp(siv: &mut Cursive) -> Vec<JournalResp> {
+    journal::journal_contents(siv)
+        .iter()
+        .map(|(title, content)| JournalResp {
+            title: title.to_string(),
+            html: styled_to_html(content),
+        })
+        .collect()
+}
+
+#[derive(Serialize)]
+struct GraphTab {
+    name: String,
+    tags: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct GraphsResp {
+    intv: u64,
+    main: String,
+    tabs: Vec<GraphTab>,
+}
+
+fn graphs_resp() -> GraphsResp {
+    GraphsResp {
+        intv: graph::graph_intv(),
+        main: format!("{:?}", graph::main_graph_tag()),
+        tabs: graph::graph_tab_names()
+            .into_iter()
+            .enumerate()
+            .map(|(pos, name)| GraphTab {
+                name,
+                tags: graph::graph_tab_tags(pos)
+                    .iter()
+                    .map(|tag| format!("{:?}", tag))
+                    .collect(),
+            })
+            .collect(),
+    }
+}
+
+fn render_prompt(idx: usize, prompt: &str, cmd: &RdCmd) -> String {
+    let trimmed = prompt.trim_start();
+    let indent = prompt.len() - trimmed.len();
+    let (prompt, trimmed) = (escape_html(prompt), escape_html(trimmed));
+
+    let inner = match cmd {
+        RdCmd::Toggle(sw) => format!(
+            "<label><input type=\"checkbox\" data-idx=\"{}\" data-toggle=\"{}\"> {}</label>",
+            idx,
+            escape_html(&doc::toggle_name(sw)),
+            trimmed
+        ),
+        RdCmd::Knob(knob, val) if *val < 0.0 => format!(
+            "<span class=\"knob-prompt\">{}</span> <span class=\"knob-val\" data-knob-val=\"{:?}\">\
+             </span> <input type=\"range\" min=\"0\" max=\"1\" step=\"0.01\" data-idx=\"{}\" \
+             data-knob=\"{:?}\">",
+            prompt, knob, idx, knob
+        ),
+        _ => format!("<button data-idx=\"{}\">{}</button>", idx, trimmed),
+    };
+    format!(
+        "<div class=\"prompt\" style=\"margin-left: {}ch\">{}</div>\n",
+        indent, inner
+    )
+}
+
+fn render_doc(doc: &RdDoc) -> String {
+    let mut html = String::new();
+    let mut idx = 0;
+    for para in doc.body.iter() {
+        match para {
+            RdPara::Text(indent, text) => {
+                html += &format!(
+                    "<div class=\"para\"><span class=\"indent\">{}</span><span>{}</span></div>\n",
+                    escape_html(indent.as_deref().unwrap_or("")),
+                    styled_to_html(text)
+                );
+            }
+            RdPara::Prompt(prompt, cmd) => {
+                html += &render_prompt(idx, prompt, cmd);
+                idx += 1;
+            }
+        }
+    }
+    html
+}
+
+#[derive(Serialize)]
+struct DocResp {
+    id: String,
+    desc: String,
+    html: String,
+    toggles: BTreeMap<String, bool>,
+    knobs: BTreeMap<String, (f64, String)>,
+}
+
+// Called on the cursive thread which also executes the commands.
+fn doc_resp(with_html: bool) -> DocResp {
+    let mut cs = CMD_STATE.lock().unwrap();
+    let cur_doc = CUR_DOC.read().unwrap();
+    cs.refresh();
+
+    DocResp {
+        id: cur_doc.id.clone(),
+        desc: cur_doc.desc.clone(),
+        html: match with_html {
+            true => render_doc(&cur_doc),
+            false => String::new(),
+        },
+        toggles: cur_doc
+            .toggles
+            .iter()
+            .map(|sw| (doc::toggle_name(sw), doc::toggle_val(sw, &cs)))
+            .collect(),
+        knobs: cur_doc
+            .knobs
+            .iter()
+            .map(|knob| {
+                let val = doc::knob_val(knob, &cs).clamp(0.0, 1.0);
+                (
+                    format!("{:?}", knob),
+                    (val, doc::format_knob_val(knob, val).trim().to_string()),
+                )
+            })
+            .collect(),
+    }
+}
+
+fn doc_back(siv: &mut Cursive) {
+    let mut doc_hist = DOC_HIST.lock().unwrap();
+    if let Some(id) = doc_hist.pop() {
+        drop(doc_hist);
+        doc::show_doc(siv, &id, true, true);
+    }
+}
+
+fn replay_op(req: &Request) -> Result<()> {
+    match req.param("op")? {
+        "pause" => replay::toggle_pause(),
+        "seek" => replay::seek(req.param("delta")?.parse::<i64>()?),
+        "faster" => replay::speed_up(),
+        "slower" => replay::slow_down(),
+        op => return Err(anyhow!("unknown replay op {:?}", op)),
+    }
+    kick_refresh();
+    Ok(())
+}
+
+fn handle(req: &Request, cb_sink: &CbSink) -> Result<Response> {
+    let get = req.method == "GET";
+    let post = req.method == "POST";
+
+    Ok(match req.path.as_str() {
+        "/" | "/index.html" if get => {
+            Response::new("200 OK", "text/html", INDEX_HTML.as_bytes().to_vec())
+        }
+        "/api/status" if get => Response::json(&call_siv(cb_sink, status_resp)?),
+        "/api/journal" if get => Response::json(&call_siv(cb_sink, journal_resp)?),
+        "/api/graphs" if get => Response::json(&graphs_resp()),
+        "/api/graph" if get => {
+            let tag = req.param("tag")?;
+            match graph::parse_graph_tag(tag).and_then(graph::web_graph) {
+                Some(data) => Response::json(&data),
+                None => Response::not_found(),
+            }
+        }
+        "/api/doc" if get => {
+            let with_html = req.params.get("html").map(|x| x.as_str()) != Some("0");
+            Response::json(&call_siv(cb_sink, move |_| doc_resp(with_html))?)
+        }
+        "/api/doc/jump" if post => {
+            let id = req.param("id")?.to_string();
+            if !doc::DOCS.contains_key(&id) {
+                return Ok(Response::not_found());
+            }
+            call_siv(cb_sink, move |siv| doc::show_doc(siv, &id, true, false))?;
+            Response::ok()
+        }
+        "/api/doc/back" if post => {
+            call_siv(cb_sink, doc_back)?;
+            Response::ok()
+        }
+        "/api/cmd" if post => {
+            let id = req.param("doc")?.to_string();
+            let idx = req.param("idx")?.parse::<usize>()?;
+            let val = match req.params.get("val") {
+                Some(v) => Some(parse_cmd_val(v)?),
+                None => None,
+            };
+            call_siv(cb_sink, move |siv| doc::exec_prompt(siv, &id, idx, val))??;
+            Response::ok()
+        }
+        "/api/timescale" if post => {
+            match req.param("dir")? {
+                "up" => graph::graph_intv_next(),
+                _ => graph::graph_intv_prev(),
+            }
+            kick_refresh();
+            Response::ok()
+        }
+        "/api/replay" if post => {
+            replay_op(req)?;
+            Response::ok()
+        }
+        _ => Response::not_found(),
+    })
+}
+
+/// Knob values end up in cmd.json which can't carry NaN or infinities.
+fn parse_cmd_val(val: &str) -> Result<f64> {
+    let v = val.parse::<f64>().context("parsing val")?;
+    if !v.is_finite() {
+        bail!("val {:?} is not finite", val);
+    }
+    Ok(v)
+}
+
+/// The Host and Origin values which address us - the bind address as
+/// specified, the address the connection came in on and, for loopback,
+/// localhost.
+fn allowed_hosts(bind_addr: &str, local_addr: SocketAddr) -> Vec<String> {
+    let mut hosts = vec![bind_addr.to_string(), local_addr.to_string()];
+    if local_addr.ip().is_loopback() {
+        hosts.push(format!("localhost:{}", local_addr.port()));
+    }
+    hosts
+}
+
+fn serve_one(mut stream: TcpStream, bind_addr: &str, cb_sink: &CbSink) -> Result<()> {
+    let req = Request::read(&stream)?;
+    let hosts = allowed_hosts(bind_addr, stream.local_addr()?);
+    let resp = match req.verify(&hosts) {
+        Ok(()) => match handle(&req, cb_sink) {
+            Ok(v) => v,
+            Err(e) => Response::error(e),
+        },
+        Err(e) => {
+            warn!("web: Rejecting {} {} ({:#})", &req.method, &req.path, &e);
+            Response::forbidden(e)
+        }
+    };
+    resp.write_to(&mut stream)
+}
+
+/// Start serving on `addr`. Must be called before the graph updaters are
+/// created so that they don't try to plot for the terminal.
+pub fn start(addr: &str, cb_sink: CbSink) -> Result<()> {
+    let listener =
+        TcpListener::bind(addr).with_context(|| format!("binding web listener to {:?}", addr))?;
+    info!("web: Serving on http://{}", addr);
+    SERVING.store(true, Ordering::Relaxed);
+
+    let bind_addr = Arc::new(addr.to_string());
+    let nr_conns = Arc::new(AtomicUsize::new(0));
+    spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if nr_conns.fetch_add(1, Ordering::Relaxed) >= MAX_CONNS {
+                        nr_conns.fetch_sub(1, Ordering::Relaxed);
+                        debug!("web: Too many connections, dropping one");
+                        continue;
+                    }
+                    let cb_sink = cb_sink.clone();
+                    let bind_addr = bind_addr.clone();
+                    let nr_conns = nr_conns.clone();
+                    spawn(move || {
+                        if let Err(e) = serve_one(stream, &bind_addr, &cb_sink) {
+                            debug!("web: Failed to serve request ({:?})", &e);
+                        }
+                        nr_conns.fetch_sub(1, Ordering::Relaxed);
+                    });
+                }
+                Err(e) => warn!("web: Failed to accept connection ({:?})", &e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Run the event loop until SIGINT/TERM.
+pub fn run(siv: &mut Cursive) {
+    setup_prog_state();
+    let cb_sink = siv.cb_sink().clone();
+    spawn(move || {
+        while wait_prog_state(Duration::from_secs(1)) != ProgState::Exiting {}
+        let _ = cb_sink.send(Box::new(|siv| siv.quit()));
+    });
+    siv.run_with(|| Box::new(HeadlessBackend));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_params() {
+        let mut params = BTreeMap::new();
+        parse_params(
+            "doc=intro.pre&idx=3&val=0.25&msg=a+b%2Fc%3D&bad=%zz&empty",
+            &mut params,
+        );
+        assert_eq!(params["doc"], "intro.pre");
+        assert_eq!(params["idx"], "3");
+        assert_eq!(params["val"], "0.25");
+        assert_eq!(params["msg"], "a b/c=");
+        assert_eq!(params["bad"], "%zz");
+        assert_eq!(params["empty"], "");
+        assert_eq!(
+            escape_html("<a href=\"x\">&</a>"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn test_parse_cmd_val() {
+        assert_eq!(parse_cmd_val("0.25").unwrap(), 0.25);
+        assert_eq!(parse_cmd_val("-1").unwrap(), -1.0);
+        for val in &["NaN", "nan", "inf", "-inf", "infinity", "", "x"] {
+            assert!(parse_cmd_val(val).is_err(), "{:?}", val);
+        }
+    }
+
+    #[test]
+    fn test_request_verify() {
+        let hosts = allowed_hosts("0.0.0.0:8080", "127.0.0.1:8080".parse().unwrap());
+        assert_eq!(
+            hosts,
+            vec!["0.0.0.0:8080", "127.0.0.1:8080", "localhost:8080"]
+        );
+
+        let req = |method: &str, headers: &[(&str, &str)]| Request {
+            method: method.into(),
+            path: "/api/cmd".into(),
+            headers: headers
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            params: BTreeMap::new(),
+        };
+
+        assert!(req("GET", &[("host", "localhost:8080")])
+            .verify(&hosts)
+            .is_ok());
+        assert!(req("GET", &[("host", "evil.com:8080")])
+            .verify(&hosts)
+            .is_err());
+        assert!(req("GET", &[]).verify(&hosts).is_err());
+
+        let good = [
+            ("host", "127.0.0.1:8080"),
+            ("origin", "http://127.0.0.1:8080"),
+            (CSRF_HEADER, "1"),
+        ];
+        assert!(req("POST", &good).verify(&hosts).is_ok());
+        assert!(req("POST", &good[..1]).verify(&hosts).is_err());
+        assert!(req("POST", &[good[0], good[2]]).verify(&hosts).is_ok());
+        assert!(
+            req("POST", &[good[0], ("origin", "http://evil.com"), good[2]])
+                .verify(&hosts)
+                .is_err()
+        );
+        assert!(req("POST", &[good[0], ("origin", "null"), good[2]])
+            .verify(&hosts)
+            .is_err());
+    }
+}